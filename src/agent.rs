@@ -1,17 +1,201 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::File,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{atomic::AtomicBool, Arc},
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 use log::{error, info, warn};
-use subprocess::{Exec, Popen};
+use subprocess::{Exec, ExitStatus, Popen, Redirection};
 
-mod poller;
+pub mod clock;
+mod collect;
+#[cfg(target_os = "linux")]
+mod container;
+#[cfg(unix)]
+mod coredump;
+mod durable;
+mod excerpt;
+#[cfg(target_os = "linux")]
+mod fault;
+#[cfg(unix)]
+mod ingest;
+#[cfg(windows)]
+mod job;
+#[cfg(target_os = "linux")]
+mod journal;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod latency;
+mod load;
+pub mod manifest;
+pub mod naming;
+mod netsetup;
+pub mod noise;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod oom;
+mod output;
+#[cfg(target_os = "linux")]
+mod pacing;
+pub mod poller;
+pub mod prerequisites;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod priority;
+#[cfg(target_os = "linux")]
+pub mod privsep;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod procfs;
 pub mod protocol;
-use protocol::{IdOrError, PmpptRequest, PmpptResponse, Protocol, SpawnMode};
+#[cfg(target_os = "linux")]
+mod sandbox;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
+mod snapshot;
+#[cfg(target_os = "linux")]
+mod systemd;
+pub mod tail;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod watch;
+#[cfg(unix)]
+mod watchdog;
+use manifest::{Entry, Manifest};
+use protocol::{
+    AbortReason, BgProcess, Fault, FgOutput, IdOrError, LatencyProbe, Load, Mode, NetObject,
+    PmpptRequest, PmpptResponse, PollOptions, PriorityOptions, Protocol, SpawnMode, SpawnOptions,
+};
+
+/// Stop the systemd unit of the exited process, killing what is left of it.
+#[cfg(target_os = "linux")]
+fn stop_unit(id: u32, unit: Option<&str>) {
+    if let Some(Err(e)) = unit.map(systemd::stop) {
+        warn!("cannot clean up after id={}: {}", id, e);
+    }
+}
+
+/// Set the umask of the agent, returning the old one.
+#[cfg(unix)]
+pub fn set_umask(mode: Mode) -> Mode {
+    // SAFETY: plain syscall which cannot fail
+    Mode(unsafe { libc::umask(mode.0 as libc::mode_t) } as u32)
+}
+
+/// Record the current clock synchronization status, if it is known.
+fn record_clock(manifest: &mut Manifest) {
+    match clock::status() {
+        Some(status) => {
+            if !status.synchronized {
+                info!("clock is not synchronized by {}", status.source);
+            }
+            manifest.record(Entry::Clock { status });
+        }
+        None => info!("clock synchronization status is unknown"),
+    }
+}
+
+/// Sample the background noise before the run and record it, warning about the busy target.
+fn record_noise(preflight: &noise::Preflight, manifest: &mut Manifest) {
+    info!("sampling the background noise before the run");
+    match noise::sample(preflight) {
+        Ok(report) => {
+            let top: Vec<String> = report
+                .top
+                .iter()
+                .map(|p| format!("{}({}) {:.1}%", p.process, p.pid, p.cpu_pct))
+                .collect();
+            match report.idle {
+                true => info!("target is idle, CPU usage {:.1}%", report.busy_pct),
+                false => warn!(
+                    "target is not idle, CPU usage {:.1}%, top: {}",
+                    report.busy_pct,
+                    top.join(", ")
+                ),
+            }
+            manifest.record(Entry::Noise { report });
+        }
+        Err(e) => warn!("cannot sample the background noise: {}", e),
+    }
+}
+
+fn exit_code(status: ExitStatus) -> Option<u32> {
+    match status {
+        ExitStatus::Exited(code) => Some(code),
+        _ => None,
+    }
+}
+
+/// Process group of the running child.
+#[cfg(unix)]
+fn process_group(pid: u32) -> Option<u32> {
+    // SAFETY: plain syscall on the own child
+    let pgid = unsafe { libc::getpgid(pid as libc::pid_t) };
+    (pgid >= 0).then_some(pgid as u32)
+}
+
+#[cfg(not(unix))]
+fn process_group(_pid: u32) -> Option<u32> {
+    None
+}
+
+/// Agent-wide settings, used when the requests do not specify the values explicitly.
+#[derive(Clone, Default)]
+pub struct Settings {
+    pub poll: PollOptions,
+    /// Collect the core dumps of the crashed processes.
+    pub core_dumps: bool,
+    /// Detect the OOM kills, only on Linux.
+    pub oom_watch: bool,
+    /// Labels of the run stored into the manifest and the structured poller outputs.
+    pub tags: BTreeMap<String, String>,
+    pub limits: Limits,
+    pub file_names: naming::FileNames,
+    /// Write the JUnit XML summary of the steps at the stop.
+    pub junit: bool,
+    /// Print the manifest records to stdout too, as the progress stream for the CI wrappers.
+    pub progress: bool,
+    /// Extra options of `scp` collecting the files of the other hosts.
+    pub scp_options: Vec<String>,
+    /// Record the clock synchronization status at the start and the stop, and periodically.
+    pub clock_status: bool,
+    pub clock_interval: Option<Duration>,
+    /// Degraded prerequisites found before the run, recorded into the manifest.
+    pub degraded: Vec<(prerequisites::Prerequisite, String)>,
+    /// Sample the background noise before the first step.
+    pub preflight: Option<noise::Preflight>,
+}
+
+/// Quotas protecting the host from the runaway scenarios, the requests exceeding them fail.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    /// Simultaneously running background processes.
+    pub processes: Option<usize>,
+    /// Simultaneously running pollers and the other followers.
+    pub pollers: Option<usize>,
+    /// Processes spawned during the whole run, both foreground and background ones.
+    pub spawned: Option<u32>,
+}
+
+/// Prefix of the errors of the requests rejected by [`Limits`].
+pub const QUOTA_ERROR: &str = "quota exceeded";
+
+/// The way the agent run has ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Finished,
+    Aborted(AbortReason),
+    Signaled(i32),
+}
+
+impl Outcome {
+    /// Reason of the abnormal stop, `None` for the finished run.
+    pub fn abort_reason(&self) -> Option<AbortReason> {
+        match self {
+            Outcome::Finished => None,
+            Outcome::Aborted(reason) => Some(*reason),
+            Outcome::Signaled(_) => Some(AbortReason::Signal),
+        }
+    }
+}
 
 /// PMPPT Agent instance.
 ///
@@ -23,59 +207,447 @@ pub struct Agent<P: Protocol> {
     proto: P,
     count: u32,
     outdir: PathBuf,
+    settings: Settings,
+    manifest: Manifest,
     polls: HashMap<u32, Poll>,
     procs: HashMap<u32, Proc>,
+    staging: Vec<PathBuf>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    oom: Option<oom::Monitor>,
+    // ids of the spawned processes by their pids
+    pids: HashMap<u32, u32>,
+    // responses of the batch being executed
+    batch: Option<Vec<PmpptResponse>>,
+    // requests being executed, innermost last, with the flag of being already responded
+    pending: Vec<(PmpptRequest, bool)>,
+    // time of receiving the current request
+    received: chrono::DateTime<chrono::Local>,
+    // number of the processes spawned so far
+    spawned: u32,
+    // network objects to remove at the stop
+    nets: HashMap<u32, NetObject>,
+    // injected faults to revert at the stop
+    #[cfg(target_os = "linux")]
+    faults: HashMap<u32, fault::Injected>,
+    // labels of the spawned processes naming their outdir files
+    labels: HashMap<u32, String>,
+    // remote files to copy at the stop
+    collects: BTreeMap<u32, (String, Vec<String>)>,
+    clock: Option<clock::Monitor>,
 }
 
 struct Poll {
     stop: Arc<AtomicBool>,
     thrd: JoinHandle<()>,
     name: String,
+    // only the pollers can be paused
+    paused: Option<Arc<AtomicBool>>,
 }
 
 struct Proc {
     popen: Popen,
     wait4: bool,
     name: String,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+    #[cfg(target_os = "freebsd")]
+    group: bool,
+    #[cfg(unix)]
+    core: Option<coredump::Spawned>,
+    // stopped by the freeze request
+    #[cfg(unix)]
+    frozen: bool,
+    // started in the fresh namespaces
+    #[cfg(target_os = "linux")]
+    isolated: bool,
+    // started in the container namespaces by `nsenter`, which does not forward the signals
+    #[cfg(target_os = "linux")]
+    entered: bool,
+    #[cfg(target_os = "linux")]
+    pacer: Option<pacing::Pacer>,
+    // name of the transient systemd unit
+    #[cfg(target_os = "linux")]
+    unit: Option<String>,
+    // copier of the stdout noting the first output
+    output: Option<output::Tracker>,
+    // terminator of the timed process
+    #[cfg(unix)]
+    watchdog: Option<watchdog::Watchdog>,
+}
+
+/// Background process not reaped yet, enough to signal it from the other threads too.
+#[cfg(unix)]
+#[derive(Clone)]
+struct Target {
+    pid: u32,
+    #[cfg(target_os = "freebsd")]
+    group: bool,
+    #[cfg(target_os = "linux")]
+    isolated: bool,
+    #[cfg(target_os = "linux")]
+    entered: bool,
+    #[cfg(target_os = "linux")]
+    unit: Option<String>,
+}
+
+#[cfg(unix)]
+impl Target {
+    /// Terminate the process, on FreeBSD together with its process tree.
+    fn terminate(&self) -> Result<(), String> {
+        #[cfg(target_os = "freebsd")]
+        if self.group {
+            return self.signal(libc::SIGTERM);
+        }
+        // the unit is stopped with all its processes
+        #[cfg(target_os = "linux")]
+        if let Some(unit) = &self.unit {
+            return systemd::stop(unit);
+        }
+        // the isolated process is the init of its PID namespace ignoring the termination signal,
+        // and `unshare` waiting for it ignores the signal too
+        #[cfg(target_os = "linux")]
+        if self.isolated {
+            return self.signal(libc::SIGKILL);
+        }
+        #[cfg(target_os = "linux")]
+        if self.entered {
+            return self.signal(libc::SIGTERM);
+        }
+        // SAFETY: plain syscall on the own child not reaped yet
+        match unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGTERM) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error().to_string()),
+        }
+    }
+
+    /// Send the signal to the process, on Linux and FreeBSD together with its process tree.
+    fn signal(&self, signum: libc::c_int) -> Result<(), String> {
+        let pid = self.pid;
+        #[cfg(target_os = "freebsd")]
+        if self.group {
+            // SAFETY: plain syscall, the group is led by the process not reaped yet
+            return match unsafe { libc::kill(-(pid as libc::pid_t), signum) } {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error().to_string()),
+            };
+        }
+
+        // listed before the signal, the children of the exiting process are reparented
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let descendants = poller::tree::descendants(pid)
+            .map_err(|e| format!("cannot list the descendants - {}", e))?;
+
+        // SAFETY: plain syscall on the own child not reaped yet
+        if unsafe { libc::kill(pid as libc::pid_t, signum) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        for child in descendants.into_iter().skip(1) {
+            // SAFETY: plain syscall, the descendants may exit meanwhile, so the errors are ignored
+            unsafe { libc::kill(child as libc::pid_t, signum) };
+        }
+        Ok(())
+    }
+}
+
+impl Proc {
+    /// Terminate the process, on Windows and FreeBSD together with its process tree.
+    fn terminate(&mut self) -> Result<(), String> {
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            return job.terminate();
+        }
+        #[cfg(unix)]
+        if let Some(target) = self.target() {
+            return target.terminate();
+        }
+        self.popen.terminate().map_err(|e| e.to_string())
+    }
+
+    /// Send the signal to the process, on Linux and FreeBSD together with its process tree.
+    #[cfg(unix)]
+    fn signal(&self, signum: libc::c_int) -> Result<(), String> {
+        self.target()
+            .ok_or("the process has already exited")?
+            .signal(signum)
+    }
+
+    /// The process to signal, none if it has already exited.
+    #[cfg(unix)]
+    fn target(&self) -> Option<Target> {
+        Some(Target {
+            pid: self.popen.pid()?,
+            #[cfg(target_os = "freebsd")]
+            group: self.group,
+            #[cfg(target_os = "linux")]
+            isolated: self.isolated,
+            #[cfg(target_os = "linux")]
+            entered: self.entered,
+            #[cfg(target_os = "linux")]
+            unit: self.unit.clone(),
+        })
+    }
+
+    /// Start the watchdog terminating the process after the duration, if any.
+    #[cfg(unix)]
+    fn timed(mut self, id: u32, duration: Option<Duration>) -> Self {
+        if let (Some(duration), Some(target)) = (duration, self.target()) {
+            let terminate = move || target.terminate();
+            self.watchdog = Some(watchdog::Watchdog::start(id, duration, terminate));
+        }
+        self
+    }
+
+    /// Reap the process if it has exited, the watchdog is held off meanwhile.
+    fn poll(&mut self) -> Option<ExitStatus> {
+        #[cfg(unix)]
+        let mut watched = self.watchdog.as_ref().map(watchdog::Watchdog::hold);
+        let status = self.popen.poll();
+        #[cfg(unix)]
+        if let (Some(exited), Some(_)) = (&mut watched, status) {
+            **exited = true;
+        }
+        status
+    }
+}
+
+/// Move the staged files into the output directory and remove the staging one.
+///
+/// The staging location is usually on another filesystem, so the files are copied when they
+/// cannot be just renamed.
+fn unstage(staging: &Path, outdir: &Path) -> Result<(), String> {
+    let err = |path: &Path, e: std::io::Error| format!("'{}' - {}", path.to_string_lossy(), e);
+
+    for entry in staging.read_dir().map_err(|e| err(staging, e))? {
+        let src = entry.map_err(|e| err(staging, e))?.path();
+        let name = src.file_name().expect("directory entry has a name");
+        let mut dst = outdir.join(name);
+        // the files shared by the pollers, like the run database, may exist in both locations
+        if dst.exists() {
+            dst = outdir.join(format!("staged-{}", name.to_string_lossy()));
+        }
+        if std::fs::rename(&src, &dst).is_err() {
+            std::fs::copy(&src, &dst).map_err(|e| err(&src, e))?;
+            std::fs::remove_file(&src).map_err(|e| err(&src, e))?;
+        }
+    }
+    std::fs::remove_dir(staging).map_err(|e| err(staging, e))?;
+
+    info!(
+        "moved staged poll logs from '{}'",
+        staging.to_string_lossy()
+    );
+    Ok(())
 }
 
 impl<P> Agent<P>
 where
     P: Protocol,
 {
-    pub fn new(proto: P, outdir: PathBuf) -> Self {
+    pub fn new(proto: P, outdir: PathBuf, settings: Settings) -> Self {
+        let manifest = Manifest::create(&outdir).echo(settings.progress);
+        Self::with_manifest(proto, outdir, settings, manifest, 0)
+    }
+
+    /// Continue the run in the existing output directory, the ids go on after the used ones.
+    pub fn resume(
+        proto: P,
+        outdir: PathBuf,
+        settings: Settings,
+        progress: manifest::Progress,
+    ) -> Result<Self, String> {
+        durable::reopen(&outdir)?;
+        let mut manifest = Manifest::append(&outdir)?.echo(settings.progress);
+        manifest.record(Entry::Resume {
+            stage: progress.stage,
+        });
+        info!(
+            "resuming the run as stage {}, after id={}",
+            progress.stage, progress.last_id
+        );
+        Ok(Self::with_manifest(
+            proto,
+            outdir,
+            settings,
+            manifest,
+            progress.last_id,
+        ))
+    }
+
+    fn with_manifest(
+        proto: P,
+        outdir: PathBuf,
+        settings: Settings,
+        mut manifest: Manifest,
+        count: u32,
+    ) -> Self {
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if settings.oom_watch {
+            info!("OOM kills are detected only on Linux");
+        }
+        if !settings.tags.is_empty() {
+            manifest.record(Entry::Tags {
+                tags: settings.tags.clone(),
+            });
+        }
+        for (check, detail) in &settings.degraded {
+            manifest.record(Entry::Degraded {
+                check: *check,
+                detail: detail.clone(),
+            });
+        }
+        if settings.clock_status {
+            record_clock(&mut manifest);
+        }
+        if let Some(preflight) = &settings.preflight {
+            record_noise(preflight, &mut manifest);
+        }
+        let clock = match settings.clock_interval {
+            Some(interval) if settings.clock_status => Some(clock::Monitor::start(interval)),
+            _ => None,
+        };
         Self {
             proto,
-            count: 0,
+            count,
+            manifest,
             outdir,
             polls: HashMap::default(),
             procs: HashMap::default(),
+            staging: Vec::new(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            oom: settings.oom_watch.then(oom::Monitor::start).flatten(),
+            pids: HashMap::default(),
+            batch: None,
+            pending: Vec::new(),
+            received: chrono::Local::now(),
+            spawned: 0,
+            nets: HashMap::new(),
+            #[cfg(target_os = "linux")]
+            faults: HashMap::new(),
+            labels: HashMap::new(),
+            collects: BTreeMap::new(),
+            clock,
+            settings,
         }
     }
 
-    pub fn serve(mut self) {
+    pub fn serve(mut self) -> Outcome {
         info!("agent started");
 
-        let is_abnormal = loop {
-            match self.proto.recv_request() {
+        let outcome = loop {
+            if let Some(signum) = crate::signals::received() {
+                warn!("got signal {}, emergency stop", signum);
+                break Outcome::Signaled(signum);
+            }
+
+            let msg = self.proto.recv_request();
+            self.received = chrono::Local::now();
+            match msg {
                 None => {
                     error!("failed to get correct message, stop serving agent");
-                    break true;
+                    break Outcome::Aborted(AbortReason::Protocol);
                 }
-                Some(PmpptRequest::Abort) => {
-                    warn!("got 'abort' request, emergency stop");
-                    break true;
+                Some(PmpptRequest::Abort { reason }) => {
+                    warn!("got 'abort' request ({}), emergency stop", reason);
+                    break Outcome::Aborted(reason);
                 }
                 Some(PmpptRequest::Finish) => {
                     info!("got 'finish' request, stopping running activities");
-                    break false;
+                    break Outcome::Finished;
+                }
+                Some(msg) => {
+                    if let Some(outcome) = self.handle_message(msg) {
+                        break outcome;
+                    }
                 }
-                Some(msg) => self.handle_message(msg),
             }
+            self.record_oom_kills();
+        };
+
+        // the abort could be caused by the signal received in the protocol
+        let outcome = match crate::signals::received() {
+            Some(signum) => Outcome::Signaled(signum),
+            None => outcome,
         };
 
         // stop itself before Drop
-        self.stop(is_abnormal);
+        self.stop(outcome.abort_reason());
+        outcome
+    }
+
+    /// Record the OOM kills detected so far.
+    fn record_oom_kills(&mut self) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(monitor) = &self.oom {
+            let events = monitor.events();
+            self.record_oom_events(events);
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn record_oom_events(&mut self, events: Vec<oom::OomKill>) {
+        for event in events {
+            let id = event.pid.and_then(|pid| self.pids.get(&pid).copied());
+            warn!(
+                "OOM kill: pid={:?}, process={:?}, id={:?}",
+                event.pid, event.process, id
+            );
+            let entry = Entry::OomKill {
+                id,
+                pid: event.pid,
+                process: event.process,
+            };
+            self.manifest.record_at(event.time, entry);
+        }
+    }
+
+    /// Check the quotas before spawning one more process.
+    fn check_process_quota(&mut self, background: bool) -> Result<(), String> {
+        let limits = self.settings.limits;
+        if let Some(limit) = limits.spawned {
+            if self.spawned >= limit {
+                return Err(format!(
+                    "{}: {} processes already spawned, the limit is {}",
+                    QUOTA_ERROR, self.spawned, limit
+                ));
+            }
+        }
+        if let (Some(limit), true) = (limits.processes, background) {
+            // the exited processes are kept until the stop, but do not count
+            let running = self
+                .procs
+                .values_mut()
+                .map(Proc::poll)
+                .filter(Option::is_none)
+                .count();
+            if running >= limit {
+                return Err(format!(
+                    "{}: {} background processes running, the limit is {}",
+                    QUOTA_ERROR, running, limit
+                ));
+            }
+        }
+        self.spawned += 1;
+        Ok(())
+    }
+
+    /// Check the quota before starting one more poller or follower.
+    fn check_poller_quota(&self) -> Result<(), String> {
+        let Some(limit) = self.settings.limits.pollers else {
+            return Ok(());
+        };
+        let running = self
+            .polls
+            .values()
+            .filter(|poll| !poll.thrd.is_finished())
+            .count();
+        match running >= limit {
+            true => Err(format!(
+                "{}: {} pollers running, the limit is {}",
+                QUOTA_ERROR, running, limit
+            )),
+            false => Ok(()),
+        }
     }
 
     fn get_next_id(&mut self) -> u32 {
@@ -83,15 +655,45 @@ where
         self.count
     }
 
-    fn spawn_poller(&mut self, paths: &[PathBuf], name: &str) -> IdOrError {
+    /// Name of the outdir file of the step by the naming template.
+    fn file_name(&self, id: u32, kind: &str, label: &str, ext: &str) -> String {
+        self.settings.file_names.name(id, kind, label, ext)
+    }
+
+    /// Path of the outdir file of the spawned process.
+    fn spawn_file(&self, id: u32, kind: &str, ext: &str) -> PathBuf {
+        let label = self.labels.get(&id).map(String::as_str).unwrap_or_default();
+        self.outdir.join(self.file_name(id, kind, label, ext))
+    }
+
+    fn spawn_poller(&mut self, srcs: poller::Sources, name: &str, opts: &PollOptions) -> IdOrError {
+        let opts = opts.or(&self.settings.poll);
+        let mut config = poller::PollConfig::try_from(&opts)?;
+        poller::check_rate(&srcs, &config)?;
+        self.check_poller_quota()?;
+        config.describe(&srcs, name);
+        let dir_out = match &opts.staging_dir {
+            Some(dir) => self.staging_dir(dir)?,
+            None => self.outdir.clone(),
+        };
+
         let id = self.get_next_id();
-        let path_out = self.outdir.join(format!("{:03}-poll.log", id));
-        let paths = paths.to_owned(); // full clone to send to thread
+        let label = naming::label(name);
+        let path_out = dir_out.join(self.file_name(id, "poll", &label, "log"));
+        if opts.sqlite.unwrap_or(false) {
+            config.store_into(&dir_out, id, &srcs, &self.settings.tags)?;
+        }
+        if opts.parquet.unwrap_or(false) {
+            let path = dir_out.join(self.file_name(id, "poll", &label, "parquet"));
+            config.write_parquet(&path, &srcs, &self.settings.tags)?;
+        }
 
+        let paused = config.pause_flag();
         let stop_flag_agent = Arc::new(AtomicBool::default());
         let stop_flag_thread = stop_flag_agent.clone();
-        let poll_thread =
-            std::thread::spawn(move || poller::poll(paths, path_out, stop_flag_thread));
+        let poll_thread = std::thread::spawn(move || {
+            poller::poll_sources(srcs, path_out, stop_flag_thread, config)
+        });
 
         let res = self.polls.insert(
             id,
@@ -99,120 +701,1582 @@ where
                 stop: stop_flag_agent,
                 thrd: poll_thread,
                 name: name.to_owned(),
+                paused: Some(paused),
             },
         );
         assert!(res.is_none(), "got duplicate poll/proc on {}", id);
 
         info!("Poller:   id={}, path='{}'", id, name);
+        self.manifest.record(Entry::Poll {
+            id,
+            pattern: name.to_owned(),
+            duration_s: opts.duration.map(|d| d.as_secs_f64()),
+        });
 
         // TODO: add checks for failures in poller spawning
         Ok(id)
     }
 
-    fn spawn_process_foreground(&mut self, cmd: String, args: Vec<String>) {
+    fn spawn_tail(&mut self, path: PathBuf) -> IdOrError {
+        tail::check(&path)?;
+        self.check_poller_quota()?;
+
         let id = self.get_next_id();
-        let file_out = File::create_new(self.outdir.join(format!("{:03}-out.log", id))).unwrap();
-        let file_err = File::create_new(self.outdir.join(format!("{:03}-err.log", id))).unwrap();
+        let name = path.to_string_lossy().into_owned();
+        let path_out = self
+            .outdir
+            .join(self.file_name(id, "tail", &naming::label(&name), "log"));
 
-        let cmd = Exec::cmd(&cmd)
-            .args(&args)
-            .stdout(file_out)
-            .stderr(file_err);
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let tail_thread =
+            std::thread::spawn(move || tail::follow(path, path_out, stop_flag_thread));
 
-        // collect the name before spawning the process
-        let name = cmd.to_cmdline_lossy();
-        let status = cmd.join().expect("failed to capture output");
+        // followed just like the pollers, they are stopped the same way
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: tail_thread,
+                name: name.clone(),
+                paused: None,
+            },
+        );
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
 
-        info!("FG spawn: id={}, name='{}', success={:?}", id, name, status);
+        info!("Tail:     id={}, path='{}'", id, name);
+        self.manifest.record(Entry::Tail { id, path: name });
+        Ok(id)
     }
 
-    fn spawn_process_background(&mut self, cmd: String, args: Vec<String>, wait4: bool) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn spawn_watch(&mut self, pattern: &str) -> IdOrError {
+        // the directories and devices are watched too, so the paths are not read
+        let paths: Vec<PathBuf> = brace_expand::brace_expand(pattern)
+            .into_iter()
+            .flat_map(|p| {
+                glob::glob(&p)
+                    .expect("failed to lookup glob pattern")
+                    .map(|g| g.unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if paths.is_empty() {
+            return Err(format!(
+                "got empty search result on expanding '{}'",
+                pattern
+            ));
+        }
+        self.check_poller_quota()?;
+        let watcher = watch::Watcher::new(&paths)?;
+
         let id = self.get_next_id();
-        let file_out = File::create_new(self.outdir.join(format!("{:03}-out.log", id))).unwrap();
-        let file_err = File::create_new(self.outdir.join(format!("{:03}-err.log", id))).unwrap();
+        let path_out =
+            self.outdir
+                .join(self.file_name(id, "watch", &naming::label(pattern), "log"));
 
-        let cmd = Exec::cmd(&cmd)
-            .args(&args)
-            .stdout(file_out)
-            .stderr(file_err);
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let watch_thread =
+            std::thread::spawn(move || watch::watch(watcher, path_out, stop_flag_thread));
 
-        let name = cmd.to_cmdline_lossy();
-        let popen = cmd.popen().expect("failed to start process");
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: watch_thread,
+                name: pattern.to_owned(),
+                paused: None,
+            },
+        );
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
 
-        let res = self.procs.insert(
+        info!(
+            "Watch:    id={}, paths={}, pattern='{}'",
             id,
-            Proc {
-                popen,
-                wait4,
+            paths.len(),
+            pattern
+        );
+        self.manifest.record(Entry::Watch {
+            id,
+            pattern: pattern.to_owned(),
+        });
+        Ok(id)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn spawn_watch(&mut self, _pattern: &str) -> IdOrError {
+        Err("filesystem events are supported only on Linux".into())
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn spawn_latency(&mut self, probe: LatencyProbe, realtime: bool) -> IdOrError {
+        self.check_poller_quota()?;
+        let runner = latency::Probe::open(&probe)?;
+
+        let id = self.get_next_id();
+        let name = probe.to_string();
+        let path_out =
+            self.outdir
+                .join(self.file_name(id, "latency", &naming::label(&name), "log"));
+        let dest = File::create_new(&path_out)
+            .map_err(|e| format!("cannot create '{}' - {}", path_out.to_string_lossy(), e))?;
+
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let title = name.clone();
+        let latency_thread =
+            std::thread::spawn(move || runner.run(title, realtime, dest, stop_flag_thread));
+
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: latency_thread,
+                name: name.clone(),
+                paused: None,
+            },
+        );
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
+
+        info!(
+            "Latency:  id={}, probe='{}', realtime={}",
+            id, name, realtime
+        );
+        self.manifest.record(Entry::Latency { id, probe });
+        Ok(id)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn spawn_latency(&mut self, _probe: LatencyProbe, _realtime: bool) -> IdOrError {
+        Err("latency probes are supported only on Linux".into())
+    }
+
+    fn spawn_load(&mut self, load: Load, duration: Option<Duration>) -> IdOrError {
+        self.check_poller_quota()?;
+        let generator = load::Generator::open(&load)?;
+
+        let id = self.get_next_id();
+        let name = load.to_string();
+        let path_out = self
+            .outdir
+            .join(self.file_name(id, "load", &naming::label(&name), "log"));
+        let dest = File::create_new(&path_out)
+            .map_err(|e| format!("cannot create '{}' - {}", path_out.to_string_lossy(), e))?;
+
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let title = name.clone();
+        let load_thread =
+            std::thread::spawn(move || generator.run(title, duration, dest, stop_flag_thread));
+
+        // the finished generator is joined at the stop like the pollers
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: load_thread,
                 name: name.clone(),
+                paused: None,
             },
         );
         assert!(res.is_none(), "got duplicate poll/proc on {}", id);
 
-        info!("BG spawn: id={}, name='{}', wait4={}", id, name, wait4);
+        info!(
+            "Load:     id={}, load='{}', duration={:?}",
+            id, name, duration
+        );
+        self.manifest.record(Entry::Load {
+            id,
+            load,
+            duration_s: duration.map(|d| d.as_secs_f64()),
+        });
+        Ok(id)
+    }
+
+    #[cfg(unix)]
+    fn spawn_ingest(&mut self, name: &str) -> IdOrError {
+        ingest::check_name(name)?;
+        self.check_poller_quota()?;
+        let fifo = self.outdir.join(name);
+        ingest::create_fifo(&fifo)?;
+
+        let id = self.get_next_id();
+        let path_out = self
+            .outdir
+            .join(self.file_name(id, "ingest", &naming::label(name), "log"));
+
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let ingest_thread =
+            std::thread::spawn(move || ingest::ingest(fifo, path_out, stop_flag_thread));
+
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: ingest_thread,
+                name: name.to_owned(),
+                paused: None,
+            },
+        );
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
+
+        info!("Ingest:   id={}, name='{}'", id, name);
+        self.manifest.record(Entry::Ingest {
+            id,
+            name: name.to_owned(),
+        });
+        Ok(id)
+    }
+
+    #[cfg(not(unix))]
+    fn spawn_ingest(&mut self, _name: &str) -> IdOrError {
+        Err("metrics ingestion is supported only on Unix".into())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn spawn_journal(&mut self, units: Vec<String>) -> IdOrError {
+        self.check_poller_quota()?;
+        let id = self.get_next_id();
+        let label = naming::label(&units.join(","));
+        let path_out = self
+            .outdir
+            .join(self.file_name(id, "journal", &label, "log"));
+        let popen = journal::start(&units, &path_out)?;
+
+        let stop_flag_agent = Arc::new(AtomicBool::default());
+        let stop_flag_thread = stop_flag_agent.clone();
+        let journal_thread = std::thread::spawn(move || journal::follow(popen, stop_flag_thread));
+
+        let name = match units.is_empty() {
+            true => "journal".to_owned(),
+            false => format!("journal of {}", units.join(",")),
+        };
+        let res = self.polls.insert(
+            id,
+            Poll {
+                stop: stop_flag_agent,
+                thrd: journal_thread,
+                name: name.clone(),
+                paused: None,
+            },
+        );
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
+
+        info!("Journal:  id={}, units={:?}", id, units);
+        self.manifest.record(Entry::Journal { id, units });
+        Ok(id)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn spawn_journal(&mut self, _units: Vec<String>) -> IdOrError {
+        Err("systemd journal is supported only on Linux".into())
+    }
+
+    fn add_collect(&mut self, host: String, paths: Vec<String>) -> IdOrError {
+        collect::check(&host, &paths)?;
+        let id = self.get_next_id();
+        info!("Collect:  id={}, host={}, paths={:?}", id, host, paths);
+        self.manifest.record(Entry::Collect {
+            id,
+            host: host.clone(),
+            paths: paths.clone(),
+        });
+        self.collects.insert(id, (host, paths));
+        Ok(id)
+    }
+
+    /// Copy the remote files, the failures do not fail the stop.
+    fn collect_files(&mut self) {
+        for (id, (host, paths)) in std::mem::take(&mut self.collects) {
+            info!("collecting files id={} from {}", id, host);
+            let dest = self.outdir.join(self.file_name(id, "collect", &host, ""));
+            let exit_code = match collect::copy(&host, &paths, &dest, &self.settings.scp_options) {
+                Ok(0) => Some(0),
+                Ok(code) => {
+                    error!("cannot collect all the files id={} from {}", id, host);
+                    Some(code)
+                }
+                Err(e) => {
+                    error!("cannot collect files id={}: {}", id, e);
+                    None
+                }
+            };
+            self.manifest.record(Entry::Done { id, exit_code });
+        }
+    }
+
+    /// Directory of this run inside the staging location, created on the first use.
+    fn staging_dir(&mut self, base: &Path) -> Result<PathBuf, String> {
+        let dir = base.join(format!("pmppt-{}", std::process::id()));
+        if !self.staging.contains(&dir) {
+            std::fs::create_dir_all(&dir).map_err(|e| {
+                format!(
+                    "cannot create staging dir '{}' - {}",
+                    dir.to_string_lossy(),
+                    e
+                )
+            })?;
+            info!("staging poll logs in '{}'", dir.to_string_lossy());
+            self.staging.push(dir.clone());
+        }
+        Ok(dir)
+    }
+
+    /// Command of the isolated or filtered process, the isolated one gets its root at the outdir
+    /// entry of its id.
+    #[cfg(target_os = "linux")]
+    fn confine(
+        &self,
+        id: u32,
+        cmd: &str,
+        args: &[String],
+        opts: &SpawnOptions,
+    ) -> Result<Exec, String> {
+        if let Some(container) = &opts.container {
+            let confined = opts.isolate.is_some()
+                || opts.seccomp.is_some()
+                || opts.pacing.is_some()
+                || opts.systemd.is_some();
+            if confined {
+                return Err(
+                    "container spawn cannot be combined with isolation, seccomp, pacing or \
+                     systemd unit"
+                        .into(),
+                );
+            }
+            return container::exec(container, opts.cwd.as_deref(), &opts.env, cmd, args);
+        }
+
+        // the seccomp filter is installed by the agent itself running the command
+        let (cmd, args, agent) = match &opts.seccomp {
+            None => (cmd.to_owned(), args.to_vec(), None),
+            Some(profile) => {
+                let agent = std::env::current_exe()
+                    .map_err(|e| format!("cannot locate the agent binary - {}", e))?;
+                let args = seccomp::command(profile, cmd, args)?;
+                (agent.to_string_lossy().into_owned(), args, Some(agent))
+            }
+        };
+
+        if let Some(unit) = &opts.systemd {
+            if opts.isolate.is_some() || opts.pacing.is_some() {
+                return Err("systemd unit cannot be combined with isolation or pacing".into());
+            }
+            let cwd = opts.cwd.as_deref();
+            return Ok(systemd::exec(unit, id, cwd, &opts.env, &cmd, &args));
+        }
+
+        let Some(isolation) = &opts.isolate else {
+            return Ok(Exec::cmd(cmd).args(&args));
+        };
+        let mut isolation = isolation.clone();
+        if let (Some(agent), false) = (agent, isolation.binds.is_empty()) {
+            if !isolation.binds.iter().any(|bind| agent.starts_with(bind)) {
+                isolation.binds.push(agent);
+            }
+        }
+        let root = self.spawn_file(id, "root", "");
+        sandbox::exec(&isolation, &root, opts.cwd.as_deref(), &cmd, &args)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn confine(
+        &self,
+        id: u32,
+        cmd: &str,
+        args: &[String],
+        opts: &SpawnOptions,
+    ) -> Result<Exec, String> {
+        let confined = opts.isolate.is_some()
+            || opts.seccomp.is_some()
+            || opts.pacing.is_some()
+            || opts.systemd.is_some()
+            || opts.container.is_some();
+        match confined {
+            false => Ok(Exec::cmd(cmd).args(args)),
+            true => Err(format!(
+                "cannot confine id={}, isolation, seccomp, pacing, systemd units and containers \
+                 are supported only on Linux",
+                id
+            )),
+        }
+    }
+
+    fn prepare_exec(
+        &self,
+        id: u32,
+        cmd: &str,
+        args: &[String],
+        opts: &SpawnOptions,
+    ) -> Result<Exec, String> {
+        let mut exec = self.confine(id, cmd, args, opts)?;
+        // the working directory of the container process is set inside it
+        if let (Some(cwd), None) = (&opts.cwd, &opts.container) {
+            exec = exec.cwd(cwd);
+        }
+        for (key, value) in &opts.env {
+            exec = exec.env(key, value);
+        }
+        Ok(exec)
+    }
+
+    /// Prepare the schedule of the process limits, logged into the outdir entry of its id.
+    #[cfg(target_os = "linux")]
+    fn pacer(&self, id: u32, opts: &SpawnOptions) -> Result<Option<pacing::Pacer>, String> {
+        let Some(pacing) = &opts.pacing else {
+            return Ok(None);
+        };
+        let log = self.spawn_file(id, "pacing", "log");
+        pacing::Pacer::prepare(pacing, id, &log).map(Some)
+    }
+
+    fn core_dumps(&self, opts: &SpawnOptions) -> bool {
+        opts.core_dumps.unwrap_or(self.settings.core_dumps)
+    }
+
+    /// Create the stdout or stderr log of the process with the permissions of the options.
+    fn output_file(&self, id: u32, kind: &str, opts: &SpawnOptions) -> File {
+        let file = File::create_new(self.spawn_file(id, kind, "log")).unwrap();
+        #[cfg(unix)]
+        if let Some(mode) = opts.output_mode {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(mode.0);
+            if let Err(e) = file.set_permissions(permissions) {
+                warn!(
+                    "cannot set the mode of the {} log of id={} - {}",
+                    kind, id, e
+                );
+            }
+        }
+        #[cfg(not(unix))]
+        if opts.output_mode.is_some() {
+            warn!("output file modes are not supported on this platform");
+        }
+        file
+    }
+
+    /// Start the process, with the core size limit raised if the core dumps are collected and
+    /// with the umask of the options.
+    fn start(exec: Exec, core_dumps: bool, umask: Option<Mode>) -> subprocess::Result<Popen> {
+        #[cfg(unix)]
+        let _limit = core_dumps.then(coredump::Unlimited::new);
+        #[cfg(not(unix))]
+        if core_dumps {
+            warn!("core dumps are not supported on this platform");
+        }
+        // the umask is process-wide, the files created by the other threads meanwhile get it too
+        #[cfg(unix)]
+        let old = umask.map(set_umask);
+        #[cfg(not(unix))]
+        if umask.is_some() {
+            warn!("umask is not supported on this platform");
+        }
+        let res = exec.popen();
+        #[cfg(unix)]
+        if let Some(old) = old {
+            set_umask(old);
+        }
+        res
+    }
+
+    #[cfg(unix)]
+    fn watch_core(
+        core_dumps: bool,
+        popen: &Popen,
+        cmd: &str,
+        opts: &SpawnOptions,
+    ) -> Option<coredump::Spawned> {
+        let pid = popen.pid().filter(|_| core_dumps)?;
+        Some(coredump::Spawned::new(pid, cmd, opts.cwd.as_deref()))
+    }
+
+    /// Move the core dump of the process crashed by the signal into the outdir.
+    #[cfg(unix)]
+    fn collect_core(&mut self, id: u32, core: Option<&coredump::Spawned>, status: ExitStatus) {
+        let (Some(spawned), ExitStatus::Signaled(signal)) = (core, status) else {
+            return;
+        };
+        let signal = signal as i32;
+        if !coredump::is_dumping(signal) {
+            return;
+        }
+
+        let Some(core) = spawned.find_core(signal) else {
+            warn!(
+                "no core dump found for id={} crashed by signal {}",
+                id, signal
+            );
+            return;
+        };
+        let path = self.spawn_file(id, "core", "");
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        match coredump::collect(&core, &path) {
+            Ok(()) => {
+                info!(
+                    "collected core dump of id={} from '{}'",
+                    id,
+                    core.to_string_lossy()
+                );
+                self.manifest.record(Entry::Core {
+                    id,
+                    signal,
+                    path: name,
+                });
+            }
+            Err(e) => error!(
+                "cannot collect core dump '{}' - {}",
+                core.to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    fn spawn_process_foreground(
+        &mut self,
+        cmd: String,
+        args: Vec<String>,
+        opts: &SpawnOptions,
+    ) -> Result<FgOutput, String> {
+        if opts.duration.is_some() {
+            return Err("duration is supported only for the background processes".into());
+        }
+        self.check_process_quota(false)?;
+        let id = self.get_next_id();
+        self.labels.insert(id, naming::program(&cmd));
+        let exec = self.prepare_exec(id, &cmd, &args, opts)?;
+        #[cfg(target_os = "linux")]
+        let mut pacer = self.pacer(id, opts)?;
+        #[cfg(target_os = "linux")]
+        let unit = opts.systemd.as_ref().map(|unit| systemd::name(unit, id));
+        let path_out = self.spawn_file(id, "out", "log");
+        let file_out = self.output_file(id, "out", opts);
+        let file_err = self.output_file(id, "err", opts);
+        let (exec, mut log_out) = match opts.first_output {
+            true => (exec.stdout(Redirection::Pipe), Some(file_out)),
+            false => (exec.stdout(file_out), None),
+        };
+        let exec = exec.stderr(file_err);
+
+        // collect the name before spawning the process, without the isolation wrapper
+        let name = Exec::cmd(&cmd).args(&args).to_cmdline_lossy();
+        self.manifest.record(Entry::Spawn {
+            id,
+            mode: SpawnMode::Foreground,
+            cmd: name.clone(),
+            pid: None,
+            pgid: None,
+            isolate: opts.isolate.clone(),
+            seccomp: opts.seccomp.clone(),
+            pacing: opts.pacing.clone(),
+            systemd: opts.systemd.clone(),
+            container: opts.container.clone(),
+            duration_s: opts.duration.map(|d| d.as_secs_f64()),
+        });
+        let core_dumps = self.core_dumps(opts);
+        let started = Instant::now();
+        let mut first_output = None;
+        let status = Self::start(exec, core_dumps, opts.umask).and_then(|mut popen| {
+            let tracker = log_out
+                .take()
+                .zip(popen.stdout.take())
+                .map(|(log, stdout)| output::Tracker::start(stdout, log, started));
+            if let Some(pid) = popen.pid() {
+                self.pids.insert(pid, id);
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(pacer) = &mut pacer {
+                pacer.start(popen.pid());
+            }
+            #[cfg(unix)]
+            let core = Self::watch_core(core_dumps, &popen, &cmd, opts);
+            let status = popen.wait()?;
+            first_output = tracker.and_then(output::Tracker::finish);
+            #[cfg(unix)]
+            self.collect_core(id, core.as_ref(), status);
+            Ok(status)
+        });
+        let duration = started.elapsed();
+        #[cfg(target_os = "linux")]
+        if let Some(pacer) = pacer {
+            pacer.finish();
+        }
+        #[cfg(target_os = "linux")]
+        stop_unit(id, unit.as_deref());
+        let status = status.map_err(|e| {
+            self.manifest.record(Entry::Done {
+                id,
+                exit_code: None,
+            });
+            format!("failed to run '{}' - {}", name, e)
+        })?;
+
+        info!("FG spawn: id={}, name='{}', success={:?}", id, name, status);
+        let first_output = first_output.map(|first| first.latency);
+        if opts.first_output {
+            match first_output {
+                Some(latency) => info!("first output of id={} after {:?}", id, latency),
+                None => info!("no output of id={}", id),
+            }
+            self.manifest.record(Entry::Startup {
+                id,
+                first_output_s: first_output.map(|d| d.as_secs_f64()),
+                exit_s: Some(duration.as_secs_f64()),
+            });
+        }
+
+        // the output is already stored, read it back to provide it to the controller
+        let (stdout, omitted) = File::open(&path_out)
+            .and_then(|file| excerpt::read(file, opts.reply.as_ref()))
+            .expect("cannot read back process output");
+        if omitted > 0 {
+            info!(
+                "{} bytes of the output of id={} are not returned",
+                omitted, id
+            );
+        }
+        let exit_code = exit_code(status);
+        self.manifest.record(Entry::Done { id, exit_code });
+
+        Ok(FgOutput {
+            id,
+            exit_code,
+            duration,
+            first_output,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            omitted,
+        })
+    }
+
+    /// Run the foreground process between two snapshots, stored with their diff by its id.
+    fn spawn_bracketed(
+        &mut self,
+        pattern: Option<&str>,
+        commands: &[Vec<String>],
+        cmd: String,
+        args: Vec<String>,
+        opts: &SpawnOptions,
+    ) -> Result<FgOutput, String> {
+        let files = match pattern {
+            Some(pattern) => Self::expand_pattern(pattern, false)?,
+            None => Vec::new(),
+        };
+
+        let before = snapshot::Snapshot::take(&files, commands)?;
+        let output = self.spawn_process_foreground(cmd, args, opts)?;
+        let after = snapshot::Snapshot::take(&files, commands)?;
+
+        let id = output.id;
+        let outputs = [
+            ("before", before.format()),
+            ("after", after.format()),
+            ("diff", before.diff(&after)),
+        ];
+        for (kind, content) in outputs {
+            let path = self.spawn_file(id, kind, "log");
+            std::fs::write(&path, content)
+                .map_err(|e| format!("cannot write '{}' - {}", path.to_string_lossy(), e))?;
+        }
+        info!(
+            "Bracket:  id={}, snapshots of {} sources",
+            id,
+            files.len() + commands.len()
+        );
+        Ok(output)
+    }
+
+    fn spawn_process_background(
+        &mut self,
+        cmd: String,
+        args: Vec<String>,
+        mode: SpawnMode,
+        opts: &SpawnOptions,
+    ) -> Result<BgProcess, String> {
+        #[cfg(not(unix))]
+        if opts.duration.is_some() {
+            return Err("timed background processes are supported only on unix".into());
+        }
+        self.check_process_quota(true)?;
+        let wait4 = matches!(mode, SpawnMode::BackgroundWait);
+        let id = self.get_next_id();
+        self.labels.insert(id, naming::program(&cmd));
+        let exec = self.prepare_exec(id, &cmd, &args, opts)?;
+        #[cfg(target_os = "linux")]
+        let mut pacer = self.pacer(id, opts)?;
+        let file_out = self.output_file(id, "out", opts);
+        let file_err = self.output_file(id, "err", opts);
+        let (exec, log_out) = match opts.first_output {
+            true => (exec.stdout(Redirection::Pipe), Some(file_out)),
+            false => (exec.stdout(file_out), None),
+        };
+        let exec = exec.stderr(file_err);
+
+        let name = Exec::cmd(&cmd).args(&args).to_cmdline_lossy();
+        let core_dumps = self.core_dumps(opts);
+        let started = Instant::now();
+        let mut popen = Self::start(exec, core_dumps, opts.umask).map_err(|e| {
+            #[cfg(target_os = "linux")]
+            if let Some(pacer) = pacer.take() {
+                pacer.finish();
+            }
+            format!("failed to start '{}' - {}", name, e)
+        })?;
+        let output = log_out
+            .zip(popen.stdout.take())
+            .map(|(log, stdout)| output::Tracker::start(stdout, log, started));
+        #[cfg(target_os = "linux")]
+        if let Some(pacer) = &mut pacer {
+            pacer.start(popen.pid());
+        }
+        #[cfg(unix)]
+        let core = Self::watch_core(core_dumps, &popen, &cmd, opts);
+        if let Some(pid) = popen.pid() {
+            self.pids.insert(pid, id);
+        }
+
+        #[cfg(windows)]
+        let job = match popen.pid().map(job::Job::assign) {
+            Some(Ok(job)) => Some(job),
+            Some(Err(e)) => {
+                warn!(
+                    "only the process itself will be terminated for id={}: {}",
+                    id, e
+                );
+                None
+            }
+            None => None, // already exited
+        };
+
+        // the group cannot be set in the child before exec, so it races with the early forks
+        #[cfg(target_os = "freebsd")]
+        let group = popen.pid().is_some_and(|pid| {
+            // SAFETY: plain syscall on the own child
+            unsafe { libc::setpgid(pid as libc::pid_t, 0) == 0 }
+        });
+
+        // taken before the process may be reaped
+        let pid = popen.pid();
+        let pgid = pid.and_then(process_group);
+
+        let proc = Proc {
+            popen,
+            wait4,
+            name: name.clone(),
+            #[cfg(windows)]
+            job,
+            #[cfg(target_os = "freebsd")]
+            group,
+            #[cfg(unix)]
+            core,
+            #[cfg(unix)]
+            frozen: false,
+            #[cfg(target_os = "linux")]
+            isolated: opts.isolate.is_some(),
+            #[cfg(target_os = "linux")]
+            entered: opts.container.as_ref().is_some_and(|c| c.nsenter),
+            #[cfg(target_os = "linux")]
+            pacer,
+            #[cfg(target_os = "linux")]
+            unit: opts.systemd.as_ref().map(|unit| systemd::name(unit, id)),
+            output,
+            #[cfg(unix)]
+            watchdog: None,
+        };
+        #[cfg(unix)]
+        let proc = proc.timed(id, opts.duration);
+        let res = self.procs.insert(id, proc);
+        assert!(res.is_none(), "got duplicate poll/proc on {}", id);
+
+        info!(
+            "BG spawn: id={}, name='{}', wait4={}, pid={:?}, pgid={:?}",
+            id, name, wait4, pid, pgid
+        );
+        self.manifest.record(Entry::Spawn {
+            id,
+            mode,
+            cmd: name,
+            pid,
+            pgid,
+            isolate: opts.isolate.clone(),
+            seccomp: opts.seccomp.clone(),
+            pacing: opts.pacing.clone(),
+            systemd: opts.systemd.clone(),
+            container: opts.container.clone(),
+            duration_s: opts.duration.map(|d| d.as_secs_f64()),
+        });
+
+        Ok(BgProcess { id, pid, pgid })
     }
 
-    fn spawn_process(&mut self, cmd: String, args: Vec<String>, mode: SpawnMode) {
-        match mode {
-            SpawnMode::Foreground => self.spawn_process_foreground(cmd, args),
-            SpawnMode::BackgroundWait => self.spawn_process_background(cmd, args, true),
-            SpawnMode::BackgroundKill => self.spawn_process_background(cmd, args, false),
+    /// Expand the poll pattern into the readable paths, in the strict mode every brace expansion
+    /// must match something.
+    fn expand_pattern(pattern: &str, strict: bool) -> Result<Vec<PathBuf>, String> {
+        // expand braces and interpret each expansion as a glob
+        let mut paths = Vec::new();
+        for p in brace_expand::brace_expand(poller::resolve_preset(pattern)?) {
+            // emulated paths do not exist in the filesystem, take them as is
+            let found = match poller::is_emulated(Path::new(&p)) {
+                true => vec![PathBuf::from(p.as_str())],
+                false => poller::readable_only(
+                    glob::glob(&p)
+                        .expect("failed to lookup glob pattern")
+                        .map(|g| g.unwrap())
+                        .collect(),
+                ),
+            };
+            if strict && found.is_empty() {
+                return Err(format!(
+                    "got empty search result for '{}' on expanding '{}'",
+                    p, pattern
+                ));
+            }
+            paths.extend(found);
         }
+
+        // interpret empty search result as a failure
+        match paths.is_empty() {
+            false => Ok(paths),
+            true => Err(format!(
+                "got empty search result on expanding '{}'",
+                pattern
+            )),
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn poll_pid(&mut self, target: &protocol::PidTarget, opts: &PollOptions) -> IdOrError {
+        let pattern = procfs::pattern(target)?;
+        let paths = Self::expand_pattern(&pattern, false)?;
+        self.spawn_poller(poller::Sources::Files(paths), &pattern, opts)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn poll_pid(&mut self, _target: &protocol::PidTarget, _opts: &PollOptions) -> IdOrError {
+        Err("polling the running processes is supported only on Linux".into())
+    }
+
+    /// Pause or resume the poller.
+    fn set_paused(&mut self, id: u32, pause: bool) -> Result<(), String> {
+        let poll = self
+            .polls
+            .get(&id)
+            .ok_or_else(|| format!("no running poller with id={}", id))?;
+        let paused = poll
+            .paused
+            .as_ref()
+            .ok_or_else(|| format!("id={} is not a poller, it cannot be paused", id))?;
+
+        let (action, entry) = match pause {
+            true => ("paused", Entry::Paused { id }),
+            false => ("resumed", Entry::Resumed { id }),
+        };
+        if paused.swap(pause, std::sync::atomic::Ordering::AcqRel) == pause {
+            warn!("poller id={} is already {}", id, action);
+            return Ok(());
+        }
+        info!("poller id={} is {}", id, action);
+        self.manifest.record(entry);
+        Ok(())
+    }
+
+    /// Stop or continue the background process with its descendants.
+    #[cfg(unix)]
+    fn set_frozen(&mut self, id: u32, freeze: bool) -> Result<(), String> {
+        let proc = self
+            .procs
+            .get_mut(&id)
+            .ok_or_else(|| format!("no background process with id={}", id))?;
+
+        let (action, signum, entry) = match freeze {
+            true => ("frozen", libc::SIGSTOP, Entry::Frozen { id }),
+            false => ("thawed", libc::SIGCONT, Entry::Thawed { id }),
+        };
+        if proc.frozen == freeze {
+            warn!("process id={} is already {}", id, action);
+            return Ok(());
+        }
+        proc.signal(signum)
+            .map_err(|e| format!("cannot signal process id={} - {}", id, e))?;
+        proc.frozen = freeze;
+
+        info!("process id={} is {}", id, action);
+        self.manifest.record(entry);
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn set_frozen(&mut self, _id: u32, _freeze: bool) -> Result<(), String> {
+        Err("freezing processes is supported only on unix".into())
+    }
+
+    /// Change the scheduling of the running background process.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn renice(&mut self, id: u32, opts: PriorityOptions) -> Result<(), String> {
+        let proc = self
+            .procs
+            .get(&id)
+            .ok_or_else(|| format!("no background process with id={}", id))?;
+        let pid = proc
+            .popen
+            .pid()
+            .ok_or_else(|| format!("process id={} has already exited", id))?;
+        priority::apply(pid, &opts)?;
+
+        info!("renice: id={}, pid={}, {:?}", id, pid, opts);
+        self.manifest.record(Entry::Renice { id, opts });
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn renice(&mut self, _id: u32, _opts: PriorityOptions) -> Result<(), String> {
+        Err("renice is supported only on Linux".into())
+    }
+
+    /// Wait for the idle system, blocking the following requests.
+    fn wait_idle(
+        &mut self,
+        cpu_below: f64,
+        window: Duration,
+        timeout: Duration,
+    ) -> Result<Duration, String> {
+        info!(
+            "waiting for CPU usage below {}% for {:?}, at most {:?}",
+            cpu_below, window, timeout
+        );
+        let waited = noise::wait_idle(cpu_below, window, timeout)?;
+        info!("system is idle after {:?}", waited);
+        self.manifest.record(Entry::Idle {
+            cpu_below,
+            window_s: window.as_secs_f64(),
+            waited_s: waited.as_secs_f64(),
+        });
+        Ok(waited)
+    }
+
+    /// Create the network object, it is removed at the stop if not destroyed explicitly.
+    fn net_create(&mut self, object: NetObject) -> IdOrError {
+        netsetup::create(&object)?;
+        let id = self.get_next_id();
+        info!("Net:      id={}, {}", id, object);
+        self.manifest.record(Entry::Net {
+            id,
+            object: object.clone(),
+        });
+        self.nets.insert(id, object);
+        Ok(id)
+    }
+
+    fn net_destroy(&mut self, id: u32) -> Result<(), String> {
+        let object = self
+            .nets
+            .remove(&id)
+            .ok_or_else(|| format!("no network object with id={}", id))?;
+        self.remove_net(id, object)
     }
 
-    fn handle_message(&mut self, msg: PmpptRequest) {
+    fn remove_net(&mut self, id: u32, object: NetObject) -> Result<(), String> {
+        info!("removing network object id={}, {}", id, object);
+        let res = netsetup::destroy(&object);
+        self.manifest.record(Entry::Done {
+            id,
+            exit_code: None,
+        });
+        res
+    }
+
+    /// Inject the fault, it is reverted at the stop if not reverted explicitly.
+    fn inject(&mut self, fault: Fault) -> IdOrError {
+        fault.check()?;
+        let kill = matches!(fault, Fault::Kill { .. });
+        if !kill && !cfg!(target_os = "linux") {
+            return Err("fault injection is supported only on Linux".into());
+        }
+        if let Fault::Kill { id, pid } = fault {
+            self.kill(id, pid)?;
+        }
+
+        let id = self.get_next_id();
+        #[cfg(target_os = "linux")]
+        if !kill {
+            self.faults.insert(id, fault::inject(&fault, id)?);
+        }
+        info!("Fault:    id={}, {}", id, fault);
+        self.manifest.record(Entry::Fault {
+            id,
+            fault: fault.clone(),
+        });
+        // nothing to revert, it is done at once
+        if kill {
+            self.manifest.record(Entry::Done {
+                id,
+                exit_code: None,
+            });
+        }
+        Ok(id)
+    }
+
+    /// Kill the background process with its tree or the external process.
+    #[cfg(unix)]
+    fn kill(&mut self, id: Option<u32>, pid: Option<u32>) -> Result<(), String> {
+        if let Some(id) = id {
+            let proc = self
+                .procs
+                .get(&id)
+                .ok_or_else(|| format!("no background process with id={}", id))?;
+            return proc
+                .signal(libc::SIGKILL)
+                .map_err(|e| format!("cannot kill process id={} - {}", id, e));
+        }
+        let pid = pid.expect("kill target is checked");
+        // SAFETY: plain syscall, the pid is not zero
+        match unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } {
+            0 => Ok(()),
+            _ => Err(format!(
+                "cannot kill pid={} - {}",
+                pid,
+                std::io::Error::last_os_error()
+            )),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn kill(&mut self, _id: Option<u32>, _pid: Option<u32>) -> Result<(), String> {
+        Err("kill faults are supported only on unix".into())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn revert(&mut self, id: u32) -> Result<(), String> {
+        let injected = self
+            .faults
+            .remove(&id)
+            .ok_or_else(|| format!("no fault to revert with id={}", id))?;
+        self.revert_fault(id, injected)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn revert(&mut self, id: u32) -> Result<(), String> {
+        Err(format!("no fault to revert with id={}", id))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn revert_fault(&mut self, id: u32, injected: fault::Injected) -> Result<(), String> {
+        info!("reverting fault id={}", id);
+        let res = injected.revert();
+        self.manifest.record(Entry::Done {
+            id,
+            exit_code: None,
+        });
+        res
+    }
+
+    fn record_failure<T>(&mut self, res: &Result<T, String>, request: &str) {
+        if let Err(error) = res {
+            self.manifest.record(Entry::Failed {
+                request: request.to_owned(),
+                error: error.clone(),
+            });
+        }
+    }
+
+    /// Resolve the `${pid:ID}` and `${outfile:ID}` references to the earlier spawns.
+    fn resolve_refs(&self, s: &str) -> Result<String, String> {
+        let re = regex::Regex::new(r"\$\{(pid|outfile):(\d+)\}").unwrap(); // static, should never fail
+
+        let mut error = None;
+        let res = re.replace_all(s, |c: &regex::Captures| {
+            let id: u32 = c[2].parse().unwrap_or(u32::MAX);
+            let value = match &c[1] {
+                "pid" => self
+                    .pids
+                    .iter()
+                    .find_map(|(&pid, &spawn)| (spawn == id).then_some(pid))
+                    .map(|pid| pid.to_string())
+                    .ok_or_else(|| format!("no spawned process with id={}", &c[2])),
+                _ => Some(self.spawn_file(id, "out", "log"))
+                    .filter(|path| path.exists())
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .ok_or_else(|| format!("no output file of id={}", &c[2])),
+            };
+            value.unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                String::new()
+            })
+        });
+
+        match error {
+            None => Ok(res.into_owned()),
+            Some(e) => Err(format!("cannot resolve '{}' - {}", s, e)),
+        }
+    }
+
+    /// Resolve the references in the poll patterns and the spawn arguments, the error response is
+    /// returned for the unresolved ones.
+    fn resolve_request(&mut self, msg: PmpptRequest) -> Result<PmpptRequest, PmpptResponse> {
         match msg {
-            PmpptRequest::Poll { pattern } => {
-                // expand braces and interpret each expansion as a glob
-                let paths: Vec<PathBuf> = brace_expand::brace_expand(&pattern)
-                    .into_iter()
-                    .flat_map(|p| {
-                        glob::glob(&p)
-                            .expect("failed to lookup glob pattern")
-                            .map(|g| g.unwrap())
+            PmpptRequest::Poll { pattern, opts } => match self.resolve_refs(&pattern) {
+                Ok(resolved) => Ok(PmpptRequest::Poll {
+                    pattern: resolved,
+                    opts,
+                }),
+                Err(e) => {
+                    let res = Err(e);
+                    self.record_failure(&res, &pattern);
+                    Err(PmpptResponse::Poll(res))
+                }
+            },
+            PmpptRequest::Spawn {
+                cmd,
+                args,
+                mode,
+                opts,
+            } => match args.iter().map(|arg| self.resolve_refs(arg)).collect() {
+                Ok(resolved) => Ok(PmpptRequest::Spawn {
+                    cmd,
+                    args: resolved,
+                    mode,
+                    opts,
+                }),
+                Err(e) => {
+                    self.record_failure::<()>(&Err(e.clone()), &format!("{} {:?}", cmd, args));
+                    Err(match mode {
+                        SpawnMode::Foreground => PmpptResponse::SpawnFg(Err(e)),
+                        _ => PmpptResponse::SpawnBg(Err(e)),
                     })
-                    .collect();
+                }
+            },
+            msg => Ok(msg),
+        }
+    }
+
+    /// Send the response, collecting it instead while executing the batch.
+    ///
+    /// The response must be the single one of the kind matching the request being executed, the
+    /// violation is the agent bug failing the debug builds.
+    fn respond(&mut self, response: PmpptResponse) {
+        if let Some((req, responded)) = self.pending.last_mut() {
+            let problem = match (*responded, response.answers(req)) {
+                (true, _) => Some("second"),
+                (false, false) => Some("mismatched"),
+                (false, true) => None,
+            };
+            *responded = true;
+            if let Some(problem) = problem {
+                error!(
+                    "{} {} response to the {} request",
+                    problem,
+                    response.kind(),
+                    req.kind()
+                );
+                if cfg!(debug_assertions) {
+                    panic!("invalid {} response to {:?}", response.kind(), req);
+                }
+            }
+        }
+
+        match &mut self.batch {
+            Some(responses) => responses.push(response),
+            None => {
+                self.proto.send_response(response);
+            }
+        }
+    }
 
-                // TODO: fail even if just a single brace expansion led to nothing
-                // interpret empty search result as a failure
-                let res = if !paths.is_empty() {
-                    self.spawn_poller(&paths, &pattern)
-                } else {
-                    Err(format!(
-                        "got empty search result on expanding '{}'",
-                        pattern
-                    ))
-                };
+    /// Execute the batch members in order, responding once with all their responses.
+    ///
+    /// The batch is cut short by the `Finish` or `Abort` member, the responses collected so far
+    /// are still sent.
+    fn handle_batch(&mut self, reqs: Vec<PmpptRequest>) -> Option<Outcome> {
+        info!("batch of {} requests", reqs.len());
+        let outer = self.batch.replace(Vec::new());
 
-                self.proto.send_response(PmpptResponse::Poll(res));
+        let mut outcome = None;
+        for req in reqs {
+            outcome = match req {
+                PmpptRequest::Finish => {
+                    info!("got 'finish' request in batch, stopping running activities");
+                    Some(Outcome::Finished)
+                }
+                PmpptRequest::Abort { reason } => {
+                    warn!("got 'abort' request ({}) in batch, emergency stop", reason);
+                    Some(Outcome::Aborted(reason))
+                }
+                req => self.handle_message(req),
+            };
+            if outcome.is_some() {
+                break;
             }
-            PmpptRequest::Spawn { cmd, args, mode } => {
-                self.spawn_process(cmd, args, mode);
+        }
+
+        let responses = std::mem::replace(&mut self.batch, outer).unwrap_or_default();
+        self.respond(PmpptResponse::Batch(responses));
+        outcome
+    }
+
+    /// Execute the request recording its timing, the outcome is returned if the agent must stop.
+    fn handle_message(&mut self, msg: PmpptRequest) -> Option<Outcome> {
+        let request = msg.kind().to_owned();
+        let started = chrono::Local::now();
+        self.pending.push((msg.clone(), false));
+        let outcome = self.execute(msg);
+        if let Some((req, false)) = self.pending.pop() {
+            // `Finish` and `Abort` are answered by stopping
+            if outcome.is_none() {
+                error!("no response to the {} request", req.kind());
+                if cfg!(debug_assertions) {
+                    panic!("no response to {:?}", req);
+                }
             }
+        }
+        self.manifest.record(Entry::Timing {
+            request,
+            received: manifest::format_time(self.received),
+            started: manifest::format_time(started),
+            completed: manifest::format_time(chrono::Local::now()),
+        });
+        outcome
+    }
+
+    fn execute(&mut self, msg: PmpptRequest) -> Option<Outcome> {
+        let msg = match self.resolve_request(msg) {
+            Ok(msg) => msg,
+            Err(response) => {
+                self.respond(response);
+                return None;
+            }
+        };
+
+        match msg {
+            #[cfg(windows)]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with('\\') => {
+                // performance counter paths, PDH expands the wildcards itself
+                let counters = brace_expand::brace_expand(&pattern);
+                let res = self.spawn_poller(poller::Sources::Counters(counters), &pattern, &opts);
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::perf::PREFIX) => {
+                let res = poller::perf::open(&pattern).and_then(|counters| {
+                    self.spawn_poller(poller::Sources::Perf(counters), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::vmstat::PREFIX) => {
+                let res = poller::vmstat::open(&pattern).and_then(|rates| {
+                    self.spawn_poller(poller::Sources::Vmstat(rates), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::thp::PREFIX) => {
+                let res = poller::thp::open(&pattern).and_then(|activity| {
+                    self.spawn_poller(poller::Sources::Thp(activity), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts }
+                if pattern.starts_with(poller::schedstat::PREFIX) =>
+            {
+                let res = poller::schedstat::open(&pattern).and_then(|delays| {
+                    self.spawn_poller(poller::Sources::Schedstat(delays), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(unix)]
+            PmpptRequest::Poll { pattern, opts }
+                if pattern.starts_with(poller::statvfs::PREFIX) =>
+            {
+                let res = poller::statvfs::open(&pattern).and_then(|mounts| {
+                    self.spawn_poller(poller::Sources::Statvfs(mounts), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::power::PREFIX) => {
+                let res = poller::power::open(&pattern).and_then(|sensors| {
+                    self.spawn_poller(poller::Sources::Power(sensors), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::tree::PREFIX) => {
+                let res = poller::tree::spawn_id(&pattern)
+                    .and_then(|id| {
+                        let proc = self.procs.get(&id);
+                        proc.and_then(|proc| proc.popen.pid())
+                            .ok_or_else(|| format!("no running background spawn with id={}", id))
+                    })
+                    .and_then(|pid| {
+                        let tree = poller::tree::Tree::new(pid);
+                        let srcs = poller::Sources::Tree(pattern.clone(), tree);
+                        self.spawn_poller(srcs, &pattern, &opts)
+                    });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            #[cfg(target_os = "linux")]
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(container::PREFIX) => {
+                let res = container::files(&pattern).and_then(|paths| {
+                    let paths = poller::readable_only(paths);
+                    self.spawn_poller(poller::Sources::Files(paths), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::meter::PREFIX) => {
+                let res = poller::meter::open(&pattern).and_then(|meter| {
+                    self.spawn_poller(poller::Sources::Meter(meter), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::bmc::PREFIX) => {
+                let res = poller::bmc::open(&pattern).and_then(|sensors| {
+                    self.spawn_poller(poller::Sources::Meter(sensors), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::snmp::PREFIX) => {
+                let res = poller::snmp::open(&pattern).and_then(|device| {
+                    self.spawn_poller(poller::Sources::Meter(device), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Poll { pattern, opts } if pattern.starts_with(poller::statsd::PREFIX) => {
+                let res = poller::statsd::open(&pattern).and_then(|listener| {
+                    let srcs = poller::Sources::Statsd(pattern.clone(), listener);
+                    self.spawn_poller(srcs, &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Poll { pattern, opts } => {
+                let strict = opts.strict.or(self.settings.poll.strict).unwrap_or(false);
+                let res = Self::expand_pattern(&pattern, strict).and_then(|paths| {
+                    self.spawn_poller(poller::Sources::Files(paths), &pattern, &opts)
+                });
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::PollPid { target, opts } => {
+                let request = target.to_string();
+                let res = self.poll_pid(&target, &opts);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Poll(res));
+            }
+            PmpptRequest::Spawn {
+                cmd,
+                args,
+                mode,
+                opts,
+            } => {
+                let request = format!("{} {:?}", cmd, args);
+                match mode {
+                    SpawnMode::Foreground => {
+                        let res = self.spawn_process_foreground(cmd, args, &opts);
+                        self.record_failure(&res, &request);
+                        self.respond(PmpptResponse::SpawnFg(res));
+                    }
+                    SpawnMode::BackgroundWait | SpawnMode::BackgroundKill => {
+                        let res = self.spawn_process_background(cmd, args, mode, &opts);
+                        self.record_failure(&res, &request);
+                        self.respond(PmpptResponse::SpawnBg(res));
+                    }
+                }
+            }
+            PmpptRequest::Bracket {
+                pattern,
+                commands,
+                cmd,
+                args,
+                opts,
+            } => {
+                let request = format!("{} {:?}", cmd, args);
+                let res = self.spawn_bracketed(pattern.as_deref(), &commands, cmd, args, &opts);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Bracket(res));
+            }
+            PmpptRequest::Tail { path } => {
+                let request = path.to_string_lossy().into_owned();
+                let res = self.spawn_tail(path);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Tail(res));
+            }
+            PmpptRequest::Watch { pattern } => {
+                let res = self.spawn_watch(&pattern);
+                self.record_failure(&res, &pattern);
+                self.respond(PmpptResponse::Watch(res));
+            }
+            PmpptRequest::Latency { probe, realtime } => {
+                let request = probe.to_string();
+                let res = self.spawn_latency(probe, realtime);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Latency(res));
+            }
+            PmpptRequest::Load { load, duration } => {
+                let request = load.to_string();
+                let res = self.spawn_load(load, duration);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Load(res));
+            }
+            PmpptRequest::Ingest { name } => {
+                let res = self.spawn_ingest(&name);
+                self.record_failure(&res, &name);
+                self.respond(PmpptResponse::Ingest(res));
+            }
+            PmpptRequest::Journal { units } => {
+                let request = format!("journal {:?}", units);
+                let res = self.spawn_journal(units);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Journal(res));
+            }
+            PmpptRequest::Collect { host, paths } => {
+                let request = format!("collect {:?} from {}", paths, host);
+                let res = self.add_collect(host, paths);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Collect(res));
+            }
+            PmpptRequest::PauseId { id } => {
+                let res = self.set_paused(id, true);
+                self.record_failure(&res, &format!("pause id={}", id));
+                self.respond(PmpptResponse::PauseId(res));
+            }
+            PmpptRequest::ResumeId { id } => {
+                let res = self.set_paused(id, false);
+                self.record_failure(&res, &format!("resume id={}", id));
+                self.respond(PmpptResponse::ResumeId(res));
+            }
+            PmpptRequest::Freeze { id } => {
+                let res = self.set_frozen(id, true);
+                self.record_failure(&res, &format!("freeze id={}", id));
+                self.respond(PmpptResponse::Freeze(res));
+            }
+            PmpptRequest::Thaw { id } => {
+                let res = self.set_frozen(id, false);
+                self.record_failure(&res, &format!("thaw id={}", id));
+                self.respond(PmpptResponse::Thaw(res));
+            }
+            PmpptRequest::Renice { id, opts } => {
+                let res = self.renice(id, opts);
+                self.record_failure(&res, &format!("renice id={}", id));
+                self.respond(PmpptResponse::Renice(res));
+            }
+            PmpptRequest::NetCreate { object } => {
+                let name = object.to_string();
+                let res = self.net_create(object);
+                self.record_failure(&res, &name);
+                self.respond(PmpptResponse::NetCreate(res));
+            }
+            PmpptRequest::NetDestroy { id } => {
+                let res = self.net_destroy(id);
+                self.record_failure(&res, &format!("destroy id={}", id));
+                self.respond(PmpptResponse::NetDestroy(res));
+            }
+            PmpptRequest::Fault { fault } => {
+                let request = fault.to_string();
+                let res = self.inject(fault);
+                self.record_failure(&res, &request);
+                self.respond(PmpptResponse::Fault(res));
+            }
+            PmpptRequest::Revert { id } => {
+                let res = self.revert(id);
+                self.record_failure(&res, &format!("revert id={}", id));
+                self.respond(PmpptResponse::Revert(res));
+            }
+            PmpptRequest::WaitIdle {
+                cpu_below,
+                window,
+                timeout,
+            } => {
+                let res = self.wait_idle(cpu_below, window, timeout);
+                self.record_failure(&res, &format!("wait idle below {}%", cpu_below));
+                self.respond(PmpptResponse::WaitIdle(res));
+            }
+            PmpptRequest::Batch(reqs) => return self.handle_batch(reqs),
             PmpptRequest::Finish => unreachable!("Finish must be already processed outside"),
-            PmpptRequest::Abort => unreachable!("Abort must be already processed outside"),
+            PmpptRequest::Abort { .. } => unreachable!("Abort must be already processed outside"),
         }
+        None
     }
 
-    fn stop(mut self, abnormal: bool) {
+    fn stop(mut self, reason: Option<AbortReason>) {
+        let abnormal = reason.is_some();
         let mode = if abnormal { "emergency" } else { "graceful" };
         info!("stopping agent in {} mode", mode);
 
         // stop in reverse order
         for i in (1..=self.count).rev() {
+            if let Some(object) = self.nets.remove(&i) {
+                if let Err(e) = self.remove_net(i, object) {
+                    error!("cannot remove network object id={}: {}", i, e);
+                }
+                continue;
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(injected) = self.faults.remove(&i) {
+                if let Err(e) = self.revert_fault(i, injected) {
+                    error!("cannot revert fault id={}: {}", i, e);
+                }
+                continue;
+            }
             match (self.procs.remove(&i), self.polls.remove(&i)) {
                 (Some(mut proc), None) => {
                     info!("stopping process id={}, name='{}'", i, proc.name);
+                    // the stopped process would neither exit nor handle the termination signal
+                    #[cfg(unix)]
+                    if proc.frozen {
+                        warn!("thawing the frozen process id={}", i);
+                        let _ = proc.signal(libc::SIGCONT);
+                    }
+                    #[cfg(unix)]
+                    if let Some(watchdog) = proc.watchdog.take() {
+                        match !proc.wait4 || abnormal {
+                            true => watchdog.cancel(),
+                            // the timed process is still terminated by the watchdog
+                            false => watchdog.finish(proc.popen.pid()),
+                        }
+                    }
                     if !proc.wait4 || abnormal {
                         // send the signal to terminate it now
-                        proc.popen
-                            .terminate()
-                            .unwrap_or_else(|_| panic!("failed to terminate process {}", i));
+                        proc.terminate()
+                            .unwrap_or_else(|e| panic!("failed to terminate process {}: {}", i, e));
                     }
 
-                    proc.popen
+                    let status = proc
+                        .popen
                         .wait()
                         .unwrap_or_else(|_| panic!("failed to wait for the process {}", i));
+                    #[cfg(unix)]
+                    self.collect_core(i, proc.core.as_ref(), status);
+                    #[cfg(target_os = "linux")]
+                    if let Some(pacer) = proc.pacer {
+                        pacer.finish();
+                    }
+                    #[cfg(target_os = "linux")]
+                    stop_unit(i, proc.unit.as_deref());
+                    if let Some(first) = proc.output.and_then(|o| o.finish_exited(i)) {
+                        let entry = Entry::Startup {
+                            id: i,
+                            first_output_s: Some(first.latency.as_secs_f64()),
+                            exit_s: None,
+                        };
+                        self.manifest.record_at(first.time, entry);
+                    }
+                    self.manifest.record(Entry::Done {
+                        id: i,
+                        exit_code: exit_code(status),
+                    });
                 }
 
                 (None, Some(poll)) => {
@@ -221,6 +2285,10 @@ where
                     poll.thrd
                         .join()
                         .unwrap_or_else(|_| panic!("cannot join polling thread: {}", i));
+                    self.manifest.record(Entry::Done {
+                        id: i,
+                        exit_code: None,
+                    });
                 }
 
                 // OK, it was FG process or it has been stopped already by the pmppt client
@@ -231,8 +2299,45 @@ where
             }
         }
 
+        // the remote processes are stopped by now too, their logs are complete
+        self.collect_files();
+
         // sanity checks
         assert!(self.polls.is_empty());
         assert!(self.procs.is_empty());
+        assert!(self.nets.is_empty());
+        #[cfg(target_os = "linux")]
+        assert!(self.faults.is_empty());
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(monitor) = self.oom.take() {
+            let events = monitor.stop();
+            self.record_oom_events(events);
+        }
+
+        // all the pollers are stopped, their output is complete
+        for dir in self.staging.drain(..) {
+            if let Err(e) = unstage(&dir, &self.outdir) {
+                error!("cannot move staged poll logs: {}", e);
+            }
+        }
+
+        if let Some(monitor) = self.clock.take() {
+            for (time, status) in monitor.stop() {
+                self.manifest.record_at(time, Entry::Clock { status });
+            }
+        }
+        if self.settings.clock_status {
+            record_clock(&mut self.manifest);
+        }
+
+        self.manifest.record(Entry::Stop { abnormal, reason });
+        if self.settings.junit {
+            match crate::junit::write(&self.outdir) {
+                Ok(path) => info!("JUnit summary is stored: {}", path.to_string_lossy()),
+                Err(e) => error!("cannot write the JUnit summary: {}", e),
+            }
+        }
+        durable::finish(&self.outdir, reason.map(|r| r.to_string()));
     }
 }
@@ -0,0 +1,200 @@
+//! Clock synchronization status, bounding the timestamp error of the results merged across hosts.
+//!
+//! The status is taken from the running synchronization daemon: `chronyc tracking`, `ntpq` of
+//! ntpd, or `pmc` of linuxptp for PTP, and from the kernel NTP state on Linux otherwise. The offset
+//! is the one of the system clock as reported by the source, the error is the bound of the offset
+//! from the reference, like the root delay and dispersion of NTP.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use subprocess::{Exec, Redirection};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClockStatus {
+    /// Tool or interface the status is taken from.
+    pub source: String,
+    pub synchronized: bool,
+    #[serde(default)]
+    pub offset_s: Option<f64>,
+    #[serde(default)]
+    pub error_s: Option<f64>,
+    /// Reference of the synchronization, like the NTP server.
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+/// Output of the tool, `None` if it is missing or fails, e.g. without the daemon running.
+fn output(exec: Exec) -> Option<String> {
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .ok()?;
+    capture.success().then(|| capture.stdout_str())
+}
+
+/// Status of the `chronyc -c tracking` CSV line.
+fn parse_chrony(output: &str) -> Option<ClockStatus> {
+    let fields: Vec<&str> = output.trim().split(',').collect();
+    let field = |i: usize| fields.get(i)?.parse::<f64>().ok();
+    let (root_delay, root_dispersion) = (field(10)?, field(11)?);
+    Some(ClockStatus {
+        source: "chrony".to_owned(),
+        synchronized: fields
+            .get(13)
+            .is_some_and(|leap| *leap != "Not synchronised"),
+        offset_s: field(4),
+        error_s: Some(root_dispersion + root_delay / 2.0),
+        reference: fields.get(1).map(|name| name.to_string()),
+    })
+}
+
+/// Status of the `ntpq -c rv` system variables, the times there are in milliseconds.
+fn parse_ntpq(output: &str) -> Option<ClockStatus> {
+    let vars: Vec<(&str, &str)> = output
+        .split([',', '\n'])
+        .filter_map(|var| var.trim().split_once('='))
+        .collect();
+    let var = |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+    let millis = |name: &str| var(name)?.parse::<f64>().ok().map(|ms| ms / 1e3);
+    Some(ClockStatus {
+        source: "ntpd".to_owned(),
+        // the leap indicator 3 is the alarm of the unsynchronized clock
+        synchronized: var("leap")? != "11",
+        offset_s: millis("offset"),
+        error_s: Some(millis("rootdisp")? + millis("rootdelay")? / 2.0),
+        reference: var("refid").map(|refid| refid.trim_matches('"').to_owned()),
+    })
+}
+
+/// Status of the `pmc 'GET TIME_STATUS_NP'` response, the offset there is in nanoseconds.
+fn parse_pmc(output: &str) -> Option<ClockStatus> {
+    let value = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(name)?.split_whitespace().next())
+    };
+    Some(ClockStatus {
+        source: "ptp".to_owned(),
+        synchronized: value("gmPresent")? == "true",
+        offset_s: value("master_offset")?
+            .parse::<f64>()
+            .ok()
+            .map(|ns| ns / 1e9),
+        error_s: None,
+        reference: value("gmIdentity").map(str::to_owned),
+    })
+}
+
+/// Kernel NTP state, maintained by any synchronization daemon.
+#[cfg(target_os = "linux")]
+fn kernel() -> Option<ClockStatus> {
+    // SAFETY: zeroed plain struct, the zero modes only read the state
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: plain call with the valid struct
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state < 0 {
+        return None;
+    }
+    let unit = match timex.status & libc::STA_NANO != 0 {
+        true => 1e9,
+        false => 1e6,
+    };
+    Some(ClockStatus {
+        source: "kernel".to_owned(),
+        synchronized: state != libc::TIME_ERROR && timex.status & libc::STA_UNSYNC == 0,
+        offset_s: Some(timex.offset as f64 / unit),
+        error_s: Some(timex.maxerror as f64 / 1e6),
+        reference: None,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel() -> Option<ClockStatus> {
+    None
+}
+
+/// Status of the clock synchronization, `None` if no source is found.
+pub fn status() -> Option<ClockStatus> {
+    let chrony = || output(Exec::cmd("chronyc").args(&["-c", "tracking"]));
+    let ntpq = || output(Exec::cmd("ntpq").args(&["-c", "rv"]));
+    let pmc = || output(Exec::cmd("pmc").args(&["-u", "-b", "0", "GET TIME_STATUS_NP"]));
+    chrony()
+        .and_then(|out| parse_chrony(&out))
+        .or_else(|| ntpq().and_then(|out| parse_ntpq(&out)))
+        .or_else(|| pmc().and_then(|out| parse_pmc(&out)))
+        .or_else(kernel)
+}
+
+/// Periodic sampling of the status during the run, the samples are returned at the stop.
+pub struct Monitor {
+    stop: Arc<AtomicBool>,
+    thrd: JoinHandle<()>,
+    samples: Receiver<(chrono::DateTime<chrono::Local>, ClockStatus)>,
+}
+
+impl Monitor {
+    pub fn start(interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::default());
+        let stop_thread = stop.clone();
+        let (sender, samples) = channel();
+        let thrd = std::thread::spawn(move || loop {
+            std::thread::park_timeout(interval);
+            if stop_thread.load(Ordering::Acquire) {
+                break;
+            }
+            if let Some(status) = status() {
+                let _ = sender.send((chrono::Local::now(), status));
+            }
+        });
+        Self {
+            stop,
+            thrd,
+            samples,
+        }
+    }
+
+    pub fn stop(self) -> Vec<(chrono::DateTime<chrono::Local>, ClockStatus)> {
+        let Self {
+            stop,
+            thrd,
+            samples,
+        } = self;
+        stop.store(true, Ordering::Release);
+        thrd.thread().unpark();
+        thrd.join().expect("cannot join clock monitor thread");
+        samples.try_iter().collect()
+    }
+}
+
+#[test]
+fn clock_sources() {
+    let chrony = "C0A80001,192.168.0.1,3,1760520000.1,-0.000012,0.000003,0.000020,-1.2,0.001,\
+                  0.02,0.004,0.001,64.4,Normal\n";
+    let status = parse_chrony(chrony).unwrap();
+    assert!(status.synchronized);
+    assert_eq!(status.offset_s, Some(-0.000012));
+    assert!((status.error_s.unwrap() - 0.003).abs() < 1e-12);
+    assert_eq!(status.reference.as_deref(), Some("192.168.0.1"));
+
+    let ntpq = "associd=0 status=0615 leap_none, sync_ntp, 1 event, clock_sync,\n\
+                version=\"ntpd 4.2.8p15\", leap=00, stratum=3, rootdelay=20.000,\n\
+                rootdisp=5.000, refid=10.0.0.1, offset=-1.500, frequency=-2.1\n";
+    let status = parse_ntpq(ntpq).unwrap();
+    assert!(status.synchronized);
+    assert_eq!(status.offset_s, Some(-0.0015));
+    assert!((status.error_s.unwrap() - 0.015).abs() < 1e-12);
+
+    let pmc = "sending: GET TIME_STATUS_NP\n\t001122.fffe.334455-0 seq 0 RESPONSE MANAGEMENT \
+               TIME_STATUS_NP\n\t\tmaster_offset              -25\n\t\tgmPresent                  \
+               true\n\t\tgmIdentity                 001122.fffe.334466\n";
+    let status = parse_pmc(pmc).unwrap();
+    assert!(status.synchronized);
+    assert_eq!(status.offset_s, Some(-25e-9));
+    assert!(parse_pmc("sending: GET TIME_STATUS_NP\n").is_none());
+}
@@ -0,0 +1,70 @@
+//! Collection of the companion files from the other hosts of the benchmark.
+//!
+//! When the agent runs only on the client of the two-host setup, the server logs are still needed
+//! next to the client results. The files are copied by `scp` at the stop, after all the local
+//! processes are stopped, so also after the remote server run by `ssh` is. The non-interactive SSH
+//! setup is the one of the agent host, with the extra `scp` options of the agent config like the
+//! identity file.
+
+use std::path::Path;
+
+use log::warn;
+use subprocess::{Exec, ExitStatus, Redirection};
+
+/// Limit of the connection time, the unreachable host must not hold the stop.
+const CONNECT_TIMEOUT: &str = "ConnectTimeout=10";
+
+/// Check the request beforehand, the mistakes must not be found only at the stop.
+pub fn check(host: &str, paths: &[String]) -> Result<(), String> {
+    if host.is_empty() || host.starts_with('-') || host.contains([':', '/', ' ']) {
+        return Err(format!("bad collection host '{}'", host));
+    }
+    match paths.iter().find(|path| path.is_empty()) {
+        _ if paths.is_empty() => Err("no paths to collect".into()),
+        Some(_) => Err("empty path to collect".into()),
+        None => Ok(()),
+    }
+}
+
+/// Copy the remote paths into the destination directory, returns the `scp` exit code.
+pub fn copy(host: &str, paths: &[String], dest: &Path, options: &[String]) -> Result<u32, String> {
+    std::fs::create_dir_all(dest).map_err(|e| {
+        format!(
+            "cannot create collection dir '{}' - {}",
+            dest.to_string_lossy(),
+            e
+        )
+    })?;
+    let sources: Vec<String> = paths
+        .iter()
+        .map(|path| format!("{}:{}", host, path))
+        .collect();
+    let capture = Exec::cmd("scp")
+        .args(&["-r", "-q", "-o", "BatchMode=yes", "-o", CONNECT_TIMEOUT])
+        .args(options)
+        .arg("--")
+        .args(&sources)
+        .arg(dest)
+        .stdin(Redirection::None)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run scp - {}", e))?;
+    for line in capture.stdout_str().lines() {
+        warn!("scp from {}: {}", host, line);
+    }
+    match capture.exit_status {
+        ExitStatus::Exited(code) => Ok(code),
+        status => Err(format!("scp from {} failed: {:?}", host, status)),
+    }
+}
+
+#[test]
+fn collection_requests() {
+    let paths = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    assert!(check("bench@server-1", &paths(&["/var/log/nginx", "~/run.log"])).is_ok());
+    assert!(check("-oProxyCommand=x", &paths(&["a"])).is_err());
+    assert!(check("server:/tmp", &paths(&["a"])).is_err());
+    assert!(check("server", &[]).is_err());
+    assert!(check("server", &paths(&["a", ""])).is_err());
+}
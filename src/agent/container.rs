@@ -0,0 +1,308 @@
+//! Workloads running in the docker, podman or Kubernetes containers.
+//!
+//! The process is spawned in the running container either by the runtime `exec`, which keeps
+//! running the process if its spawn is stopped as the runtime does not forward the signals, or by
+//! `nsenter` into all the namespaces of the container init found by the runtime `inspect`. The
+//! Kubernetes containers are found by `crictl` of the node by their pod and container names.
+//!
+//! The containers are referred by the `[RUNTIME/]NAME` specs, `cri/POD[/CONTAINER]` for the
+//! Kubernetes ones with the whole pod taken if the container is not given. The
+//! `container:SPEC` patterns are polled as the resource files of their cgroups, v2 or v1, and
+//! their processes are found by the cgroup membership.
+
+use std::path::{Path, PathBuf};
+
+use subprocess::{Exec, Redirection};
+
+use super::protocol::{Container, ContainerRuntime};
+
+pub const PREFIX: &str = "container:";
+
+/// Resource files of the cgroup v2 and of the v1 controllers.
+const CGROUP_FILES: &[(&str, &[&str])] = &[
+    (
+        "",
+        &[
+            "cpu.stat",
+            "memory.current",
+            "memory.stat",
+            "io.stat",
+            "pids.current",
+        ],
+    ),
+    ("cpuacct", &["cpuacct.usage", "cpuacct.stat"]),
+    ("cpu", &["cpu.stat"]),
+    ("memory", &["memory.usage_in_bytes", "memory.stat"]),
+    ("blkio", &["blkio.throttle.io_service_bytes"]),
+    ("pids", &["pids.current"]),
+];
+
+fn runtime(container: &Container) -> &'static str {
+    match container.runtime {
+        ContainerRuntime::Docker => "docker",
+        ContainerRuntime::Podman => "podman",
+        ContainerRuntime::Cri => "crictl",
+    }
+}
+
+/// Run the runtime tool, returns its output.
+fn capture(exec: Exec) -> Result<String, String> {
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(capture.stdout_str()),
+        false => Err(format!(
+            "'{}' failed: {}",
+            name,
+            capture.stderr_str().trim()
+        )),
+    }
+}
+
+/// Parse the container spec, the pod flag is set for the CRI one without the container.
+fn parse(spec: &str) -> Result<(Container, bool), String> {
+    let (runtime, name) = match spec.split_once('/') {
+        None => (ContainerRuntime::Docker, spec),
+        Some(("docker", name)) => (ContainerRuntime::Docker, name),
+        Some(("podman", name)) => (ContainerRuntime::Podman, name),
+        Some(("cri", name)) => (ContainerRuntime::Cri, name),
+        Some((runtime, _)) => return Err(format!("unknown container runtime '{}'", runtime)),
+    };
+    let container = Container {
+        name: name.to_owned(),
+        runtime,
+        nsenter: false,
+    };
+    Ok((
+        container,
+        runtime == ContainerRuntime::Cri && !name.contains('/'),
+    ))
+}
+
+/// Id of the running CRI container by the `POD[/CONTAINER]` name, the first one of the pod if
+/// the container is not given.
+fn cri_id(name: &str) -> Result<String, String> {
+    let (pod, container) = match name.split_once('/') {
+        Some((pod, container)) => (pod, Some(container)),
+        None => (name, None),
+    };
+    let pods =
+        capture(Exec::cmd("crictl").args(&["pods", "--quiet", "--state", "ready", "--name", pod]))?;
+    let pod_id = match pods.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [id] => id.to_string(),
+        [] => return Err(format!("no ready pod '{}'", pod)),
+        _ => return Err(format!("pod name '{}' is ambiguous", pod)),
+    };
+
+    let mut args = vec!["ps", "--quiet", "--state", "running", "--pod", &pod_id];
+    if let Some(container) = container {
+        args.extend(["--name", container]);
+    }
+    let ids = capture(Exec::cmd("crictl").args(&args))?;
+    ids.split_whitespace()
+        .next()
+        .map(str::to_owned)
+        .ok_or_else(|| format!("no running container '{}'", name))
+}
+
+/// Pid of the container init in the agent PID namespace.
+fn init_pid(container: &Container) -> Result<u32, String> {
+    let output = match container.runtime {
+        ContainerRuntime::Cri => capture(Exec::cmd("crictl").args(&[
+            "inspect",
+            "--output",
+            "go-template",
+            "--template",
+            "{{.info.pid}}",
+            &cri_id(&container.name)?,
+        ]))?,
+        _ => capture(Exec::cmd(runtime(container)).args(&[
+            "inspect",
+            "--format",
+            "{{.State.Pid}}",
+            &container.name,
+        ]))?,
+    };
+    match output.trim().parse() {
+        Ok(0) | Err(_) => Err(format!("container '{}' is not running", container.name)),
+        Ok(pid) => Ok(pid),
+    }
+}
+
+/// Command running the process in the container.
+pub fn exec(
+    container: &Container,
+    cwd: Option<&Path>,
+    env: &[(String, String)],
+    cmd: &str,
+    args: &[String],
+) -> Result<Exec, String> {
+    let exec = match (container.nsenter, container.runtime) {
+        (true, _) => {
+            let pid = init_pid(container)?;
+            // the host working directory makes no sense in the container, the init one is used
+            let wd = match cwd {
+                Some(cwd) => format!("--wd={}", cwd.to_string_lossy()),
+                None => "--wd".to_owned(),
+            };
+            Exec::cmd("nsenter")
+                .args(&["--target", &pid.to_string(), "--all", &wd])
+                .arg("--")
+        }
+        (false, ContainerRuntime::Cri) => {
+            if cwd.is_some() || !env.is_empty() {
+                return Err("crictl exec cannot set cwd and env, use nsenter".into());
+            }
+            Exec::cmd("crictl")
+                .arg("exec")
+                .arg(cri_id(&container.name)?)
+        }
+        (false, _) => {
+            let mut exec = Exec::cmd(runtime(container)).arg("exec");
+            if let Some(cwd) = cwd {
+                exec = exec.arg("--workdir").arg(cwd);
+            }
+            for (key, value) in env {
+                exec = exec.arg("--env").arg(format!("{}={}", key, value));
+            }
+            exec.arg(&container.name)
+        }
+    };
+    Ok(exec.arg(cmd).args(args))
+}
+
+/// Controllers and paths of the `/proc/PID/cgroup` content, the parent paths for the pod.
+///
+/// The root cgroups are skipped, they are the hierarchies not used by the container runtime.
+fn cgroup_paths(cgroups: &str, pod: bool) -> Vec<(&str, &str)> {
+    cgroups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+            let path = match pod {
+                true => &path[..path.rfind('/')?],
+                false => path,
+            };
+            (!path.trim_start_matches('/').is_empty()).then_some((controllers, path))
+        })
+        .collect()
+}
+
+/// Cgroup resource files of the paths, existing or not.
+fn cgroup_files(paths: &[(&str, &str)]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for (controllers, path) in paths {
+        let dir = Path::new("/sys/fs/cgroup")
+            .join(controllers)
+            .join(path.trim_start_matches('/'));
+        for (controller, names) in CGROUP_FILES {
+            let matched = match *controllers {
+                "" => controller.is_empty(),
+                _ => controllers.split(',').any(|c| c == *controller),
+            };
+            if matched {
+                files.extend(names.iter().map(|name| dir.join(name)));
+            }
+        }
+    }
+    files
+}
+
+/// Whether the process with the `/proc/PID/cgroup` content is inside the cgroups in all their
+/// hierarchies.
+fn is_inside(paths: &[(&str, &str)], cgroups: &str) -> bool {
+    let own = cgroup_paths(cgroups, false);
+    paths.iter().all(|(controllers, base)| {
+        own.iter().any(|(own_controllers, path)| {
+            own_controllers == controllers
+                && path
+                    .strip_prefix(base)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    })
+}
+
+/// Content of `/proc/PID/cgroup` of the container init.
+fn init_cgroups(container: &Container) -> Result<String, String> {
+    let pid = init_pid(container)?;
+    std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).map_err(|e| {
+        format!(
+            "cannot read the cgroups of container '{}' - {}",
+            container.name, e
+        )
+    })
+}
+
+/// Resolve the `container:SPEC` pattern into the existing files of the container cgroups.
+pub fn files(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let spec = pattern.strip_prefix(PREFIX).unwrap_or(pattern);
+    let (container, pod) = parse(spec)?;
+    let cgroups = init_cgroups(&container)?;
+    let files: Vec<PathBuf> = cgroup_files(&cgroup_paths(&cgroups, pod))
+        .into_iter()
+        .filter(|file| file.exists())
+        .collect();
+    match files.is_empty() {
+        true => Err(format!("no cgroup files of container '{}'", spec)),
+        false => Ok(files),
+    }
+}
+
+/// Pids of the processes of the container or the pod.
+pub fn pids(spec: &str) -> Result<Vec<u32>, String> {
+    let (container, pod) = parse(spec)?;
+    let cgroups = init_cgroups(&container)?;
+    let paths = cgroup_paths(&cgroups, pod);
+    let entries = std::fs::read_dir("/proc").map_err(|e| format!("cannot list /proc - {}", e))?;
+
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            // the process may exit while enumerating
+            std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+                .is_ok_and(|cgroups| is_inside(&paths, &cgroups))
+        })
+        .collect();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+#[test]
+fn container_cgroups() {
+    let v2 = "0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-1f.scope\n";
+    let files = cgroup_files(&cgroup_paths(v2, false));
+    assert_eq!(files.len(), 5);
+    assert_eq!(
+        files[0],
+        Path::new(
+            "/sys/fs/cgroup/kubepods.slice/kubepods-pod1.slice/cri-containerd-1f.scope/cpu.stat"
+        )
+    );
+    let pod = cgroup_paths(v2, true);
+    assert_eq!(pod, [("", "/kubepods.slice/kubepods-pod1.slice")]);
+    assert!(is_inside(
+        &pod,
+        "0::/kubepods.slice/kubepods-pod1.slice/cri-containerd-2e.scope"
+    ));
+    assert!(!is_inside(
+        &pod,
+        "0::/kubepods.slice/kubepods-pod10.slice/cri-containerd-3d.scope"
+    ));
+
+    let v1 = "4:cpu,cpuacct:/docker/1f\n3:blkio:/\n2:freezer:/docker/1f\n";
+    assert_eq!(
+        cgroup_files(&cgroup_paths(v1, false)),
+        [
+            "/sys/fs/cgroup/cpu,cpuacct/docker/1f/cpuacct.usage",
+            "/sys/fs/cgroup/cpu,cpuacct/docker/1f/cpuacct.stat",
+            "/sys/fs/cgroup/cpu,cpuacct/docker/1f/cpu.stat",
+        ]
+        .map(PathBuf::from)
+    );
+}
@@ -0,0 +1,214 @@
+//! Collection of the core dumps of the crashed spawned processes.
+//!
+//! The core size limit is raised for the processes spawned with the core dumps enabled, and the
+//! core file of the process killed by a dumping signal is looked up by the system core pattern
+//! (`/proc/sys/kernel/core_pattern` on Linux, the `kern.corefile` sysctl on BSD and macOS). The
+//! patterns piping the cores into a handler like `systemd-coredump` cannot be collected, the core
+//! is left to the handler then.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+/// Signals with the core dump as the default action.
+const DUMPING_SIGNALS: &[i32] = &[
+    libc::SIGQUIT,
+    libc::SIGILL,
+    libc::SIGTRAP,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGSEGV,
+    libc::SIGSYS,
+    libc::SIGXCPU,
+    libc::SIGXFSZ,
+];
+
+pub fn is_dumping(signal: i32) -> bool {
+    DUMPING_SIGNALS.contains(&signal)
+}
+
+/// Raised core size limit of the agent, inherited by the processes spawned while it is alive.
+pub struct Unlimited(Option<libc::rlimit>);
+
+impl Unlimited {
+    pub fn new() -> Self {
+        let mut old = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: plain syscalls with the valid rlimit structures
+        unsafe {
+            if libc::getrlimit(libc::RLIMIT_CORE, &mut old) != 0 {
+                warn!(
+                    "cannot get core size limit - {}",
+                    std::io::Error::last_os_error()
+                );
+                return Self(None);
+            }
+            let raised = libc::rlimit {
+                rlim_cur: old.rlim_max,
+                rlim_max: old.rlim_max,
+            };
+            if libc::setrlimit(libc::RLIMIT_CORE, &raised) != 0 {
+                warn!(
+                    "cannot raise core size limit - {}",
+                    std::io::Error::last_os_error()
+                );
+                return Self(None);
+            }
+        }
+        if old.rlim_max == 0 {
+            warn!("core dumps are disabled by the hard limit");
+        }
+        Self(Some(old))
+    }
+}
+
+impl Drop for Unlimited {
+    fn drop(&mut self) {
+        if let Some(old) = &self.0 {
+            // SAFETY: plain syscall with the limit got before
+            unsafe { libc::setrlimit(libc::RLIMIT_CORE, old) };
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn core_pattern() -> Option<String> {
+    let pattern = std::fs::read_to_string("/proc/sys/kernel/core_pattern").ok()?;
+    let pattern = pattern.trim_end().to_owned();
+    let uses_pid = std::fs::read_to_string("/proc/sys/kernel/core_uses_pid")
+        .is_ok_and(|value| value.trim() == "1");
+    match uses_pid && !pattern.contains("%p") && !pattern.starts_with('|') {
+        true => Some(pattern + ".%p"),
+        false => Some(pattern),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn core_pattern() -> Option<String> {
+    let mut buf = [0u8; 1024];
+    let mut len = buf.len();
+    // SAFETY: the name is NUL-terminated, the output buffer has the declared size
+    let res = unsafe {
+        libc::sysctlbyname(
+            c"kern.corefile".as_ptr(),
+            buf.as_mut_ptr().cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if res != 0 {
+        return None;
+    }
+    let value = std::ffi::CStr::from_bytes_until_nul(&buf[..len]).ok()?;
+    Some(value.to_string_lossy().into_owned())
+}
+
+/// Glob pattern of the core file, the specifiers not known here match anything.
+fn core_glob(pattern: &str, pid: u32, name: &str, signal: i32) -> String {
+    let mut glob = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            glob.push_str(&glob::Pattern::escape(c.encode_utf8(&mut [0; 4])));
+            continue;
+        }
+        match chars.next() {
+            Some('%') => glob.push('%'),
+            // the pid is %p on Linux and %P on BSD and macOS, the global pid on Linux is the same
+            Some('p' | 'P') => glob.push_str(&pid.to_string()),
+            Some('e' | 'N') => glob.push_str(&glob::Pattern::escape(name)),
+            Some('s') => glob.push_str(&signal.to_string()),
+            _ => glob.push('*'),
+        }
+    }
+    glob
+}
+
+/// Spawned process which core is collected if it crashes.
+pub struct Spawned {
+    pub pid: u32,
+    cmd: String,
+    cwd: PathBuf,
+    started: SystemTime,
+}
+
+impl Spawned {
+    /// The process just started with the given command and working directory.
+    pub fn new(pid: u32, cmd: &str, cwd: Option<&Path>) -> Self {
+        let cwd = match cwd {
+            Some(cwd) => cwd.to_owned(),
+            None => std::env::current_dir().unwrap_or_default(),
+        };
+        Self {
+            pid,
+            cmd: cmd.to_owned(),
+            cwd,
+            // the filesystem times may be coarser than the clock
+            started: SystemTime::now() - Duration::from_secs(1),
+        }
+    }
+
+    /// Find the core file of the process crashed by the signal.
+    pub fn find_core(&self, signal: i32) -> Option<PathBuf> {
+        let pattern = core_pattern()?;
+        if pattern.starts_with('|') {
+            warn!(
+                "core of pid {} is piped to '{}', it is not collected",
+                self.pid,
+                pattern.trim_start_matches('|')
+            );
+            return None;
+        }
+
+        // the kernel truncates the command name
+        let name: String = Path::new(&self.cmd)
+            .file_name()
+            .map_or(self.cmd.clone(), |name| name.to_string_lossy().into_owned())
+            .chars()
+            .take(15)
+            .collect();
+        let glob = core_glob(&pattern, self.pid, &name, signal);
+        let glob = match Path::new(&glob).is_absolute() {
+            true => glob,
+            false => format!(
+                "{}/{}",
+                glob::Pattern::escape(&self.cwd.to_string_lossy()),
+                glob
+            ),
+        };
+
+        glob::glob(&glob)
+            .ok()?
+            .filter_map(Result::ok)
+            .filter_map(|path| {
+                let modified = path.metadata().and_then(|meta| meta.modified()).ok()?;
+                (modified >= self.started).then_some((modified, path))
+            })
+            .max()
+            .map(|(_, path)| path)
+    }
+}
+
+/// Move the core into the destination, copying it when it is on another filesystem.
+pub fn collect(core: &Path, dst: &Path) -> std::io::Result<()> {
+    if std::fs::rename(core, dst).is_err() {
+        std::fs::copy(core, dst)?;
+        std::fs::remove_file(core)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn core_pattern_glob() {
+    assert_eq!(core_glob("core.%p", 42, "app", 11), "core.42");
+    assert_eq!(
+        core_glob("/tmp/%e-%s-%t.core", 42, "app", 11),
+        "/tmp/app-11-*.core"
+    );
+    assert_eq!(core_glob("%N.core%%", 7, "a[1]", 6), "a[[]1[]].core%");
+}
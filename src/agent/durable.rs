@@ -0,0 +1,111 @@
+//! Durable finish of the run: the outdir is synced to the disk and marked by the sentinel file.
+//!
+//! The `COMPLETE` sentinel is written only after all the output files and directories are synced,
+//! so the collectors can tell the fully written results from the ones cut by a power loss or a
+//! crash, which have no sentinel at all. The aborted runs, and the ones failed to be synced, get
+//! the `INCOMPLETE` sentinel with the reason instead.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use log::{info, warn};
+
+const COMPLETE_NAME: &str = "COMPLETE";
+const INCOMPLETE_NAME: &str = "INCOMPLETE";
+
+/// Open the file or the directory for the sync, Windows needs the write access for it.
+fn open(path: &Path) -> std::io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(windows)]
+    options.write(true);
+    options.open(path)
+}
+
+/// Sync the file or the directory, the directories cannot be synced on Windows.
+fn sync(path: &Path, dir: bool) -> bool {
+    if dir && cfg!(windows) {
+        return true;
+    }
+    match open(path).and_then(|file| file.sync_all()) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("cannot sync '{}' - {}", path.to_string_lossy(), e);
+            false
+        }
+    }
+}
+
+/// Sync the regular files and the directories of the tree, returns the number of the failures.
+///
+/// The symlinks are not followed and the special files, like the ingest FIFOs, are skipped.
+fn sync_tree(dir: &Path) -> usize {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("cannot read '{}' - {}", dir.to_string_lossy(), e);
+            return 1;
+        }
+    };
+    let mut failed = 0;
+    for entry in entries.flatten() {
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            failed += sync_tree(&entry.path());
+        } else if kind.is_file() {
+            failed += !sync(&entry.path(), false) as usize;
+        }
+    }
+    // the directory is synced after its entries, so they are all persisted
+    failed + !sync(dir, true) as usize
+}
+
+/// Write the sentinel file atomically and durably.
+fn write_sentinel(outdir: &Path, name: &str, content: &str) -> std::io::Result<()> {
+    let tmp = outdir.join(format!(".{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, outdir.join(name))?;
+    sync(outdir, true);
+    Ok(())
+}
+
+/// Sync the outdir and mark it complete, or incomplete with the abort reason.
+pub fn finish(outdir: &Path, abort: Option<String>) {
+    let failed = sync_tree(outdir);
+    let reason = match (abort, failed) {
+        (Some(reason), _) => Some(format!("aborted: {}", reason)),
+        (None, 0) => None,
+        (None, n) => Some(format!("not durable: {} files cannot be synced", n)),
+    };
+    let (name, content) = match &reason {
+        None => (COMPLETE_NAME, "finished\n".to_owned()),
+        Some(reason) => (INCOMPLETE_NAME, format!("{}\n", reason)),
+    };
+    match write_sentinel(outdir, name, &content) {
+        Ok(()) => info!("output directory is synced and marked {}", name),
+        Err(e) => warn!("cannot write the {} sentinel - {}", name, e),
+    }
+}
+
+/// Remove the sentinel of the finished run continued by another invocation.
+pub fn reopen(outdir: &Path) -> Result<(), String> {
+    for name in [COMPLETE_NAME, INCOMPLETE_NAME] {
+        let path = outdir.join(name);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "cannot remove '{}' - {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
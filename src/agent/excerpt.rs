@@ -0,0 +1,58 @@
+//! Excerpts of the foreground output returned in the responses.
+//!
+//! The full output is stored in the outdir anyway, so the controllers on the slow links may ask
+//! only for its head and tail. The omitted middle is replaced by the marker line with its size,
+//! and only the requested parts are read from the log.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use super::protocol::Excerpt;
+
+/// Read the whole output, or only its excerpt, returns the content and the omitted bytes.
+pub fn read<R>(mut output: R, excerpt: Option<&Excerpt>) -> std::io::Result<(Vec<u8>, u64)>
+where
+    R: Read + Seek,
+{
+    let size = output.seek(SeekFrom::End(0))?;
+    output.rewind()?;
+    let (head, tail) = match excerpt {
+        Some(excerpt) => (excerpt.head_kib * 1024, excerpt.tail_kib * 1024),
+        None => (size, 0),
+    };
+    let mut content = Vec::new();
+    if head.saturating_add(tail) >= size {
+        output.read_to_end(&mut content)?;
+        return Ok((content, 0));
+    }
+
+    output.by_ref().take(head).read_to_end(&mut content)?;
+    let omitted = size - head - tail;
+    if !content.is_empty() && !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+    content.extend(format!("<<pmppt: {} bytes omitted>>\n", omitted).bytes());
+    output.seek(SeekFrom::Start(size - tail))?;
+    output.read_to_end(&mut content)?;
+    Ok((content, omitted))
+}
+
+#[test]
+fn output_excerpts() {
+    let output = std::io::Cursor::new(vec![b'x'; 4096]);
+    let excerpt = |head_kib, tail_kib| Excerpt { head_kib, tail_kib };
+
+    let (content, omitted) = read(output.clone(), None).unwrap();
+    assert_eq!((content.len(), omitted), (4096, 0));
+    let (content, omitted) = read(output.clone(), Some(&excerpt(2, 2))).unwrap();
+    assert_eq!((content.len(), omitted), (4096, 0));
+
+    let (content, omitted) = read(output.clone(), Some(&excerpt(0, 1))).unwrap();
+    assert_eq!(omitted, 3072);
+    assert!(content.starts_with(b"<<pmppt: 3072 bytes omitted>>\nxxx"));
+    assert_eq!(content.len(), 30 + 1024);
+
+    let (content, omitted) = read(output, Some(&excerpt(1, 2))).unwrap();
+    assert_eq!(omitted, 1024);
+    let marker = b"x\n<<pmppt: 1024 bytes omitted>>\nx";
+    assert_eq!(&content[1023..1023 + marker.len()], marker);
+}
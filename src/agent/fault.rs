@@ -0,0 +1,181 @@
+//! Fault injections reverted at the stop, for the scenarios running under the faults.
+//!
+//! Every injection keeps what is needed to revert it: the interface to set up again, the qdisc to
+//! delete, the fill file to remove or the old content of the cgroup file to write back. The kills
+//! are done by the agent itself, as they target its processes too.
+
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use super::netsetup::ip;
+use super::pacing::tc;
+use super::protocol::Fault;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Injected fault with its revert.
+pub enum Injected {
+    LinkDown { dev: String },
+    Netem { dev: String },
+    Fill { path: PathBuf },
+    Throttle { path: PathBuf, lines: Vec<String> },
+}
+
+fn write(path: &Path, value: &str) -> Result<(), String> {
+    std::fs::write(path, value).map_err(|e| {
+        format!(
+            "cannot write '{}' to '{}' - {}",
+            value,
+            path.to_string_lossy(),
+            e
+        )
+    })
+}
+
+/// Bytes to allocate for the filesystem usage of the percent, like `df` counts it.
+fn fill_size(dir: &Path, percent: f64) -> Result<u64, String> {
+    let name = dir.to_string_lossy();
+    let cpath = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes())
+        .map_err(|_| format!("bad path '{}'", name))?;
+    // SAFETY: zeroed plain struct filled by the call
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: plain call with the valid path and the struct
+    if unsafe { libc::statvfs(cpath.as_ptr(), &mut st) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("cannot get the usage of '{}' - {}", name, e));
+    }
+    let block = st.f_frsize as f64;
+    let used = (st.f_blocks - st.f_bfree) as f64 * block;
+    let avail = st.f_bavail as f64 * block;
+    let need = percent / 100.0 * (used + avail) - used;
+    match need >= block {
+        true => Ok(need as u64),
+        false => Err(format!(
+            "'{}' is already {:.1}% full",
+            name,
+            100.0 * used / (used + avail)
+        )),
+    }
+}
+
+fn fill(dir: &Path, percent: f64, id: u32) -> Result<PathBuf, String> {
+    let size = fill_size(dir, percent)?;
+    let path = dir.join(format!(".pmppt-fill-{}-{:03}", std::process::id(), id));
+    let file = File::create_new(&path)
+        .map_err(|e| format!("cannot create '{}' - {}", path.to_string_lossy(), e))?;
+    // SAFETY: plain call on the own descriptor, the error is returned and not in errno
+    let res = unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, size as libc::off_t) };
+    if res != 0 {
+        let _ = std::fs::remove_file(&path);
+        return Err(format!(
+            "cannot allocate {} bytes in '{}' - {}",
+            size,
+            path.to_string_lossy(),
+            std::io::Error::from_raw_os_error(res)
+        ));
+    }
+    Ok(path)
+}
+
+/// Lines restoring the old content of the cgroup file after writing the value.
+///
+/// The `io.max` lists only the limited devices, so the newly limited ones are reset to no limits.
+fn restore_lines(file: &str, old: &str, value: &str) -> Vec<String> {
+    let mut lines: Vec<String> = old
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_owned)
+        .collect();
+    if file == "io.max" {
+        for line in value.lines() {
+            let Some(dev) = line.split_whitespace().next() else {
+                continue;
+            };
+            if !old
+                .lines()
+                .any(|l| l.split_whitespace().next() == Some(dev))
+            {
+                lines.push(format!("{} rbps=max wbps=max riops=max wiops=max", dev));
+            }
+        }
+    }
+    lines
+}
+
+fn throttle(cgroup: &Path, file: &str, value: &str) -> Result<Injected, String> {
+    let path = Path::new(CGROUP_ROOT).join(cgroup).join(file);
+    let old = std::fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read '{}' - {}", path.to_string_lossy(), e))?;
+    let injected = Injected::Throttle {
+        lines: restore_lines(file, &old, value),
+        path: path.clone(),
+    };
+    match value.lines().try_for_each(|line| write(&path, line)) {
+        Ok(()) => Ok(injected),
+        Err(e) => {
+            let _ = injected.revert();
+            Err(e)
+        }
+    }
+}
+
+/// Inject the fault, the id names the fill file.
+pub fn inject(fault: &Fault, id: u32) -> Result<Injected, String> {
+    match fault {
+        Fault::Kill { .. } => unreachable!("kills are done by the agent"),
+        Fault::LinkDown { dev } => {
+            ip(None, &["link", "set", "dev", dev, "down"])?;
+            Ok(Injected::LinkDown { dev: dev.clone() })
+        }
+        Fault::Netem { dev, params } => {
+            // added, not replaced, so the configured root qdisc is not lost
+            let mut args = vec!["qdisc", "add", "dev", dev, "root", "netem"];
+            args.extend(params.split_whitespace());
+            tc(&args)?;
+            Ok(Injected::Netem { dev: dev.clone() })
+        }
+        Fault::Fill { dir, percent } => Ok(Injected::Fill {
+            path: fill(dir, *percent, id)?,
+        }),
+        Fault::Throttle {
+            cgroup,
+            file,
+            value,
+        } => throttle(cgroup, file, value),
+    }
+}
+
+impl Injected {
+    pub fn revert(self) -> Result<(), String> {
+        match self {
+            Injected::LinkDown { dev } => ip(None, &["link", "set", "dev", &dev, "up"]),
+            Injected::Netem { dev } => tc(&["qdisc", "del", "dev", &dev, "root"]),
+            Injected::Fill { path } => std::fs::remove_file(&path)
+                .map_err(|e| format!("cannot remove '{}' - {}", path.to_string_lossy(), e)),
+            Injected::Throttle { path, lines } => {
+                // the file accepts a single setting per write
+                lines.iter().try_for_each(|line| write(&path, line))
+            }
+        }
+    }
+}
+
+#[test]
+fn throttle_restore() {
+    assert_eq!(
+        restore_lines("cpu.max", "max 100000\n", "10000 100000"),
+        ["max 100000"]
+    );
+    assert_eq!(
+        restore_lines(
+            "io.max",
+            "8:0 rbps=1000 wbps=max riops=max wiops=max\n",
+            "8:0 wbps=500\n8:16 wbps=500"
+        ),
+        [
+            "8:0 rbps=1000 wbps=max riops=max wiops=max",
+            "8:16 rbps=max wbps=max riops=max wiops=max"
+        ]
+    );
+}
@@ -0,0 +1,75 @@
+//! Endpoint for the metrics written by the workload itself.
+//!
+//! The FIFO named by the request is created in the output directory, and the lines written into
+//! it by any number of writers are stored with their timestamps like the followed files in
+//! [`tail`](super::tail), so the application metrics share the timeline with the system ones. The
+//! lines of the concurrent writers are not mixed up as long as they are written by the single
+//! write shorter than `PIPE_BUF`. The FIFO is removed when the ingestion is stopped.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::tail;
+
+/// Interval of checking for the new lines when there are no writers.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Check the name of the FIFO, it is created right in the output directory.
+pub fn check_name(name: &str) -> Result<(), String> {
+    match name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        true => Err(format!("bad ingest name '{}'", name)),
+        false => Ok(()),
+    }
+}
+
+pub fn create_fifo(path: &Path) -> Result<(), String> {
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| format!("bad path '{}' - {}", path.to_string_lossy(), e))?;
+    // SAFETY: the path is NUL-terminated
+    if unsafe { libc::mkfifo(cpath.as_ptr(), 0o666) } != 0 {
+        return Err(format!(
+            "cannot create FIFO '{}' - {}",
+            path.to_string_lossy(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+pub fn ingest(fifo: PathBuf, dest: PathBuf, stop: Arc<AtomicBool>) {
+    let mut out = BufWriter::new(File::create(dest).expect("cannot open file"));
+    // the non-blocking open does not wait for the writers
+    let mut input = File::options()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&fifo)
+        .expect("cannot open FIFO");
+    let mut buf = Vec::new();
+    let mut chunk = vec![0u8; 64 << 10];
+
+    while !stop.load(Ordering::Acquire) {
+        match input.read(&mut chunk) {
+            // no writers at the moment
+            Ok(0) => std::thread::sleep(CHECK_INTERVAL),
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(CHECK_INTERVAL),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => panic!("cannot read FIFO - {}", e),
+        }
+        tail::write_lines(&mut out, &mut buf).expect("cannot write");
+    }
+
+    // the last line may have no newline yet
+    if !buf.is_empty() {
+        buf.push(b'\n');
+        tail::write_lines(&mut out, &mut buf).expect("cannot write");
+    }
+    let _ = std::fs::remove_file(&fifo);
+}
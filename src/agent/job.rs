@@ -0,0 +1,80 @@
+//! Windows Job Objects to control the whole process tree of the background processes.
+//!
+//! On Windows there are no process groups and no termination signals, so terminating just the
+//! spawned process leaves its children running. Every background process is put into its own job
+//! instead, and the job is terminated at once.
+
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows_sys::Win32::System::Threading::{OpenProcess, PROCESS_SET_QUOTA, PROCESS_TERMINATE};
+
+/// Exit code of the processes terminated with the job, the same as for Ctrl-C.
+const TERMINATED_EXIT_CODE: u32 = 0xc000013a;
+
+pub struct Job(HANDLE);
+
+impl Job {
+    /// Create a new job and assign the process with the given PID to it.
+    ///
+    /// The processes of the job are killed when the job is dropped, so the process tree never
+    /// outlives the agent.
+    pub fn assign(pid: u32) -> Result<Self, String> {
+        let last_error = |what: &str| format!("{} - {}", what, std::io::Error::last_os_error());
+
+        // SAFETY: plain WinAPI calls, all the handles are checked and owned by this function
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job.is_null() {
+                return Err(last_error("cannot create job object"));
+            }
+            let job = Self(job);
+
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let res = SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const _,
+                std::mem::size_of_val(&limits) as u32,
+            );
+            if res == 0 {
+                return Err(last_error("cannot set job limits"));
+            }
+
+            let process = OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid);
+            if process.is_null() {
+                return Err(last_error(&format!("cannot open process {}", pid)));
+            }
+            let res = AssignProcessToJobObject(job.0, process);
+            CloseHandle(process);
+            if res == 0 {
+                return Err(last_error(&format!("cannot assign process {} to job", pid)));
+            }
+
+            Ok(job)
+        }
+    }
+
+    /// Terminate all the processes of the job.
+    pub fn terminate(&self) -> Result<(), String> {
+        // SAFETY: the handle is valid while self is alive
+        match unsafe { TerminateJobObject(self.0, TERMINATED_EXIT_CODE) } {
+            0 => Err(format!(
+                "cannot terminate job - {}",
+                std::io::Error::last_os_error()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: the handle is owned by self
+        unsafe { CloseHandle(self.0) };
+    }
+}
@@ -0,0 +1,60 @@
+//! Capture of the systemd journal entries appearing during the run.
+//!
+//! The journal is followed by `journalctl`, only the entries added after the start are written,
+//! for the given units or the whole system, with the precise ISO timestamps comparable with the
+//! poll logs. This catches the errors of the daemons not spawned by the agent.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use subprocess::{Exec, Popen, Redirection};
+
+/// Interval of checking for the stop.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Start following the journal of the units (all if empty) into the destination.
+pub fn start(units: &[String], dest: &Path) -> Result<Popen, String> {
+    let out = File::create(dest).map_err(|e| format!("cannot create journal log - {}", e))?;
+    let mut args = vec![
+        "--follow",
+        "--lines=0",
+        "--no-pager",
+        "--output=short-iso-precise",
+    ];
+    for unit in units {
+        args.push("--unit");
+        args.push(unit);
+    }
+    Exec::cmd("journalctl")
+        .args(&args)
+        .stdin(Redirection::None)
+        .stdout(Redirection::File(out))
+        .stderr(Redirection::Merge)
+        .popen()
+        .map_err(|e| format!("failed to start journalctl - {}", e))
+}
+
+/// Wait for the stop, then terminate the follower.
+pub fn follow(mut popen: Popen, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Acquire) {
+        match popen.wait_timeout(CHECK_INTERVAL) {
+            Ok(None) => (),
+            Ok(Some(status)) => {
+                warn!("journalctl exited unexpectedly: {:?}", status);
+                return;
+            }
+            Err(e) => {
+                warn!("cannot wait for journalctl - {}", e);
+                return;
+            }
+        }
+    }
+
+    // the last entries are flushed by journalctl on the termination signal
+    let _ = popen.terminate();
+    let _ = popen.wait();
+}
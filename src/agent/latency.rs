@@ -0,0 +1,282 @@
+//! Built-in latency probes recording the histograms, for the jitter characterization without the
+//! extra tools.
+//!
+//! The wakeup probe sleeps till the absolute times on the monotonic clock like `cyclictest` and
+//! records how late it wakes up, the read one records the time of the reads at the random
+//! offsets with `O_DIRECT`. The latencies are counted in the log-linear buckets with about 3%
+//! precision like the HDR histograms, and the histogram is written when the probe is stopped.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+use super::poller;
+use super::protocol::LatencyProbe;
+
+/// Bits of the sub-buckets of every power of two.
+const SUB_BITS: u32 = 5;
+const SUB: u64 = 1 << SUB_BITS;
+
+/// Alignment of the `O_DIRECT` buffer, enough for the usual logical block sizes.
+const ALIGN: usize = 4096;
+
+const PERCENTILES: &[f64] = &[50.0, 90.0, 99.0, 99.9, 99.99];
+
+#[derive(Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+/// Bucket of the value, the small values are counted exactly.
+fn bucket(value: u64) -> usize {
+    if value < 2 * SUB {
+        return value as usize;
+    }
+    let shift = 63 - value.leading_zeros() - SUB_BITS;
+    (shift as u64 * SUB + (value >> shift)) as usize
+}
+
+/// Lowest value of the bucket.
+fn lowest(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < 2 * SUB {
+        return bucket;
+    }
+    let shift = bucket / SUB - 1;
+    (bucket - shift * SUB) << shift
+}
+
+impl Histogram {
+    fn record(&mut self, value: u64) {
+        let i = bucket(value);
+        if i >= self.counts.len() {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum += value as u128;
+    }
+
+    /// Lowest value of the bucket holding the percentile.
+    fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return lowest(i);
+            }
+        }
+        self.max
+    }
+
+    fn format(&self, title: &str) -> String {
+        let mut out = format!("# latency of {} in ns\n", title);
+        if self.count == 0 {
+            out.push_str("# no samples\n");
+            return out;
+        }
+        out.push_str(&format!(
+            "# samples {} min {} mean {} max {}\n",
+            self.count,
+            self.min,
+            self.sum / self.count as u128,
+            self.max
+        ));
+        let percentiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|p| format!("p{} {}", p, self.percentile(*p)))
+            .collect();
+        out.push_str(&format!("# {}\n", percentiles.join(" ")));
+        out.push_str("# bucket count\n");
+        for (i, count) in self.counts.iter().enumerate() {
+            if *count > 0 {
+                out.push_str(&format!("{} {}\n", lowest(i), count));
+            }
+        }
+        out
+    }
+}
+
+fn monotonic() -> libc::timespec {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: plain call filling the struct
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now
+}
+
+fn nanos(time: &libc::timespec) -> i128 {
+    time.tv_sec as i128 * 1_000_000_000 + time.tv_nsec as i128
+}
+
+fn timespec(nanos: i128) -> libc::timespec {
+    libc::timespec {
+        tv_sec: (nanos / 1_000_000_000) as libc::time_t,
+        tv_nsec: (nanos % 1_000_000_000) as libc::c_long,
+    }
+}
+
+/// Probe ready to run, its resources are acquired in advance to report the errors early.
+pub enum Probe {
+    Wakeup {
+        interval: Duration,
+    },
+    Read {
+        file: File,
+        block_size: usize,
+        blocks: u64,
+        interval: Duration,
+    },
+}
+
+impl Probe {
+    pub fn open(probe: &LatencyProbe) -> Result<Self, String> {
+        probe.check()?;
+        match probe {
+            LatencyProbe::Wakeup { interval_us } => Ok(Probe::Wakeup {
+                interval: Duration::from_micros(*interval_us),
+            }),
+            LatencyProbe::Read {
+                path,
+                block_size,
+                interval_us,
+            } => {
+                let name = path.to_string_lossy();
+                let direct = std::fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_DIRECT)
+                    .open(path);
+                let mut file = match direct {
+                    // e.g. tmpfs has no direct IO
+                    Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                        warn!("no direct IO for '{}', the page cache is used", name);
+                        File::open(path)
+                    }
+                    res => res,
+                }
+                .map_err(|e| format!("cannot open '{}' - {}", name, e))?;
+                // works for the block devices too
+                let size = file
+                    .seek(SeekFrom::End(0))
+                    .map_err(|e| format!("cannot get the size of '{}' - {}", name, e))?;
+                let blocks = size / *block_size as u64;
+                if blocks == 0 {
+                    return Err(format!("'{}' is smaller than the block", name));
+                }
+                Ok(Probe::Read {
+                    file,
+                    block_size: *block_size,
+                    blocks,
+                    interval: Duration::from_micros(*interval_us),
+                })
+            }
+        }
+    }
+
+    fn wakeup(interval: Duration, stop: &AtomicBool, hist: &mut Histogram) {
+        let interval = interval.as_nanos() as i128;
+        let mut next = nanos(&monotonic());
+        while !stop.load(Ordering::Acquire) {
+            next += interval;
+            let wakeup = timespec(next);
+            // SAFETY: plain call, the interruption by a signal is just an early wakeup
+            unsafe {
+                libc::clock_nanosleep(
+                    libc::CLOCK_MONOTONIC,
+                    libc::TIMER_ABSTIME,
+                    &wakeup,
+                    std::ptr::null_mut(),
+                )
+            };
+            let now = nanos(&monotonic());
+            hist.record((now - next).max(0) as u64);
+            // the missed periods are skipped instead of waking up immediately
+            if now - next > interval {
+                next = now;
+            }
+        }
+    }
+
+    fn read(
+        file: &File,
+        block_size: usize,
+        blocks: u64,
+        interval: Duration,
+        stop: &AtomicBool,
+        hist: &mut Histogram,
+    ) -> std::io::Result<()> {
+        let mut buf = vec![0u8; block_size + ALIGN];
+        let start = buf.as_ptr().align_offset(ALIGN);
+        let buf = &mut buf[start..start + block_size];
+        // xorshift is enough to defeat the readahead
+        let mut state = nanos(&monotonic()) as u64 | 1;
+        while !stop.load(Ordering::Acquire) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let offset = (state % blocks) * block_size as u64;
+            let started = Instant::now();
+            file.read_exact_at(buf, offset)?;
+            hist.record(started.elapsed().as_nanos() as u64);
+            if !interval.is_zero() {
+                std::thread::sleep(interval);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the probe till the stop, then write the histogram.
+    pub fn run(self, title: String, realtime: bool, mut dest: File, stop: Arc<AtomicBool>) {
+        if realtime {
+            poller::set_realtime();
+        }
+        let mut hist = Histogram::default();
+        match &self {
+            Probe::Wakeup { interval } => Self::wakeup(*interval, &stop, &mut hist),
+            Probe::Read {
+                file,
+                block_size,
+                blocks,
+                interval,
+            } => {
+                if let Err(e) = Self::read(file, *block_size, *blocks, *interval, &stop, &mut hist)
+                {
+                    error!("latency probe '{}' failed - {}", title, e);
+                }
+            }
+        }
+        if let Err(e) = dest.write_all(hist.format(&title).as_bytes()) {
+            error!("cannot write the histogram of '{}' - {}", title, e);
+        }
+    }
+}
+
+#[test]
+fn histogram_buckets() {
+    for value in [0, 63, 64, 127, 128, 1_000_000, u64::MAX] {
+        let i = bucket(value);
+        assert!(lowest(i) <= value && (i + 1 == bucket(u64::MAX) + 1 || value < lowest(i + 1)));
+    }
+    let mut hist = Histogram::default();
+    (1..=1000).for_each(|v| hist.record(v * 1000));
+    assert_eq!(hist.percentile(50.0), lowest(bucket(500_000)));
+    assert_eq!((hist.min, hist.max), (1000, 1_000_000));
+}
@@ -0,0 +1,244 @@
+//! Built-in load generators for the baseline and interference experiments.
+//!
+//! The generators run in the agent threads till the stop or the end of their duration, the rates
+//! are kept by the work done so far against the elapsed time, so the lag is caught up. The
+//! summary of the done work is written when the generator is stopped.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+use super::protocol::Load;
+
+/// Period of the cpu load, short enough for the smooth utilization.
+const PERIOD: Duration = Duration::from_millis(100);
+
+/// Sleep of the generators ahead of their rate, also the stop latency.
+const TICK: Duration = Duration::from_millis(10);
+
+const MB: usize = 1024 * 1024;
+
+/// Generator ready to run, the disk one creates its file in advance to report the errors early.
+pub enum Generator {
+    Cpu {
+        threads: u32,
+        percent: u32,
+    },
+    Memory {
+        size_mb: u64,
+        rate_mb_s: f64,
+    },
+    Disk {
+        file: File,
+        path: PathBuf,
+        blocks: u64,
+        block_size: usize,
+        random: bool,
+        fsync: bool,
+        rate_mb_s: Option<f64>,
+    },
+}
+
+struct Until<'a> {
+    stop: &'a AtomicBool,
+    deadline: Option<Instant>,
+}
+
+impl Until<'_> {
+    fn running(&self) -> bool {
+        !self.stop.load(Ordering::Acquire) && self.deadline.is_none_or(|d| Instant::now() < d)
+    }
+}
+
+/// Whether the work in megabytes is ahead of the rate, sleeping a tick if so.
+fn ahead(started: Instant, done_mb: f64, rate_mb_s: f64) -> bool {
+    let ahead = done_mb > rate_mb_s * started.elapsed().as_secs_f64();
+    if ahead {
+        std::thread::sleep(TICK);
+    }
+    ahead
+}
+
+/// Busy time of the thread spinning the percent of every period.
+fn spin(percent: u32, until: &Until) -> Duration {
+    let busy = PERIOD * percent / 100;
+    let mut total = Duration::ZERO;
+    while until.running() {
+        let started = Instant::now();
+        while started.elapsed() < busy {
+            std::hint::spin_loop();
+        }
+        total += busy;
+        let idle = PERIOD.saturating_sub(started.elapsed());
+        if !idle.is_zero() {
+            std::thread::sleep(idle);
+        }
+    }
+    total
+}
+
+impl Generator {
+    pub fn open(load: &Load) -> Result<Self, String> {
+        load.check()?;
+        Ok(match load {
+            Load::Cpu { threads, percent } => Generator::Cpu {
+                threads: *threads,
+                percent: *percent,
+            },
+            Load::Memory { size_mb, rate_mb_s } => Generator::Memory {
+                size_mb: *size_mb,
+                rate_mb_s: *rate_mb_s,
+            },
+            Load::Disk {
+                path,
+                size_mb,
+                block_size,
+                random,
+                fsync,
+                rate_mb_s,
+            } => {
+                // the existing files are not overwritten, the load file is removed at the end
+                let file = File::create_new(path)
+                    .map_err(|e| format!("cannot create '{}' - {}", path.to_string_lossy(), e))?;
+                Generator::Disk {
+                    file,
+                    path: path.clone(),
+                    blocks: size_mb * MB as u64 / *block_size as u64,
+                    block_size: *block_size,
+                    random: *random,
+                    fsync: *fsync,
+                    rate_mb_s: *rate_mb_s,
+                }
+            }
+        })
+    }
+
+    fn cpu(threads: u32, percent: u32, until: &Until) -> Vec<String> {
+        let busy: Duration = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| scope.spawn(|| spin(percent, until)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().expect("cpu load thread panicked"))
+                .sum()
+        });
+        vec![format!("busy_s {:.3}", busy.as_secs_f64())]
+    }
+
+    fn memory(size_mb: u64, rate_mb_s: f64, until: &Until) -> Vec<String> {
+        let mut chunks = std::collections::VecDeque::new();
+        let (started, mut allocated, mut freed) = (Instant::now(), 0u64, 0u64);
+        while until.running() {
+            if ahead(started, allocated as f64, rate_mb_s) {
+                continue;
+            }
+            if chunks.len() as u64 == size_mb {
+                chunks.pop_front();
+                freed += 1;
+            }
+            // filled with the non-zero bytes, so every page is touched
+            chunks.push_back(vec![0xa5u8; MB]);
+            allocated += 1;
+        }
+        vec![
+            format!("allocated_mb {}", allocated),
+            format!("freed_mb {}", freed),
+        ]
+    }
+
+    fn disk(
+        file: &mut File,
+        blocks: u64,
+        block_size: usize,
+        random: bool,
+        fsync: bool,
+        rate_mb_s: Option<f64>,
+        until: &Until,
+    ) -> std::io::Result<u64> {
+        // xorshift both for the incompressible data and the random offsets
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let buf: Vec<u8> = (0..block_size).map(|_| next() as u8).collect();
+        let (started, mut written) = (Instant::now(), 0u64);
+        while until.running() {
+            if let Some(rate) = rate_mb_s {
+                if ahead(started, written as f64 / MB as f64, rate) {
+                    continue;
+                }
+            }
+            let block = match random {
+                true => next() % blocks,
+                false => written / block_size as u64 % blocks,
+            };
+            file.seek(SeekFrom::Start(block * block_size as u64))?;
+            file.write_all(&buf)?;
+            if fsync {
+                file.sync_data()?;
+            }
+            written += block_size as u64;
+        }
+        Ok(written)
+    }
+
+    /// Run the generator till the stop or the duration, then write the summary.
+    pub fn run(
+        self,
+        title: String,
+        duration: Option<Duration>,
+        mut dest: File,
+        stop: Arc<AtomicBool>,
+    ) {
+        let started = Instant::now();
+        let until = Until {
+            stop: &stop,
+            deadline: duration.map(|d| started + d),
+        };
+        let mut summary = match self {
+            Generator::Cpu { threads, percent } => Self::cpu(threads, percent, &until),
+            Generator::Memory { size_mb, rate_mb_s } => Self::memory(size_mb, rate_mb_s, &until),
+            Generator::Disk {
+                mut file,
+                path,
+                blocks,
+                block_size,
+                random,
+                fsync,
+                rate_mb_s,
+            } => {
+                let res = Self::disk(
+                    &mut file, blocks, block_size, random, fsync, rate_mb_s, &until,
+                );
+                drop(file);
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("cannot remove '{}' - {}", path.to_string_lossy(), e);
+                }
+                match res {
+                    Ok(written) => vec![format!("written_mb {:.3}", written as f64 / MB as f64)],
+                    Err(e) => {
+                        error!("load '{}' failed - {}", title, e);
+                        vec![format!("error {}", e)]
+                    }
+                }
+            }
+        };
+        summary.insert(
+            0,
+            format!("elapsed_s {:.3}", started.elapsed().as_secs_f64()),
+        );
+        let content = format!("# load {}\n{}\n", title, summary.join("\n"));
+        if let Err(e) = dest.write_all(content.as_bytes()) {
+            error!("cannot write the summary of '{}' - {}", title, e);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Run manifest: the journal of everything the agent did during the run.
+//!
+//! The manifest is stored as JSON lines in the output directory, one entry per event. Every entry
+//! is written and flushed immediately, so the manifest stays consistent even if the agent crashes.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::clock::ClockStatus;
+use super::noise::NoiseReport;
+use super::prerequisites::Prerequisite;
+use super::protocol::{
+    AbortReason, Container, Fault, Isolation, LatencyProbe, Load, NetObject, Pacing,
+    PriorityOptions, SeccompProfile, SpawnMode, SystemdUnit,
+};
+
+pub const MANIFEST_NAME: &str = "manifest.jsonl";
+
+/// Single manifest event.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    /// Labels of the run given by the controller and the agent config.
+    Tags {
+        tags: BTreeMap<String, String>,
+    },
+    Poll {
+        id: u32,
+        pattern: String,
+        /// Time after which the step ends by itself.
+        #[serde(default)]
+        duration_s: Option<f64>,
+    },
+    Spawn {
+        id: u32,
+        mode: SpawnMode,
+        cmd: String,
+        /// OS pid and process group of the background process.
+        #[serde(default)]
+        pid: Option<u32>,
+        #[serde(default)]
+        pgid: Option<u32>,
+        #[serde(default)]
+        isolate: Option<Isolation>,
+        #[serde(default)]
+        seccomp: Option<SeccompProfile>,
+        #[serde(default)]
+        pacing: Option<Pacing>,
+        #[serde(default)]
+        systemd: Option<SystemdUnit>,
+        #[serde(default)]
+        container: Option<Container>,
+        #[serde(default)]
+        duration_s: Option<f64>,
+    },
+    Tail {
+        id: u32,
+        path: String,
+    },
+    Watch {
+        id: u32,
+        pattern: String,
+    },
+    Ingest {
+        id: u32,
+        name: String,
+    },
+    Latency {
+        id: u32,
+        #[serde(flatten)]
+        probe: LatencyProbe,
+    },
+    Load {
+        id: u32,
+        #[serde(flatten)]
+        load: Load,
+        duration_s: Option<f64>,
+    },
+    Journal {
+        id: u32,
+        units: Vec<String>,
+    },
+    /// The remote files are copied at the stop, the copy is recorded as done.
+    Collect {
+        id: u32,
+        host: String,
+        paths: Vec<String>,
+    },
+    Done {
+        id: u32,
+        exit_code: Option<u32>,
+    },
+    /// The poller sampling is suspended till the `Resumed` entry, it gives no samples.
+    Paused {
+        id: u32,
+    },
+    Resumed {
+        id: u32,
+    },
+    /// The background process is stopped by `SIGSTOP` till the `Thawed` entry.
+    Frozen {
+        id: u32,
+    },
+    Thawed {
+        id: u32,
+    },
+    /// The scheduling of the background process is changed, the missing values are kept.
+    Renice {
+        id: u32,
+        #[serde(flatten)]
+        opts: PriorityOptions,
+    },
+    /// The network object is created, its removal is recorded as done.
+    Net {
+        id: u32,
+        #[serde(flatten)]
+        object: NetObject,
+    },
+    /// The fault is injected, its revert is recorded as done. The fault is nested as the kill one
+    /// has its own id.
+    Fault {
+        id: u32,
+        fault: Fault,
+    },
+    Core {
+        id: u32,
+        signal: i32,
+        path: String,
+    },
+    /// Latencies from the start of the process tracking its output, recorded at the first output
+    /// of the background process, which exit is not timed.
+    Startup {
+        id: u32,
+        first_output_s: Option<f64>,
+        exit_s: Option<f64>,
+    },
+    /// The process killed by the OOM killer, the spawned one if `id` is known.
+    OomKill {
+        id: Option<u32>,
+        pid: Option<u32>,
+        process: Option<String>,
+    },
+    Failed {
+        request: String,
+        error: String,
+    },
+    /// Times of receiving the request, starting and completing its execution.
+    Timing {
+        request: String,
+        received: String,
+        started: String,
+        completed: String,
+    },
+    Stop {
+        abnormal: bool,
+        #[serde(default)]
+        reason: Option<AbortReason>,
+    },
+    /// Clock synchronization status at the start, periodically and at the stop.
+    Clock {
+        #[serde(flatten)]
+        status: ClockStatus,
+    },
+    /// Background noise of the target sampled before the first step.
+    Noise {
+        #[serde(flatten)]
+        report: NoiseReport,
+    },
+    /// The CPU usage of the whole system stayed below the percentage for the window.
+    Idle {
+        cpu_below: f64,
+        window_s: f64,
+        waited_s: f64,
+    },
+    /// Prerequisite of the reliable measurements degraded at the start of the permissive run.
+    Degraded {
+        check: Prerequisite,
+        detail: String,
+    },
+    /// The run is continued by another agent invocation, numbered from 2, the ids go on.
+    Resume {
+        stage: u32,
+    },
+}
+
+/// Manifest entry with the wall-clock time of the event.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub time: String,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// Time in the manifest format.
+pub fn format_time(time: chrono::DateTime<chrono::Local>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Micros, false)
+}
+
+pub struct Manifest {
+    file: File,
+    // the records are printed to stdout too
+    echo: bool,
+}
+
+impl Manifest {
+    pub fn create(outdir: &Path) -> Self {
+        let file = File::create_new(outdir.join(MANIFEST_NAME)).expect("cannot create manifest");
+        Self { file, echo: false }
+    }
+
+    /// Continue the manifest of the run in the existing output directory.
+    pub fn append(outdir: &Path) -> Result<Self, String> {
+        let path = outdir.join(MANIFEST_NAME);
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("cannot open '{}' - {}", path.to_string_lossy(), e))?;
+        Ok(Self { file, echo: false })
+    }
+
+    /// Print the records to stdout too, one JSON line per event, as the progress of the run.
+    pub fn echo(self, echo: bool) -> Self {
+        Self { echo, ..self }
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        self.record_at(chrono::Local::now(), entry);
+    }
+
+    /// Record the event detected earlier than it is recorded.
+    pub fn record_at(&mut self, time: chrono::DateTime<chrono::Local>, entry: Entry) {
+        let record = Record {
+            time: format_time(time),
+            entry,
+        };
+        let mut line = serde_json::to_string(&record).unwrap(); // should never fail
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .expect("cannot write manifest");
+        if self.echo {
+            // the readers may go away, the run goes on without them
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout
+                .write_all(line.as_bytes())
+                .and_then(|()| stdout.flush());
+        }
+    }
+}
+
+/// Read all the records of the manifest stored in the output directory.
+pub fn read(outdir: &Path) -> Result<Vec<Record>, String> {
+    let path = outdir.join(MANIFEST_NAME);
+    let file = File::open(&path)
+        .map_err(|e| format!("cannot open '{}' - {}", path.to_string_lossy(), e))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.map_err(|e| format!("cannot read manifest - {}", e))?;
+            serde_json::from_str(&line)
+                .map_err(|e| format!("bad manifest record at line {} - {}", i + 1, e))
+        })
+        .collect()
+}
+
+/// Ids and stages of the run continued by another agent invocation.
+pub struct Progress {
+    /// The highest id used so far, the ids of the continued run go on after it.
+    pub last_id: u32,
+    /// Number of the agent invocations including the continuing one.
+    pub stage: u32,
+}
+
+/// Progress of the run stored in the output directory, to continue it.
+pub fn progress(outdir: &Path) -> Result<Progress, String> {
+    let records = read(outdir)?;
+    if !matches!(
+        records.last(),
+        Some(Record {
+            entry: Entry::Stop { .. },
+            ..
+        })
+    ) {
+        warn!(
+            "the previous run in '{}' did not stop properly",
+            outdir.to_string_lossy()
+        );
+    }
+    let stages = records
+        .iter()
+        .filter(|r| matches!(r.entry, Entry::Resume { .. }))
+        .count();
+    // the entries of the steps have their ids on top
+    let last_id = records
+        .iter()
+        .filter_map(|r| serde_json::to_value(&r.entry).ok()?["id"].as_u64())
+        .max()
+        .unwrap_or(0);
+    Ok(Progress {
+        last_id: last_id as u32,
+        stage: stages as u32 + 2,
+    })
+}
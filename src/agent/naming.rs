@@ -0,0 +1,159 @@
+//! Names of the files created in the outdir for the steps, by the configurable template.
+//!
+//! The template like `{seq:03}-{kind}-{label}.{ext}` has the placeholders of the step id, padded
+//! with zeros to the width if given, of the kind of the file like `out` or `poll`, of the short
+//! label of the step like the program name or the polled pattern, and of the extension. The empty
+//! value also drops the separator before it, so the directories and the core dumps have no
+//! trailing dots. The files derived from the poll logs, like the split logs and the read stats,
+//! are named after them.
+
+use serde::{Deserialize, Serialize};
+
+/// Default template giving the names like `003-out.log`.
+const DEFAULT: &str = "{seq:03}-{kind}.{ext}";
+
+/// Length limit of the labels, the long patterns and command lines are cut.
+const LABEL_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Seq(usize),
+    Kind,
+    Label,
+    Ext,
+}
+
+/// Parsed naming template, each name has the step id and the file kind to be unique.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FileNames {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl Default for FileNames {
+    fn default() -> Self {
+        DEFAULT
+            .to_owned()
+            .try_into()
+            .expect("bad default naming template")
+    }
+}
+
+impl TryFrom<String> for FileNames {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        let bad = |why: &str| format!("bad file name template '{}' - {}", template, why);
+        let mut parts = Vec::new();
+        let mut rest = template.as_str();
+        while !rest.is_empty() {
+            let Some(start) = rest.find(['{', '}']) else {
+                parts.push(Part::Text(rest.to_owned()));
+                break;
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = match rest[start..].starts_with('{') {
+                true => rest[start..].find('}').map(|end| start + end),
+                false => None,
+            }
+            .ok_or_else(|| bad("unbalanced braces"))?;
+            parts.push(match &rest[start + 1..end] {
+                "seq" => Part::Seq(0),
+                "kind" => Part::Kind,
+                "label" => Part::Label,
+                "ext" => Part::Ext,
+                spec => match spec.strip_prefix("seq:0").map(str::parse) {
+                    Some(Ok(width)) => Part::Seq(width),
+                    _ => return Err(bad(&format!("unknown placeholder '{{{}}}'", spec))),
+                },
+            });
+            rest = &rest[end + 1..];
+        }
+
+        let text = |part: &Part| match part {
+            Part::Text(text) => text.contains(['/', '\\']),
+            _ => false,
+        };
+        if parts.iter().any(text) {
+            return Err(bad("names cannot have path separators"));
+        }
+        if !parts.iter().any(|part| matches!(part, Part::Seq(_))) || !parts.contains(&Part::Kind) {
+            return Err(bad("both {seq} and {kind} are needed for the unique names"));
+        }
+        Ok(Self { template, parts })
+    }
+}
+
+impl From<FileNames> for String {
+    fn from(names: FileNames) -> Self {
+        names.template
+    }
+}
+
+/// Label of the step from its program, pattern or path, sanitized to be the valid file name.
+pub fn label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || "._-".contains(c) {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    let label = label.trim_matches(['_', '.']);
+    label[..label.len().min(LABEL_LEN)].to_owned()
+}
+
+/// Label of the spawned process, its program name without the directories.
+pub fn program(cmd: &str) -> String {
+    label(cmd.rsplit(['/', '\\']).next().unwrap_or(cmd))
+}
+
+impl FileNames {
+    /// Name of the file, the extension is empty for the directories and the core dumps.
+    pub fn name(&self, seq: u32, kind: &str, label: &str, ext: &str) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) => text.clone(),
+                Part::Seq(width) => format!("{:0width$}", seq, width = width),
+                Part::Kind => kind.to_owned(),
+                Part::Label => label.to_owned(),
+                Part::Ext => ext.to_owned(),
+            };
+            if value.is_empty() && name.ends_with(['-', '_', '.']) {
+                name.pop();
+            }
+            name.push_str(&value);
+        }
+        name
+    }
+}
+
+#[test]
+fn template_names() {
+    let default = FileNames::default();
+    assert_eq!(default.name(3, "out", "sleep", "log"), "003-out.log");
+    assert_eq!(default.name(1000, "core", "sleep", ""), "1000-core");
+
+    let custom = FileNames::try_from("run-{seq}_{kind}-{label}.{ext}".to_owned()).unwrap();
+    assert_eq!(
+        custom.name(7, "poll", "proc_stat", "log"),
+        "run-7_poll-proc_stat.log"
+    );
+    assert_eq!(custom.name(7, "root", "", ""), "run-7_root");
+
+    assert_eq!(label("/proc/{stat,meminfo}"), "proc__stat_meminfo");
+    assert_eq!(program("/usr/bin/stress-ng"), "stress-ng");
+    for bad in [
+        "{seq}.{ext}",
+        "{seq}-{kind}/{label}",
+        "{seq}-{kind}-{name}",
+        "{seq}-{kind",
+    ] {
+        assert!(FileNames::try_from(bad.to_owned()).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,100 @@
+//! Network namespaces, veth pairs and bridges for the self-contained network benchmarks.
+//!
+//! The objects are created by `ip` from iproute2 and removed in the reverse order, so the veth
+//! pairs and bridges go before the namespaces they use. The partially created object is removed
+//! right away, deleting either end of the veth pair removes both of them.
+
+use subprocess::{Exec, Redirection};
+
+use super::protocol::NetObject;
+
+/// Run `ip` with the arguments, optionally inside the namespace, its output is the error.
+pub fn ip(netns: Option<&str>, args: &[&str]) -> Result<(), String> {
+    let mut exec = Exec::cmd("ip");
+    if let Some(netns) = netns {
+        exec = exec.args(&["-n", netns]);
+    }
+    let exec = exec.args(args);
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' failed: {}",
+            name,
+            capture.stdout_str().trim()
+        )),
+    }
+}
+
+/// Address the interface and bring it up.
+fn configure(name: &str, netns: Option<&str>, addr: Option<&str>) -> Result<(), String> {
+    if let Some(addr) = addr {
+        ip(netns, &["addr", "add", addr, "dev", name])?;
+    }
+    ip(netns, &["link", "set", name, "up"])
+}
+
+fn setup(object: &NetObject) -> Result<(), String> {
+    match object {
+        NetObject::Netns { name } => {
+            ip(None, &["netns", "add", name])?;
+            ip(Some(name), &["link", "set", "lo", "up"])
+        }
+        NetObject::Veth {
+            name,
+            peer,
+            netns,
+            peer_netns,
+            addr,
+            peer_addr,
+        } => {
+            ip(
+                None,
+                &["link", "add", name, "type", "veth", "peer", "name", peer],
+            )?;
+            for (end, netns, addr) in [(name, netns, addr), (peer, peer_netns, peer_addr)] {
+                if let Some(netns) = netns {
+                    ip(None, &["link", "set", end, "netns", netns])?;
+                }
+                configure(end, netns.as_deref(), addr.as_deref())?;
+            }
+            Ok(())
+        }
+        NetObject::Bridge { name, ports, addr } => {
+            ip(None, &["link", "add", name, "type", "bridge"])?;
+            for port in ports {
+                ip(None, &["link", "set", port, "master", name])?;
+            }
+            configure(name, None, addr.as_deref())
+        }
+    }
+}
+
+/// Create the object, removing its already created part on failure.
+pub fn create(object: &NetObject) -> Result<(), String> {
+    if !cfg!(target_os = "linux") {
+        return Err("network setup is supported only on Linux".into());
+    }
+    setup(object).inspect_err(|_| {
+        let _ = destroy(object);
+    })
+}
+
+pub fn destroy(object: &NetObject) -> Result<(), String> {
+    match object {
+        NetObject::Netns { name } => ip(None, &["netns", "delete", name]),
+        NetObject::Veth { name, netns, .. } => {
+            // the end is still on the host if the creation failed before moving it
+            match (ip(netns.as_deref(), &["link", "delete", name]), netns) {
+                (Err(_), Some(_)) => ip(None, &["link", "delete", name]),
+                (res, _) => res,
+            }
+        }
+        NetObject::Bridge { name, .. } => ip(None, &["link", "delete", name, "type", "bridge"]),
+    }
+}
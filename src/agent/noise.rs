@@ -0,0 +1,283 @@
+//! Background noise of the target sampled before the run, a frequent source of invalid results.
+//!
+//! The scenario asks for the preflight sampling of the CPU usage for a few seconds before its
+//! first step. The usage of the whole system is taken from `/proc/stat`, the top offenders from the
+//! `/proc/PID/stat` of all the processes but the agent. The report is recorded into the manifest,
+//! the usage above the threshold is warned about as the target is not idle. Only on Linux.
+//!
+//! The `WaitIdle` step rather waits for the usage of the whole system to stay below the threshold
+//! for the stabilization window, e.g. while the cleanup of the previous test is still running.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_DURATION_S: f64 = 3.0;
+const DEFAULT_THRESHOLD_PCT: f64 = 5.0;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const DEFAULT_TOP: usize = 5;
+/// Limit of the sampling time, the preflight must not delay the run for long.
+const MAX_DURATION_S: f64 = 60.0;
+/// Interval of sampling the usage while waiting for the idle system.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const IDLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Preflight sampling requested by the scenario.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preflight {
+    /// Time of sampling the CPU usage, 3 seconds by default.
+    pub duration_s: Option<f64>,
+    /// Usage of the whole system in percent of all the CPUs treated as the noise, 5 by default.
+    pub threshold_pct: Option<f64>,
+    /// Number of the top processes reported, 5 by default.
+    pub top: Option<usize>,
+}
+
+impl Preflight {
+    pub fn check(&self) -> Result<(), String> {
+        let duration = self.duration_s.unwrap_or(DEFAULT_DURATION_S);
+        if !(duration > 0.0 && duration <= MAX_DURATION_S) {
+            return Err(format!(
+                "preflight duration {}s is not within (0, {}]",
+                duration, MAX_DURATION_S
+            ));
+        }
+        let threshold = self.threshold_pct.unwrap_or(DEFAULT_THRESHOLD_PCT);
+        if !(0.0..=100.0).contains(&threshold) {
+            return Err(format!(
+                "preflight threshold {}% is not a percentage",
+                threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Process using the CPU during the sampling.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Offender {
+    pub pid: u32,
+    pub process: String,
+    /// Usage in percent of a single CPU.
+    pub cpu_pct: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoiseReport {
+    pub duration_s: f64,
+    /// Usage of the whole system in percent of all the CPUs.
+    pub busy_pct: f64,
+    /// The usage is not above the threshold.
+    pub idle: bool,
+    /// Processes using the most CPU, the busiest first.
+    pub top: Vec<Offender>,
+}
+
+/// Busy and total times of all the CPUs from the `/proc/stat` content.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let times: Vec<u64> = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .map(|time| time.parse().ok())
+        .collect::<Option<_>>()?;
+    // user, nice, system, idle, iowait, irq, softirq, steal, the guest time is within the user one
+    let total = times.iter().take(8).sum();
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((total - idle, total))
+}
+
+/// Usage in percent of all the CPUs between the busy and total times of `/proc/stat`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn usage(before: (u64, u64), after: (u64, u64)) -> f64 {
+    let total = after.1.saturating_sub(before.1).max(1);
+    after.0.saturating_sub(before.0) as f64 / total as f64 * 100.0
+}
+
+/// Name and the CPU time of the process from its `/proc/PID/stat` content.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_process(stat: &str) -> Option<(String, u64)> {
+    // the name may have spaces and parentheses itself
+    let (start, end) = (stat.find('(')?, stat.rfind(')')?);
+    let fields: Vec<&str> = stat.get(end + 1..)?.split_whitespace().collect();
+    // utime and stime are the fields 14 and 15, the state one is the 3rd
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((stat.get(start + 1..end)?.to_owned(), utime + stime))
+}
+
+/// Processes by the CPU time used between the snapshots, `ticks` is the time of a single CPU.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn offenders(
+    before: &std::collections::HashMap<u32, (String, u64)>,
+    after: &std::collections::HashMap<u32, (String, u64)>,
+    ticks: f64,
+    top: usize,
+) -> Vec<Offender> {
+    let mut offenders: Vec<Offender> = after
+        .iter()
+        .filter_map(|(&pid, (process, time))| {
+            let used = time.checked_sub(before.get(&pid)?.1)?;
+            (used > 0).then(|| Offender {
+                pid,
+                process: process.clone(),
+                cpu_pct: used as f64 / ticks * 100.0,
+            })
+        })
+        .collect();
+    offenders.sort_by(|a, b| b.cpu_pct.total_cmp(&a.cpu_pct).then(a.pid.cmp(&b.pid)));
+    offenders.truncate(top);
+    offenders
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn cpu_times() -> Result<(u64, u64), String> {
+    std::fs::read_to_string("/proc/stat")
+        .ok()
+        .and_then(|stat| parse_cpu_times(&stat))
+        .ok_or_else(|| "cannot read the CPU times of /proc/stat".to_owned())
+}
+
+/// CPU times of all the processes but the agent, the exited ones are just missing.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn processes() -> std::collections::HashMap<u32, (String, u64)> {
+    let own = std::process::id();
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Default::default();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            (pid != own).then_some((pid, parse_process(&stat)?))
+        })
+        .collect()
+}
+
+/// Sample the CPU usage for the preflight time.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn sample(preflight: &Preflight) -> Result<NoiseReport, String> {
+    let duration_s = preflight.duration_s.unwrap_or(DEFAULT_DURATION_S);
+    // SAFETY: plain call without arguments
+    let hz = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if hz <= 0 {
+        return Err("cannot get the clock ticks per second".into());
+    }
+
+    let times = cpu_times()?;
+    let before = processes();
+    let started = std::time::Instant::now();
+    std::thread::sleep(Duration::from_secs_f64(duration_s));
+    let busy_pct = usage(times, cpu_times()?);
+    let after = processes();
+    let ticks = started.elapsed().as_secs_f64() * hz as f64;
+
+    Ok(NoiseReport {
+        duration_s,
+        busy_pct,
+        idle: busy_pct <= preflight.threshold_pct.unwrap_or(DEFAULT_THRESHOLD_PCT),
+        top: offenders(&before, &after, ticks, preflight.top.unwrap_or(DEFAULT_TOP)),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn sample(_preflight: &Preflight) -> Result<NoiseReport, String> {
+    Err("background noise is sampled only on Linux".into())
+}
+
+/// Check the idle wait beforehand, the window longer than the timeout is never waited out.
+pub fn check_idle(cpu_below: f64, window: Duration, timeout: Duration) -> Result<(), String> {
+    if !(cpu_below > 0.0 && cpu_below <= 100.0) {
+        return Err(format!(
+            "idle threshold {}% is not within (0, 100]",
+            cpu_below
+        ));
+    }
+    if window.is_zero() || window > timeout {
+        return Err(format!(
+            "idle window {:?} is not within the timeout {:?}",
+            window, timeout
+        ));
+    }
+    Ok(())
+}
+
+/// Wait for the usage of the whole system to stay below the threshold for the window, returns the
+/// time of waiting.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn wait_idle(cpu_below: f64, window: Duration, timeout: Duration) -> Result<Duration, String> {
+    check_idle(cpu_below, window, timeout)?;
+    let started = std::time::Instant::now();
+    let mut quiet = started;
+    let mut times = cpu_times()?;
+    loop {
+        crate::signals::sleep(IDLE_INTERVAL);
+        if crate::signals::received().is_some() {
+            return Err("waiting for the idle system is interrupted".into());
+        }
+        let now = cpu_times()?;
+        let busy_pct = usage(times, now);
+        times = now;
+        if busy_pct >= cpu_below {
+            quiet = std::time::Instant::now();
+        } else if quiet.elapsed() >= window {
+            return Ok(started.elapsed());
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "system is not idle after {:?}, CPU usage {:.1}% is not below {}%",
+                timeout, busy_pct, cpu_below
+            ));
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn wait_idle(
+    _cpu_below: f64,
+    _window: Duration,
+    _timeout: Duration,
+) -> Result<Duration, String> {
+    Err("waiting for the idle system is supported only on Linux".into())
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn noise_sampling() {
+    let stat = "cpu  100 10 50 800 40 0 0 0 0 0\ncpu0 100 10 50 800 40 0 0 0 0 0\n";
+    assert_eq!(parse_cpu_times(stat), Some((160, 1000)));
+    assert_eq!(usage((160, 1000), (220, 1200)), 30.0);
+    assert_eq!(usage((160, 1000), (160, 1000)), 0.0);
+
+    let stat = "42 (tmux: server) S 1 42 42 0 -1 4194560 1 0 0 0 150 30 0 0 20 0 1 0 100 0 0";
+    assert_eq!(parse_process(stat), Some(("tmux: server".to_owned(), 180)));
+
+    let process = |name: &str, time| (name.to_owned(), time);
+    let before = [(1, process("init", 10)), (2, process("spin", 0))].into();
+    let after = [
+        (1, process("init", 10)),
+        (2, process("spin", 90)),
+        (3, process("new", 5)),
+    ]
+    .into();
+    let top = offenders(&before, &after, 100.0, 5);
+    assert_eq!(top.len(), 1);
+    assert_eq!((top[0].pid, top[0].cpu_pct), (2, 90.0));
+
+    assert!(Preflight::default().check().is_ok());
+    let preflight = Preflight {
+        threshold_pct: Some(150.0),
+        ..Default::default()
+    };
+    assert!(preflight.check().is_err());
+
+    let secs = Duration::from_secs;
+    assert!(check_idle(10.0, secs(3), secs(60)).is_ok());
+    assert!(check_idle(0.0, secs(3), secs(60)).is_err());
+    assert!(check_idle(10.0, secs(0), secs(60)).is_err());
+    assert!(check_idle(10.0, secs(90), secs(60)).is_err());
+}
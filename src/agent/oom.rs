@@ -0,0 +1,189 @@
+//! Detection of the OOM kills happening during the run.
+//!
+//! The kernel log is followed through `/dev/kmsg` for the `Killed process PID (name)` reports of
+//! the OOM killer. When it cannot be read, usually without root, the `oom_kill` counter of the
+//! agent cgroup is watched instead, it covers the spawned processes too but does not tell the
+//! killed process.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use regex::Regex;
+
+/// Interval of checking for the new kernel messages or the counter changes.
+const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct OomKill {
+    pub time: DateTime<Local>,
+    pub pid: Option<u32>,
+    pub process: Option<String>,
+}
+
+enum Source {
+    Kmsg(File),
+    Cgroup(PathBuf),
+}
+
+pub struct Monitor {
+    stop: Arc<AtomicBool>,
+    thrd: JoinHandle<()>,
+    events: Receiver<OomKill>,
+}
+
+impl Monitor {
+    /// Start watching for the OOM kills, `None` if there is nothing to watch.
+    pub fn start() -> Option<Self> {
+        let source = match open_log() {
+            Ok(kmsg) => Source::Kmsg(kmsg),
+            Err(e) => match cgroup_counter() {
+                Some(path) => {
+                    info!(
+                        "cannot read kernel log ({}), OOM kills are detected by '{}'",
+                        e,
+                        path.to_string_lossy()
+                    );
+                    Source::Cgroup(path)
+                }
+                None => {
+                    warn!("cannot read kernel log ({}), OOM kills are not detected", e);
+                    return None;
+                }
+            },
+        };
+
+        let stop = Arc::new(AtomicBool::default());
+        let stop_thread = stop.clone();
+        let (sender, events) = channel();
+        let thrd = std::thread::spawn(move || match source {
+            Source::Kmsg(kmsg) => follow_kmsg(kmsg, &sender, &stop_thread),
+            Source::Cgroup(path) => watch_counter(&path, &sender, &stop_thread),
+        });
+
+        Some(Self { stop, thrd, events })
+    }
+
+    /// The OOM kills detected so far.
+    pub fn events(&self) -> Vec<OomKill> {
+        self.events.try_iter().collect()
+    }
+
+    /// Stop watching, returning the last OOM kills detected.
+    pub fn stop(self) -> Vec<OomKill> {
+        let Self { stop, thrd, events } = self;
+        stop.store(true, Ordering::Release);
+        thrd.join().expect("cannot join OOM monitor thread");
+        events.try_iter().collect()
+    }
+}
+
+/// Open the kernel log, by the privileged helper if the agent has dropped root.
+fn open_log() -> std::io::Result<File> {
+    #[cfg(target_os = "linux")]
+    if let Some(res) = super::privsep::delegate(super::privsep::Request::Kmsg) {
+        return res;
+    }
+    open_kmsg()
+}
+
+pub fn open_kmsg() -> std::io::Result<File> {
+    let mut kmsg = File::options()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/kmsg")?;
+    // only the messages of this run are interesting
+    kmsg.seek(SeekFrom::End(0))?;
+    Ok(kmsg)
+}
+
+/// Path of the file with the `oom_kill` counter of the agent cgroup, v2 or v1.
+fn cgroup_counter() -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let mut candidates = cgroups.lines().filter_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let (_, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+        let path = path.trim_start_matches('/');
+        match controllers {
+            "" => Some(Path::new("/sys/fs/cgroup").join(path).join("memory.events")),
+            "memory" => Some(
+                Path::new("/sys/fs/cgroup/memory")
+                    .join(path)
+                    .join("memory.oom_control"),
+            ),
+            _ => None,
+        }
+    });
+    candidates.rfind(|path| oom_kills(path).is_some())
+}
+
+fn oom_kills(path: &Path) -> Option<u64> {
+    let content = std::fs::read_to_string(path).ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn follow_kmsg(mut kmsg: File, events: &Sender<OomKill>, stop: &AtomicBool) {
+    let killed = Regex::new(r"Killed process (\d+) \(([^)]*)\)").unwrap();
+    // every read returns the single record
+    let mut buf = vec![0u8; 8 << 10];
+
+    while !stop.load(Ordering::Acquire) {
+        let len = match kmsg.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(CHECK_INTERVAL);
+                continue;
+            }
+            // the records were overwritten before being read, the next read continues
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(e) => {
+                warn!(
+                    "cannot read kernel log, OOM kills are not detected further - {}",
+                    e
+                );
+                return;
+            }
+        };
+
+        // the record is "prio,seq,time,flags;message"
+        let record = String::from_utf8_lossy(&buf[..len]);
+        let Some((_, message)) = record.split_once(';') else {
+            continue;
+        };
+        if let Some(caps) = killed.captures(message) {
+            let _ = events.send(OomKill {
+                time: Local::now(),
+                pid: caps[1].parse().ok(),
+                process: Some(caps[2].to_owned()),
+            });
+        }
+    }
+}
+
+fn watch_counter(path: &Path, events: &Sender<OomKill>, stop: &AtomicBool) {
+    let mut last = oom_kills(path).unwrap_or(0);
+    while !stop.load(Ordering::Acquire) {
+        std::thread::sleep(CHECK_INTERVAL);
+        let Some(count) = oom_kills(path) else {
+            continue;
+        };
+        for _ in last..count {
+            let _ = events.send(OomKill {
+                time: Local::now(),
+                pid: None,
+                process: None,
+            });
+        }
+        last = count;
+    }
+}
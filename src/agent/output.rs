@@ -0,0 +1,79 @@
+//! Tracking of the time to the first output of the spawned process.
+//!
+//! The stdout of the process is piped to the thread copying it into the output log, which notes
+//! the arrival of the first byte. The copy ends when all the writers close the pipe, so the
+//! descendants keeping the output open delay the completion of the foreground process, and the
+//! background one is not waited for them at the stop longer than the grace time.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::{Arc, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+/// Wait of the output end of the exited background process.
+const GRACE: Duration = Duration::from_secs(1);
+
+/// Time of the first output byte, both for the latency and for the manifest.
+#[derive(Clone)]
+pub struct FirstOutput {
+    pub latency: Duration,
+    pub time: chrono::DateTime<chrono::Local>,
+}
+
+pub struct Tracker {
+    first: Arc<OnceLock<FirstOutput>>,
+    thrd: JoinHandle<()>,
+}
+
+impl Tracker {
+    /// Copy the piped stdout of the process started at the moment into the log.
+    pub fn start(mut stdout: File, mut log: File, started: Instant) -> Self {
+        let first = Arc::new(OnceLock::new());
+        let first_thread = first.clone();
+        let thrd = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        error!("cannot read the process output - {}", e);
+                        break;
+                    }
+                };
+                first_thread.get_or_init(|| FirstOutput {
+                    latency: started.elapsed(),
+                    time: chrono::Local::now(),
+                });
+                if let Err(e) = log.write_all(&buf[..n]) {
+                    error!("cannot write the process output - {}", e);
+                    break;
+                }
+            }
+        });
+        Self { first, thrd }
+    }
+
+    /// Wait for the end of the output, none if there was no output.
+    pub fn finish(self) -> Option<FirstOutput> {
+        self.thrd.join().expect("output thread panicked");
+        self.first.get().cloned()
+    }
+
+    /// Wait for the end of the output of the exited process at most the grace time.
+    pub fn finish_exited(self, id: u32) -> Option<FirstOutput> {
+        let deadline = Instant::now() + GRACE;
+        while !self.thrd.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if !self.thrd.is_finished() {
+            warn!("output of id={} is still open by its descendants", id);
+            return self.first.get().cloned();
+        }
+        self.finish()
+    }
+}
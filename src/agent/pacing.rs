@@ -0,0 +1,192 @@
+//! Staircase load schedules of the spawned processes.
+//!
+//! The cpu and io limits are written to the own cgroup v2 of the process created under the root
+//! one, the egress limit replaces the root qdisc of the interface by `tc`. The process is moved
+//! into the cgroup right after the start, so it races with the early forks. The first limit is
+//! applied before the start, the next ones by the thread every step, and the times of the changes
+//! are logged next to the process output. The cgroup and the qdisc are removed after the process
+//! exits.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{info, warn};
+use subprocess::{Exec, Redirection};
+
+use super::manifest::format_time;
+use super::protocol::{Pacing, PacingKnob};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+enum Target {
+    Cgroup { path: PathBuf, file: &'static str },
+    Egress { dev: String },
+}
+
+impl Target {
+    fn create(knob: &PacingKnob, id: u32) -> Result<Self, String> {
+        let (controller, file) = match knob {
+            PacingKnob::Cpu => ("cpu", "cpu.max"),
+            PacingKnob::Io => ("io", "io.max"),
+            PacingKnob::Egress { dev } => return Ok(Target::Egress { dev: dev.clone() }),
+        };
+        let root = Path::new(CGROUP_ROOT);
+        let controllers =
+            std::fs::read_to_string(root.join("cgroup.controllers")).unwrap_or_default();
+        if !controllers.split_whitespace().any(|c| c == controller) {
+            return Err(format!(
+                "{} controller is not available in cgroup v2 at '{}'",
+                controller, CGROUP_ROOT
+            ));
+        }
+        write(
+            &root.join("cgroup.subtree_control"),
+            &format!("+{}", controller),
+        )?;
+        let path = root.join(format!("pmppt-{}-{:03}", std::process::id(), id));
+        std::fs::create_dir(&path)
+            .map_err(|e| format!("cannot create cgroup '{}' - {}", path.to_string_lossy(), e))?;
+        Ok(Target::Cgroup { path, file })
+    }
+
+    fn apply(&self, limit: &str) -> Result<(), String> {
+        match self {
+            Target::Cgroup { path, file } => write(&path.join(file), limit),
+            Target::Egress { dev } => {
+                let mut args = vec!["qdisc", "replace", "dev", dev, "root"];
+                args.extend(limit.split_whitespace());
+                tc(&args)
+            }
+        }
+    }
+
+    fn remove(&self) -> Result<(), String> {
+        match self {
+            // fails if the descendants of the process are still running
+            Target::Cgroup { path, .. } => std::fs::remove_dir(path)
+                .map_err(|e| format!("cannot remove cgroup '{}' - {}", path.to_string_lossy(), e)),
+            Target::Egress { dev } => tc(&["qdisc", "del", "dev", dev, "root"]),
+        }
+    }
+}
+
+fn write(path: &Path, value: &str) -> Result<(), String> {
+    std::fs::write(path, value).map_err(|e| {
+        format!(
+            "cannot write '{}' to '{}' - {}",
+            value,
+            path.to_string_lossy(),
+            e
+        )
+    })
+}
+
+/// Run `tc` with the arguments, its output is the error.
+pub fn tc(args: &[&str]) -> Result<(), String> {
+    let exec = Exec::cmd("tc").args(args);
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' failed: {}",
+            name,
+            capture.stdout_str().trim()
+        )),
+    }
+}
+
+fn log_limit(log: &mut File, limit: &str) {
+    let _ = writeln!(log, "{} {}", format_time(chrono::Local::now()), limit);
+}
+
+/// Schedule of the single process, from the preparation before its start till its exit.
+pub struct Pacer {
+    id: u32,
+    target: Option<Target>,
+    pacing: Pacing,
+    log: Option<File>,
+    thrd: Option<(Sender<()>, JoinHandle<Target>)>,
+}
+
+impl Pacer {
+    /// Create the cgroup or take the interface and apply the first limit, the changes are logged
+    /// into the given file.
+    pub fn prepare(pacing: &Pacing, id: u32, log: &Path) -> Result<Self, String> {
+        pacing.check()?;
+        let target = Target::create(&pacing.knob, id)?;
+        let log = target.apply(&pacing.limits[0]).and_then(|()| {
+            File::create_new(log)
+                .map_err(|e| format!("cannot create '{}' - {}", log.to_string_lossy(), e))
+        });
+        let mut log = log.inspect_err(|_| {
+            let _ = target.remove();
+        })?;
+        log_limit(&mut log, &pacing.limits[0]);
+        Ok(Self {
+            id,
+            target: Some(target),
+            pacing: pacing.clone(),
+            log: Some(log),
+            thrd: None,
+        })
+    }
+
+    /// Move the started process into the cgroup and run the rest of the schedule.
+    pub fn start(&mut self, pid: Option<u32>) {
+        let (Some(target), Some(mut log)) = (self.target.take(), self.log.take()) else {
+            return;
+        };
+        if let (Target::Cgroup { path, .. }, Some(pid)) = (&target, pid) {
+            if let Err(e) = write(&path.join("cgroup.procs"), &pid.to_string()) {
+                warn!("process id={} is not paced: {}", self.id, e);
+            }
+        }
+
+        let (id, step) = (self.id, Duration::from_secs_f64(self.pacing.step_s));
+        let limits = self.pacing.limits[1..].to_vec();
+        let (stop, rx) = mpsc::channel();
+        let thrd = std::thread::spawn(move || {
+            for limit in limits {
+                match rx.recv_timeout(step) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    _ => break,
+                }
+                match target.apply(&limit) {
+                    Ok(()) => {
+                        info!("pacing id={}: limit '{}'", id, limit);
+                        log_limit(&mut log, &limit);
+                    }
+                    Err(e) => warn!("pacing id={} failed: {}", id, e),
+                }
+            }
+            target
+        });
+        self.thrd = Some((stop, thrd));
+    }
+
+    /// Stop the schedule and remove the limit, called after the process exits.
+    pub fn finish(mut self) {
+        let target = match self.thrd.take() {
+            Some((stop, thrd)) => {
+                let _ = stop.send(());
+                thrd.join().expect("pacing thread panicked")
+            }
+            None => match self.target.take() {
+                Some(target) => target,
+                None => return,
+            },
+        };
+        if let Err(e) = target.remove() {
+            warn!("cannot remove the limit of id={}: {}", self.id, e);
+        }
+    }
+}
@@ -1,99 +1,1389 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use serde::Serialize;
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 
-const DEFAULT_SLEEP_TIME: Duration = Duration::from_millis(250);
+use super::protocol::{Encoding, PollOptions, TimeFormat};
+
+pub mod binary;
+pub mod bmc;
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "freebsd")]
+use freebsd as emulated;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos as emulated;
+pub mod meter;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(windows)]
+mod pdh;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod perf;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod power;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod schedstat;
+pub mod snmp;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod statsd;
+#[cfg(unix)]
+pub mod statvfs;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod thp;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod tree;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
+#[cfg(any(feature = "sqlite", feature = "parquet"))]
+mod values;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod vmstat;
+
+/// Whether the path is emulated by the poller instead of being read from the filesystem.
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+pub use emulated::is_emulated;
+#[cfg(not(any(target_os = "freebsd", target_os = "macos")))]
+pub fn is_emulated(_path: &Path) -> bool {
+    false
+}
+
+pub const DEFAULT_SLEEP_TIME: Duration = Duration::from_millis(250);
 const FILE_CAP: usize = 4 << 10;
 const TOTAL_CAP: usize = 32 << 10;
 
+/// The shortest poll period supported.
+pub const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+const DEFAULT_CHANGE_THRESHOLD: f64 = 0.01;
+const DEFAULT_KEYFRAME: Duration = Duration::from_secs(60);
+
+/// Line appended to the file content cut by the per-file size cap.
+pub const TRUNCATED_MARKER: &str = "<<pmppt: truncated>>";
+
 pub struct PollConfig {
     sleep_time: Duration,
+    realtime: bool,
+    adaptive: Option<Adaptive>,
+    // deduplication of the samples, the unchanged ones are stored only once per keyframe period
+    keyframe: Option<Duration>,
+    // per-file limit of the content stored
+    max_bytes: Option<usize>,
+    encoding: Encoding,
+    timestamp: TimeFormat,
+    // number of threads reading the files concurrently
+    parallel: Option<usize>,
+    // output buffering, every sample is written immediately if not set
+    flush_interval: Option<Duration>,
+    fsync_interval: Option<Duration>,
+    // compact binary output, see [`binary`]
+    binary: bool,
+    // separate poll log per source
+    split: bool,
+    // read latency statistics
+    stats: bool,
+    // initial samples kept out of the poll log
+    warmup: Option<Warmup>,
+    // polling ends by itself after this time
+    duration: Option<Duration>,
+    // no samples are taken while set, the schedule goes on
+    paused: Arc<AtomicBool>,
+    // metadata of the polled files for the header
+    files: Vec<SourceFile>,
+    // structured copy of the samples
+    #[cfg(feature = "sqlite")]
+    database: Option<sqlite::Database>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<parquet::Writer>,
+}
+
+impl PollConfig {
+    /// The shortest period the poller may use.
+    fn fastest(&self) -> Duration {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.fast,
+            None => self.sleep_time,
+        }
+    }
+
+    /// Also store the structured samples into the run database in the directory.
+    #[cfg(feature = "sqlite")]
+    pub fn store_into(
+        &mut self,
+        dir: &Path,
+        poll: u32,
+        srcs: &Sources,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        let path = dir.join(sqlite::DATABASE_NAME);
+        self.database = Some(sqlite::Database::open(&path, poll, srcs, tags)?);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn store_into(
+        &mut self,
+        _dir: &Path,
+        _poll: u32,
+        _srcs: &Sources,
+        _tags: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        Err("SQLite output is not supported, the agent is built without the sqlite feature".into())
+    }
+
+    /// Also write the structured samples into the Parquet file.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet(
+        &mut self,
+        path: &Path,
+        srcs: &Sources,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        self.parquet = Some(parquet::Writer::create(path, srcs, tags)?);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    pub fn write_parquet(
+        &mut self,
+        _path: &Path,
+        _srcs: &Sources,
+        _tags: &BTreeMap<String, String>,
+    ) -> Result<(), String> {
+        Err(
+            "Parquet output is not supported, the agent is built without the parquet feature"
+                .into(),
+        )
+    }
+
+    /// Describe the polled files in the header, with the pattern they are expanded from.
+    pub fn describe(&mut self, srcs: &Sources, pattern: &str) {
+        if let Sources::Files(paths) = srcs {
+            self.files = describe_files(paths, pattern);
+        }
+    }
+
+    /// Flag pausing the poller while set, the skipped samples are seen as the sequence gap.
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    /// Period of the next sample, changed only by the adaptive polling.
+    fn next_period(&mut self, current: Duration, sample: &str) -> Duration {
+        match &mut self.adaptive {
+            Some(adaptive) => adaptive.next_period(current, sample),
+            None => current,
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct PollHeader {
-    files: Vec<String>,
+impl TryFrom<&PollOptions> for PollConfig {
+    type Error = String;
+
+    fn try_from(opts: &PollOptions) -> Result<Self, String> {
+        let sleep_time = opts.period.unwrap_or(DEFAULT_SLEEP_TIME);
+        if sleep_time < MIN_PERIOD {
+            return Err(format!(
+                "poll period {:?} is shorter than the minimum of {:?}",
+                sleep_time, MIN_PERIOD
+            ));
+        }
+
+        let parallel = match opts.parallel {
+            Some(0) => return Err("parallel poller needs at least one thread".into()),
+            parallel => parallel,
+        };
+
+        let timestamp = opts.timestamp.unwrap_or_default();
+        if timestamp == TimeFormat::MonotonicNs {
+            if !cfg!(unix) {
+                return Err("monotonic timestamps are supported only on Unix".into());
+            }
+            if opts.binary.unwrap_or(false) {
+                return Err("binary poll logs store the epoch timestamps only".into());
+            }
+        }
+
+        let adaptive = match opts.fast_period {
+            Some(fast) if fast < MIN_PERIOD || fast > sleep_time => {
+                return Err(format!(
+                    "fast poll period {:?} must be within {:?}..={:?}",
+                    fast, MIN_PERIOD, sleep_time
+                ))
+            }
+            Some(fast) => Some(Adaptive {
+                fast,
+                slow: sleep_time,
+                threshold: opts.change_threshold.unwrap_or(DEFAULT_CHANGE_THRESHOLD),
+                values: Vec::new(),
+                prev: Vec::new(),
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            sleep_time,
+            realtime: opts.realtime.unwrap_or(false),
+            adaptive,
+            keyframe: opts
+                .dedup
+                .unwrap_or(false)
+                .then(|| opts.keyframe.unwrap_or(DEFAULT_KEYFRAME)),
+            max_bytes: opts.max_bytes,
+            encoding: opts.encoding.unwrap_or_default(),
+            timestamp,
+            parallel,
+            flush_interval: opts.flush_interval,
+            fsync_interval: opts.fsync_interval,
+            binary: opts.binary.unwrap_or(false),
+            split: opts.split.unwrap_or(false),
+            stats: opts.stats.unwrap_or(false),
+            warmup: (opts.warmup_samples.is_some() || opts.warmup.is_some()).then(|| Warmup {
+                samples: opts.warmup_samples.unwrap_or(0),
+                time: opts.warmup.unwrap_or_default(),
+                log: opts.warmup_log.unwrap_or(false),
+            }),
+            duration: opts.duration,
+            paused: Arc::default(),
+            files: Vec::new(),
+            #[cfg(feature = "sqlite")]
+            database: None,
+            #[cfg(feature = "parquet")]
+            parquet: None,
+        })
+    }
+}
+
+/// Warm-up of the poller, the samples are not stored until both the samples are taken and the
+/// time passes. They may be stored into the `NNN-poll-warmup.log` file next to the poll log, the
+/// sequence numbers of the poll log then start after them.
+struct Warmup {
+    samples: u64,
+    time: Duration,
+    log: bool,
+}
+
+/// Change detection of the adaptive polling.
+///
+/// The poller samples with the fast period while any numeric value of the sample changes by more
+/// than the threshold relative to its previous value, and doubles the period on every sample
+/// without changes until it reaches the regular one.
+struct Adaptive {
+    fast: Duration,
+    slow: Duration,
+    threshold: f64,
+    // the buffers are kept to not allocate on every sample
+    values: Vec<f64>,
+    prev: Vec<f64>,
+}
+
+impl Adaptive {
+    fn next_period(&mut self, current: Duration, sample: &str) -> Duration {
+        std::mem::swap(&mut self.values, &mut self.prev);
+        self.values.clear();
+        self.values.extend(
+            sample
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .filter_map(|token| token.parse::<f64>().ok()),
+        );
+
+        let changed = self.values.len() != self.prev.len()
+            || self.values.iter().zip(&self.prev).any(|(value, prev)| {
+                (value - prev).abs() > self.threshold * value.abs().max(prev.abs())
+            });
+
+        match changed {
+            true => self.fast,
+            false => (current * 2).min(self.slow),
+        }
+    }
+}
+
+/// Fixed-rate schedule of the samples, tracking the ones which could not be taken in time.
+struct Ticker {
     period: Duration,
+    next: Instant,
+    overruns: u64,
+    // number of the current tick in the schedule, including the missed ones
+    seq: u64,
 }
 
-fn create_header(files: &[PathBuf], cfg: &PollConfig) -> String {
-    let header = PollHeader {
-        files: files
+impl Ticker {
+    fn new(period: Duration) -> Self {
+        Self {
+            period,
+            next: Instant::now(),
+            overruns: 0,
+            seq: 0,
+        }
+    }
+
+    /// Sleep until the next sample, the late samples are taken immediately and true is returned.
+    fn wait(&mut self) -> bool {
+        self.next += self.period;
+        self.seq += 1;
+        let now = Instant::now();
+        if now < self.next {
+            std::thread::sleep(self.next - now);
+            return false;
+        }
+
+        // do not try to catch up, the schedule just restarts from now skipping the missed ticks
+        self.seq += ((now - self.next).as_nanos() / self.period.as_nanos()) as u64;
+        self.next = now;
+        self.overruns += 1;
+        if self.overruns.is_power_of_two() {
+            warn!(
+                "poller cannot keep up with the period {:?}, {} late samples so far",
+                self.period, self.overruns
+            );
+        }
+        true
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        if self.overruns > 0 {
+            warn!(
+                "poller with the period {:?} was late {} times, the sample times are not uniform",
+                self.period, self.overruns
+            );
+        }
+    }
+}
+
+/// Switch the current thread to `SCHED_FIFO`, keeping the default scheduling on failure.
+pub fn set_realtime() {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let param = libc::sched_param { sched_priority: 1 };
+        // SAFETY: plain call for the current thread
+        let res =
+            unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+        if res != 0 {
+            warn!(
+                "cannot set real-time priority - {}",
+                std::io::Error::from_raw_os_error(res)
+            );
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    warn!("real-time priority is not supported on this platform");
+}
+
+/// Check that the sources can be read at least once per the period.
+pub fn check_rate(srcs: &Sources, cfg: &PollConfig) -> Result<(), String> {
+    // the generated sources are cheap to read, they exist not on every platform
+    #[allow(clippy::infallible_destructuring_match)]
+    let paths = match srcs {
+        Sources::Files(paths) => paths,
+        #[cfg(windows)]
+        Sources::Counters(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Perf(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Vmstat(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Thp(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Schedstat(_) => return Ok(()),
+        #[cfg(unix)]
+        Sources::Statvfs(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Power(_) => return Ok(()),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Tree(..) => return Ok(()),
+        Sources::Statsd(..) => return Ok(()),
+        // the meter readings are slow, but their failures are tolerated
+        Sources::Meter(_) => return Ok(()),
+    };
+
+    let mut buf = String::with_capacity(TOTAL_CAP);
+    let start = Instant::now();
+    for path in paths {
+        read_source(path, cfg.max_bytes, cfg.encoding, &mut buf)
+            .map_err(|e| format!("cannot read '{}' - {}", path.to_string_lossy(), e))?;
+    }
+
+    let elapsed = start.elapsed();
+    match elapsed < cfg.fastest() {
+        true => Ok(()),
+        false => Err(format!(
+            "reading the files takes {:?}, it cannot be sustained with the period {:?}",
+            elapsed,
+            cfg.fastest()
+        )),
+    }
+}
+
+/// Named sets of files commonly polled on mobile and embedded targets, referred as `@name`.
+const PRESETS: &[(&str, &str)] = &[
+    ("thermal", "/sys/class/thermal/thermal_zone*/{type,temp}"),
+    (
+        "cpufreq",
+        "/sys/devices/system/cpu/cpu*/cpufreq/scaling_cur_freq",
+    ),
+    ("devfreq", "/sys/class/devfreq/*/cur_freq"),
+];
+
+/// Resolve the `@name` preset into its pattern, other patterns are returned as is.
+pub fn resolve_preset(pattern: &str) -> Result<&str, String> {
+    match pattern.strip_prefix('@') {
+        None => Ok(pattern),
+        Some(name) => PRESETS
             .iter()
-            .cloned()
-            .map(|p| p.to_str().unwrap().to_owned())
-            .collect(),
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, pattern)| *pattern)
+            .ok_or_else(|| {
+                let known: Vec<&str> = PRESETS.iter().map(|(preset, _)| *preset).collect();
+                format!("unknown preset '{}', known: {}", pattern, known.join(", "))
+            }),
+    }
+}
+
+/// Drop the files which cannot be read, e.g. restricted by SELinux, warning about them.
+pub fn readable_only(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| {
+            // just a single byte, some files are endless
+            let mut buf = String::new();
+            match read_source(path, Some(1), Encoding::Lossy, &mut buf) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("skipping unreadable '{}' - {}", path.to_string_lossy(), e);
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+/// What the poller reads on every sample.
+pub enum Sources {
+    Files(Vec<PathBuf>),
+    /// Windows performance counter paths, like `\Processor(_Total)\% Processor Time`.
+    #[cfg(windows)]
+    Counters(Vec<String>),
+    /// Performance events opened in advance, see [`perf`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Perf(Vec<perf::Counter>),
+    /// Rates of the memory management counters, see [`vmstat`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Vmstat(vmstat::Rates),
+    /// Hugepages settings, memory and events, see [`thp`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Thp(thp::Activity),
+    /// Run delays of the CPUs or processes, see [`schedstat`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Schedstat(schedstat::Delays),
+    /// Usage of the filesystems, see [`statvfs`].
+    #[cfg(unix)]
+    Statvfs(statvfs::Mounts),
+    /// Power and voltage sensors, see [`power`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Power(power::Sensors),
+    /// Descendants of the spawned process, see [`tree`].
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Tree(String, tree::Tree),
+    /// Metrics sent by the workload over statsd, see [`statsd`].
+    Statsd(String, statsd::Listener),
+    /// Readings of the external devices, see [`meter`], [`bmc`] and [`snmp`].
+    Meter(meter::Meter),
+}
+
+/// Version of the poll log format written by the poller.
+///
+/// Version 2 added the sample sequence numbers, the logs without the version are of version 1.
+/// Version 3 added the timestamp formats other than the local RFC 3339 time.
+pub const FORMAT_VERSION: u32 = 3;
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Self-description of the poll log, stored as its first line.
+#[derive(Serialize, Deserialize)]
+pub struct PollHeader {
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+    pub files: Vec<String>,
+    pub period: Duration,
+    /// The fastest period of the adaptive poller, the samples are not uniform then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fast_period: Option<Duration>,
+    /// Samples equal to the previous one are not stored, but at least once per this period.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyframe: Option<Duration>,
+    /// Per-file content limit, the cut files end with the [`TRUNCATED_MARKER`] line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    /// Encoding of the file contents, see [`Encoding`].
+    #[serde(default, skip_serializing_if = "Encoding::is_lossy")]
+    pub encoding: Encoding,
+    /// Format of the sample timestamps of the text log, see [`TimeFormat`].
+    #[serde(default, skip_serializing_if = "TimeFormat::is_local")]
+    pub timestamp: TimeFormat,
+    /// The samples are stored as the [`binary`] frames after the header.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+    /// Metadata of the polled files taken at the start, in the order of `files`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SourceFile>,
+}
+
+/// Polled file as found at the start, identifying it after the process or the device is gone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceFile {
+    /// Absolute path with the symlinks resolved, like the pid of `/proc/self`.
+    pub path: String,
+    /// Brace expansion of the pattern the file is found by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ino: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Permission bits in octal, like `0644`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+#[cfg(unix)]
+fn file_ids(meta: &std::fs::Metadata) -> (Option<u64>, Option<u64>, Option<String>) {
+    use std::os::unix::fs::MetadataExt;
+    let mode = format!("{:04o}", meta.mode() & 0o7777);
+    (Some(meta.dev()), Some(meta.ino()), Some(mode))
+}
+
+#[cfg(not(unix))]
+fn file_ids(_meta: &std::fs::Metadata) -> (Option<u64>, Option<u64>, Option<String>) {
+    (None, None, None)
+}
+
+/// Metadata of the files expanded from the pattern, the emulated ones have only the path.
+fn describe_files(paths: &[PathBuf], pattern: &str) -> Vec<SourceFile> {
+    let branches: Vec<String> = match resolve_preset(pattern) {
+        Ok(pattern) => brace_expand::brace_expand(pattern),
+        Err(_) => Vec::new(),
+    };
+    paths
+        .iter()
+        .map(|path| {
+            let branch = branches
+                .iter()
+                .find(|branch| glob::Pattern::new(branch).is_ok_and(|glob| glob.matches_path(path)))
+                .cloned();
+            let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            let meta = std::fs::metadata(path).ok();
+            let (dev, ino, mode) = meta.as_ref().map_or((None, None, None), file_ids);
+            SourceFile {
+                path: resolved.to_string_lossy().into_owned(),
+                branch,
+                dev,
+                ino,
+                size: meta.map(|meta| meta.len()),
+                mode,
+            }
+        })
+        .collect()
+}
+
+fn create_header(files: &[String], sources: Range<usize>, cfg: &PollConfig) -> String {
+    let header = PollHeader {
+        version: FORMAT_VERSION,
+        files: files[sources.clone()].to_vec(),
         period: cfg.sleep_time,
+        fast_period: cfg.adaptive.as_ref().map(|adaptive| adaptive.fast),
+        keyframe: cfg.keyframe,
+        max_bytes: cfg.max_bytes,
+        encoding: cfg.encoding,
+        timestamp: cfg.timestamp,
+        binary: cfg.binary,
+        sources: cfg.files.get(sources).unwrap_or_default().to_vec(),
     };
     let mut header = serde_json::to_string(&header).unwrap(); // should never fail
     header.push('\n'); // insert newline after the header
     header
 }
 
-fn store_header(output: &mut dyn Write, header: &str) {
-    // dump and flush the poller header first to improve potential diagnostics
-    output
-        .write_all(header.as_bytes())
-        .expect("failed to write header");
-    output
-        .flush()
-        .expect("cannot flush the file after writing header");
+/// Poll log file written according to the flush and fsync policy.
+struct Sink {
+    file: BufWriter<File>,
+    flush_interval: Option<Duration>,
+    fsync_interval: Option<Duration>,
+    last_flush: Instant,
+    last_fsync: Instant,
+    // offset of the next write, used by the binary index
+    pos: u64,
 }
 
-pub fn poll_with_config(srcs: Vec<PathBuf>, dest: PathBuf, stop: Arc<AtomicBool>, cfg: PollConfig) {
-    // open destination file with the final content and store header
-    let mut output = File::create(dest).expect("cannot open file");
-    store_header(&mut output, &create_header(&srcs, &cfg));
+impl Sink {
+    fn create(dest: PathBuf, cfg: &PollConfig) -> Self {
+        let file = File::create(dest).expect("cannot open file");
+        Self {
+            file: BufWriter::with_capacity(TOTAL_CAP, file),
+            flush_interval: cfg.flush_interval,
+            fsync_interval: cfg.fsync_interval,
+            last_flush: Instant::now(),
+            last_fsync: Instant::now(),
+            pos: 0,
+        }
+    }
 
-    let mut strbuffer = String::with_capacity(FILE_CAP);
-    let mut outbuffer = String::with_capacity(TOTAL_CAP);
+    fn header(&mut self, header: &str) {
+        // dump and flush the poller header first to improve potential diagnostics
+        self.file
+            .write_all(header.as_bytes())
+            .expect("failed to write header");
+        self.file
+            .flush()
+            .expect("cannot flush the file after writing header");
+        self.pos += header.len() as u64;
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.file.write_all(data).expect("cannot write");
+        self.pos += data.len() as u64;
+
+        // without the flush interval every sample is written immediately
+        if self
+            .flush_interval
+            .is_none_or(|interval| self.last_flush.elapsed() >= interval)
+        {
+            self.file.flush().expect("cannot flush");
+            self.last_flush = Instant::now();
+        }
+
+        if let Some(interval) = self.fsync_interval {
+            if self.last_fsync.elapsed() >= interval {
+                self.sync();
+                self.last_fsync = Instant::now();
+            }
+        }
+    }
+
+    fn sync(&mut self) {
+        self.file.flush().expect("cannot flush");
+        self.file.get_ref().sync_data().expect("cannot fsync");
+    }
+
+    fn finish(mut self) {
+        match self.fsync_interval {
+            Some(_) => self.sync(),
+            None => self.file.flush().expect("cannot flush"),
+        }
+    }
+}
+
+/// Content of the sample being collected: the concatenated sources with their boundaries.
+struct SampleBuf {
+    text: String,
+    ends: Vec<usize>,
+}
+
+impl SampleBuf {
+    fn new() -> Self {
+        Self {
+            text: String::with_capacity(TOTAL_CAP),
+            ends: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.text.clear();
+        self.ends.clear();
+    }
+
+    /// Mark the end of the current source content.
+    fn end_source(&mut self) {
+        self.ends.push(self.text.len());
+    }
+
+    /// Content of the sources in the range.
+    fn text_of(&self, sources: Range<usize>) -> &str {
+        let start = match sources.start {
+            0 => 0,
+            i => self.ends[i - 1],
+        };
+        let end = sources.end.checked_sub(1).map_or(start, |i| self.ends[i]);
+        &self.text[start..end]
+    }
+
+    fn sources(&self) -> impl Iterator<Item = &str> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(&self.ends)
+            .map(|(start, &end)| &self.text[start..end])
+    }
+}
+
+/// Read the whole content of the source once, as the poller would read it.
+pub fn read_once(src: &Path) -> std::io::Result<String> {
+    let mut buf = String::new();
+    read_source(src, None, Encoding::Lossy, &mut buf)?;
+    Ok(buf)
+}
+
+/// Read the source content, cutting it to `cap` bytes with the [`TRUNCATED_MARKER`] line.
+///
+/// The content is read as bytes and stored in the given encoding, so the binary sources do not
+/// fail the poller.
+fn read_source(
+    src: &Path,
+    cap: Option<usize>,
+    encoding: Encoding,
+    buf: &mut String,
+) -> std::io::Result<usize> {
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    if emulated::is_emulated(src) {
+        return emulated::read(src, buf);
+    }
+
+    let mut file = File::open(src)?;
+    let mut bytes = Vec::with_capacity(cap.map_or(FILE_CAP, |cap| cap.min(FILE_CAP) + 1));
+    match cap {
+        // read one byte more to detect the truncation
+        Some(cap) => file.take(cap as u64 + 1).read_to_end(&mut bytes)?,
+        None => file.read_to_end(&mut bytes)?,
+    };
+
+    let start = buf.len();
+    push_content(buf, &bytes, cap, encoding);
+    Ok(buf.len() - start)
+}
+
+/// Append the content in the encoding, cut to `cap` bytes and marking the truncation if any.
+fn push_content(buf: &mut String, bytes: &[u8], cap: Option<usize>, encoding: Encoding) {
+    let cap = cap.unwrap_or(usize::MAX);
+    let content = &bytes[..bytes.len().min(cap)];
+    match encoding {
+        // the cut may split a multibyte character
+        Encoding::Lossy => buf.push_str(&String::from_utf8_lossy(content)),
+        Encoding::Base64 => {
+            push_base64(buf, content);
+            buf.push('\n');
+        }
+    }
+    if bytes.len() > cap {
+        if !buf.ends_with('\n') {
+            buf.push('\n');
+        }
+        buf.push_str(TRUNCATED_MARKER);
+        buf.push('\n');
+    }
+}
+
+/// Append the standard padded base64 of the bytes.
+fn push_base64(buf: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => buf.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => buf.push('='),
+            }
+        }
+    }
+}
+
+/// Poll log storing the content of the range of the sources.
+struct Stream {
+    output: Sink,
+    sources: Range<usize>,
+    buffer: Vec<u8>,
+    // the last sample and its storing time for the dedup mode
+    last: String,
+    last_written: Option<Instant>,
+    // the index of the binary log
+    index: Vec<binary::IndexEntry>,
+    last_indexed: Option<Instant>,
+}
+
+impl Stream {
+    fn create(dest: PathBuf, names: &[String], sources: Range<usize>, cfg: &PollConfig) -> Self {
+        // open destination file with the final content and store header
+        let mut output = Sink::create(dest, cfg);
+        output.header(&create_header(names, sources.clone(), cfg));
+
+        Self {
+            output,
+            sources,
+            buffer: Vec::with_capacity(TOTAL_CAP),
+            last: String::new(),
+            last_written: None,
+            index: Vec::new(),
+            last_indexed: None,
+        }
+    }
+
+    /// Store the sample unless it is skipped by the dedup mode, returning whether it is stored.
+    fn store(
+        &mut self,
+        now: &DateTime<Local>,
+        stamp: &str,
+        seq: u64,
+        sample: &SampleBuf,
+        cfg: &PollConfig,
+    ) -> bool {
+        let content = sample.text_of(self.sources.clone());
+
+        // the duplicates are skipped in the dedup mode, but not for longer than the keyframe
+        let store = match cfg.keyframe {
+            None => true,
+            Some(keyframe) => {
+                content != self.last || self.last_written.is_none_or(|t| t.elapsed() >= keyframe)
+            }
+        };
+        if cfg.keyframe.is_some() {
+            self.last.clear();
+            self.last.push_str(content);
+        }
+        if !store {
+            return false;
+        }
+
+        self.buffer.clear();
+        if cfg.binary {
+            let timestamp = now.timestamp_micros();
+            if self
+                .last_indexed
+                .is_none_or(|t| t.elapsed() >= binary::INDEX_INTERVAL)
+            {
+                self.index.push(binary::IndexEntry {
+                    timestamp,
+                    offset: self.output.pos,
+                });
+                self.last_indexed = Some(Instant::now());
+            }
+            let sources = sample.sources().skip(self.sources.start);
+            for (id, source) in sources.take(self.sources.len()).enumerate() {
+                let payload = source.as_bytes();
+                binary::encode_frame(&mut self.buffer, timestamp, seq, id as u16, payload);
+            }
+        } else {
+            writeln!(self.buffer, "{} {}", stamp, seq).expect("cannot write");
+            self.buffer.extend_from_slice(content.as_bytes());
+            // add the final delimiter
+            self.buffer.push(b'\n');
+        }
+
+        self.output.write(&self.buffer);
+        self.last_written = Some(Instant::now());
+        true
+    }
+
+    fn finish(mut self, cfg: &PollConfig) {
+        if cfg.binary {
+            let start = self.output.pos;
+            self.output.write(&binary::encode_index(&self.index, start));
+        }
+        self.output.finish();
+    }
+}
+
+/// Timestamp of the sample taken at the given time, in the configured format.
+fn format_timestamp(format: TimeFormat, now: &DateTime<Local>) -> String {
+    match format {
+        TimeFormat::Local => now.to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+        TimeFormat::Utc => now
+            .with_timezone(&chrono::Utc)
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        TimeFormat::EpochNs => now.timestamp_nanos_opt().unwrap_or_default().to_string(),
+        TimeFormat::MonotonicNs => monotonic_nanos().to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn monotonic_nanos() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: plain call filling the struct
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+#[cfg(not(unix))]
+fn monotonic_nanos() -> u64 {
+    unreachable!("monotonic timestamps are rejected on this platform")
+}
+
+/// Path of the file stored next to the poll log, like `NNN-poll-times.log` for `NNN-poll.log`.
+fn sidecar(dest: &Path, kind: &str) -> PathBuf {
+    let mut name = dest.file_stem().expect("no poll log name").to_owned();
+    name.push(format!("-{}.log", kind));
+    dest.with_file_name(name)
+}
+
+/// Read latency statistics stored into the `NNN-poll-stats.log` file.
+///
+/// The line per sample contains its time, the read time in microseconds and the late flag, set
+/// when the sample made the next one miss its deadline. The summary line starting with `#` is
+/// appended when the poller stops.
+struct Stats {
+    file: BufWriter<File>,
+    name: String,
+    samples: u64,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+    late: u64,
+}
+
+impl Stats {
+    fn create(dest: &Path) -> Self {
+        let file = File::create(sidecar(dest, "stats")).expect("cannot open file");
+        Self {
+            file: BufWriter::new(file),
+            name: dest
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            samples: 0,
+            total: Duration::ZERO,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            late: 0,
+        }
+    }
+
+    fn record(&mut self, stamp: &str, read: Duration, late: bool) {
+        self.samples += 1;
+        self.total += read;
+        self.min = self.min.min(read);
+        self.max = self.max.max(read);
+        self.late += late as u64;
+
+        writeln!(self.file, "{} {} {}", stamp, read.as_micros(), late as u8).expect("cannot write");
+    }
+
+    fn finish(mut self) {
+        let summary = match self.samples {
+            0 => "no samples".to_owned(),
+            n => format!(
+                "samples={} read_us min={} avg={} max={} late={}",
+                n,
+                self.min.as_micros(),
+                (self.total / n as u32).as_micros(),
+                self.max.as_micros(),
+                self.late
+            ),
+        };
+        info!("poller stats of '{}': {}", self.name, summary);
+        writeln!(self.file, "# {}", summary).expect("cannot write");
+        self.file.flush().expect("cannot flush");
+    }
+}
+
+/// Poll logs of the split sources: `NNN-poll.log` turns into `NNN-<source>-poll.log`, where the
+/// source name is sanitized to be the valid file name. The logs of the other naming templates get
+/// the source before the extension.
+fn split_dests(dest: &Path, names: &[String]) -> Vec<PathBuf> {
+    let name = dest
+        .file_name()
+        .expect("no poll log name")
+        .to_string_lossy();
+    let (prefix, suffix) = match name.strip_suffix("-poll.log") {
+        Some(prefix) => (prefix, "-poll.log"),
+        None => match name.rfind('.') {
+            Some(dot) => name.split_at(dot),
+            None => (name.as_ref(), ""),
+        },
+    };
+
+    let mut seen = HashSet::new();
+    names
+        .iter()
+        .map(|name| {
+            let base: String = name
+                .trim_start_matches(['/', '\\'])
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || "._-".contains(c) {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+
+            // different paths may give the same name, like `/a/b` and `/a_b`
+            let mut safe = base.clone();
+            for n in 2.. {
+                if seen.insert(safe.clone()) {
+                    break;
+                }
+                safe = format!("{}-{}", base, n);
+            }
+            dest.with_file_name(format!("{}-{}{}", prefix, safe, suffix))
+        })
+        .collect()
+}
+
+/// Sample the content produced by `collect` and store it into the poll log.
+fn poll_loop<F>(
+    names: Vec<String>,
+    dest: PathBuf,
+    stop: Arc<AtomicBool>,
+    mut cfg: PollConfig,
+    mut collect: F,
+) where
+    F: FnMut(&mut SampleBuf),
+{
+    let mut stats = cfg.stats.then(|| Stats::create(&dest));
+    let log_name = dest.to_string_lossy().into_owned();
+    let mut warmup = match &cfg.warmup {
+        Some(warmup) if warmup.log => {
+            let path = sidecar(&dest, "warmup");
+            Some(Stream::create(path, &names, 0..names.len(), &cfg))
+        }
+        _ => None,
+    };
+    let mut streams: Vec<Stream> = match cfg.split {
+        false => vec![Stream::create(dest, &names, 0..names.len(), &cfg)],
+        true => split_dests(&dest, &names)
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| Stream::create(path, &names, i..i + 1, &cfg))
+            .collect(),
+    };
+
+    let mut sample = SampleBuf::new();
+    if cfg.realtime {
+        set_realtime();
+    }
+    let mut ticker = Ticker::new(cfg.sleep_time);
+    let (started, mut taken) = (Instant::now(), 0u64);
 
     while !stop.load(Ordering::Acquire) {
+        if let Some(duration) = cfg.duration.filter(|&d| started.elapsed() >= d) {
+            info!("poll of '{}' is done after {:?}", log_name, duration);
+            break;
+        }
+        if cfg.paused.load(Ordering::Acquire) {
+            ticker.wait();
+            continue;
+        }
+
         // clear the previous content
-        outbuffer.clear();
+        sample.clear();
 
         // prepare the common timestamp
         let now = chrono::Local::now();
-        outbuffer.push_str(&now.to_rfc3339_opts(chrono::SecondsFormat::Micros, false));
-        outbuffer.push('\n');
+        let stamp = format_timestamp(cfg.timestamp, &now);
+        let read_start = Instant::now();
+        collect(&mut sample);
+        let read = read_start.elapsed();
+
+        let warming = cfg
+            .warmup
+            .as_ref()
+            .is_some_and(|w| taken < w.samples || started.elapsed() < w.time);
+        taken += 1;
+        if !warming {
+            if let Some(stream) = warmup.take() {
+                stream.finish(&cfg);
+            }
+        }
+
+        let mut stored = false;
+        match (warming, &mut warmup) {
+            (true, Some(stream)) => {
+                stream.store(&now, &stamp, ticker.seq, &sample, &cfg);
+            }
+            (true, None) => (),
+            (false, _) => {
+                for stream in &mut streams {
+                    stored |= stream.store(&now, &stamp, ticker.seq, &sample, &cfg);
+                }
+            }
+        }
+        if stored {
+            #[cfg(feature = "sqlite")]
+            if let Some(database) = &mut cfg.database {
+                database
+                    .store(now.timestamp_micros(), &names, &sample)
+                    .expect("cannot store the sample into the database");
+            }
+            #[cfg(feature = "parquet")]
+            if let Some(parquet) = &mut cfg.parquet {
+                parquet
+                    .store(now.timestamp_micros(), &names, &sample)
+                    .expect("cannot write the sample into the Parquet file");
+            }
+        }
+
+        ticker.period = cfg.next_period(ticker.period, &sample.text);
+        let late = ticker.wait();
+        if let Some(stats) = &mut stats {
+            stats.record(&stamp, read, late);
+        }
+    }
+
+    for stream in streams.into_iter().chain(warmup) {
+        stream.finish(&cfg);
+    }
+    if let Some(stats) = stats {
+        stats.finish();
+    }
+
+    #[cfg(feature = "parquet")]
+    if let Some(parquet) = cfg.parquet {
+        parquet.finish().expect("cannot finish the Parquet file");
+    }
+}
+
+/// Read the files concurrently, storing the read start offsets from the `start`.
+fn read_parallel(
+    srcs: &[PathBuf],
+    cap: Option<usize>,
+    encoding: Encoding,
+    threads: usize,
+    start: Instant,
+    bufs: &mut [String],
+    offsets: &mut [Duration],
+) {
+    let chunk = srcs.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let chunks = srcs
+            .chunks(chunk)
+            .zip(bufs.chunks_mut(chunk))
+            .zip(offsets.chunks_mut(chunk));
+        for ((srcs, bufs), offsets) in chunks {
+            scope.spawn(move || {
+                for ((src, buf), offset) in srcs.iter().zip(bufs).zip(offsets) {
+                    buf.clear();
+                    *offset = start.elapsed();
+                    read_source(src, cap, encoding, buf).expect("cannot open/read file");
+                }
+            });
+        }
+    });
+}
+
+/// Poll the files, reading them concurrently if configured or with io_uring if enabled.
+///
+/// The concurrent reads also store the per-file read times into the `NNN-poll-times.log` file
+/// next to the poll log: the line per sample with the tick start time followed by the read start
+/// offsets of the files in microseconds.
+pub fn poll_with_config(srcs: Vec<PathBuf>, dest: PathBuf, stop: Arc<AtomicBool>, cfg: PollConfig) {
+    let files = srcs
+        .iter()
+        .map(|p| p.to_str().unwrap().to_owned())
+        .collect();
+
+    let (cap, encoding, timestamp) = (cfg.max_bytes, cfg.encoding, cfg.timestamp);
+    if let Some(threads) = cfg.parallel {
+        let mut times = File::create(sidecar(&dest, "times")).expect("cannot open file");
+        let mut bufs = vec![String::with_capacity(FILE_CAP); srcs.len()];
+        let mut offsets = vec![Duration::ZERO; srcs.len()];
+        let mut line = String::with_capacity(TOTAL_CAP);
+
+        return poll_loop(files, dest, stop, cfg, |out| {
+            let now = chrono::Local::now();
+            read_parallel(
+                &srcs,
+                cap,
+                encoding,
+                threads,
+                Instant::now(),
+                &mut bufs,
+                &mut offsets,
+            );
+            for buf in &bufs {
+                out.text.push_str(buf);
+                out.end_source();
+            }
+
+            line.clear();
+            line.push_str(&format_timestamp(timestamp, &now));
+            for offset in &offsets {
+                line.push_str(&format!(" {}", offset.as_micros()));
+            }
+            line.push('\n');
+            times.write_all(line.as_bytes()).expect("cannot write");
+        });
+    }
 
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    match uring::Batch::new(&srcs, cap, encoding) {
+        Ok(mut batch) => {
+            return poll_loop(files, dest, stop, cfg, |out| {
+                batch.read_all(out).expect("cannot open/read file")
+            });
+        }
+        Err(e) => warn!("io_uring is not available, using the regular reads - {}", e),
+    }
+
+    let mut strbuffer = String::with_capacity(FILE_CAP);
+    poll_loop(files, dest, stop, cfg, |out| {
         // read the files
         for src in &srcs {
-            // read the file content
             strbuffer.clear();
-            File::open(src)
-                .and_then(|mut f| f.read_to_string(&mut strbuffer))
-                .expect("cannot open/read file");
+            read_source(src, cap, encoding, &mut strbuffer).expect("cannot open/read file");
+            out.text.push_str(&strbuffer);
+            out.end_source();
+        }
+    })
+}
 
-            outbuffer.push_str(&strbuffer);
+pub fn poll_sources(srcs: Sources, dest: PathBuf, stop: Arc<AtomicBool>, cfg: PollConfig) {
+    match srcs {
+        Sources::Files(paths) => poll_with_config(paths, dest, stop, cfg),
+        #[cfg(windows)]
+        Sources::Counters(paths) => pdh::poll_counters(paths, dest, stop, cfg),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Perf(counters) => {
+            let names = counters.iter().map(|c| c.name().to_owned()).collect();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for counter in &counters {
+                    let value = counter.read().expect("cannot read perf counter");
+                    out.text
+                        .push_str(&format!("{} {}\n", counter.name(), value));
+                    out.end_source();
+                }
+            })
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Vmstat(mut rates) => {
+            let names = rates.names().to_vec();
+            poll_loop(names.clone(), dest, stop, cfg, |out| {
+                let values = rates.read().expect("cannot read vmstat");
+                for (name, rate) in names.iter().zip(values) {
+                    out.text.push_str(&format!("{} {:.3}\n", name, rate));
+                    out.end_source();
+                }
+            })
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Thp(mut activity) => {
+            let names = activity.names();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for line in activity.read().expect("cannot read THP sources") {
+                    out.text.push_str(&line);
+                    out.text.push('\n');
+                    out.end_source();
+                }
+            })
         }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Schedstat(mut delays) => {
+            let names = delays.names();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for line in delays.read().expect("cannot read schedstat") {
+                    out.text.push_str(&line);
+                    out.text.push('\n');
+                    out.end_source();
+                }
+            })
+        }
+        #[cfg(unix)]
+        Sources::Statvfs(mounts) => {
+            let names = mounts.names();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for line in mounts.read().expect("cannot statvfs") {
+                    out.text.push_str(&line);
+                    out.text.push('\n');
+                    out.end_source();
+                }
+            })
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Power(mut sensors) => {
+            let names = sensors.names();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for line in sensors.read().expect("cannot read power sensors") {
+                    out.text.push_str(&line);
+                    out.text.push('\n');
+                    out.end_source();
+                }
+            })
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Sources::Tree(name, tree) => poll_loop(vec![name], dest, stop, cfg, |out| {
+            tree.read(&mut out.text).expect("cannot read process tree");
+            out.end_source();
+        }),
+        Sources::Statsd(name, mut listener) => poll_loop(vec![name], dest, stop, cfg, |out| {
+            listener
+                .read(&mut out.text)
+                .expect("cannot receive statsd metrics");
+            out.end_source();
+        }),
+        Sources::Meter(mut meter) => {
+            let names = meter.names();
+            poll_loop(names, dest, stop, cfg, |out| {
+                for line in meter.read() {
+                    out.text.push_str(&line);
+                    out.text.push('\n');
+                    out.end_source();
+                }
+            })
+        }
+    }
+}
 
-        // add the final delimiter and flush the output
-        outbuffer.push('\n');
-        output
-            .write_all(outbuffer.as_bytes())
-            .expect("cannot write");
+#[cfg(test)]
+pub fn poll(srcs: Vec<PathBuf>, dest: PathBuf, stop: Arc<AtomicBool>) {
+    let cfg = PollConfig::try_from(&PollOptions::default()).unwrap();
+    poll_with_config(srcs, dest, stop, cfg)
+}
 
-        std::thread::sleep(cfg.sleep_time);
-    }
+#[test]
+fn split_poll_log_names() {
+    let names = ["/proc/1/stat", "/proc/1_stat", "all.cpu-clock"].map(String::from);
+    let dests = split_dests(Path::new("out/003-poll.log"), &names);
+    assert_eq!(
+        dests,
+        [
+            "out/003-proc_1_stat-poll.log",
+            "out/003-proc_1_stat-2-poll.log",
+            "out/003-all.cpu-clock-poll.log",
+        ]
+        .map(PathBuf::from)
+    );
+    let dests = split_dests(Path::new("out/poll-003-cpu.log"), &names[2..]);
+    assert_eq!(dests, [PathBuf::from("out/poll-003-cpu-all.cpu-clock.log")]);
+}
 
-    output.flush().expect("cannot flush");
+#[test]
+fn base64_content() {
+    let mut buf = String::new();
+    push_content(&mut buf, b"\xffbin\x00", Some(4), Encoding::Base64);
+    assert_eq!(buf, format!("/2Jpbg==\n{}\n", TRUNCATED_MARKER));
 }
 
-pub fn poll(srcs: Vec<PathBuf>, dest: PathBuf, stop: Arc<AtomicBool>) {
-    poll_with_config(
-        srcs,
-        dest,
-        stop,
-        PollConfig {
-            sleep_time: DEFAULT_SLEEP_TIME,
-        },
-    )
+#[test]
+fn source_file_metadata() {
+    let paths = ["/proc/self/stat", "/proc/sys/kernel/ostype", "/nonexistent"].map(PathBuf::from);
+    let files = describe_files(&paths, "{/proc/{self/stat,sys/kernel/os*},/nonexistent}");
+    assert_eq!(files[0].path, format!("/proc/{}/stat", std::process::id()));
+    assert_eq!(files[0].branch.as_deref(), Some("/proc/self/stat"));
+    assert_eq!(files[1].branch.as_deref(), Some("/proc/sys/kernel/os*"));
+    assert_eq!(files[1].mode.as_deref(), Some("0444"));
+    assert!(files[1].ino.is_some());
+    assert_eq!(files[2].path, "/nonexistent");
+    assert_eq!(files[2].size, None);
 }
 
 #[test]
@@ -0,0 +1,173 @@
+//! Compact binary poll log format.
+//!
+//! The log starts with the same JSON [`PollHeader`](super::PollHeader) line as the text one, with
+//! the `binary` flag set. The samples follow as frames, one per source, all the integers are
+//! little-endian:
+//!
+//! ```text
+//! u32 payload length | i64 timestamp, us since epoch | u64 sequence number | u16 source id | payload
+//! ```
+//!
+//! The sequence number is missing in the logs of the format version 1. The source ids are the
+//! positions of the sources in the header, so every sample starts with the frame of the source 0.
+//! The poller stopped properly appends the index footer: the entries of the sample timestamps with
+//! their file offsets, recorded not more often than [`INDEX_INTERVAL`], then the offset of the
+//! first entry and the [`INDEX_MAGIC`].
+
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::time::Duration;
+
+pub const INDEX_MAGIC: &[u8; 8] = b"PMPPTIDX";
+
+/// Minimal time between the indexed samples, keeping the index small for the fast pollers.
+pub const INDEX_INTERVAL: Duration = Duration::from_secs(1);
+
+const FRAME_HEADER_LEN: usize = 4 + 8 + 8 + 2;
+const FRAME_HEADER_LEN_V1: usize = 4 + 8 + 2;
+const INDEX_ENTRY_LEN: u64 = 8 + 8;
+const TRAILER_LEN: u64 = 8 + INDEX_MAGIC.len() as u64;
+
+pub struct Frame {
+    pub timestamp: i64,
+    pub seq: Option<u64>,
+    pub source: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Sample timestamp with the file offset of its first frame.
+#[derive(Clone, Copy)]
+pub struct IndexEntry {
+    pub timestamp: i64,
+    pub offset: u64,
+}
+
+pub fn encode_frame(out: &mut Vec<u8>, timestamp: i64, seq: u64, source: u16, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&timestamp.to_le_bytes());
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(&source.to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Encode the index footer, `start` is the file offset the footer is written at.
+pub fn encode_index(index: &[IndexEntry], start: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(index.len() * INDEX_ENTRY_LEN as usize + TRAILER_LEN as usize);
+    for entry in index {
+        out.extend_from_slice(&entry.timestamp.to_le_bytes());
+        out.extend_from_slice(&entry.offset.to_le_bytes());
+    }
+    out.extend_from_slice(&start.to_le_bytes());
+    out.extend_from_slice(INDEX_MAGIC);
+    out
+}
+
+/// Read the next frame of the log with the given format version, `None` at the end of the frames.
+///
+/// The incomplete frame at the end is treated as the end too, as the poller might be killed in
+/// the middle of writing.
+pub fn read_frame(input: &mut impl Read, version: u32) -> Result<Option<Frame>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let header = match version {
+        1 => &mut header[..FRAME_HEADER_LEN_V1],
+        _ => &mut header[..],
+    };
+    if !read_full(input, header)? {
+        return Ok(None);
+    }
+
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let timestamp = i64::from_le_bytes(header[4..12].try_into().unwrap());
+    let (seq, rest) = match version {
+        1 => (None, &header[12..]),
+        _ => (
+            Some(u64::from_le_bytes(header[12..20].try_into().unwrap())),
+            &header[20..],
+        ),
+    };
+    let source = u16::from_le_bytes(rest.try_into().unwrap());
+
+    let mut payload = vec![0; len as usize];
+    if !read_full(input, &mut payload)? {
+        return Ok(None);
+    }
+
+    Ok(Some(Frame {
+        timestamp,
+        seq,
+        source,
+        payload,
+    }))
+}
+
+/// Fill the buffer completely, false if the input ends before.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..])? {
+            0 => return Ok(false),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+/// Load the index footer, returning the end offset of the frames with the index entries.
+///
+/// The logs of the pollers not stopped properly have no index, `None` is returned then.
+pub fn read_index(file: &mut File, frames_start: u64) -> Result<Option<(u64, Vec<IndexEntry>)>> {
+    let len = file.metadata()?.len();
+    if len < frames_start + TRAILER_LEN {
+        return Ok(None);
+    }
+
+    let mut trailer = [0u8; TRAILER_LEN as usize];
+    file.seek(SeekFrom::Start(len - TRAILER_LEN))?;
+    file.read_exact(&mut trailer)?;
+    let start = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+    let end = len - TRAILER_LEN;
+    if &trailer[8..] != INDEX_MAGIC || !(frames_start..=end).contains(&start) {
+        return Ok(None);
+    }
+    let size = end - start;
+    if !size.is_multiple_of(INDEX_ENTRY_LEN) {
+        return Ok(None);
+    }
+
+    let mut raw = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut raw)?;
+    let index = raw
+        .chunks_exact(INDEX_ENTRY_LEN as usize)
+        .map(|entry| IndexEntry {
+            timestamp: i64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+        })
+        .collect();
+
+    Ok(Some((start, index)))
+}
+
+#[test]
+fn frames_roundtrip() {
+    let mut data = Vec::new();
+    encode_frame(&mut data, -5, 7, 0, b"first");
+    encode_frame(&mut data, 42, 9, 1, b"");
+    encode_frame(&mut data, 43, 10, 0, b"cut");
+
+    let mut input = &data[..data.len() - 1];
+    let frame = read_frame(&mut input, 2).unwrap().unwrap();
+    assert_eq!((frame.timestamp, frame.seq, frame.source), (-5, Some(7), 0));
+    assert_eq!(frame.payload, b"first");
+    let frame = read_frame(&mut input, 2).unwrap().unwrap();
+    assert_eq!(
+        (
+            frame.timestamp,
+            frame.seq,
+            frame.source,
+            frame.payload.len()
+        ),
+        (42, Some(9), 1, 0)
+    );
+    assert!(read_frame(&mut input, 2).unwrap().is_none());
+}
@@ -0,0 +1,201 @@
+//! BMC sensors poller, for the chassis data invisible to the host OS.
+//!
+//! The pattern is `bmc:ipmi` for the threshold sensors of the local BMC read by `ipmitool sdr`,
+//! or `bmc:redfish:URL` for the `Thermal` and `Power` resources of the Redfish chassis like
+//! `https://bmc/redfish/v1/Chassis/1`, fetched like the power meter ones (see [`meter`]). The
+//! sample contains the line per numeric sensor found at the start, named by its source, name and
+//! unit like `ipmi.FAN1.rpm`, `redfish.temp.Inlet_Temp.celsius` or `redfish.psu.PSU1.input_watts`.
+//! The discrete and absent sensors are skipped, the failed reading gives the sample without values.
+
+use subprocess::Exec;
+
+use super::meter::{self, Meter};
+
+pub const PREFIX: &str = "bmc:";
+
+/// Sensor name usable in the `name value` lines.
+fn sanitize(name: &str) -> String {
+    let name = name
+        .trim()
+        .replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+    match name.is_empty() {
+        false => name,
+        true => "unnamed".to_owned(),
+    }
+}
+
+fn unit(unit: &str) -> String {
+    match unit.trim() {
+        "RPM" => "rpm".to_owned(),
+        "degrees C" | "Cel" => "celsius".to_owned(),
+        "Volts" | "V" => "volts".to_owned(),
+        "Watts" | "W" => "watts".to_owned(),
+        "Amps" | "A" => "amps".to_owned(),
+        "percent" | "Percent" | "%" => "percent".to_owned(),
+        unit => sanitize(&unit.to_lowercase()),
+    }
+}
+
+/// Numeric sensors of the `ipmitool -c sdr list full` CSV output.
+fn parse_sdr(output: &str) -> Vec<(String, f64)> {
+    output
+        .lines()
+        .filter_map(|line| match line.split(',').collect::<Vec<_>>()[..] {
+            [name, value, units, ..] if units != "discrete" => {
+                let value = value.trim().parse().ok()?;
+                Some((format!("ipmi.{}.{}", sanitize(name), unit(units)), value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Readings of the array members in the Redfish resource.
+fn members<F>(json: &serde_json::Value, array: &str, mut reading: F) -> Vec<(String, f64)>
+where
+    F: FnMut(&str, &serde_json::Value) -> Vec<(String, f64)>,
+{
+    let Some(members) = json[array].as_array() else {
+        return Vec::new();
+    };
+    members
+        .iter()
+        .enumerate()
+        .flat_map(|(i, member)| {
+            let name = member["Name"]
+                .as_str()
+                .or(member["MemberId"].as_str())
+                .map_or(i.to_string(), sanitize);
+            reading(&name, member)
+        })
+        .collect()
+}
+
+/// Fans and temperatures of the Redfish `Thermal` resource.
+fn parse_thermal(json: &serde_json::Value) -> Vec<(String, f64)> {
+    let mut readings = members(json, "Fans", |name, fan| {
+        let units = fan["ReadingUnits"].as_str().unwrap_or("RPM");
+        fan["Reading"]
+            .as_f64()
+            .map(|value| (format!("redfish.fan.{}.{}", name, unit(units)), value))
+            .into_iter()
+            .collect()
+    });
+    readings.extend(members(json, "Temperatures", |name, temp| {
+        temp["ReadingCelsius"]
+            .as_f64()
+            .map(|value| (format!("redfish.temp.{}.celsius", name), value))
+            .into_iter()
+            .collect()
+    }));
+    readings
+}
+
+/// Power supplies, voltages and consumed power of the Redfish `Power` resource.
+fn parse_power(json: &serde_json::Value) -> Vec<(String, f64)> {
+    let mut readings = members(json, "PowerSupplies", |name, psu| {
+        [
+            ("PowerInputWatts", "input_watts"),
+            ("PowerOutputWatts", "output_watts"),
+            ("LineInputVoltage", "line_volts"),
+        ]
+        .into_iter()
+        .filter_map(|(field, suffix)| {
+            let value = psu[field].as_f64()?;
+            Some((format!("redfish.psu.{}.{}", name, suffix), value))
+        })
+        .collect()
+    });
+    readings.extend(members(json, "Voltages", |name, voltage| {
+        voltage["ReadingVolts"]
+            .as_f64()
+            .map(|value| (format!("redfish.voltage.{}.volts", name), value))
+            .into_iter()
+            .collect()
+    }));
+    readings.extend(members(json, "PowerControl", |name, control| {
+        control["PowerConsumedWatts"]
+            .as_f64()
+            .map(|value| (format!("redfish.consumed.{}.watts", name), value))
+            .into_iter()
+            .collect()
+    }));
+    readings
+}
+
+fn ipmi_sensors() -> Result<Vec<(String, f64)>, String> {
+    let output = meter::run(Exec::cmd("ipmitool").args(&["-c", "sdr", "list", "full"]))?;
+    match parse_sdr(&output) {
+        readings if readings.is_empty() => Err("no numeric sensors in the SDR output".into()),
+        readings => Ok(readings),
+    }
+}
+
+/// Sensors of the chassis, either of the resources may be missing on the BMC.
+fn redfish_sensors(chassis: &str) -> Result<Vec<(String, f64)>, String> {
+    let mut readings = Vec::new();
+    let mut errors = Vec::new();
+    for resource in ["Thermal", "Power"] {
+        let url = format!("{}/{}", chassis.trim_end_matches('/'), resource);
+        let json = meter::fetch(&url).and_then(|body| {
+            serde_json::from_str(&body).map_err(|e| format!("bad Redfish response - {}", e))
+        });
+        match json {
+            Ok(json) if resource == "Thermal" => readings.extend(parse_thermal(&json)),
+            Ok(json) => readings.extend(parse_power(&json)),
+            Err(e) => errors.push(format!("{} - {}", url, e)),
+        }
+    }
+    match readings.is_empty() {
+        false => Ok(readings),
+        true if errors.is_empty() => Err("no sensors in the Redfish chassis".into()),
+        true => Err(errors.join(", ")),
+    }
+}
+
+/// Open the sensors of the `bmc:` pattern, reading them once.
+pub fn open(pattern: &str) -> Result<Meter, String> {
+    match pattern.strip_prefix(PREFIX) {
+        Some("ipmi") => Meter::new("BMC sensors", ipmi_sensors),
+        Some(spec) => match spec.split_once(':') {
+            Some(("redfish", url)) if url.starts_with("http") => {
+                let chassis = url.to_owned();
+                Meter::new("BMC sensors", move || redfish_sensors(&chassis))
+            }
+            _ => Err(format!("unsupported BMC sensors '{}'", pattern)),
+        },
+        None => Err(format!("not a BMC pattern '{}'", pattern)),
+    }
+}
+
+#[test]
+fn bmc_sensors() {
+    let sdr = "FAN1,3600,RPM,ok\nInlet Temp,24,degrees C,ok\nPS1 Status,0x01,discrete,ok\n\
+               FAN2,na,RPM,ns\n";
+    assert_eq!(
+        parse_sdr(sdr),
+        [
+            ("ipmi.FAN1.rpm".to_owned(), 3600.0),
+            ("ipmi.Inlet_Temp.celsius".to_owned(), 24.0)
+        ]
+    );
+
+    let thermal = serde_json::json!({
+        "Fans": [{"Name": "Fan 1", "Reading": 40, "ReadingUnits": "Percent"}, {"Name": "Fan 2"}],
+        "Temperatures": [{"Name": "Inlet Temp", "ReadingCelsius": 23.5}],
+    });
+    assert_eq!(
+        parse_thermal(&thermal),
+        [
+            ("redfish.fan.Fan_1.percent".to_owned(), 40.0),
+            ("redfish.temp.Inlet_Temp.celsius".to_owned(), 23.5)
+        ]
+    );
+    let power = serde_json::json!({
+        "PowerSupplies": [{"MemberId": "0", "PowerInputWatts": 210, "PowerOutputWatts": 190}],
+    });
+    assert_eq!(
+        parse_power(&power)[1],
+        ("redfish.psu.0.output_watts".to_owned(), 190.0)
+    );
+}
@@ -0,0 +1,111 @@
+//! FreeBSD replacements of the procfs files, synthesized from the sysctl statistics.
+//!
+//! The content mimics the Linux format closely enough for the scenarios and the post-processing
+//! written for Linux targets. Only memory and CPU statistics are emulated.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const MEMINFO: &str = "/proc/meminfo";
+const STAT: &str = "/proc/stat";
+
+/// Per-CPU states in `kern.cp_times`.
+const CPUSTATES: usize = 5;
+const CP_USER: usize = 0;
+const CP_NICE: usize = 1;
+const CP_SYS: usize = 2;
+const CP_INTR: usize = 3;
+const CP_IDLE: usize = 4;
+
+pub fn is_emulated(path: &Path) -> bool {
+    path == Path::new(MEMINFO) || path == Path::new(STAT)
+}
+
+pub fn read(path: &Path, buf: &mut String) -> Result<usize> {
+    let start = buf.len();
+    match path.to_str() {
+        Some(MEMINFO) => read_meminfo(buf)?,
+        Some(STAT) => read_stat(buf)?,
+        _ => return Err(Error::new(ErrorKind::NotFound, "not an emulated file")),
+    }
+    Ok(buf.len() - start)
+}
+
+/// Read the sysctl value into the buffer, returning the number of bytes read.
+fn sysctl_raw(name: &CStr, value: *mut libc::c_void, size: usize) -> Result<usize> {
+    let mut size = size;
+    // SAFETY: the name is NUL-terminated, the output buffer has the declared size
+    let res = unsafe { libc::sysctlbyname(name.as_ptr(), value, &mut size, std::ptr::null(), 0) };
+    match res {
+        0 => Ok(size),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+fn sysctl_u32(name: &CStr) -> Result<u32> {
+    let mut value: u32 = 0;
+    sysctl_raw(
+        name,
+        &mut value as *mut u32 as *mut _,
+        std::mem::size_of::<u32>(),
+    )?;
+    Ok(value)
+}
+
+fn read_meminfo(buf: &mut String) -> Result<()> {
+    let page_kb = sysctl_u32(c"hw.pagesize")? as u64 / 1024;
+    let kb = |name: &CStr| sysctl_u32(name).map(|pages| pages as u64 * page_kb);
+
+    let free = kb(c"vm.stats.vm.v_free_count")?;
+    let inactive = kb(c"vm.stats.vm.v_inactive_count")?;
+    let laundry = kb(c"vm.stats.vm.v_laundry_count")?;
+    let lines = [
+        ("MemTotal", kb(c"vm.stats.vm.v_page_count")?),
+        ("MemFree", free),
+        ("MemAvailable", free + inactive),
+        ("Active", kb(c"vm.stats.vm.v_active_count")?),
+        ("Inactive", inactive),
+        ("Laundry", laundry),
+        ("Wired", kb(c"vm.stats.vm.v_wire_count")?),
+    ];
+    for (name, value) in lines {
+        let _ = writeln!(buf, "{:<16}{:>8} kB", format!("{}:", name), value);
+    }
+    Ok(())
+}
+
+fn read_stat(buf: &mut String) -> Result<()> {
+    // query the size first, it depends on the number of CPUs
+    let size = sysctl_raw(c"kern.cp_times", std::ptr::null_mut(), 0)?;
+    let mut ticks: Vec<libc::c_long> = vec![0; size / std::mem::size_of::<libc::c_long>()];
+    let size = sysctl_raw(c"kern.cp_times", ticks.as_mut_ptr() as *mut _, size)?;
+    ticks.truncate(size / std::mem::size_of::<libc::c_long>());
+
+    // Linux order is "user nice system idle iowait irq softirq steal guest guest_nice"
+    let mut line = |name: &str, cpu: &[u64]| {
+        let _ = writeln!(
+            buf,
+            "{} {} {} {} {} 0 {} 0 0 0 0",
+            name, cpu[CP_USER], cpu[CP_NICE], cpu[CP_SYS], cpu[CP_IDLE], cpu[CP_INTR]
+        );
+    };
+
+    let per_cpu: Vec<Vec<u64>> = ticks
+        .chunks_exact(CPUSTATES)
+        .map(|cpu| cpu.iter().map(|&t| t as u64).collect())
+        .collect();
+    let mut total = [0u64; CPUSTATES];
+    for cpu in &per_cpu {
+        for (t, v) in total.iter_mut().zip(cpu) {
+            *t += v;
+        }
+    }
+
+    line("cpu ", &total);
+    for (i, cpu) in per_cpu.iter().enumerate() {
+        line(&format!("cpu{}", i), cpu);
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! macOS replacements of the procfs files, synthesized from the Mach host statistics.
+//!
+//! The content mimics the Linux format closely enough for the scenarios and the post-processing
+//! written for Linux targets. Only memory and CPU statistics are emulated, the disk statistics are
+//! available through IOKit only and are not supported yet.
+
+// libc deprecates its Mach bindings in favor of the mach2 crate, not worth it for a few calls
+#![allow(deprecated)]
+
+use std::fmt::Write;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const MEMINFO: &str = "/proc/meminfo";
+const STAT: &str = "/proc/stat";
+
+pub fn is_emulated(path: &Path) -> bool {
+    path == Path::new(MEMINFO) || path == Path::new(STAT)
+}
+
+pub fn read(path: &Path, buf: &mut String) -> Result<usize> {
+    let start = buf.len();
+    match path.to_str() {
+        Some(MEMINFO) => read_meminfo(buf)?,
+        Some(STAT) => read_stat(buf)?,
+        _ => return Err(Error::new(ErrorKind::NotFound, "not an emulated file")),
+    }
+    Ok(buf.len() - start)
+}
+
+fn kern_error(what: &str, res: libc::kern_return_t) -> Error {
+    Error::other(format!("{} failed: {}", what, res))
+}
+
+fn memsize() -> Result<u64> {
+    let mut value: u64 = 0;
+    let mut size = std::mem::size_of_val(&value);
+    // SAFETY: the name is NUL-terminated, the output buffer has the declared size
+    let res = unsafe {
+        libc::sysctlbyname(
+            c"hw.memsize".as_ptr(),
+            &mut value as *mut u64 as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    match res {
+        0 => Ok(value),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+fn read_meminfo(buf: &mut String) -> Result<()> {
+    let mut vm: libc::vm_statistics64 = unsafe { std::mem::zeroed() };
+    let mut count = libc::HOST_VM_INFO64_COUNT;
+    // SAFETY: the output structure matches the flavor and the count
+    let res = unsafe {
+        libc::host_statistics64(
+            libc::mach_host_self(),
+            libc::HOST_VM_INFO64,
+            &mut vm as *mut libc::vm_statistics64 as libc::host_info64_t,
+            &mut count,
+        )
+    };
+    if res != libc::KERN_SUCCESS {
+        return Err(kern_error("host_statistics64", res));
+    }
+
+    // SAFETY: sysconf is always safe to call
+    let page_kb = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64 / 1024;
+    let kb = |pages: libc::natural_t| pages as u64 * page_kb;
+
+    let free = vm.free_count - vm.speculative_count;
+    let available = vm.free_count + vm.inactive_count + vm.purgeable_count;
+    let lines = [
+        ("MemTotal", memsize()? / 1024),
+        ("MemFree", kb(free)),
+        ("MemAvailable", kb(available)),
+        ("Active", kb(vm.active_count)),
+        ("Inactive", kb(vm.inactive_count)),
+        ("Wired", kb(vm.wire_count)),
+        ("Speculative", kb(vm.speculative_count)),
+        ("Purgeable", kb(vm.purgeable_count)),
+        ("Compressed", kb(vm.compressor_page_count)),
+    ];
+    for (name, value) in lines {
+        let _ = writeln!(buf, "{:<16}{:>8} kB", format!("{}:", name), value);
+    }
+    Ok(())
+}
+
+fn read_stat(buf: &mut String) -> Result<()> {
+    let mut ncpu: libc::natural_t = 0;
+    let mut info: libc::processor_info_array_t = std::ptr::null_mut();
+    let mut count: libc::mach_msg_type_number_t = 0;
+    // SAFETY: the kernel allocates the array, it is released below
+    let res = unsafe {
+        libc::host_processor_info(
+            libc::mach_host_self(),
+            libc::PROCESSOR_CPU_LOAD_INFO,
+            &mut ncpu,
+            &mut info,
+            &mut count,
+        )
+    };
+    if res != libc::KERN_SUCCESS {
+        return Err(kern_error("host_processor_info", res));
+    }
+
+    // SAFETY: the array holds CPU_STATE_MAX ticks counters per CPU
+    let ticks = unsafe { std::slice::from_raw_parts(info as *const u32, count as usize) };
+    let per_cpu: Vec<&[u32]> = ticks
+        .chunks(libc::CPU_STATE_MAX as usize)
+        .take(ncpu as usize)
+        .collect();
+
+    // Linux order is "user nice system idle iowait irq softirq steal guest guest_nice"
+    let mut line = |name: &str, ticks: [u64; 4]| {
+        let [user, system, idle, nice] = ticks;
+        let _ = writeln!(
+            buf,
+            "{} {} {} {} {} 0 0 0 0 0 0",
+            name, user, nice, system, idle
+        );
+    };
+    let state = |cpu: &[u32], i: libc::c_int| cpu[i as usize] as u64;
+    let states = |cpu: &[u32]| {
+        [
+            state(cpu, libc::CPU_STATE_USER),
+            state(cpu, libc::CPU_STATE_SYSTEM),
+            state(cpu, libc::CPU_STATE_IDLE),
+            state(cpu, libc::CPU_STATE_NICE),
+        ]
+    };
+
+    let mut total = [0u64; 4];
+    for cpu in &per_cpu {
+        for (t, v) in total.iter_mut().zip(states(cpu)) {
+            *t += v;
+        }
+    }
+    line("cpu ", total);
+    for (i, cpu) in per_cpu.iter().enumerate() {
+        line(&format!("cpu{}", i), states(cpu));
+    }
+
+    // SAFETY: releasing the array allocated by host_processor_info
+    unsafe {
+        libc::vm_deallocate(
+            libc::mach_task_self(),
+            info as libc::vm_address_t,
+            count as usize * std::mem::size_of::<libc::integer_t>(),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! External power meter poller, for the energy measured outside of the host.
+//!
+//! The pattern is `meter:ipmi` for the DCMI power reading of the local BMC by `ipmitool`,
+//! `meter:redfish:URL` for the Redfish power resource of the BMC fetched by `curl`, or
+//! `meter:cmd:COMMAND` for the shell command printing the `name value` lines or the single value,
+//! e.g. of the lab PDU. The credentials are given in the URL or in `~/.netrc`. The meter is read
+//! once at the start to find its readings, the sample contains the line per reading with the power
+//! in watts, named like `ipmi.power` or `redfish.0.power`.
+//!
+//! The meters are slow and sometimes unreachable for a while, so the failed reading gives the
+//! sample without the values instead of stopping the poller.
+
+use log::{info, warn};
+use subprocess::{Exec, Redirection};
+
+pub const PREFIX: &str = "meter:";
+
+/// Limit of the Redfish request, the hung BMC must not hold the poller forever.
+const TIMEOUT_S: &str = "10";
+
+enum Kind {
+    Ipmi,
+    Redfish(String),
+    Command(String),
+}
+
+/// Run the tool, returning its output.
+pub fn run(exec: Exec) -> Result<String, String> {
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(capture.stdout_str()),
+        false => Err(format!(
+            "'{}' failed: {:?} {}",
+            name,
+            capture.exit_status,
+            capture.stdout_str().trim()
+        )),
+    }
+}
+
+/// Instantaneous power of the `ipmitool dcmi power reading` output.
+fn parse_ipmi(output: &str) -> Result<Vec<(String, f64)>, String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Instantaneous power reading:"))
+        .and_then(|value| value.split_whitespace().next()?.parse().ok())
+        .map(|watts| vec![("ipmi.power".to_owned(), watts)])
+        .ok_or_else(|| "no instantaneous power reading in the DCMI output".to_owned())
+}
+
+/// Consumed power of the Redfish `Power` resource, or the reading of the `EnvironmentMetrics` one.
+fn parse_redfish(output: &str) -> Result<Vec<(String, f64)>, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(output).map_err(|e| format!("bad Redfish response - {}", e))?;
+    let readings: Vec<(String, f64)> = match json["PowerControl"].as_array() {
+        Some(controls) => controls
+            .iter()
+            .enumerate()
+            .filter_map(|(i, control)| {
+                let id = control["MemberId"]
+                    .as_str()
+                    .map_or(i.to_string(), str::to_owned);
+                let watts = control["PowerConsumedWatts"].as_f64()?;
+                Some((format!("redfish.{}.power", id), watts))
+            })
+            .collect(),
+        None => json["PowerWatts"]["Reading"]
+            .as_f64()
+            .map(|watts| ("redfish.power".to_owned(), watts))
+            .into_iter()
+            .collect(),
+    };
+    match readings.is_empty() {
+        false => Ok(readings),
+        true => Err("no power readings in the Redfish response".into()),
+    }
+}
+
+/// The `name value` lines of the command output, the single value is named `power`.
+fn parse_command(output: &str) -> Result<Vec<(String, f64)>, String> {
+    let bad = |line: &str| format!("bad meter output line '{}'", line);
+    let lines: Vec<&str> = output.lines().filter(|l| !l.trim().is_empty()).collect();
+    if let [line] = lines[..] {
+        if let Ok(watts) = line.trim().parse() {
+            return Ok(vec![("power".to_owned(), watts)]);
+        }
+    }
+    let readings = lines
+        .into_iter()
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, value] => Ok((name.to_owned(), value.parse().map_err(|_| bad(line))?)),
+                _ => Err(bad(line)),
+            },
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+    match readings.is_empty() {
+        false => Ok(readings),
+        true => Err("no readings in the meter output".into()),
+    }
+}
+
+/// Fetch the JSON resource of the BMC, the credentials are in the URL or in `~/.netrc`.
+pub fn fetch(url: &str) -> Result<String, String> {
+    run(Exec::cmd("curl")
+        .args(&["--fail", "--silent", "--show-error", "--netrc-optional"])
+        .args(&["--max-time", TIMEOUT_S])
+        .arg(url))
+}
+
+impl Kind {
+    fn measure(&self) -> Result<Vec<(String, f64)>, String> {
+        match self {
+            Kind::Ipmi => parse_ipmi(&run(
+                Exec::cmd("ipmitool").args(&["dcmi", "power", "reading"])
+            )?),
+            Kind::Redfish(url) => parse_redfish(&fetch(url)?),
+            Kind::Command(command) => parse_command(&run(Exec::shell(command))?),
+        }
+    }
+}
+
+type Measure = Box<dyn FnMut() -> Result<Vec<(String, f64)>, String> + Send>;
+
+/// Readings of the external device, named by the first measurement.
+pub struct Meter {
+    what: &'static str,
+    measure: Measure,
+    names: Vec<String>,
+    failing: bool,
+}
+
+impl Meter {
+    /// Take the first measurement to find the readings, it must succeed.
+    pub fn new<F>(what: &'static str, mut measure: F) -> Result<Self, String>
+    where
+        F: FnMut() -> Result<Vec<(String, f64)>, String> + Send + 'static,
+    {
+        let names = measure()?.into_iter().map(|(name, _)| name).collect();
+        Ok(Self {
+            what,
+            measure: Box::new(measure),
+            names,
+            failing: false,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+
+    /// The `name value` lines of the readings in the order of the names, empty if it failed.
+    pub fn read(&mut self) -> Vec<String> {
+        let readings = match (self.measure)() {
+            Ok(readings) => {
+                if std::mem::replace(&mut self.failing, false) {
+                    info!("{} is read again", self.what);
+                }
+                readings
+            }
+            Err(e) => {
+                // warn once per the failure streak not to flood the log
+                if !std::mem::replace(&mut self.failing, true) {
+                    warn!("cannot read the {} - {}", self.what, e);
+                }
+                Vec::new()
+            }
+        };
+        self.names
+            .iter()
+            .map(|name| match readings.iter().find(|(n, _)| n == name) {
+                Some((_, value)) => format!("{} {:.3}", name, value),
+                None => String::new(),
+            })
+            .collect()
+    }
+}
+
+/// Open the meter of the `meter:` pattern, reading it once.
+pub fn open(pattern: &str) -> Result<Meter, String> {
+    let kind = match pattern.strip_prefix(PREFIX) {
+        Some("ipmi") => Kind::Ipmi,
+        Some(spec) => match spec.split_once(':') {
+            Some(("redfish", url)) if url.starts_with("http") => Kind::Redfish(url.to_owned()),
+            Some(("cmd", command)) if !command.is_empty() => Kind::Command(command.to_owned()),
+            _ => return Err(format!("unsupported power meter '{}'", pattern)),
+        },
+        None => return Err(format!("not a meter pattern '{}'", pattern)),
+    };
+    Meter::new("power meter", move || kind.measure())
+}
+
+#[test]
+fn meter_readings() {
+    let dcmi = "\n    Instantaneous power reading:                   220 Watts\n    \
+                Minimum during sampling period:                 80 Watts\n";
+    assert_eq!(
+        parse_ipmi(dcmi).unwrap(),
+        [("ipmi.power".to_owned(), 220.0)]
+    );
+
+    let power = r#"{"PowerControl": [{"MemberId": "0", "PowerConsumedWatts": 344}, {}]}"#;
+    assert_eq!(
+        parse_redfish(power).unwrap(),
+        [("redfish.0.power".to_owned(), 344.0)]
+    );
+    let metrics = r#"{"PowerWatts": {"Reading": 12.5}}"#;
+    assert_eq!(
+        parse_redfish(metrics).unwrap(),
+        [("redfish.power".to_owned(), 12.5)]
+    );
+
+    assert_eq!(
+        parse_command("41.5\n").unwrap(),
+        [("power".to_owned(), 41.5)]
+    );
+    let pdu = parse_command("outlet1 10\noutlet2 20.5\n").unwrap();
+    assert_eq!(pdu[1], ("outlet2".to_owned(), 20.5));
+    assert!(parse_command("outlet1 ten\n").is_err());
+}
@@ -0,0 +1,146 @@
+//! Parquet output of the structured samples, the file per poller next to its poll log.
+//!
+//! Every row is a single numeric value of the sample, see [`values`](super::values):
+//!
+//! ```text
+//! time_us: TIMESTAMP(MICROS) | source: STRING | key: optional STRING | value: DOUBLE
+//! ```
+//!
+//! The labels of the run are stored as the key-value metadata of the file.
+//!
+//! The rows are buffered and written in row groups, the file is readable only after the poller
+//! is stopped properly, as the Parquet metadata is stored at the end.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+
+use super::values::Extractor;
+use super::{SampleBuf, Sources};
+
+/// Rows buffered before writing them as the row group.
+const ROW_GROUP_ROWS: usize = 64 << 10;
+
+const SCHEMA: &str = "
+    message sample {
+        REQUIRED INT64 time_us (TIMESTAMP(MICROS, true));
+        REQUIRED BYTE_ARRAY source (UTF8);
+        OPTIONAL BYTE_ARRAY key (UTF8);
+        REQUIRED DOUBLE value;
+    }
+";
+
+/// Create the Parquet file with the schema in the parquet message type syntax.
+pub fn create(
+    path: &Path,
+    schema: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<SerializedFileWriter<File>, String> {
+    let err = |e: &dyn std::fmt::Display| format!("cannot create '{}' - {}", path.display(), e);
+
+    let schema = parse_message_type(schema).map_err(|e| err(&e))?;
+    let metadata = metadata
+        .iter()
+        .map(|(key, value)| KeyValue::new(key.clone(), value.clone()))
+        .collect::<Vec<_>>();
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata((!metadata.is_empty()).then_some(metadata))
+        .build();
+    let file = File::create(path).map_err(|e| err(&e))?;
+    SerializedFileWriter::new(file, Arc::new(schema), Arc::new(props)).map_err(|e| err(&e))
+}
+
+/// Write the next column of the row group, `defs` are the definition levels of optional columns.
+pub fn write_column<T: DataType>(
+    group: &mut SerializedRowGroupWriter<'_, File>,
+    values: &[T::T],
+    defs: Option<&[i16]>,
+) -> parquet::errors::Result<()> {
+    let mut column = group.next_column()?.expect("column missing in the schema");
+    column.typed::<T>().write_batch(values, defs, None)?;
+    column.close()
+}
+
+pub(super) struct Writer {
+    file: SerializedFileWriter<File>,
+    extractor: Extractor,
+    times: Vec<i64>,
+    sources: Vec<ByteArray>,
+    keys: Vec<ByteArray>,
+    key_defs: Vec<i16>,
+    values: Vec<f64>,
+}
+
+impl Writer {
+    pub(super) fn create(
+        path: &Path,
+        srcs: &Sources,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            file: create(path, SCHEMA, tags)?,
+            extractor: Extractor::new(srcs),
+            times: Vec::new(),
+            sources: Vec::new(),
+            keys: Vec::new(),
+            key_defs: Vec::new(),
+            values: Vec::new(),
+        })
+    }
+
+    /// Store the values of the sample, the sources are named by `names`.
+    pub(super) fn store(
+        &mut self,
+        time_us: i64,
+        names: &[String],
+        sample: &SampleBuf,
+    ) -> parquet::errors::Result<()> {
+        for v in self.extractor.values(names, sample) {
+            self.times.push(time_us);
+            self.sources.push(v.source.into());
+            self.key_defs.push(v.key.is_some() as i16);
+            if let Some(key) = v.key {
+                self.keys.push(key.into());
+            }
+            self.values.push(v.value);
+        }
+
+        match self.times.len() >= ROW_GROUP_ROWS {
+            true => self.write_group(),
+            false => Ok(()),
+        }
+    }
+
+    fn write_group(&mut self) -> parquet::errors::Result<()> {
+        let mut group = self.file.next_row_group()?;
+        write_column::<Int64Type>(&mut group, &self.times, None)?;
+        write_column::<ByteArrayType>(&mut group, &self.sources, None)?;
+        write_column::<ByteArrayType>(&mut group, &self.keys, Some(&self.key_defs))?;
+        write_column::<DoubleType>(&mut group, &self.values, None)?;
+        group.close()?;
+
+        self.times.clear();
+        self.sources.clear();
+        self.keys.clear();
+        self.key_defs.clear();
+        self.values.clear();
+        Ok(())
+    }
+
+    /// Write the remaining rows and the file metadata.
+    pub(super) fn finish(mut self) -> parquet::errors::Result<()> {
+        if !self.times.is_empty() {
+            self.write_group()?;
+        }
+        self.file.close().map(|_| ())
+    }
+}
@@ -0,0 +1,90 @@
+//! Windows performance counters poller, the replacement for the procfs files there.
+//!
+//! The log has the same layout as for the files: every sample contains the line per counter with
+//! its formatted value, the counters whose values are not available yet (e.g. the rates on the
+//! first sample) are skipped.
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use windows_sys::Win32::System::Performance::{
+    PdhAddEnglishCounterW, PdhCloseQuery, PdhCollectQueryData, PdhGetFormattedCounterValue,
+    PdhOpenQueryW, PDH_FMT_COUNTERVALUE, PDH_FMT_DOUBLE,
+};
+
+use super::{poll_loop, PollConfig, SampleBuf};
+
+const ERROR_SUCCESS: u32 = 0;
+
+/// PDH query with the counters added, closed on drop.
+struct Query {
+    query: isize,
+    counters: Vec<(String, isize)>,
+}
+
+impl Query {
+    fn open(paths: &[String]) -> Result<Self, String> {
+        let mut query = Self {
+            query: 0,
+            counters: Vec::with_capacity(paths.len()),
+        };
+
+        // SAFETY: the out pointers are valid, the strings are NUL-terminated
+        unsafe {
+            let res = PdhOpenQueryW(std::ptr::null(), 0, &mut query.query);
+            if res != ERROR_SUCCESS {
+                return Err(format!("cannot open PDH query, status {:#x}", res));
+            }
+
+            for path in paths {
+                let wide: Vec<u16> = path.encode_utf16().chain(Some(0)).collect();
+                let mut counter = 0;
+                let res = PdhAddEnglishCounterW(query.query, wide.as_ptr(), 0, &mut counter);
+                if res != ERROR_SUCCESS {
+                    return Err(format!("cannot add counter '{}', status {:#x}", path, res));
+                }
+                query.counters.push((path.clone(), counter));
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn collect(&self, output: &mut SampleBuf) {
+        // SAFETY: the query and counter handles are valid while self is alive
+        unsafe {
+            let res = PdhCollectQueryData(self.query);
+            assert_eq!(res, ERROR_SUCCESS, "cannot collect PDH query data");
+
+            for (path, counter) in &self.counters {
+                let mut value: PDH_FMT_COUNTERVALUE = std::mem::zeroed();
+                let res = PdhGetFormattedCounterValue(
+                    *counter,
+                    PDH_FMT_DOUBLE,
+                    std::ptr::null_mut(),
+                    &mut value,
+                );
+                if res == ERROR_SUCCESS {
+                    let line = format!("{}: {}\n", path, value.Anonymous.doubleValue);
+                    output.text.push_str(&line);
+                }
+                output.end_source();
+            }
+        }
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        if self.query != 0 {
+            // SAFETY: the query is owned by self
+            unsafe { PdhCloseQuery(self.query) };
+        }
+    }
+}
+
+pub fn poll_counters(paths: Vec<String>, dest: PathBuf, stop: Arc<AtomicBool>, cfg: PollConfig) {
+    let query = Query::open(&paths).expect("cannot prepare performance counters");
+    poll_loop(paths, dest, stop, cfg, |out| query.collect(out));
+}
@@ -0,0 +1,307 @@
+//! Hardware and software performance counters poller based on `perf_event_open`.
+//!
+//! The pattern is `perf:EVENT[,EVENT...]`, where the event is either a generic perf name like
+//! `cycles` or `context-switches`, an explicit PMU event like `armv8_pmuv3_0/inst_retired/`, or
+//! a bare event name exported by the core PMUs in sysfs like `inst_retired` on ARM. The core PMUs
+//! covering different sets of CPUs (big.LITTLE clusters, hybrid x86) are counted separately. The
+//! sample contains the line per PMU and event with the counter value summed over its CPUs.
+
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+use crate::agent::privsep;
+
+pub const PREFIX: &str = "perf:";
+
+const PMU_DIR: &str = "/sys/bus/event_source/devices";
+const ONLINE_CPUS: &str = "/sys/devices/system/cpu/online";
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+
+/// Generic events available on every CPU, as named by the perf tool.
+const GENERIC_EVENTS: &[(&str, u32, u64)] = &[
+    ("cycles", PERF_TYPE_HARDWARE, 0),
+    ("instructions", PERF_TYPE_HARDWARE, 1),
+    ("cache-references", PERF_TYPE_HARDWARE, 2),
+    ("cache-misses", PERF_TYPE_HARDWARE, 3),
+    ("branches", PERF_TYPE_HARDWARE, 4),
+    ("branch-misses", PERF_TYPE_HARDWARE, 5),
+    ("bus-cycles", PERF_TYPE_HARDWARE, 6),
+    ("cpu-clock", PERF_TYPE_SOFTWARE, 0),
+    ("task-clock", PERF_TYPE_SOFTWARE, 1),
+    ("page-faults", PERF_TYPE_SOFTWARE, 2),
+    ("context-switches", PERF_TYPE_SOFTWARE, 3),
+    ("cpu-migrations", PERF_TYPE_SOFTWARE, 4),
+    ("minor-faults", PERF_TYPE_SOFTWARE, 5),
+    ("major-faults", PERF_TYPE_SOFTWARE, 6),
+];
+
+/// The first version of `struct perf_event_attr`, the kernel accepts it by its size.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// Single event counted on the set of CPUs.
+pub struct Counter {
+    name: String,
+    fds: Vec<File>,
+}
+
+impl Counter {
+    fn open(name: String, type_: u32, config: u64, cpus: &[u32]) -> Result<Self, String> {
+        let fds = cpus
+            .iter()
+            .map(|&cpu| open_counter(type_, config, cpu))
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| format!("cannot open counter '{}' - {}", name, e))?;
+        Ok(Self { name, fds })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn read(&self) -> std::io::Result<u64> {
+        let mut total = 0;
+        for mut fd in &self.fds {
+            let mut value = [0u8; 8];
+            fd.read_exact(&mut value)?;
+            total += u64::from_ne_bytes(value);
+        }
+        Ok(total)
+    }
+}
+
+/// Performance monitoring unit as exported in sysfs.
+struct Pmu {
+    name: String,
+    type_: u32,
+    cpus: Vec<u32>,
+}
+
+impl Pmu {
+    fn load(name: &str) -> Result<Self, String> {
+        let dir = Path::new(PMU_DIR).join(name);
+        let type_ = read_trimmed(&dir.join("type"))?
+            .parse()
+            .map_err(|e| format!("bad type of PMU '{}' - {}", name, e))?;
+
+        // the core PMUs of heterogeneous systems cover only their own CPUs
+        let cpus = match read_trimmed(&dir.join("cpus")) {
+            Ok(cpus) => parse_cpu_list(&cpus)?,
+            Err(_) => online_cpus()?,
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            type_,
+            cpus,
+        })
+    }
+
+    /// Core PMUs, i.e. the ones bound to the specific set of CPUs.
+    fn cores() -> Vec<Self> {
+        let Ok(entries) = Path::new(PMU_DIR).read_dir() else {
+            return Vec::new();
+        };
+
+        let mut pmus: Vec<Self> = entries
+            .flatten()
+            .filter(|e| e.path().join("cpus").exists())
+            .filter_map(|e| Self::load(&e.file_name().to_string_lossy()).ok())
+            .collect();
+        pmus.sort_by(|a, b| a.name.cmp(&b.name));
+        pmus
+    }
+
+    fn events_dir(&self) -> PathBuf {
+        Path::new(PMU_DIR).join(&self.name).join("events")
+    }
+
+    /// Encode the sysfs event description like `event=0x11,umask=0x1` using the PMU format.
+    fn config(&self, event: &str) -> Result<u64, String> {
+        let desc = read_trimmed(&self.events_dir().join(event))?;
+
+        let mut config = 0;
+        for term in desc.split(',') {
+            let (field, value) = term.split_once('=').unwrap_or((term, "1"));
+            let value = parse_number(value)
+                .ok_or_else(|| format!("bad value in '{}/{}': {}", self.name, event, term))?;
+            let format = Path::new(PMU_DIR)
+                .join(&self.name)
+                .join("format")
+                .join(field);
+            config |= encode_field(&read_trimmed(&format)?, value)
+                .ok_or_else(|| format!("unsupported field '{}' of '{}'", field, self.name))?;
+        }
+        Ok(config)
+    }
+}
+
+fn read_trimmed(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path)
+        .map(|s| s.trim().to_owned())
+        .map_err(|e| format!("cannot read '{}' - {}", path.to_string_lossy(), e))
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Place the value into the `config:LO-HI` bits, other attribute fields are not supported.
+fn encode_field(format: &str, value: u64) -> Option<u64> {
+    let bits = format.strip_prefix("config:")?;
+    let (lo, hi) = match bits.split_once('-') {
+        Some((lo, hi)) => (lo.parse::<u32>().ok()?, hi.parse::<u32>().ok()?),
+        None => (bits.parse().ok()?, bits.parse().ok()?),
+    };
+    if lo > hi || hi > 63 {
+        return None;
+    }
+    let mask = u64::MAX >> (63 - (hi - lo));
+    Some((value & mask) << lo)
+}
+
+fn parse_cpu_list(list: &str) -> Result<Vec<u32>, String> {
+    let bad = || format!("bad CPU list '{}'", list);
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|r| !r.is_empty()) {
+        let (lo, hi) = range.split_once('-').unwrap_or((range, range));
+        let lo: u32 = lo.parse().map_err(|_| bad())?;
+        let hi: u32 = hi.parse().map_err(|_| bad())?;
+        cpus.extend(lo..=hi);
+    }
+    Ok(cpus)
+}
+
+fn online_cpus() -> Result<Vec<u32>, String> {
+    parse_cpu_list(&read_trimmed(Path::new(ONLINE_CPUS))?)
+}
+
+/// Open the system-wide counter on the CPU, by the privileged helper if the agent has dropped root.
+fn open_counter(type_: u32, config: u64, cpu: u32) -> std::io::Result<File> {
+    #[cfg(target_os = "linux")]
+    if let Some(res) = privsep::delegate(privsep::Request::PerfEvent { type_, config, cpu }) {
+        return res;
+    }
+    open_event(type_, config, cpu)
+}
+
+pub fn open_event(type_: u32, config: u64, cpu: u32) -> std::io::Result<File> {
+    let attr = PerfEventAttr {
+        type_,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config,
+        ..Default::default()
+    };
+
+    // SAFETY: the attribute is fully initialized and outlives the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1 as libc::pid_t, // all the processes
+            cpu as libc::c_int,
+            -1 as libc::c_int, // no group
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: the descriptor has been just created and is owned by nobody else
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+fn open_one(event: &str, cores: &[Pmu]) -> Result<Vec<Counter>, String> {
+    // explicit PMU event as written by perf, like "armv8_pmuv3_0/br_mis_pred/"
+    if let Some((pmu, name)) = event.trim_end_matches('/').split_once('/') {
+        let pmu = Pmu::load(pmu)?;
+        let config = pmu.config(name)?;
+        let name = format!("{}.{}", pmu.name, name);
+        return Ok(vec![Counter::open(name, pmu.type_, config, &pmu.cpus)?]);
+    }
+
+    // named event exported by the core PMUs, counted per cluster
+    let named: Vec<&Pmu> = cores
+        .iter()
+        .filter(|pmu| pmu.events_dir().join(event).exists())
+        .collect();
+    if !named.is_empty() {
+        return named
+            .into_iter()
+            .map(|pmu| {
+                let name = format!("{}.{}", pmu.name, event);
+                Counter::open(name, pmu.type_, pmu.config(event)?, &pmu.cpus)
+            })
+            .collect();
+    }
+
+    let (_, type_, config) = GENERIC_EVENTS
+        .iter()
+        .find(|(name, _, _)| *name == event)
+        .ok_or_else(|| format!("unknown event '{}'", event))?;
+
+    // generic hardware events have to be counted on each core PMU separately, the extended type
+    // in the upper config bits selects the PMU
+    if *type_ == PERF_TYPE_HARDWARE && cores.len() > 1 {
+        return cores
+            .iter()
+            .map(|pmu| {
+                let name = format!("{}.{}", pmu.name, event);
+                let config = config | (pmu.type_ as u64) << 32;
+                Counter::open(name, *type_, config, &pmu.cpus)
+            })
+            .collect();
+    }
+
+    let name = format!("all.{}", event);
+    Ok(vec![Counter::open(name, *type_, *config, &online_cpus()?)?])
+}
+
+/// Open the counters for the `perf:` pattern.
+pub fn open(pattern: &str) -> Result<Vec<Counter>, String> {
+    let events = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a perf pattern '{}'", pattern))?;
+
+    let cores = Pmu::cores();
+    let mut counters = Vec::new();
+    for event in events.split(',').filter(|e| !e.is_empty()) {
+        counters.extend(open_one(event, &cores)?);
+    }
+
+    match counters.is_empty() {
+        false => Ok(counters),
+        true => Err(format!("no events in '{}'", pattern)),
+    }
+}
+
+#[test]
+fn encode_format_fields() {
+    assert_eq!(encode_field("config:0-7", 0x11), Some(0x11));
+    assert_eq!(encode_field("config:8-15", 0x1ff), Some(0xff00));
+    assert_eq!(encode_field("config:63", 1), Some(1 << 63));
+    assert_eq!(encode_field("config1:0-31", 1), None);
+    assert_eq!(parse_cpu_list("0-3,6").unwrap(), vec![0, 1, 2, 3, 6]);
+}
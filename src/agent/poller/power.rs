@@ -0,0 +1,185 @@
+//! Power and energy poller for the RAPL domains and the hwmon sensors.
+//!
+//! The pattern is `power:`. The sensors are found in sysfs: the RAPL energy counters of the
+//! powercap zones like `intel-rapl:0` (the package) and `intel-rapl:0:0` (its cores), and the
+//! power, energy and voltage inputs of the hwmon devices. The energy counters give the average
+//! power since the previous sample, the first one is since the poller start, accounting for the
+//! counter wrapping. The sample contains the line per sensor with the power in watts or the
+//! voltage in volts, named like `intel-rapl:0.package-0.power` or `hwmon1.ina3221.in1`.
+//!
+//! The RAPL counters are usually readable only by root, the unreadable sensors are skipped.
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use log::warn;
+
+pub const PREFIX: &str = "power:";
+
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+const HWMON_DIR: &str = "/sys/class/hwmon";
+
+enum Kind {
+    /// Energy counter in microjoules, wrapping at the range if known.
+    Energy(Option<u64>),
+    /// Power in microwatts.
+    Power,
+    /// Voltage in millivolts.
+    Voltage,
+}
+
+struct Sensor {
+    name: String,
+    path: PathBuf,
+    kind: Kind,
+}
+
+pub struct Sensors {
+    sensors: Vec<Sensor>,
+    last: Vec<u64>,
+    time: Instant,
+}
+
+impl Sensors {
+    pub fn names(&self) -> Vec<String> {
+        self.sensors.iter().map(|s| s.name.clone()).collect()
+    }
+
+    /// The `name value` lines of the sensors, in the order of the names.
+    pub fn read(&mut self) -> std::io::Result<Vec<String>> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.time).as_secs_f64();
+        let mut lines = Vec::with_capacity(self.sensors.len());
+        for (sensor, last) in self.sensors.iter().zip(self.last.iter_mut()) {
+            let raw = read_u64(&sensor.path)?;
+            let value = match sensor.kind {
+                Kind::Energy(range) => watts(*last, raw, range, elapsed),
+                Kind::Power => raw as f64 / 1e6,
+                Kind::Voltage => raw as f64 / 1e3,
+            };
+            *last = raw;
+            lines.push(format!("{} {:.3}", sensor.name, value));
+        }
+        self.time = now;
+        Ok(lines)
+    }
+}
+
+/// Average power in watts from the energy counter values in microjoules.
+fn watts(last: u64, current: u64, range: Option<u64>, elapsed: f64) -> f64 {
+    let delta = match (current.checked_sub(last), range) {
+        (Some(delta), _) => delta,
+        (None, Some(range)) => range.saturating_sub(last) + current,
+        // cannot tell the energy spent without the range
+        (None, None) => 0,
+    };
+    match elapsed > 0.0 {
+        true => delta as f64 / 1e6 / elapsed,
+        false => 0.0,
+    }
+}
+
+fn read_u64(path: &Path) -> std::io::Result<u64> {
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Entries of the directory sorted by name.
+fn sorted_entries(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    paths
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn rapl_sensors() -> Vec<Sensor> {
+    sorted_entries(Path::new(POWERCAP_DIR))
+        .into_iter()
+        .filter(|zone| zone.join("energy_uj").exists())
+        .map(|zone| {
+            let name = std::fs::read_to_string(zone.join("name")).unwrap_or_default();
+            Sensor {
+                name: format!("{}.{}.power", file_name(&zone), name.trim()),
+                path: zone.join("energy_uj"),
+                kind: Kind::Energy(read_u64(&zone.join("max_energy_range_uj")).ok()),
+            }
+        })
+        .collect()
+}
+
+fn hwmon_sensors() -> Vec<Sensor> {
+    let mut sensors = Vec::new();
+    for device in sorted_entries(Path::new(HWMON_DIR)) {
+        let name = std::fs::read_to_string(device.join("name")).unwrap_or_default();
+        let prefix = format!("{}.{}", file_name(&device), name.trim());
+        for input in sorted_entries(&device) {
+            let file = file_name(&input);
+            let Some(channel) = file.strip_suffix("_input") else {
+                continue;
+            };
+            let kind = match channel {
+                c if c.starts_with("power") => Kind::Power,
+                c if c.starts_with("energy") => Kind::Energy(None),
+                c if c.starts_with("in") => Kind::Voltage,
+                _ => continue,
+            };
+            sensors.push(Sensor {
+                name: format!("{}.{}", prefix, channel),
+                path: input,
+                kind,
+            });
+        }
+    }
+    sensors
+}
+
+/// Open the sensors for the `power:` pattern.
+pub fn open(pattern: &str) -> Result<Sensors, String> {
+    match pattern.strip_prefix(PREFIX) {
+        Some("") => (),
+        Some(_) => return Err(format!("no arguments expected in '{}'", pattern)),
+        None => return Err(format!("not a power pattern '{}'", pattern)),
+    }
+
+    let mut sensors = Vec::new();
+    let mut last = Vec::new();
+    for sensor in rapl_sensors().into_iter().chain(hwmon_sensors()) {
+        match read_u64(&sensor.path) {
+            Ok(value) => {
+                sensors.push(sensor);
+                last.push(value);
+            }
+            Err(e) => warn!(
+                "skipping unreadable '{}' - {}",
+                sensor.path.to_string_lossy(),
+                e
+            ),
+        }
+    }
+
+    match sensors.is_empty() {
+        false => Ok(Sensors {
+            sensors,
+            last,
+            time: Instant::now(),
+        }),
+        true => Err("no readable power sensors found".into()),
+    }
+}
+
+#[test]
+fn energy_power() {
+    assert_eq!(watts(1_000_000, 3_000_000, None, 2.0), 1.0);
+    assert_eq!(watts(9_000_000, 1_000_000, Some(10_000_000), 1.0), 2.0);
+    assert_eq!(watts(9_000_000, 1_000_000, None, 1.0), 0.0);
+}
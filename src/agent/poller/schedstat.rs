@@ -0,0 +1,132 @@
+//! Scheduler latency poller giving the run delays of the CPUs and the processes.
+//!
+//! The pattern is `schedstat:` for the CPUs from `/proc/schedstat` or `schedstat:PID[,PID...]` for
+//! the processes, summing `/proc/PID/task/*/schedstat` over their threads. The sample contains the
+//! `.run` and `.delay` lines per CPU or process with the nanoseconds spent on the CPU and waiting
+//! on the runqueue since the previous sample, the first one is since the poller start. The
+//! process which has exited gives zeros.
+
+use std::path::{Path, PathBuf};
+
+pub const PREFIX: &str = "schedstat:";
+
+const SCHEDSTAT: &str = "/proc/schedstat";
+
+/// CPU named as in `/proc/schedstat` or the process directory.
+enum Source {
+    Cpu,
+    Process(PathBuf),
+}
+
+pub struct Delays {
+    sources: Vec<(String, Source)>,
+    // the total run and wait times of the sources
+    last: Vec<(u64, u64)>,
+}
+
+impl Delays {
+    pub fn names(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .flat_map(|(name, _)| [format!("{}.run", name), format!("{}.delay", name)])
+            .collect()
+    }
+
+    /// The `name value` lines of the deltas, in the order of the names.
+    pub fn read(&mut self) -> std::io::Result<Vec<String>> {
+        let totals = totals(&self.sources)?;
+        let mut lines = Vec::with_capacity(2 * totals.len());
+        for (((name, _), total), last) in self.sources.iter().zip(&totals).zip(&self.last) {
+            let run = total.0.saturating_sub(last.0);
+            let delay = total.1.saturating_sub(last.1);
+            lines.push(format!("{}.run {}", name, run));
+            lines.push(format!("{}.delay {}", name, delay));
+        }
+        self.last = totals;
+        Ok(lines)
+    }
+}
+
+/// Run and wait times of the CPU line `cpuN ... run wait timeslices`.
+fn cpu_times(line: &str) -> Option<(u64, u64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [.., run, wait, _] = fields.as_slice() else {
+        return None;
+    };
+    Some((run.parse().ok()?, wait.parse().ok()?))
+}
+
+/// Run and wait times of the process summed over its threads, zeros if it has exited.
+fn process_times(dir: &Path) -> (u64, u64) {
+    let Ok(tasks) = dir.join("task").read_dir() else {
+        return (0, 0);
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| std::fs::read_to_string(task.path().join("schedstat")).ok())
+        .filter_map(|content| {
+            let mut fields = content.split_whitespace();
+            Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+        })
+        .fold((0, 0), |(run, wait), (r, w): (u64, u64)| {
+            (run + r, wait + w)
+        })
+}
+
+fn totals(sources: &[(String, Source)]) -> std::io::Result<Vec<(u64, u64)>> {
+    let cpus = match sources.iter().any(|(_, s)| matches!(s, Source::Cpu)) {
+        true => std::fs::read_to_string(SCHEDSTAT)?,
+        false => String::new(),
+    };
+    let totals = sources
+        .iter()
+        .map(|(name, source)| match source {
+            Source::Cpu => cpus
+                .lines()
+                .find(|line| line.split_whitespace().next() == Some(name))
+                .and_then(cpu_times)
+                .unwrap_or_default(),
+            Source::Process(dir) => process_times(dir),
+        })
+        .collect();
+    Ok(totals)
+}
+
+/// Open the sources for the `schedstat:` pattern.
+pub fn open(pattern: &str) -> Result<Delays, String> {
+    let pids = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a schedstat pattern '{}'", pattern))?;
+
+    let mut sources = Vec::new();
+    if pids.is_empty() {
+        let content = std::fs::read_to_string(SCHEDSTAT)
+            .map_err(|e| format!("cannot read '{}' - {}", SCHEDSTAT, e))?;
+        for line in content.lines().filter(|line| line.starts_with("cpu")) {
+            let cpu = line.split_whitespace().next().unwrap_or_default();
+            sources.push((cpu.to_owned(), Source::Cpu));
+        }
+    }
+    for pid in pids.split(',').filter(|p| !p.is_empty()) {
+        let pid: u32 = pid
+            .parse()
+            .map_err(|e| format!("bad pid '{}' in '{}' - {}", pid, pattern, e))?;
+        let dir = Path::new("/proc").join(pid.to_string());
+        if !dir.join("schedstat").exists() {
+            return Err(format!("no schedstat of pid {}", pid));
+        }
+        sources.push((pid.to_string(), Source::Process(dir)));
+    }
+    if sources.is_empty() {
+        return Err(format!("no CPUs in '{}'", SCHEDSTAT));
+    }
+
+    let last = totals(&sources).map_err(|e| format!("cannot read schedstat - {}", e))?;
+    Ok(Delays { sources, last })
+}
+
+#[test]
+fn cpu_line_times() {
+    assert_eq!(cpu_times("cpu0 0 0 0 0 0 0 2500 300 17"), Some((2500, 300)));
+    assert_eq!(cpu_times("cpu0 17"), None);
+}
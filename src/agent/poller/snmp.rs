@@ -0,0 +1,107 @@
+//! SNMP poller for the counters of the network devices, like the switch ports on the test path.
+//!
+//! The pattern is `snmp:[COMMUNITY@]HOST[:PORT]/OID[,OID...]`, the OIDs are queried by the SNMPv2c
+//! `snmpget` of net-snmp, with the `public` community by default. The OIDs are numeric or named by
+//! the installed MIBs like `IF-MIB::ifHCInOctets.3`. The sample contains the `OID value` line per
+//! OID with the numeric value, the counters as is, the missing instances are left out.
+
+use subprocess::Exec;
+
+use super::meter::{self, Meter};
+
+pub const PREFIX: &str = "snmp:";
+
+const DEFAULT_COMMUNITY: &str = "public";
+/// Timeout of the request in seconds and its retries, the device must not hold the poller.
+const TIMEOUT_S: &str = "1";
+const RETRIES: &str = "1";
+
+struct Query {
+    community: String,
+    host: String,
+    oids: Vec<String>,
+}
+
+fn parse_pattern(pattern: &str) -> Result<Query, String> {
+    let bad = || {
+        format!(
+            "bad SNMP pattern '{}', expected {}HOST/OID[,OID...]",
+            pattern, PREFIX
+        )
+    };
+    let spec = pattern.strip_prefix(PREFIX).ok_or_else(bad)?;
+    let (target, oids) = spec.split_once('/').ok_or_else(bad)?;
+    let (community, host) = target
+        .rsplit_once('@')
+        .unwrap_or((DEFAULT_COMMUNITY, target));
+    let oids: Vec<String> = oids.split(',').map(|oid| oid.trim().to_owned()).collect();
+    if host.is_empty() || community.is_empty() || oids.iter().any(String::is_empty) {
+        return Err(bad());
+    }
+    Ok(Query {
+        community: community.to_owned(),
+        host: host.to_owned(),
+        oids,
+    })
+}
+
+/// Pair the values printed one per line by `snmpget -Oqv` with the requested OIDs.
+fn parse_values(oids: &[String], output: &str) -> Vec<(String, f64)> {
+    oids.iter()
+        .zip(output.lines())
+        .filter_map(|(oid, value)| {
+            // the strings are quoted, the missing instances are reported as text
+            let value = value.trim().trim_matches('"').parse().ok()?;
+            Some((oid.clone(), value))
+        })
+        .collect()
+}
+
+impl Query {
+    fn get(&self) -> Result<Vec<(String, f64)>, String> {
+        // the bare values, without the enum labels, the units and the timeticks formatting
+        let exec = Exec::cmd("snmpget")
+            .args(&["-v2c", "-c", &self.community])
+            .args(&["-t", TIMEOUT_S, "-r", RETRIES])
+            .args(&["-Oqv", "-Oe", "-OU", "-Ot"])
+            .arg(&self.host)
+            .args(&self.oids);
+        let readings = parse_values(&self.oids, &meter::run(exec)?);
+        match readings.is_empty() {
+            false => Ok(readings),
+            true => Err(format!("no numeric values of the OIDs on {}", self.host)),
+        }
+    }
+}
+
+/// Open the query of the `snmp:` pattern, running it once.
+pub fn open(pattern: &str) -> Result<Meter, String> {
+    let query = parse_pattern(pattern)?;
+    Meter::new("SNMP device", move || query.get())
+}
+
+#[test]
+fn snmp_queries() {
+    let query =
+        parse_pattern("snmp:lab@sw1:1161/IF-MIB::ifHCInOctets.3, 1.3.6.1.2.1.1.3.0").unwrap();
+    assert_eq!(
+        (query.community.as_str(), query.host.as_str()),
+        ("lab", "sw1:1161")
+    );
+    assert_eq!(query.oids, ["IF-MIB::ifHCInOctets.3", "1.3.6.1.2.1.1.3.0"]);
+    assert_eq!(
+        parse_pattern("snmp:sw1/1.3.6.1.2.1.1.3.0")
+            .unwrap()
+            .community,
+        "public"
+    );
+    assert!(parse_pattern("snmp:sw1").is_err());
+    assert!(parse_pattern("snmp:sw1/a,,b").is_err());
+
+    let output = "123456789012\nNo Such Instance currently exists at this OID\n\"42\"\n";
+    let oids = ["a", "b", "c"].map(str::to_owned);
+    assert_eq!(
+        parse_values(&oids, output),
+        [("a".to_owned(), 123456789012.0), ("c".to_owned(), 42.0)]
+    );
+}
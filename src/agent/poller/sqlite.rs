@@ -0,0 +1,102 @@
+//! SQLite output of the structured samples, stored next to the poll logs.
+//!
+//! All the pollers of the run share the single database, every poller writes its samples using
+//! its own connection. The table per poller kind is used:
+//!
+//! - `files(poll, time_us, file, key, value)` with the numeric values found in the files, the
+//!   `key: value` lines like in `/proc/meminfo`, or the whole content with the NULL key for the
+//!   single-value files like the sysfs attributes;
+//! - `counters(poll, time_us, counter, value)` with the values of the performance counters.
+//!
+//! The `tags(key, value)` table contains the labels of the run.
+//!
+//! The `poll` column is the id of the poller as in the manifest, and the `time_us` is the sample
+//! time in microseconds since the epoch.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{params, Connection};
+
+use super::values::Extractor;
+use super::{SampleBuf, Sources};
+
+/// Name of the database file in the output directory.
+pub const DATABASE_NAME: &str = "samples.db";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (poll INTEGER, time_us INTEGER, file TEXT, key TEXT, value REAL);
+    CREATE TABLE IF NOT EXISTS counters (poll INTEGER, time_us INTEGER, counter TEXT, value REAL);
+    CREATE TABLE IF NOT EXISTS tags (key TEXT PRIMARY KEY, value TEXT);
+";
+
+/// Wait for the other pollers writing into the same database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Database {
+    conn: Connection,
+    poll: u32,
+    extractor: Extractor,
+}
+
+impl Database {
+    pub fn open(
+        path: &Path,
+        poll: u32,
+        srcs: &Sources,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<Self, String> {
+        let err = |e: rusqlite::Error| format!("cannot open '{}' - {}", path.to_string_lossy(), e);
+
+        let conn = Connection::open(path).map_err(err)?;
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(err)?;
+        // the concurrent pollers do not block each other in the WAL mode
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(err)?;
+        conn.execute_batch(SCHEMA).map_err(err)?;
+        // every poller stores the same tags
+        for (key, value) in tags {
+            conn.execute(
+                "INSERT OR REPLACE INTO tags (key, value) VALUES (?, ?)",
+                params![key, value],
+            )
+            .map_err(err)?;
+        }
+
+        Ok(Self {
+            conn,
+            poll,
+            extractor: Extractor::new(srcs),
+        })
+    }
+
+    /// Store the values of the sample, the sources are named by `names`.
+    pub fn store(
+        &mut self,
+        time_us: i64,
+        names: &[String],
+        sample: &SampleBuf,
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = match self.extractor.is_counters() {
+                false => tx.prepare_cached(
+                    "INSERT INTO files (poll, time_us, file, key, value) VALUES (?, ?, ?, ?, ?)",
+                )?,
+                true => tx.prepare_cached(
+                    "INSERT INTO counters (poll, time_us, counter, value) VALUES (?, ?, ?, ?)",
+                )?,
+            };
+            for v in self.extractor.values(names, sample) {
+                match self.extractor.is_counters() {
+                    false => {
+                        insert.execute(params![self.poll, time_us, v.source, v.key, v.value])?
+                    }
+                    true => insert.execute(params![self.poll, time_us, v.source, v.value])?,
+                };
+            }
+        }
+        tx.commit()
+    }
+}
@@ -0,0 +1,130 @@
+//! Statsd listener poller capturing the metrics emitted by the workload.
+//!
+//! The pattern is `statsd:` to listen on `127.0.0.1:8125`, `statsd:PORT` or `statsd:ADDR:PORT`.
+//! The counters (`name:value|c`, scaled by the `@rate` sample rate) are summed over the poll
+//! interval, the gauges (`name:value|g`, changed by the signed `+value`/`-value`) keep their last
+//! value. The timers and other types are ignored. The sample contains the `name value` line per
+//! metric seen so far sorted by name, the counters not updated in the interval give zero.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+
+pub const PREFIX: &str = "statsd:";
+
+const DEFAULT_ADDR: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8125;
+
+pub struct Listener {
+    socket: UdpSocket,
+    counters: BTreeMap<String, f64>,
+    gauges: BTreeMap<String, f64>,
+}
+
+impl Listener {
+    /// Parse the single metric line, ignoring the malformed ones.
+    fn update(&mut self, line: &str) {
+        let Some((name, rest)) = line.split_once(':') else {
+            return;
+        };
+        let mut fields = rest.split('|');
+        let (Some(value), Some(kind)) = (fields.next(), fields.next()) else {
+            return;
+        };
+        let Ok(number) = value.parse::<f64>() else {
+            return;
+        };
+
+        match kind {
+            "c" => {
+                let rate = fields
+                    .find_map(|f| f.strip_prefix('@')?.parse::<f64>().ok())
+                    .filter(|&rate| rate > 0.0)
+                    .unwrap_or(1.0);
+                *self.counters.entry(name.to_owned()).or_default() += number / rate;
+            }
+            "g" => {
+                let gauge = self.gauges.entry(name.to_owned()).or_default();
+                match value.starts_with(['+', '-']) {
+                    true => *gauge += number,
+                    false => *gauge = number,
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Receive the pending packets, writing the metrics into the buffer.
+    pub fn read(&mut self, buf: &mut String) -> std::io::Result<()> {
+        let mut packet = [0u8; 64 << 10];
+        loop {
+            match self.socket.recv(&mut packet) {
+                Ok(len) => {
+                    let text = String::from_utf8_lossy(&packet[..len]).into_owned();
+                    text.lines().for_each(|line| self.update(line.trim()));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut metrics: Vec<(&String, f64)> = self
+            .counters
+            .iter()
+            .chain(&self.gauges)
+            .map(|(name, value)| (name, *value))
+            .collect();
+        metrics.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in metrics {
+            let _ = writeln!(buf, "{} {}", name, value);
+        }
+        self.counters.values_mut().for_each(|value| *value = 0.0);
+        Ok(())
+    }
+}
+
+/// Start listening for the `statsd:` pattern.
+pub fn open(pattern: &str) -> Result<Listener, String> {
+    let addr = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a statsd pattern '{}'", pattern))?;
+    let addr = match addr {
+        "" => format!("{}:{}", DEFAULT_ADDR, DEFAULT_PORT),
+        port if port.parse::<u16>().is_ok() => format!("{}:{}", DEFAULT_ADDR, port),
+        addr => addr.to_owned(),
+    };
+
+    let socket = UdpSocket::bind(&addr).map_err(|e| format!("cannot bind '{}' - {}", addr, e))?;
+    socket
+        .set_nonblocking(true)
+        .map_err(|e| format!("cannot set up '{}' - {}", addr, e))?;
+    Ok(Listener {
+        socket,
+        counters: BTreeMap::new(),
+        gauges: BTreeMap::new(),
+    })
+}
+
+#[test]
+fn counters_and_gauges() {
+    let mut listener = open("statsd:127.0.0.1:0").unwrap();
+    for line in [
+        "hits:2|c",
+        "hits:1|c|@0.5",
+        "mem:10|g",
+        "mem:-3|g",
+        "lat:5|ms",
+        "bad",
+    ] {
+        listener.update(line);
+    }
+    let mut buf = String::new();
+    listener.read(&mut buf).unwrap();
+    assert_eq!(buf, "hits 4\nmem 7\n");
+
+    buf.clear();
+    listener.read(&mut buf).unwrap();
+    assert_eq!(buf, "hits 0\nmem 7\n");
+}
@@ -0,0 +1,88 @@
+//! Filesystem usage poller based on `statvfs`.
+//!
+//! The pattern is `statvfs:PATH[,PATH...]` with the mount points or any paths on the filesystems.
+//! The sample contains the lines per path with the filesystem size, the free and available to
+//! the unprivileged users space in bytes, and the total and free inodes.
+
+use std::ffi::CString;
+use std::path::PathBuf;
+
+pub const PREFIX: &str = "statvfs:";
+
+const METRICS: &[&str] = &["size", "free", "avail", "inodes", "ifree"];
+
+pub struct Mounts(Vec<(PathBuf, CString)>);
+
+impl Mounts {
+    pub fn names(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|(path, _)| {
+                let path = path.to_string_lossy();
+                METRICS.iter().map(move |m| format!("{}.{}", path, m))
+            })
+            .collect()
+    }
+
+    /// The `name value` lines of the usage, in the order of the names.
+    pub fn read(&self) -> std::io::Result<Vec<String>> {
+        let mut lines = Vec::with_capacity(self.0.len() * METRICS.len());
+        for (path, cpath) in &self.0 {
+            let usage = usage(cpath)?;
+            for (metric, value) in METRICS.iter().zip(usage) {
+                lines.push(format!("{}.{} {}", path.to_string_lossy(), metric, value));
+            }
+        }
+        Ok(lines)
+    }
+}
+
+/// Values of the metrics of the filesystem.
+fn usage(path: &CString) -> std::io::Result<[u64; 5]> {
+    // SAFETY: the structure is plain data, it is filled by the call
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is NUL-terminated, the output structure is valid
+    if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // the field types differ between the platforms
+    #[allow(clippy::unnecessary_cast)]
+    let block = st.f_frsize as u64;
+    #[allow(clippy::unnecessary_cast)]
+    Ok([
+        st.f_blocks as u64 * block,
+        st.f_bfree as u64 * block,
+        st.f_bavail as u64 * block,
+        st.f_files as u64,
+        st.f_ffree as u64,
+    ])
+}
+
+/// Open the filesystems for the `statvfs:` pattern.
+pub fn open(pattern: &str) -> Result<Mounts, String> {
+    let paths = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a statvfs pattern '{}'", pattern))?;
+
+    let mut mounts = Vec::new();
+    for path in paths.split(',').filter(|p| !p.is_empty()) {
+        let cpath =
+            CString::new(path.as_bytes()).map_err(|e| format!("bad path '{}' - {}", path, e))?;
+        usage(&cpath).map_err(|e| format!("cannot statvfs '{}' - {}", path, e))?;
+        mounts.push((PathBuf::from(path), cpath));
+    }
+
+    match mounts.is_empty() {
+        false => Ok(Mounts(mounts)),
+        true => Err(format!("no paths in '{}'", pattern)),
+    }
+}
+
+#[test]
+fn root_usage() {
+    let mounts = open("statvfs:/").unwrap();
+    assert_eq!(mounts.names()[0], "/.size");
+    let lines = mounts.read().unwrap();
+    assert_eq!(lines.len(), METRICS.len());
+    assert!(lines[2].starts_with("/.avail "));
+}
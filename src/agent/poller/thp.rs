@@ -0,0 +1,127 @@
+//! Hugepages activity poller joining the THP settings, the hugepage memory and the THP events.
+//!
+//! The pattern is `thp:`. The sample contains the line per source: the settings from
+//! `/sys/kernel/mm/transparent_hugepage` and its `khugepaged` directory (the selected value for
+//! the choices like `always [madvise] never`, the `khugepaged.` prefix for the latter), the
+//! hugepage fields of `/proc/meminfo` without their units, and the rates per second of the
+//! `thp_*` vmstat counters like in the [`vmstat`](super::vmstat) poller.
+
+use std::path::{Path, PathBuf};
+
+use super::vmstat;
+
+pub const PREFIX: &str = "thp:";
+
+const THP_DIR: &str = "/sys/kernel/mm/transparent_hugepage";
+const MEMINFO: &str = "/proc/meminfo";
+
+pub struct Activity {
+    settings: Vec<(String, PathBuf)>,
+    meminfo: Vec<String>,
+    rates: vmstat::Rates,
+}
+
+impl Activity {
+    pub fn names(&self) -> Vec<String> {
+        let settings = self.settings.iter().map(|(name, _)| name);
+        let meminfo = self.meminfo.iter();
+        let rates = self.rates.names().iter();
+        settings.chain(meminfo).chain(rates).cloned().collect()
+    }
+
+    /// The `name value` lines of the sources, in the order of the names.
+    pub fn read(&mut self) -> std::io::Result<Vec<String>> {
+        let mut lines = Vec::with_capacity(self.settings.len() + self.meminfo.len());
+        for (name, path) in &self.settings {
+            let value = std::fs::read_to_string(path)?;
+            lines.push(format!("{} {}", name, selected(&value)));
+        }
+
+        let meminfo = std::fs::read_to_string(MEMINFO)?;
+        for name in &self.meminfo {
+            let value = meminfo_field(&meminfo, name).unwrap_or_default();
+            lines.push(format!("{} {}", name, value));
+        }
+
+        let rates = self.rates.read()?;
+        for (name, rate) in self.rates.names().iter().zip(rates) {
+            lines.push(format!("{} {:.3}", name, rate));
+        }
+        Ok(lines)
+    }
+}
+
+/// The selected choice of the setting like `always [madvise] never`, or the whole value.
+fn selected(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .split_whitespace()
+        .find_map(|choice| choice.strip_prefix('[')?.strip_suffix(']'))
+        .unwrap_or(value)
+}
+
+fn meminfo_field<'a>(meminfo: &'a str, name: &str) -> Option<&'a str> {
+    meminfo.lines().find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        (field == name).then(|| value.split_whitespace().next())?
+    })
+}
+
+/// Regular files of the directory as the named settings, sorted by name.
+fn settings(dir: &Path, prefix: &str) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+    let mut settings: Vec<_> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter(|e| std::fs::read_to_string(e.path()).is_ok())
+        .map(|e| {
+            let name = format!("{}{}", prefix, e.file_name().to_string_lossy());
+            (name, e.path())
+        })
+        .collect();
+    settings.sort();
+    settings
+}
+
+/// Open the sources for the `thp:` pattern.
+pub fn open(pattern: &str) -> Result<Activity, String> {
+    match pattern.strip_prefix(PREFIX) {
+        Some("") => (),
+        Some(_) => return Err(format!("no arguments expected in '{}'", pattern)),
+        None => return Err(format!("not a THP pattern '{}'", pattern)),
+    }
+
+    // the kernels without THP still have the hugetlb pages
+    let dir = Path::new(THP_DIR);
+    let mut settings = settings(dir, "");
+    settings.extend(self::settings(&dir.join("khugepaged"), "khugepaged."));
+
+    let meminfo = std::fs::read_to_string(MEMINFO)
+        .map_err(|e| format!("cannot read '{}' - {}", MEMINFO, e))?;
+    let meminfo = meminfo
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(field, _)| field)
+        .filter(|field| field.contains("Huge"))
+        .map(str::to_owned)
+        .collect();
+
+    let rates = vmstat::Rates::new(vmstat::fields("thp_")?, true)?;
+    Ok(Activity {
+        settings,
+        meminfo,
+        rates,
+    })
+}
+
+#[test]
+fn selected_setting() {
+    assert_eq!(selected("always [madvise] never\n"), "madvise");
+    assert_eq!(selected("511\n"), "511");
+    assert_eq!(
+        meminfo_field("AnonHugePages:    2048 kB\n", "AnonHugePages"),
+        Some("2048")
+    );
+}
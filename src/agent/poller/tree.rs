@@ -0,0 +1,146 @@
+//! Process tree poller accounting all the descendants of a background spawn.
+//!
+//! The pattern is `tree:ID` with the id of the background spawn. Every sample enumerates the
+//! processes in procfs and contains the line per process of the tree rooted at the spawned one:
+//! `PID PPID CPU_MS RSS_KB COMM`, where the CPU time is the total user and system time spent by
+//! the process so far. The sample of the exited tree is empty.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub const PREFIX: &str = "tree:";
+
+/// Accounting of the single process as in `/proc/PID/stat`.
+struct Stat {
+    ppid: u32,
+    comm: String,
+    // user and system time in clock ticks
+    cpu: u64,
+    // resident set in pages
+    rss: u64,
+}
+
+fn parse_stat(content: &str) -> Option<Stat> {
+    // the command may contain spaces and parentheses itself
+    let (head, tail) = content.rsplit_once(')')?;
+    let (_, comm) = head.split_once('(')?;
+    let fields: Vec<&str> = tail.split_whitespace().collect();
+    let field = |i: usize| fields.get(i)?.parse::<u64>().ok();
+    Some(Stat {
+        ppid: field(1)? as u32,
+        comm: comm.to_owned(),
+        cpu: field(11)? + field(12)?,
+        rss: field(21)?,
+    })
+}
+
+pub struct Tree {
+    root: u32,
+    tick_ms: u64,
+    page_kb: u64,
+}
+
+impl Tree {
+    pub fn new(root: u32) -> Self {
+        // SAFETY: sysconf is always safe to call
+        let (ticks, page) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        Self {
+            root,
+            tick_ms: 1000 / ticks.max(1) as u64,
+            page_kb: page as u64 / 1024,
+        }
+    }
+
+    /// Lines of the processes of the tree, parents before their children.
+    pub fn read(&self, buf: &mut String) -> std::io::Result<()> {
+        let stats = read_stats()?;
+        for pid in walk(self.root, &stats) {
+            let stat = &stats[&pid];
+            let _ = writeln!(
+                buf,
+                "{} {} {} {} {}",
+                pid,
+                stat.ppid,
+                stat.cpu * self.tick_ms,
+                stat.rss * self.page_kb,
+                stat.comm
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Accounting of all the running processes by their pids.
+fn read_stats() -> std::io::Result<HashMap<u32, Stat>> {
+    let mut stats = HashMap::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // the process may exit while enumerating
+        let Ok(content) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some(stat) = parse_stat(&content) {
+            stats.insert(pid, stat);
+        }
+    }
+    Ok(stats)
+}
+
+/// Pids of the tree rooted at the process, parents before their children.
+fn walk(root: u32, stats: &HashMap<u32, Stat>) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (&pid, stat) in stats {
+        children.entry(stat.ppid).or_default().push(pid);
+    }
+
+    let mut pids = Vec::new();
+    let mut queue = match stats.contains_key(&root) {
+        true => vec![root],
+        false => Vec::new(),
+    };
+    while let Some(pid) = queue.pop() {
+        pids.push(pid);
+        if let Some(kids) = children.get_mut(&pid) {
+            kids.sort_unstable_by(|a, b| b.cmp(a));
+            queue.extend(kids.iter());
+        }
+    }
+    pids
+}
+
+/// Pids of the process and all its descendants, parents before their children.
+pub fn descendants(root: u32) -> std::io::Result<Vec<u32>> {
+    Ok(walk(root, &read_stats()?))
+}
+
+/// Id of the background spawn in the `tree:` pattern.
+pub fn spawn_id(pattern: &str) -> Result<u32, String> {
+    let id = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a tree pattern '{}'", pattern))?;
+    id.parse()
+        .map_err(|e| format!("bad spawn id '{}' in '{}' - {}", id, pattern, e))
+}
+
+#[test]
+fn stat_fields() {
+    let stat = parse_stat(
+        "42 (my (app)) S 7 42 42 0 -1 4194560 100 0 0 0 15 5 0 0 20 0 3 0 100 1000000 250 ...",
+    )
+    .unwrap();
+    assert_eq!(stat.ppid, 7);
+    assert_eq!(stat.comm, "my (app)");
+    assert_eq!(stat.cpu, 20);
+    assert_eq!(stat.rss, 250);
+}
@@ -0,0 +1,128 @@
+//! io_uring backend of the file poller, batching the syscalls of all the files in a sample.
+//!
+//! Every sample is read in three batches: open all the files, read them at once into the
+//! preallocated buffers, close them. The files not fitting into the buffer are read again in the
+//! regular way.
+
+use std::ffi::CString;
+use std::io::{Error, Result};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use super::{push_content, read_source, SampleBuf, FILE_CAP};
+use crate::agent::protocol::Encoding;
+
+/// Upper limit of the ring size, the larger batches are split.
+const MAX_ENTRIES: usize = 1024;
+
+pub struct Batch {
+    ring: IoUring,
+    srcs: Vec<PathBuf>,
+    paths: Vec<CString>,
+    cap: Option<usize>,
+    encoding: Encoding,
+    bufs: Vec<Vec<u8>>,
+    fds: Vec<i32>,
+    lens: Vec<i32>,
+}
+
+impl Batch {
+    pub fn new(srcs: &[PathBuf], cap: Option<usize>, encoding: Encoding) -> Result<Self> {
+        let entries = srcs.len().clamp(1, MAX_ENTRIES).next_power_of_two();
+        let paths = srcs
+            .iter()
+            .map(|p| CString::new(p.as_os_str().as_bytes()).map_err(Error::other))
+            .collect::<Result<_>>()?;
+
+        // one byte more than the cap to detect the truncation
+        let buf_size = cap.map_or(FILE_CAP, |cap| cap + 1);
+
+        Ok(Self {
+            ring: IoUring::new(entries as u32)?,
+            srcs: srcs.to_owned(),
+            paths,
+            cap,
+            encoding,
+            bufs: vec![vec![0; buf_size]; srcs.len()],
+            fds: vec![-1; srcs.len()],
+            lens: vec![0; srcs.len()],
+        })
+    }
+
+    /// Submit the operations in chunks fitting into the ring, storing the results by index.
+    fn run<F>(&mut self, indices: &[usize], results: Results, mut make: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, usize) -> squeue::Entry,
+    {
+        let chunk_size = self.ring.params().sq_entries() as usize;
+        for chunk in indices.chunks(chunk_size) {
+            for &i in chunk {
+                let entry = make(self, i).user_data(i as u64);
+                // SAFETY: the buffers and paths are owned by self and outlive the submission
+                unsafe { self.ring.submission().push(&entry) }.map_err(Error::other)?;
+            }
+            self.ring.submit_and_wait(chunk.len())?;
+
+            for cqe in self.ring.completion() {
+                let i = cqe.user_data() as usize;
+                match results {
+                    Results::Fds => self.fds[i] = cqe.result(),
+                    Results::Lens => self.lens[i] = cqe.result(),
+                    Results::Ignore => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read all the files, appending their content to `out` in order.
+    pub fn read_all(&mut self, out: &mut SampleBuf) -> Result<()> {
+        let all: Vec<usize> = (0..self.srcs.len()).collect();
+
+        self.run(&all, Results::Fds, |batch, i| {
+            let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+            opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), batch.paths[i].as_ptr())
+                .flags(flags)
+                .build()
+        })?;
+
+        let opened: Vec<usize> = all.iter().copied().filter(|&i| self.fds[i] >= 0).collect();
+        self.run(&opened, Results::Lens, |batch, i| {
+            let buf = &mut batch.bufs[i];
+            opcode::Read::new(types::Fd(batch.fds[i]), buf.as_mut_ptr(), buf.len() as u32).build()
+        })?;
+        self.run(&opened, Results::Ignore, |batch, i| {
+            opcode::Close::new(types::Fd(batch.fds[i])).build()
+        })?;
+
+        for i in all {
+            if self.fds[i] < 0 {
+                return Err(Error::from_raw_os_error(-self.fds[i]));
+            }
+            if self.lens[i] < 0 {
+                return Err(Error::from_raw_os_error(-self.lens[i]));
+            }
+
+            let len = self.lens[i] as usize;
+            match self.cap {
+                // the buffer is full, the rest of the file is read in the regular way
+                None if len == self.bufs[i].len() => {
+                    read_source(&self.srcs[i], None, self.encoding, &mut out.text)?;
+                }
+                cap => push_content(&mut out.text, &self.bufs[i][..len], cap, self.encoding),
+            }
+            out.end_source();
+        }
+        Ok(())
+    }
+}
+
+/// Where to store the completion results of the batch.
+#[derive(Clone, Copy)]
+enum Results {
+    Fds,
+    Lens,
+    Ignore,
+}
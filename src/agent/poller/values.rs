@@ -0,0 +1,71 @@
+//! Numeric values of the samples for the structured outputs.
+//!
+//! The files give the `key: value` lines like in `/proc/meminfo`, or the whole content for the
+//! single-value files like the sysfs attributes, the key is missing then. Every performance
+//! counter gives the single value without the key.
+
+use regex::Regex;
+
+use super::{SampleBuf, Sources};
+
+/// Single value of the sample, the source is the file or the counter name.
+pub struct Value<'a> {
+    pub source: &'a str,
+    pub key: Option<&'a str>,
+    pub value: f64,
+}
+
+pub struct Extractor {
+    // the performance counters instead of the files
+    counters: bool,
+    line: Regex,
+}
+
+impl Extractor {
+    pub fn new(srcs: &Sources) -> Self {
+        Self {
+            counters: !matches!(srcs, Sources::Files(_)),
+            line: Regex::new(r"^([A-Za-z_][\w().-]*):?\s+(-?\d+(?:\.\d+)?)(?:\s|$)").unwrap(),
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn is_counters(&self) -> bool {
+        self.counters
+    }
+
+    /// Values of the sample, the sources are named by `names`.
+    pub fn values<'a>(&self, names: &'a [String], sample: &'a SampleBuf) -> Vec<Value<'a>> {
+        let mut values = Vec::new();
+        for (source, content) in names.iter().zip(sample.sources()) {
+            // the value is the last word of the counter line
+            let single = match self.counters {
+                true => content.split_whitespace().last().map(str::parse::<f64>),
+                false => Some(content.trim().parse::<f64>()),
+            };
+            if let Some(Ok(value)) = single {
+                values.push(Value {
+                    source,
+                    key: None,
+                    value,
+                });
+                continue;
+            }
+            if self.counters {
+                continue;
+            }
+
+            for caps in content.lines().filter_map(|line| self.line.captures(line)) {
+                let (Some(key), Ok(value)) = (caps.get(1), caps[2].parse::<f64>()) else {
+                    continue;
+                };
+                values.push(Value {
+                    source,
+                    key: Some(key.as_str()),
+                    value,
+                });
+            }
+        }
+        values
+    }
+}
@@ -0,0 +1,164 @@
+//! Memory management counters poller giving the rates of the `/proc/vmstat` counters.
+//!
+//! The pattern is `vmstat:` for the default set of counters (page faults, swapping, direct reclaim
+//! and compaction) or `vmstat:COUNTER[,COUNTER...]`. The counter is the vmstat field named by it
+//! or, when there is no such field, the sum of the fields starting with it followed by `_`, e.g.
+//! `allocstall` covers the per-zone `allocstall_normal` and `allocstall_movable` of the newer
+//! kernels. The sample contains the line
+//! per counter with its rate per second over the interval since the previous sample, the first
+//! one is over the interval since the poller start.
+
+use std::time::Instant;
+
+pub const PREFIX: &str = "vmstat:";
+
+const VMSTAT: &str = "/proc/vmstat";
+
+const DEFAULT_COUNTERS: &[&str] = &[
+    "pgfault",
+    "pgmajfault",
+    "pswpin",
+    "pswpout",
+    "allocstall",
+    "compact_stall",
+    "compact_fail",
+    "compact_success",
+];
+
+/// Counters read on every sample with their previous values.
+pub struct Rates {
+    counters: Vec<String>,
+    last: Vec<u64>,
+    time: Instant,
+}
+
+impl Rates {
+    pub fn names(&self) -> &[String] {
+        &self.counters
+    }
+
+    /// Rates of the counters since the previous read, in the order of the names.
+    pub fn read(&mut self) -> std::io::Result<Vec<f64>> {
+        let content = std::fs::read_to_string(VMSTAT)?;
+        let now = Instant::now();
+        let totals: Vec<u64> = totals(&content, &self.counters)
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect();
+
+        let elapsed = now.duration_since(self.time).as_secs_f64();
+        let rates = totals
+            .iter()
+            .zip(&self.last)
+            .map(|(total, last)| match elapsed > 0.0 {
+                // the counters are not reset, but better safe than sorry with the wrapped ones
+                true => total.saturating_sub(*last) as f64 / elapsed,
+                false => 0.0,
+            })
+            .collect();
+
+        self.last = totals;
+        self.time = now;
+        Ok(rates)
+    }
+}
+
+/// Totals of the counters in the vmstat content, `None` for the missing ones.
+fn totals(content: &str, counters: &[String]) -> Vec<Option<u64>> {
+    let fields: Vec<(&str, u64)> = content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter_map(|(field, value)| Some((field, value.trim().parse().ok()?)))
+        .collect();
+
+    counters
+        .iter()
+        .map(|counter| {
+            if let Some((_, value)) = fields.iter().find(|(field, _)| field == counter) {
+                return Some(*value);
+            }
+            fields
+                .iter()
+                .filter(|(field, _)| {
+                    field
+                        .strip_prefix(counter.as_str())
+                        .is_some_and(|rest| rest.starts_with('_'))
+                })
+                .map(|(_, value)| *value)
+                .reduce(|total, value| total + value)
+        })
+        .collect()
+}
+
+impl Rates {
+    /// Start counting, the counters missing in vmstat are an error if `required` or skipped.
+    pub fn new(counters: Vec<String>, required: bool) -> Result<Self, String> {
+        let content = std::fs::read_to_string(VMSTAT)
+            .map_err(|e| format!("cannot read '{}' - {}", VMSTAT, e))?;
+        let time = Instant::now();
+
+        let found = totals(&content, &counters);
+        let missing = counters
+            .iter()
+            .zip(&found)
+            .find(|(_, total)| total.is_none());
+        if let (true, Some((counter, _))) = (required, missing) {
+            return Err(format!("unknown vmstat counter '{}'", counter));
+        }
+        let (counters, found): (Vec<_>, Vec<_>) = counters
+            .into_iter()
+            .zip(found)
+            .filter(|(_, total)| total.is_some())
+            .unzip();
+
+        Ok(Self {
+            counters,
+            last: found.into_iter().map(Option::unwrap_or_default).collect(),
+            time,
+        })
+    }
+}
+
+/// Names of the vmstat fields with the prefix.
+pub fn fields(prefix: &str) -> Result<Vec<String>, String> {
+    let content =
+        std::fs::read_to_string(VMSTAT).map_err(|e| format!("cannot read '{}' - {}", VMSTAT, e))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(field, _)| field.starts_with(prefix))
+        .map(|(field, _)| field.to_owned())
+        .collect())
+}
+
+/// Open the counters for the `vmstat:` pattern.
+pub fn open(pattern: &str) -> Result<Rates, String> {
+    let counters = pattern
+        .strip_prefix(PREFIX)
+        .ok_or_else(|| format!("not a vmstat pattern '{}'", pattern))?;
+    let counters: Vec<String> = counters
+        .split(',')
+        .filter(|c| !c.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    match counters.is_empty() {
+        // the default counters depend on the kernel version and configuration
+        true => Rates::new(
+            DEFAULT_COUNTERS.iter().map(|&c| c.to_owned()).collect(),
+            false,
+        ),
+        false => Rates::new(counters, true),
+    }
+}
+
+#[test]
+fn counter_totals() {
+    let content =
+        "pgfault 100\nallocstall_normal 3\nallocstall_movable 4\nallocstalls 9\nthp_fault 1\nthp_fault_charge 2\n";
+    let counters = ["pgfault", "allocstall", "pgmajfault", "thp_fault"].map(String::from);
+    assert_eq!(
+        totals(content, &counters),
+        [Some(100), Some(7), None, Some(1)]
+    );
+}
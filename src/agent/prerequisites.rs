@@ -0,0 +1,194 @@
+//! Prerequisites of the reliable measurements on the host, checked before the run.
+//!
+//! The degraded prerequisites are the unsynchronized clock, the CPU frequency governors other than
+//! `performance`, the enabled swap and the thermal throttling going on. The strict policy refuses
+//! to start the run with any of them, the permissive one records them into the manifest. The
+//! interfaces missing on the host, like cpufreq in the VMs, are not treated as degraded.
+
+use std::fmt::Display;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Time of watching the throttling counters for the new events.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const THROTTLE_WINDOW: std::time::Duration = std::time::Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Prerequisite {
+    Clock,
+    Governor,
+    Swap,
+    Throttling,
+}
+
+pub const ALL: &[Prerequisite] = &[
+    Prerequisite::Clock,
+    Prerequisite::Governor,
+    Prerequisite::Swap,
+    Prerequisite::Throttling,
+];
+
+impl Display for Prerequisite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Prerequisite::Clock => "clock",
+            Prerequisite::Governor => "governor",
+            Prerequisite::Swap => "swap",
+            Prerequisite::Throttling => "throttling",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do with the degraded prerequisites.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Run anyway, recording them into the manifest.
+    Permissive,
+    /// Refuse to start the run.
+    Strict,
+}
+
+fn clock() -> Option<String> {
+    match super::clock::status() {
+        Some(status) if status.synchronized => None,
+        Some(status) => Some(format!("clock is not synchronized by {}", status.source)),
+        None => Some("clock synchronization status is unknown".into()),
+    }
+}
+
+/// CPUs of the governors other than `performance`, like `cpu3=powersave`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn slow_governors(governors: &[(String, String)]) -> Vec<String> {
+    governors
+        .iter()
+        .filter(|(_, governor)| governor != "performance")
+        .map(|(cpu, governor)| format!("{}={}", cpu, governor))
+        .collect()
+}
+
+/// Swap devices and files of the `/proc/swaps` content.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_swaps(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn governor() -> Option<String> {
+    let pattern = "/sys/devices/system/cpu/cpu[0-9]*/cpufreq/scaling_governor";
+    let governors: Vec<(String, String)> = glob::glob(pattern)
+        .expect("bad governor pattern")
+        .flatten()
+        .filter_map(|path| {
+            let governor = std::fs::read_to_string(&path).ok()?;
+            let cpu = path.parent()?.parent()?.file_name()?.to_string_lossy();
+            Some((cpu.into_owned(), governor.trim().to_owned()))
+        })
+        .collect();
+    let slow = slow_governors(&governors);
+    (!slow.is_empty()).then(|| format!("CPU governors are not performance: {}", slow.join(", ")))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn swap() -> Option<String> {
+    let swaps = parse_swaps(&std::fs::read_to_string("/proc/swaps").ok()?);
+    (!swaps.is_empty()).then(|| format!("swap is enabled: {}", swaps.join(", ")))
+}
+
+/// Total of the thermal throttling events of all the CPUs, none without the counters.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn throttle_events() -> Option<u64> {
+    let pattern = "/sys/devices/system/cpu/cpu[0-9]*/thermal_throttle/*_throttle_count";
+    let counts: Vec<u64> = glob::glob(pattern)
+        .expect("bad throttling pattern")
+        .flatten()
+        .filter_map(|path| std::fs::read_to_string(path).ok()?.trim().parse().ok())
+        .collect();
+    (!counts.is_empty()).then(|| counts.iter().sum())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn throttling() -> Option<String> {
+    // the counters are since the boot, only the new events tell it is going on
+    let before = throttle_events()?;
+    std::thread::sleep(THROTTLE_WINDOW);
+    let events = throttle_events()?.saturating_sub(before);
+    (events > 0).then(|| {
+        format!(
+            "{} thermal throttling events in {:?}",
+            events, THROTTLE_WINDOW
+        )
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn governor() -> Option<String> {
+    None
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn swap() -> Option<String> {
+    None
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn throttling() -> Option<String> {
+    None
+}
+
+/// The degraded ones of the prerequisites with their details.
+pub fn check(prerequisites: &[Prerequisite]) -> Vec<(Prerequisite, String)> {
+    prerequisites
+        .iter()
+        .filter_map(|&prerequisite| {
+            let detail = match prerequisite {
+                Prerequisite::Clock => clock(),
+                Prerequisite::Governor => governor(),
+                Prerequisite::Swap => swap(),
+                Prerequisite::Throttling => throttling(),
+            };
+            Some((prerequisite, detail?))
+        })
+        .collect()
+}
+
+/// Check the prerequisites by the policy, returning the degraded ones to record if allowed.
+pub fn enforce(
+    policy: Policy,
+    prerequisites: &[Prerequisite],
+) -> Result<Vec<(Prerequisite, String)>, String> {
+    let degraded = check(prerequisites);
+    if policy == Policy::Strict && !degraded.is_empty() {
+        let details: Vec<&str> = degraded.iter().map(|(_, detail)| detail.as_str()).collect();
+        return Err(format!(
+            "refusing to run with the degraded prerequisites: {}",
+            details.join("; ")
+        ));
+    }
+    for (prerequisite, detail) in &degraded {
+        warn!("degraded {} prerequisite: {}", prerequisite, detail);
+    }
+    Ok(degraded)
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn degraded_prerequisites() {
+    let governors = [("cpu0", "performance"), ("cpu1", "powersave")]
+        .map(|(cpu, governor)| (cpu.to_owned(), governor.to_owned()));
+    assert_eq!(slow_governors(&governors), ["cpu1=powersave"]);
+
+    let swaps = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n\
+                 /swap.img                               file\t\t2097148\t\t0\t\t-2\n";
+    assert_eq!(parse_swaps(swaps), ["/swap.img"]);
+    assert!(parse_swaps("Filename\tType\tSize\tUsed\tPriority\n").is_empty());
+    assert!(check(&[]).is_empty());
+}
@@ -0,0 +1,93 @@
+//! Change of the scheduling of the running processes.
+//!
+//! The nice value, the CPU affinity and the IO priority are per-thread in Linux, so they are
+//! changed for every thread of the process. The children forked later inherit them, but the
+//! already running ones are not changed.
+
+use super::protocol::{IoClass, PriorityOptions};
+
+// from include/uapi/linux/ioprio.h
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+/// Level of the class when only the class is set, the kernel default for the best-effort one.
+const DEFAULT_IO_LEVEL: u8 = 4;
+
+fn os_error(what: &str, tid: u32) -> String {
+    format!(
+        "cannot set {} of tid {} - {}",
+        what,
+        tid,
+        std::io::Error::last_os_error()
+    )
+}
+
+fn set_nice(tid: u32, nice: i32) -> Result<(), String> {
+    // SAFETY: plain syscall
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) } {
+        0 => Ok(()),
+        _ => Err(os_error("nice value", tid)),
+    }
+}
+
+fn set_affinity(tid: u32, cpus: &[usize]) -> Result<(), String> {
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    // SAFETY: the zeroed set is valid and CPU_SET is bounded by the check below
+    let res = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            if cpu >= 8 * size {
+                return Err(format!("CPU {} is out of the supported range", cpu));
+            }
+            libc::CPU_SET(cpu, &mut set);
+        }
+        libc::sched_setaffinity(tid as libc::pid_t, size, &set)
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(os_error("CPU affinity", tid)),
+    }
+}
+
+fn set_io_priority(tid: u32, class: IoClass, level: u8) -> Result<(), String> {
+    let class = match class {
+        IoClass::Realtime => 1,
+        IoClass::BestEffort => 2,
+        IoClass::Idle => 3,
+    };
+    let ioprio = (class << IOPRIO_CLASS_SHIFT) | level as libc::c_int;
+    // SAFETY: plain syscall, there is no libc wrapper
+    match unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid, ioprio) } {
+        0 => Ok(()),
+        _ => Err(os_error("IO priority", tid)),
+    }
+}
+
+/// Threads of the process, the process itself if they cannot be listed.
+fn threads(pid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir(format!("/proc/{}/task", pid)) else {
+        return vec![pid];
+    };
+    let mut tids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect();
+    tids.sort_unstable();
+    tids
+}
+
+/// Apply the scheduling parameters to all the threads of the process.
+pub fn apply(pid: u32, opts: &PriorityOptions) -> Result<(), String> {
+    opts.check()?;
+    for tid in threads(pid) {
+        if let Some(nice) = opts.nice {
+            set_nice(tid, nice)?;
+        }
+        if let Some(cpus) = &opts.cpus {
+            set_affinity(tid, cpus)?;
+        }
+        if let Some(class) = opts.io_class {
+            set_io_priority(tid, class, opts.io_level.unwrap_or(DEFAULT_IO_LEVEL))?;
+        }
+    }
+    Ok(())
+}
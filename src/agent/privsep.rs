@@ -0,0 +1,223 @@
+//! Privilege separation: the agent started as root drops to the unprivileged user, the few
+//! privileged operations are done for it by the root helper process forked before that.
+//!
+//! The helper serves the requests over the Unix socket pair, a JSON message per request, and
+//! returns the opened descriptors with `SCM_RIGHTS`. It only opens the system-wide perf counters
+//! and the kernel log, so the workloads, the scenario parsing and the rest of the agent run
+//! unprivileged. The helper exits when the agent closes its end of the socket.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::oom;
+use super::poller::perf;
+
+/// Agent end of the socket, set once the privileges are dropped.
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+
+const MAX_MESSAGE: usize = 4096;
+
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    PerfEvent { type_: u32, config: u64, cpu: u32 },
+    Kmsg,
+}
+
+/// Send the message with the optional descriptor.
+fn send(sock: RawFd, data: &[u8], fd: Option<RawFd>) -> std::io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // aligned for cmsghdr, large enough for the single descriptor
+    let mut control = [0u64; 4];
+    // SAFETY: the header points to the buffers living until sendmsg returns, the control message
+    // fits into the control buffer
+    let res = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if let Some(fd) = fd {
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        }
+        libc::sendmsg(sock, &msg, libc::MSG_NOSIGNAL)
+    };
+    match res < 0 {
+        true => Err(std::io::Error::last_os_error()),
+        false => Ok(()),
+    }
+}
+
+/// Receive the message with the optional descriptor, the empty one means the peer has exited.
+fn recv(sock: RawFd) -> std::io::Result<(Vec<u8>, Option<File>)> {
+    let mut data = vec![0u8; MAX_MESSAGE];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = [0u64; 4];
+    // SAFETY: the header points to the buffers living until recvmsg returns, the received
+    // descriptor is owned by nobody else
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let len = libc::recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        data.truncate(len as usize);
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        let file = match !cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        {
+            true => {
+                let fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd);
+                Some(File::from_raw_fd(fd))
+            }
+            false => None,
+        };
+        Ok((data, file))
+    }
+}
+
+/// Serve the agent requests until it exits.
+fn serve(sock: OwnedFd) {
+    loop {
+        let request = match recv(sock.as_raw_fd()) {
+            Ok((data, _)) if data.is_empty() => return,
+            Ok((data, _)) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let opened = match serde_json::from_slice(&request) {
+            Ok(Request::PerfEvent { type_, config, cpu }) => perf::open_event(type_, config, cpu),
+            Ok(Request::Kmsg) => oom::open_kmsg(),
+            Err(_) => Err(std::io::Error::from_raw_os_error(libc::EINVAL)),
+        };
+        // the errno is sent back to be reported by the agent as if the call failed there
+        let (reply, fd): (Result<(), i32>, _) = match &opened {
+            Ok(file) => (Ok(()), Some(file.as_raw_fd())),
+            Err(e) => (Err(e.raw_os_error().unwrap_or(libc::EIO)), None),
+        };
+        let reply = serde_json::to_vec(&reply).expect("cannot serialize the reply");
+        if send(sock.as_raw_fd(), &reply, fd).is_err() {
+            return;
+        }
+    }
+}
+
+/// Do the privileged operation by the helper, `None` if the privileges are not separated.
+pub fn delegate(request: Request) -> Option<std::io::Result<File>> {
+    let sock = HELPER
+        .get()?
+        .lock()
+        .expect("privileged helper lock is poisoned");
+    let request = serde_json::to_vec(&request).expect("cannot serialize the request");
+    let res = send(sock.as_raw_fd(), &request, None).and_then(|()| {
+        let (reply, file) = recv(sock.as_raw_fd())?;
+        let lost = || std::io::Error::other("privileged helper has exited");
+        match serde_json::from_slice::<Result<(), i32>>(&reply) {
+            Ok(Ok(())) => file.ok_or_else(lost),
+            Ok(Err(errno)) => Err(std::io::Error::from_raw_os_error(errno)),
+            Err(_) => Err(lost()),
+        }
+    });
+    Some(res)
+}
+
+fn lookup(user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let name = CString::new(user).map_err(|_| format!("bad user name '{}'", user))?;
+    let mut buf = vec![0 as libc::c_char; 16 << 10];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the entry and the buffer outlive the call, the strings are not used
+    let entry = unsafe {
+        let mut entry: libc::passwd = std::mem::zeroed();
+        let res = libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        );
+        (res == 0 && !found.is_null()).then_some(entry)
+    };
+    entry
+        .map(|entry| (entry.pw_uid, entry.pw_gid))
+        .ok_or_else(|| format!("unknown user '{}'", user))
+}
+
+/// Fork the root helper and drop the agent privileges to the user owning the output directory.
+///
+/// Must be called before starting any threads.
+pub fn start(user: &str, outdir: &Path) -> Result<(), String> {
+    // SAFETY: plain syscall
+    if unsafe { libc::geteuid() } != 0 {
+        return Err("privilege separation needs the agent started as root".into());
+    }
+    let (uid, gid) = lookup(user)?;
+    std::os::unix::fs::chown(outdir, Some(uid), Some(gid))
+        .map_err(|e| format!("cannot chown '{}' - {}", outdir.to_string_lossy(), e))?;
+
+    let mut fds = [0; 2];
+    let kind = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+    // SAFETY: plain syscall filling the array
+    if unsafe { libc::socketpair(libc::AF_UNIX, kind, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(format!(
+            "cannot create helper socket - {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    // SAFETY: the descriptors have been just created and are owned by nobody else
+    let (agent, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // SAFETY: the agent is single-threaded yet, so the child may continue running Rust code
+    match unsafe { libc::fork() } {
+        -1 => Err(format!(
+            "cannot fork helper - {}",
+            std::io::Error::last_os_error()
+        )),
+        0 => {
+            drop(agent);
+            // the terminal signals are for the agent, the helper exits after it
+            // SAFETY: plain syscalls
+            unsafe {
+                libc::signal(libc::SIGINT, libc::SIG_IGN);
+                libc::signal(libc::SIGTERM, libc::SIG_IGN);
+            }
+            serve(helper);
+            std::process::exit(0);
+        }
+        _ => {
+            drop(helper);
+            // SAFETY: plain syscalls, the group is dropped before the user
+            let dropped = unsafe {
+                libc::setgroups(1, &gid) == 0 && libc::setgid(gid) == 0 && libc::setuid(uid) == 0
+            };
+            if !dropped {
+                return Err(format!(
+                    "cannot drop privileges to '{}' - {}",
+                    user,
+                    std::io::Error::last_os_error()
+                ));
+            }
+            let _ = HELPER.set(Mutex::new(agent));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Lookup of the running processes not spawned by the agent.
+
+use regex::Regex;
+
+use super::protocol::PidTarget;
+
+/// Per-process files polled for the attached processes.
+const PROCESS_FILES: &str = "{stat,status,io}";
+
+/// Pids of the processes running the command matching the regex, the agent excluded.
+fn find_by_name(name: &str) -> Result<Vec<u32>, String> {
+    let re = Regex::new(name).map_err(|e| format!("bad name pattern '{}' - {}", name, e))?;
+    let entries = std::fs::read_dir("/proc").map_err(|e| format!("cannot list /proc - {}", e))?;
+
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| pid != std::process::id())
+        .filter(|pid| {
+            // the process may exit while enumerating
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .is_ok_and(|comm| re.is_match(comm.trim_end()))
+        })
+        .collect();
+    pids.sort_unstable();
+    Ok(pids)
+}
+
+/// Poll pattern of the per-process files of the target processes.
+pub fn pattern(target: &PidTarget) -> Result<String, String> {
+    let pids = match target {
+        PidTarget::Pid(pid) => vec![*pid],
+        PidTarget::Name(name) => find_by_name(name)?,
+        #[cfg(target_os = "linux")]
+        PidTarget::Container(spec) => super::container::pids(spec)?,
+        #[cfg(not(target_os = "linux"))]
+        PidTarget::Container(_) => return Err("containers are supported only on Linux".into()),
+    };
+    let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+    match pids.as_slice() {
+        [] => Err(format!("no running processes matching {}", target)),
+        [pid] => Ok(format!("/proc/{}/{}", pid, PROCESS_FILES)),
+        _ => Ok(format!("/proc/{{{}}}/{}", pids.join(","), PROCESS_FILES)),
+    }
+}
@@ -1,32 +1,902 @@
 //! Module defining PMPPT protocol between host and agent.
 
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
 /// Input data for the agent.
 #[derive(Debug, Clone)]
 pub enum PmpptRequest {
     Poll {
         pattern: String,
+        opts: PollOptions,
     },
     Spawn {
         cmd: String,
         args: Vec<String>,
         mode: SpawnMode,
+        opts: SpawnOptions,
+    },
+    /// Poll the per-process files of the already running processes.
+    PollPid {
+        target: PidTarget,
+        opts: PollOptions,
+    },
+    /// Snapshot the files and the command outputs right before and after the foreground spawn.
+    Bracket {
+        pattern: Option<String>,
+        commands: Vec<Vec<String>>,
+        cmd: String,
+        args: Vec<String>,
+        opts: SpawnOptions,
+    },
+    /// Follow the growing file, copying the appended lines with their timestamps.
+    Tail {
+        path: PathBuf,
+    },
+    /// Record the filesystem events of the paths matching the pattern.
+    Watch {
+        pattern: String,
+    },
+    /// Create the FIFO in the output directory, storing the lines written into it by the workload.
+    Ingest {
+        name: String,
+    },
+    /// Capture the systemd journal entries of the units, of the whole system if empty.
+    Journal {
+        units: Vec<String>,
+    },
+    /// Copy the files of the other host into the output directory at the stop, over SSH.
+    Collect {
+        host: String,
+        paths: Vec<String>,
+    },
+    /// Run the built-in latency probe, storing its histogram when it is stopped.
+    Latency {
+        probe: LatencyProbe,
+        realtime: bool,
+    },
+    /// Run the built-in load generator till the stop or for the duration.
+    Load {
+        load: Load,
+        duration: Option<Duration>,
+    },
+    /// Suspend the sampling of the poller, it is resumed by [`PmpptRequest::ResumeId`].
+    PauseId {
+        id: u32,
+    },
+    ResumeId {
+        id: u32,
+    },
+    /// Stop the background process with its descendants, it is continued by [`PmpptRequest::Thaw`].
+    Freeze {
+        id: u32,
     },
+    Thaw {
+        id: u32,
+    },
+    /// Change the scheduling of the running background process.
+    Renice {
+        id: u32,
+        opts: PriorityOptions,
+    },
+    /// Create the network object, it is removed by [`PmpptRequest::NetDestroy`] or at the stop.
+    NetCreate {
+        object: NetObject,
+    },
+    NetDestroy {
+        id: u32,
+    },
+    /// Inject the fault, it is reverted by [`PmpptRequest::Revert`] or at the stop.
+    Fault {
+        fault: Fault,
+    },
+    Revert {
+        id: u32,
+    },
+    /// Block till the CPU usage of the whole system stays below the percentage for the window.
+    WaitIdle {
+        cpu_below: f64,
+        window: Duration,
+        timeout: Duration,
+    },
+    /// Execute the requests in order, responding once with [`PmpptResponse::Batch`].
+    Batch(Vec<PmpptRequest>),
     Finish,
-    Abort,
+    Abort {
+        reason: AbortReason,
+    },
+}
+
+/// Why the run is aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbortReason {
+    /// Explicitly requested by the controller.
+    Requested,
+    /// The step failed and the controller gave up on it.
+    StepFailed,
+    /// No valid request can be received from the controller.
+    Protocol,
+    /// The agent got the termination signal.
+    Signal,
+}
+
+impl std::fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            AbortReason::Requested => "requested by the controller",
+            AbortReason::StepFailed => "step failed",
+            AbortReason::Protocol => "protocol failure",
+            AbortReason::Signal => "signal",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl PmpptRequest {
+    /// Name of the request kind for the diagnostics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PmpptRequest::Poll { .. } => "Poll",
+            PmpptRequest::Spawn { .. } => "Spawn",
+            PmpptRequest::PollPid { .. } => "PollPid",
+            PmpptRequest::Bracket { .. } => "Bracket",
+            PmpptRequest::Tail { .. } => "Tail",
+            PmpptRequest::Watch { .. } => "Watch",
+            PmpptRequest::Ingest { .. } => "Ingest",
+            PmpptRequest::Journal { .. } => "Journal",
+            PmpptRequest::Collect { .. } => "Collect",
+            PmpptRequest::Latency { .. } => "Latency",
+            PmpptRequest::Load { .. } => "Load",
+            PmpptRequest::PauseId { .. } => "PauseId",
+            PmpptRequest::ResumeId { .. } => "ResumeId",
+            PmpptRequest::Freeze { .. } => "Freeze",
+            PmpptRequest::Thaw { .. } => "Thaw",
+            PmpptRequest::Renice { .. } => "Renice",
+            PmpptRequest::NetCreate { .. } => "NetCreate",
+            PmpptRequest::NetDestroy { .. } => "NetDestroy",
+            PmpptRequest::Fault { .. } => "Fault",
+            PmpptRequest::Revert { .. } => "Revert",
+            PmpptRequest::WaitIdle { .. } => "WaitIdle",
+            PmpptRequest::Batch(_) => "Batch",
+            PmpptRequest::Finish => "Finish",
+            PmpptRequest::Abort { .. } => "Abort",
+        }
+    }
+}
+
+/// Processes not spawned by the agent, selected by the pid, the command name regex or the
+/// container spec like in the container poll patterns.
+#[derive(Debug, Clone)]
+pub enum PidTarget {
+    Pid(u32),
+    Name(String),
+    Container(String),
+}
+
+impl std::fmt::Display for PidTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PidTarget::Pid(pid) => write!(f, "pid {}", pid),
+            PidTarget::Name(name) => write!(f, "name '{}'", name),
+            PidTarget::Container(spec) => write!(f, "container '{}'", spec),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum SpawnMode {
     Foreground,
     BackgroundWait,
     BackgroundKill,
 }
 
+/// Representation of the polled content in the poll logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// Text with the invalid UTF-8 sequences replaced by U+FFFD.
+    #[default]
+    Lossy,
+    /// Base64 of the raw bytes, the single line per source.
+    Base64,
+}
+
+impl Encoding {
+    pub fn is_lossy(&self) -> bool {
+        *self == Encoding::Lossy
+    }
+}
+
+/// Format of the sample timestamps in the poll logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// RFC 3339 time with the local offset.
+    #[default]
+    Local,
+    /// RFC 3339 time in UTC, merged across the hosts of the different timezones as is.
+    Utc,
+    /// Nanoseconds since the Unix epoch.
+    EpochNs,
+    /// Nanoseconds of the monotonic clock, not affected by the clock adjustments.
+    MonotonicNs,
+}
+
+impl TimeFormat {
+    pub fn is_local(&self) -> bool {
+        *self == TimeFormat::Local
+    }
+}
+
+/// Optional poller parameters, agent defaults are used for the missing ones.
+#[derive(Debug, Clone, Default)]
+pub struct PollOptions {
+    pub period: Option<Duration>,
+    /// Run the poller with the real-time scheduling priority if possible.
+    pub realtime: Option<bool>,
+    /// Enables the adaptive polling, speeding up to this period when the values change.
+    pub fast_period: Option<Duration>,
+    /// Relative change of any value treated as the activity by the adaptive polling.
+    pub change_threshold: Option<f64>,
+    /// Store only the samples differing from the previous one.
+    pub dedup: Option<bool>,
+    /// Period of the forced storing of the unchanged samples in the dedup mode.
+    pub keyframe: Option<Duration>,
+    /// Limit of the content read from every file per sample.
+    pub max_bytes: Option<usize>,
+    /// Number of threads reading the files of every sample concurrently.
+    pub parallel: Option<usize>,
+    /// Buffer the output and write it once per this interval instead of every sample.
+    pub flush_interval: Option<Duration>,
+    /// Periodically fsync the output for the crash safety.
+    pub fsync_interval: Option<Duration>,
+    /// Store the samples in the compact binary format instead of the text one.
+    pub binary: Option<bool>,
+    /// Write the separate poll log per source instead of the single one.
+    pub split: Option<bool>,
+    /// Record the read time and the deadline misses of every sample.
+    pub stats: Option<bool>,
+    /// Also store the structured samples into the run SQLite database.
+    pub sqlite: Option<bool>,
+    /// Also write the structured samples into the Parquet file next to the poll log.
+    pub parquet: Option<bool>,
+    /// Write the output to this directory during the run and move it to the outdir at stop.
+    pub staging_dir: Option<PathBuf>,
+    /// Fail if any of the brace expansions of the pattern matches nothing.
+    pub strict: Option<bool>,
+    /// Encoding of the content, e.g. for the binary sources.
+    pub encoding: Option<Encoding>,
+    /// Initial samples not stored into the poll log, e.g. as the rates derived from them are
+    /// meaningless. The warm-up lasts for both the samples and the time if both are given.
+    pub warmup_samples: Option<u64>,
+    pub warmup: Option<Duration>,
+    /// Store the warm-up samples into the separate log next to the poll log.
+    pub warmup_log: Option<bool>,
+    /// Stop polling after this time, without waiting for the stop of the agent.
+    pub duration: Option<Duration>,
+    /// Format of the sample timestamps.
+    pub timestamp: Option<TimeFormat>,
+}
+
+impl PollOptions {
+    /// Fill the missing options from the defaults.
+    pub fn or(&self, defaults: &PollOptions) -> PollOptions {
+        PollOptions {
+            period: self.period.or(defaults.period),
+            realtime: self.realtime.or(defaults.realtime),
+            fast_period: self.fast_period.or(defaults.fast_period),
+            change_threshold: self.change_threshold.or(defaults.change_threshold),
+            dedup: self.dedup.or(defaults.dedup),
+            keyframe: self.keyframe.or(defaults.keyframe),
+            max_bytes: self.max_bytes.or(defaults.max_bytes),
+            parallel: self.parallel.or(defaults.parallel),
+            flush_interval: self.flush_interval.or(defaults.flush_interval),
+            fsync_interval: self.fsync_interval.or(defaults.fsync_interval),
+            binary: self.binary.or(defaults.binary),
+            split: self.split.or(defaults.split),
+            stats: self.stats.or(defaults.stats),
+            sqlite: self.sqlite.or(defaults.sqlite),
+            parquet: self.parquet.or(defaults.parquet),
+            staging_dir: self
+                .staging_dir
+                .clone()
+                .or_else(|| defaults.staging_dir.clone()),
+            strict: self.strict.or(defaults.strict),
+            encoding: self.encoding.or(defaults.encoding),
+            warmup_samples: self.warmup_samples.or(defaults.warmup_samples),
+            warmup: self.warmup.or(defaults.warmup),
+            warmup_log: self.warmup_log.or(defaults.warmup_log),
+            duration: self.duration.or(defaults.duration),
+            timestamp: self.timestamp.or(defaults.timestamp),
+        }
+    }
+}
+
+/// IO scheduling class as in `ioprio_set(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// Scheduling parameters of the running process, the missing ones are kept as is.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriorityOptions {
+    pub nice: Option<i32>,
+    /// CPUs the process is allowed to run on.
+    pub cpus: Option<Vec<usize>>,
+    pub io_class: Option<IoClass>,
+    /// Priority within the IO class, from 0 (the highest) to 7.
+    pub io_level: Option<u8>,
+}
+
+impl PriorityOptions {
+    pub fn check(&self) -> Result<(), String> {
+        if self.nice.is_none() && self.cpus.is_none() && self.io_class.is_none() {
+            return Err("nothing to change, expected any of 'nice', 'cpus' or 'io_class'".into());
+        }
+        if let Some(nice) = self.nice.filter(|nice| !(-20..=19).contains(nice)) {
+            return Err(format!("nice value {} is out of range -20..19", nice));
+        }
+        if self.cpus.as_ref().is_some_and(Vec::is_empty) {
+            return Err("empty CPU list".into());
+        }
+        if let Some(level) = self.io_level.filter(|&level| level > 7) {
+            return Err(format!("IO priority level {} is out of range 0..7", level));
+        }
+        if self.io_level.is_some() && self.io_class.is_none() {
+            return Err("IO priority level is set without the IO class".into());
+        }
+        Ok(())
+    }
+}
+
+/// Optional parameters of the spawned process, inherited from the agent if missing.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    /// Collect the core dump into the outdir if the process crashes.
+    pub core_dumps: Option<bool>,
+    /// Run the process in the fresh namespaces, only on Linux.
+    pub isolate: Option<Isolation>,
+    /// Restrict the syscalls of the process, only on Linux.
+    pub seccomp: Option<SeccompProfile>,
+    /// Change the limits of the process by the schedule, only on Linux.
+    pub pacing: Option<Pacing>,
+    /// Run the process in the transient systemd unit, only on Linux.
+    pub systemd: Option<SystemdUnit>,
+    /// Run the process inside the running container, only on Linux.
+    pub container: Option<Container>,
+    /// Track the time from the start to the first stdout byte.
+    pub first_output: bool,
+    /// Umask inherited by the process, only on unix. The service managers like systemd and the
+    /// container runtimes start the process with their own one.
+    pub umask: Option<Mode>,
+    /// Permissions of the stdout and stderr logs of the process, only on unix.
+    pub output_mode: Option<Mode>,
+    /// Terminate the background process after this time, only on unix.
+    pub duration: Option<Duration>,
+    /// Return only the excerpt of the foreground output, the full one stays in the outdir.
+    pub reply: Option<Excerpt>,
+}
+
+/// Head and tail of the foreground output returned in the response, e.g. for the slow links.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Excerpt {
+    #[serde(default)]
+    pub head_kib: u64,
+    #[serde(default)]
+    pub tail_kib: u64,
+}
+
+/// File mode bits in the octal form like "027", as JSON has no octal numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Mode(pub u32);
+
+impl TryFrom<String> for Mode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match u32::from_str_radix(&value, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Mode(mode)),
+            _ => Err(format!("bad octal file mode '{}'", value)),
+        }
+    }
+}
+
+impl From<Mode> for String {
+    fn from(mode: Mode) -> Self {
+        format!("{:03o}", mode.0)
+    }
+}
+
+/// Network object for the self-contained network benchmarks, the addresses are in the CIDR form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NetObject {
+    /// Named network namespace with the loopback up, usable by `ip netns exec` in the spawns.
+    Netns { name: String },
+    /// Veth pair with the ends optionally moved into the namespaces and addressed.
+    Veth {
+        name: String,
+        peer: String,
+        netns: Option<String>,
+        peer_netns: Option<String>,
+        addr: Option<String>,
+        peer_addr: Option<String>,
+    },
+    /// Bridge with the host interfaces attached as its ports.
+    Bridge {
+        name: String,
+        #[serde(default)]
+        ports: Vec<String>,
+        addr: Option<String>,
+    },
+}
+
+impl std::fmt::Display for NetObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetObject::Netns { name } => write!(f, "netns {}", name),
+            NetObject::Veth { name, peer, .. } => write!(f, "veth {}/{}", name, peer),
+            NetObject::Bridge { name, .. } => write!(f, "bridge {}", name),
+        }
+    }
+}
+
+/// Fault injected into the system, reverted in the reverse order at the stop.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// `SIGKILL` of the background process of the agent with its tree or of the external
+    /// process, there is nothing to revert.
+    Kill { id: Option<u32>, pid: Option<u32> },
+    /// Interface set down, it is set up again.
+    LinkDown { dev: String },
+    /// Root netem qdisc of the interface, e.g. "delay 100ms 10ms loss 1%", it is deleted.
+    Netem { dev: String, params: String },
+    /// Filesystem of the directory filled up to the percent like `df` counts it by the
+    /// preallocated file in the directory, the file is removed.
+    Fill { dir: PathBuf, percent: f64 },
+    /// Cgroup v2 interface file written, e.g. "cpu.max" of "10000 100000", its content is
+    /// restored. The cgroup path is relative to the cgroup root if not absolute.
+    Throttle {
+        cgroup: PathBuf,
+        file: String,
+        value: String,
+    },
+}
+
+impl Fault {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Fault::Kill { id, pid } if id.is_some() == pid.is_some() => {
+                Err("kill fault needs exactly one of id and pid".into())
+            }
+            // zero would kill the process group of the agent
+            Fault::Kill { pid: Some(0), .. } => Err("bad pid 0 of the kill fault".into()),
+            Fault::Fill { percent, .. } if !(*percent > 0.0 && *percent <= 100.0) => {
+                Err(format!("fill {}% is out of 0..100", percent))
+            }
+            Fault::Throttle { file, .. } if file.is_empty() || file.contains('/') => {
+                Err(format!("bad cgroup file name '{}'", file))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Kill { id: Some(id), .. } => write!(f, "kill id={}", id),
+            Fault::Kill { pid, .. } => write!(f, "kill pid={}", pid.unwrap_or_default()),
+            Fault::LinkDown { dev } => write!(f, "link down {}", dev),
+            Fault::Netem { dev, params } => write!(f, "netem '{}' on {}", params, dev),
+            Fault::Fill { dir, percent } => {
+                write!(f, "fill '{}' to {}%", dir.to_string_lossy(), percent)
+            }
+            Fault::Throttle {
+                cgroup,
+                file,
+                value,
+            } => write!(
+                f,
+                "throttle {}='{}' of '{}'",
+                file,
+                value,
+                cgroup.to_string_lossy()
+            ),
+        }
+    }
+}
+
+/// Seccomp filter of the spawned process, the denied syscalls fail with `EPERM`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SeccompProfile {
+    Preset(SeccompPreset),
+    /// Raw BPF program file, e.g. exported by `seccomp_export_bpf()` of libseccomp.
+    Policy {
+        policy: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeccompPreset {
+    /// Deny the administrative syscalls: mounts, modules, reboot, tracing other processes, etc.
+    Restricted,
+    /// Also deny the network sockets and io_uring, the Unix sockets are allowed.
+    NoNetwork,
+}
+
+/// Namespaces of the isolated process, the mount and PID ones are always new.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Isolation {
+    /// New network namespace with only the loopback interface.
+    #[serde(default)]
+    pub network: bool,
+    /// Host paths bind-mounted read-only into the empty root, the host root is kept if none.
+    #[serde(default)]
+    pub binds: Vec<PathBuf>,
+}
+
+/// Limit changed by the pacing schedule, its values are applied verbatim.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "knob", rename_all = "lowercase")]
+pub enum PacingKnob {
+    /// `cpu.max` of the process cgroup, e.g. "50000 100000" or "max".
+    Cpu,
+    /// `io.max` of the process cgroup, e.g. "8:0 wbps=1048576" or "8:0 wbps=max".
+    Io,
+    /// Root qdisc of the interface shaping all its traffic, not only the process one, e.g.
+    /// "tbf rate 100mbit burst 256kb latency 50ms".
+    Egress { dev: String },
+}
+
+/// Staircase schedule of the process limit: every value is held for the step, the last one till
+/// the process exits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pacing {
+    #[serde(flatten)]
+    pub knob: PacingKnob,
+    pub step_s: f64,
+    pub limits: Vec<String>,
+}
+
+impl Pacing {
+    pub fn check(&self) -> Result<(), String> {
+        if !(self.step_s.is_finite() && self.step_s > 0.0) {
+            return Err(format!("bad pacing step {}s", self.step_s));
+        }
+        if self.limits.is_empty() {
+            return Err("pacing schedule has no limits".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitKind {
+    /// The agent child placed into the scope, inheriting its environment.
+    #[default]
+    Scope,
+    /// The service started by systemd with the output piped to the agent.
+    Service,
+}
+
+/// Transient systemd unit of the process, stopped with all its processes when the process exits.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SystemdUnit {
+    #[serde(default)]
+    pub kind: UnitKind,
+    /// Unit properties like "CPUQuota=50%" or "MemoryMax=1G".
+    #[serde(default)]
+    pub properties: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    #[default]
+    Docker,
+    Podman,
+    /// Kubernetes node runtime managed by `crictl`.
+    Cri,
+}
+
+/// Running container to spawn the process in, by its name or id, the CRI one by `POD/CONTAINER`
+/// or just `POD` for its first container.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Container {
+    pub name: String,
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
+    /// Enter the namespaces of the container by `nsenter` instead of the runtime `exec`, so the
+    /// process is the agent descendant and is stopped together with its spawn.
+    #[serde(default)]
+    pub nsenter: bool,
+}
+
+/// Built-in latency probe, the latencies are in the nanoseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "probe", rename_all = "lowercase")]
+pub enum LatencyProbe {
+    /// Oversleep of the periodic wakeups at the absolute times, like `cyclictest` does.
+    Wakeup { interval_us: u64 },
+    /// Time of the reads of the blocks at the random offsets of the file or the block device,
+    /// bypassing the page cache, back to back if the interval is zero.
+    Read {
+        path: PathBuf,
+        block_size: usize,
+        #[serde(default)]
+        interval_us: u64,
+    },
+}
+
+impl LatencyProbe {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            LatencyProbe::Wakeup { interval_us: 0 } => {
+                Err("wakeup interval must be positive".into())
+            }
+            LatencyProbe::Read { block_size, .. } if *block_size == 0 || block_size % 512 != 0 => {
+                Err(format!(
+                    "block size {} is not a multiple of 512",
+                    block_size
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for LatencyProbe {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LatencyProbe::Wakeup { interval_us } => write!(f, "wakeup every {}us", interval_us),
+            LatencyProbe::Read {
+                path, block_size, ..
+            } => write!(f, "read {}B of '{}'", block_size, path.to_string_lossy()),
+        }
+    }
+}
+
+/// Built-in load generator, for the baseline and interference runs without `stress-ng`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Load {
+    /// Threads spinning the percent of every 100ms and sleeping the rest of it.
+    Cpu {
+        threads: u32,
+        #[serde(default = "full_load")]
+        percent: u32,
+    },
+    /// Memory allocated and touched at the rate up to the size, then its oldest megabytes are
+    /// freed and allocated again at the same rate.
+    Memory { size_mb: u64, rate_mb_s: f64 },
+    /// Blocks written to the new file up to its size, the sequential writes wrap around, the
+    /// file is removed at the stop.
+    Disk {
+        path: PathBuf,
+        size_mb: u64,
+        block_size: usize,
+        #[serde(default)]
+        random: bool,
+        #[serde(default)]
+        fsync: bool,
+        rate_mb_s: Option<f64>,
+    },
+}
+
+fn full_load() -> u32 {
+    100
+}
+
+fn check_rate(rate_mb_s: f64) -> Result<(), String> {
+    match rate_mb_s.is_finite() && rate_mb_s > 0.0 {
+        true => Ok(()),
+        false => Err(format!("bad load rate {}MB/s", rate_mb_s)),
+    }
+}
+
+impl Load {
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            Load::Cpu { threads: 0, .. } => Err("cpu load needs at least one thread".into()),
+            Load::Cpu { percent, .. } if !(1..=100).contains(percent) => {
+                Err(format!("cpu load {}% is out of 1..100", percent))
+            }
+            Load::Memory { size_mb: 0, .. } | Load::Disk { size_mb: 0, .. } => {
+                Err("load size must be positive".into())
+            }
+            Load::Memory { rate_mb_s, .. } => check_rate(*rate_mb_s),
+            Load::Disk {
+                size_mb,
+                block_size,
+                rate_mb_s,
+                ..
+            } => {
+                if *block_size == 0 || *block_size as u64 > size_mb * 1024 * 1024 {
+                    return Err(format!("bad block size {} of the disk load", block_size));
+                }
+                rate_mb_s.map_or(Ok(()), check_rate)
+            }
+            Load::Cpu { .. } => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Load {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Load::Cpu { threads, percent } => write!(f, "cpu {}x{}%", threads, percent),
+            Load::Memory { size_mb, rate_mb_s } => {
+                write!(f, "memory {}MB at {}MB/s", size_mb, rate_mb_s)
+            }
+            Load::Disk {
+                path,
+                size_mb,
+                random,
+                ..
+            } => {
+                let order = if *random { "random" } else { "sequential" };
+                write!(
+                    f,
+                    "disk {} {}MB of '{}'",
+                    order,
+                    size_mb,
+                    path.to_string_lossy()
+                )
+            }
+        }
+    }
+}
+
 pub type IdOrError = Result<u32, String>;
 
+/// Outcome of the completed foreground process.
+#[derive(Debug, Clone)]
+pub struct FgOutput {
+    pub id: u32,
+    pub exit_code: Option<u32>,
+    /// Wall-clock time from the start of the process to its exit.
+    pub duration: Duration,
+    /// Time from the start to the first stdout byte, if tracked and there was any.
+    pub first_output: Option<Duration>,
+    /// Output, or its excerpt with the marker line in place of the omitted bytes.
+    pub stdout: String,
+    /// Bytes of the output omitted from the excerpt.
+    pub omitted: u64,
+}
+
+/// Background process started by the agent.
+#[derive(Debug, Clone)]
+pub struct BgProcess {
+    pub id: u32,
+    /// OS pid, unknown if the process has already exited.
+    pub pid: Option<u32>,
+    /// Process group the process belongs to, unix only.
+    pub pgid: Option<u32>,
+}
+
+impl FgOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
 /// Agent's responses.
 pub enum PmpptResponse {
     Poll(IdOrError),
+    SpawnFg(Result<FgOutput, String>),
+    SpawnBg(Result<BgProcess, String>),
+    Bracket(Result<FgOutput, String>),
+    Tail(IdOrError),
+    Watch(IdOrError),
+    Ingest(IdOrError),
+    Journal(IdOrError),
+    Collect(IdOrError),
+    Latency(IdOrError),
+    Load(IdOrError),
+    PauseId(Result<(), String>),
+    ResumeId(Result<(), String>),
+    Freeze(Result<(), String>),
+    Thaw(Result<(), String>),
+    Renice(Result<(), String>),
+    NetCreate(IdOrError),
+    NetDestroy(Result<(), String>),
+    Fault(IdOrError),
+    Revert(Result<(), String>),
+    /// Time of waiting for the idle system.
+    WaitIdle(Result<Duration, String>),
+    /// Responses of the batch members in order, the ones after `Finish` or `Abort` are missing.
+    Batch(Vec<PmpptResponse>),
+}
+
+impl PmpptResponse {
+    /// Name of the response kind for the diagnostics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PmpptResponse::Poll(_) => "Poll",
+            PmpptResponse::SpawnFg(_) => "SpawnFg",
+            PmpptResponse::SpawnBg(_) => "SpawnBg",
+            PmpptResponse::Bracket(_) => "Bracket",
+            PmpptResponse::Tail(_) => "Tail",
+            PmpptResponse::Watch(_) => "Watch",
+            PmpptResponse::Ingest(_) => "Ingest",
+            PmpptResponse::Journal(_) => "Journal",
+            PmpptResponse::Collect(_) => "Collect",
+            PmpptResponse::Latency(_) => "Latency",
+            PmpptResponse::Load(_) => "Load",
+            PmpptResponse::PauseId(_) => "PauseId",
+            PmpptResponse::ResumeId(_) => "ResumeId",
+            PmpptResponse::Freeze(_) => "Freeze",
+            PmpptResponse::Thaw(_) => "Thaw",
+            PmpptResponse::Renice(_) => "Renice",
+            PmpptResponse::NetCreate(_) => "NetCreate",
+            PmpptResponse::NetDestroy(_) => "NetDestroy",
+            PmpptResponse::Fault(_) => "Fault",
+            PmpptResponse::Revert(_) => "Revert",
+            PmpptResponse::WaitIdle(_) => "WaitIdle",
+            PmpptResponse::Batch(_) => "Batch",
+        }
+    }
+
+    /// Check that this is the response to the request, for the batch also all the member ones.
+    pub fn answers(&self, req: &PmpptRequest) -> bool {
+        match (self, req) {
+            (PmpptResponse::Poll(_), PmpptRequest::Poll { .. } | PmpptRequest::PollPid { .. }) => {
+                true
+            }
+            (PmpptResponse::SpawnFg(_), PmpptRequest::Spawn { mode, .. }) => {
+                matches!(mode, SpawnMode::Foreground)
+            }
+            (PmpptResponse::SpawnBg(_), PmpptRequest::Spawn { mode, .. }) => {
+                !matches!(mode, SpawnMode::Foreground)
+            }
+            (PmpptResponse::Bracket(_), PmpptRequest::Bracket { .. })
+            | (PmpptResponse::Tail(_), PmpptRequest::Tail { .. })
+            | (PmpptResponse::Watch(_), PmpptRequest::Watch { .. })
+            | (PmpptResponse::Ingest(_), PmpptRequest::Ingest { .. })
+            | (PmpptResponse::Journal(_), PmpptRequest::Journal { .. })
+            | (PmpptResponse::Collect(_), PmpptRequest::Collect { .. })
+            | (PmpptResponse::Latency(_), PmpptRequest::Latency { .. })
+            | (PmpptResponse::Load(_), PmpptRequest::Load { .. })
+            | (PmpptResponse::PauseId(_), PmpptRequest::PauseId { .. })
+            | (PmpptResponse::ResumeId(_), PmpptRequest::ResumeId { .. })
+            | (PmpptResponse::Freeze(_), PmpptRequest::Freeze { .. })
+            | (PmpptResponse::Thaw(_), PmpptRequest::Thaw { .. })
+            | (PmpptResponse::Renice(_), PmpptRequest::Renice { .. })
+            | (PmpptResponse::NetCreate(_), PmpptRequest::NetCreate { .. })
+            | (PmpptResponse::NetDestroy(_), PmpptRequest::NetDestroy { .. })
+            | (PmpptResponse::Fault(_), PmpptRequest::Fault { .. })
+            | (PmpptResponse::Revert(_), PmpptRequest::Revert { .. })
+            | (PmpptResponse::WaitIdle(_), PmpptRequest::WaitIdle { .. }) => true,
+            // the batch stopped by `Finish` or `Abort` has fewer responses
+            (PmpptResponse::Batch(responses), PmpptRequest::Batch(reqs)) => {
+                responses.len() <= reqs.len()
+                    && responses
+                        .iter()
+                        .zip(reqs)
+                        .all(|(resp, req)| resp.answers(req))
+            }
+            _ => false,
+        }
+    }
 }
 
 /// Generic transport protocol interface.
@@ -34,3 +904,28 @@ pub trait Protocol {
     fn recv_request(&mut self) -> Option<PmpptRequest>;
     fn send_response(&mut self, response: PmpptResponse) -> Option<()>;
 }
+
+#[test]
+fn responses_answer_requests() {
+    let spawn = |mode| PmpptRequest::Spawn {
+        cmd: "true".to_owned(),
+        args: Vec::new(),
+        mode,
+        opts: SpawnOptions::default(),
+    };
+    let bg = PmpptResponse::SpawnBg(Err("failed".to_owned()));
+    assert!(bg.answers(&spawn(SpawnMode::BackgroundKill)));
+    assert!(!bg.answers(&spawn(SpawnMode::Foreground)));
+
+    let batch = PmpptRequest::Batch(vec![
+        PmpptRequest::Freeze { id: 1 },
+        PmpptRequest::Thaw { id: 1 },
+    ]);
+    let freeze = || PmpptResponse::Freeze(Ok(()));
+    let thaw = || PmpptResponse::Thaw(Ok(()));
+    assert!(PmpptResponse::Batch(vec![freeze()]).answers(&batch));
+    assert!(PmpptResponse::Batch(vec![freeze(), thaw()]).answers(&batch));
+    assert!(!PmpptResponse::Batch(vec![thaw(), freeze()]).answers(&batch));
+    assert!(!PmpptResponse::Batch(vec![freeze(), thaw(), thaw()]).answers(&batch));
+    assert!(!freeze().answers(&batch));
+}
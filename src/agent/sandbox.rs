@@ -0,0 +1,114 @@
+//! Isolation of the spawned processes in the fresh namespaces.
+//!
+//! The process is started by `unshare` from util-linux in the new mount and PID namespaces with
+//! its own `/proc`, optionally also in the new network namespace with only the loopback up. With
+//! the binds the root is replaced by the empty tmpfs with the host paths bind-mounted read-only
+//! plus `/proc`, `/dev` and the writable `/tmp`. The namespaces are set up by the small `sh`
+//! script run by `unshare`, so no container runtime is needed, but the agent must run as root.
+
+use std::path::{Path, PathBuf};
+
+use subprocess::Exec;
+
+use super::protocol::Isolation;
+
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// Location of the host path inside the new root.
+fn inside(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Script mounting the new root at the given empty directory.
+fn mount_root(root: &Path, binds: &[PathBuf]) -> Result<Vec<String>, String> {
+    let mut lines = vec![format!("mount -t tmpfs pmppt-root {}", quote(root))];
+    for bind in binds {
+        if !bind.is_absolute() {
+            return Err(format!("bind '{}' is not absolute", bind.to_string_lossy()));
+        }
+        let target = inside(root, bind);
+        let metadata = std::fs::metadata(bind)
+            .map_err(|e| format!("bad bind '{}' - {}", bind.to_string_lossy(), e))?;
+        match metadata.is_dir() {
+            true => lines.push(format!("mkdir -p {}", quote(&target))),
+            false => {
+                let parent = target.parent().unwrap_or(root);
+                lines.push(format!("mkdir -p {}", quote(parent)));
+                lines.push(format!("touch {}", quote(&target)));
+            }
+        }
+        lines.push(format!("mount --rbind {} {}", quote(bind), quote(&target)));
+        lines.push(format!("mount -o remount,bind,ro {}", quote(&target)));
+    }
+
+    let (proc, dev, tmp) = (
+        inside(root, Path::new("/proc")),
+        inside(root, Path::new("/dev")),
+        inside(root, Path::new("/tmp")),
+    );
+    lines.push(format!(
+        "mkdir -p {} {} {}",
+        quote(&proc),
+        quote(&dev),
+        quote(&tmp)
+    ));
+    lines.push(format!("mount -t proc proc {}", quote(&proc)));
+    lines.push(format!("mount --rbind /dev {}", quote(&dev)));
+    Ok(lines)
+}
+
+/// Command running the process isolated, the new root is mounted at the given path if needed.
+pub fn exec(
+    isolation: &Isolation,
+    root: &Path,
+    cwd: Option<&Path>,
+    cmd: &str,
+    args: &[String],
+) -> Result<Exec, String> {
+    let mut script = vec!["set -e".to_owned()];
+    let run = match isolation.binds.is_empty() {
+        true => {
+            script.push("mount -t proc proc /proc".to_owned());
+            r#"exec "$@""#.to_owned()
+        }
+        false => {
+            std::fs::create_dir(root)
+                .map_err(|e| format!("cannot create '{}' - {}", root.to_string_lossy(), e))?;
+            script.extend(mount_root(root, &isolation.binds)?);
+            // the nested `unshare` only changes the root and the working directory
+            format!(
+                r#"exec unshare --root={} --wd={} -- "$@""#,
+                quote(root),
+                quote(cwd.unwrap_or(Path::new("/")))
+            )
+        }
+    };
+    if isolation.network {
+        script.push("ip link set lo up || echo 'loopback is not available' >&2".to_owned());
+    }
+    script.push(run);
+
+    let mut unshare = vec!["--mount", "--pid", "--fork", "--kill-child"];
+    if isolation.network {
+        unshare.push("--net");
+    }
+    Ok(Exec::cmd("unshare")
+        .args(&unshare)
+        .args(&["--", "sh", "-c"])
+        .arg(script.join("\n"))
+        .arg("sh")
+        .arg(cmd)
+        .args(args))
+}
+
+#[test]
+fn root_paths() {
+    let root = Path::new("/out/002-root");
+    assert_eq!(
+        inside(root, Path::new("/usr/lib")),
+        Path::new("/out/002-root/usr/lib")
+    );
+    assert_eq!(quote(Path::new("/it's")), r"'/it'\''s'");
+}
@@ -0,0 +1,245 @@
+//! Seccomp filters of the spawned processes.
+//!
+//! The filter cannot be installed between fork and exec by the `subprocess` crate, so the process
+//! is started by the agent binary itself with the hidden [`COMMAND`]: it installs the filter with
+//! the `no_new_privs` flag set and execs the real command, which inherits the filter together with
+//! all its children. The presets check the architecture and return `EPERM` for the denied
+//! syscalls, the policy files are loaded as is.
+
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+
+use super::protocol::{SeccompPreset, SeccompProfile};
+
+/// Hidden agent command running the process under the filter.
+pub const COMMAND: &str = "exec-seccomp";
+
+const PRESETS: &[(&str, SeccompPreset)] = &[
+    ("restricted", SeccompPreset::Restricted),
+    ("no-network", SeccompPreset::NoNetwork),
+];
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls of the x32 ABI have this bit set in their numbers.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const RESTRICTED: &[libc::c_long] = &[
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_reboot,
+    libc::SYS_kexec_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_acct,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_bpf,
+    libc::SYS_setns,
+    libc::SYS_unshare,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+];
+
+/// Denied in addition to the restricted ones, the sockets are checked by their family.
+const NO_NETWORK: &[libc::c_long] = &[libc::SYS_io_uring_setup];
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn deny() -> libc::sock_filter {
+    stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    )
+}
+
+fn preset_filter(preset: SeccompPreset) -> Result<Vec<libc::sock_filter>, String> {
+    let arch = AUDIT_ARCH.ok_or("seccomp presets are not supported on this architecture")?;
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+    let jeq = |k: u32, jt: u8, jf: u8| jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, k, jt, jf);
+
+    let mut prog = vec![
+        load(std::mem::offset_of!(libc::seccomp_data, arch)),
+        jeq(arch, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        load(std::mem::offset_of!(libc::seccomp_data, nr)),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    prog.extend([
+        jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        ),
+        deny(),
+    ]);
+
+    let mut denied = RESTRICTED.to_vec();
+    if preset == SeccompPreset::NoNetwork {
+        denied.extend(NO_NETWORK);
+    }
+    for nr in denied {
+        prog.extend([jeq(nr as u32, 0, 1), deny()]);
+    }
+
+    if preset == SeccompPreset::NoNetwork {
+        // the lower half of the family argument on the little-endian targets
+        let family = std::mem::offset_of!(libc::seccomp_data, args);
+        prog.extend([
+            jeq(libc::SYS_socket as u32, 0, 4),
+            load(family),
+            jeq(libc::AF_UNIX as u32, 0, 1),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW),
+            deny(),
+        ]);
+    }
+    prog.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    Ok(prog)
+}
+
+fn load_policy(path: &Path) -> Result<Vec<libc::sock_filter>, String> {
+    let name = path.to_string_lossy();
+    let data = std::fs::read(path)
+        .map_err(|e| format!("cannot read seccomp policy '{}' - {}", name, e))?;
+    let size = std::mem::size_of::<libc::sock_filter>();
+    let len = data.len() / size;
+    if data.is_empty() || data.len() % size != 0 || len > libc::BPF_MAXINSNS as usize {
+        return Err(format!(
+            "bad seccomp policy '{}' of {} bytes",
+            name,
+            data.len()
+        ));
+    }
+    Ok(data
+        .chunks_exact(size)
+        .map(|insn| libc::sock_filter {
+            code: u16::from_ne_bytes([insn[0], insn[1]]),
+            jt: insn[2],
+            jf: insn[3],
+            k: u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]),
+        })
+        .collect())
+}
+
+fn install(filter: &[libc::sock_filter]) -> Result<(), String> {
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+    // SAFETY: plain syscalls, the program outlives the call copying it
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(format!(
+                "cannot set no_new_privs - {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let mode = libc::SECCOMP_MODE_FILTER as libc::c_ulong;
+        if libc::prctl(libc::PR_SET_SECCOMP, mode, &prog as *const libc::sock_fprog) != 0 {
+            return Err(format!(
+                "cannot install seccomp filter - {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Arguments of the agent itself running the command under the filter.
+pub fn command(
+    profile: &SeccompProfile,
+    cmd: &str,
+    args: &[String],
+) -> Result<Vec<String>, String> {
+    let mut wrapper = vec![COMMAND.to_owned()];
+    match profile {
+        SeccompProfile::Preset(preset) => {
+            // the filter is built in the child, so fail early on the unsupported architecture
+            preset_filter(*preset)?;
+            let (name, _) = PRESETS
+                .iter()
+                .find(|(_, p)| p == preset)
+                .expect("unknown preset");
+            wrapper.extend(["--preset".to_owned(), name.to_string()]);
+        }
+        SeccompProfile::Policy { policy } => {
+            let path = policy.canonicalize().map_err(|e| {
+                format!("bad seccomp policy '{}' - {}", policy.to_string_lossy(), e)
+            })?;
+            load_policy(&path)?;
+            wrapper.extend(["--policy".to_owned(), path.to_string_lossy().into_owned()]);
+        }
+    }
+    wrapper.extend(["--".to_owned(), cmd.to_owned()]);
+    wrapper.extend(args.iter().cloned());
+    Ok(wrapper)
+}
+
+/// Run the hidden command: install the filter and exec the command, returns only on failure.
+pub fn run(args: &[String]) -> String {
+    let filter = match args {
+        [kind, name, sep, _, ..] if kind == "--preset" && sep == "--" => {
+            match PRESETS.iter().find(|(n, _)| n == name) {
+                Some((_, preset)) => preset_filter(*preset),
+                None => Err(format!("unknown seccomp preset '{}'", name)),
+            }
+        }
+        [kind, path, sep, _, ..] if kind == "--policy" && sep == "--" => {
+            load_policy(Path::new(path))
+        }
+        _ => Err(format!(
+            "usage: {} (--preset NAME|--policy PATH) -- CMD ARGS...",
+            COMMAND
+        )),
+    };
+    if let Err(e) = filter.and_then(|filter| install(&filter)) {
+        return e;
+    }
+
+    let (cmd, args) = (&args[3], &args[4..]);
+    let e = std::process::Command::new(cmd).args(args).exec();
+    format!("cannot run '{}' - {}", cmd, e)
+}
+
+#[test]
+fn preset_jumps_stay_in_program() {
+    for (_, preset) in PRESETS {
+        let prog = preset_filter(*preset).unwrap();
+        for (i, insn) in prog.iter().enumerate() {
+            if insn.code as u32 & 0x07 == libc::BPF_JMP {
+                assert!(i + 1 + (insn.jt.max(insn.jf) as usize) < prog.len());
+            }
+        }
+        assert_eq!(
+            prog.last().unwrap().code as u32,
+            libc::BPF_RET | libc::BPF_K
+        );
+    }
+}
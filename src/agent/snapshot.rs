@@ -0,0 +1,124 @@
+//! One-shot snapshots of the files and command outputs taken around a workload.
+//!
+//! The snapshot is stored as the sections of the sources, every one starts with the `==> name <==`
+//! line like in the output of `head` for several files. The diff of two snapshots keeps the lines
+//! of the same shape with their numbers replaced by the deltas, so the totals of the counters
+//! like in `/proc/net/snmp` are seen directly, the other changed lines are shown as `-`/`+` pairs.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use subprocess::{Exec, Redirection};
+
+use super::poller;
+
+/// Content of the snapshot sources, in the order they are given.
+pub struct Snapshot(Vec<(String, String)>);
+
+impl Snapshot {
+    pub fn take(files: &[PathBuf], commands: &[Vec<String>]) -> Result<Self, String> {
+        let mut sources = Vec::with_capacity(files.len() + commands.len());
+        for file in files {
+            let name = file.to_string_lossy().into_owned();
+            let content =
+                poller::read_once(file).map_err(|e| format!("cannot read '{}' - {}", name, e))?;
+            sources.push((name, content));
+        }
+        for command in commands {
+            let Some((cmd, args)) = command.split_first() else {
+                return Err("empty snapshot command".into());
+            };
+            let exec = Exec::cmd(cmd)
+                .args(args)
+                .stdout(Redirection::Pipe)
+                .stderr(Redirection::Merge);
+            let name = exec.to_cmdline_lossy();
+            let output = exec
+                .capture()
+                .map_err(|e| format!("failed to run '{}' - {}", name, e))?;
+            sources.push((name, output.stdout_str()));
+        }
+        Ok(Self(sources))
+    }
+
+    pub fn format(&self) -> String {
+        let mut out = String::new();
+        for (name, content) in &self.0 {
+            let _ = writeln!(out, "==> {} <==", name);
+            out.push_str(content);
+            if !content.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Diff of the snapshots of the same sources.
+    pub fn diff(&self, after: &Snapshot) -> String {
+        let mut out = String::new();
+        for ((name, before), (_, after)) in self.0.iter().zip(&after.0) {
+            let _ = writeln!(out, "==> {} <==", name);
+            let (mut before, mut after) = (before.lines(), after.lines());
+            loop {
+                match (before.next(), after.next()) {
+                    (None, None) => break,
+                    (Some(old), Some(new)) => match diff_line(old, new) {
+                        Some(delta) => out.push_str(&delta),
+                        None => {
+                            let _ = write!(out, "- {}\n+ {}", old, new);
+                        }
+                    },
+                    (Some(old), None) => {
+                        let _ = write!(out, "- {}", old);
+                    }
+                    (None, Some(new)) => {
+                        let _ = write!(out, "+ {}", new);
+                    }
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// The line with the numbers replaced by their deltas, `None` if the lines differ in shape.
+fn diff_line(old: &str, new: &str) -> Option<String> {
+    let (old_tokens, new_tokens): (Vec<_>, Vec<_>) = (
+        old.split_whitespace().collect(),
+        new.split_whitespace().collect(),
+    );
+    if old_tokens.len() != new_tokens.len() {
+        return None;
+    }
+
+    let mut tokens = Vec::with_capacity(new_tokens.len());
+    for (old, new) in old_tokens.into_iter().zip(new_tokens) {
+        let delta = match (old.parse::<i128>(), new.parse::<i128>()) {
+            (Ok(old), Ok(new)) => (new - old).to_string(),
+            _ => match (old.parse::<f64>(), new.parse::<f64>()) {
+                (Ok(old), Ok(new)) => (new - old).to_string(),
+                _ if old == new => old.to_owned(),
+                _ => return None,
+            },
+        };
+        tokens.push(delta);
+    }
+    Some(tokens.join(" "))
+}
+
+#[test]
+fn counters_diff() {
+    let before = Snapshot(vec![(
+        "snmp".into(),
+        "Tcp: RtoMin ActiveOpens\nTcp: 200 10\nstate: idle\n".into(),
+    )]);
+    let after = Snapshot(vec![(
+        "snmp".into(),
+        "Tcp: RtoMin ActiveOpens\nTcp: 200 25\nstate: busy\nextra\n".into(),
+    )]);
+    assert_eq!(
+        before.diff(&after),
+        "==> snmp <==\nTcp: RtoMin ActiveOpens\nTcp: 0 15\n- state: idle\n+ state: busy\n+ extra\n"
+    );
+}
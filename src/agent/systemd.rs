@@ -0,0 +1,93 @@
+//! Spawning in the transient systemd units.
+//!
+//! The process is started by `systemd-run` in the unit named after the agent pid and the spawn
+//! id, so its journal is available to the journal requests by that name. The scope runs the agent
+//! child itself, the service is started by systemd and waited by `systemd-run` with the output
+//! piped to the agent, so the working directory and the environment are passed explicitly. The
+//! unit is stopped by `systemctl` killing all its processes, which terminates the process and
+//! cleans up what is left of it after the exit.
+
+use std::path::Path;
+
+use subprocess::{Exec, Redirection};
+
+use super::protocol::{SystemdUnit, UnitKind};
+
+/// Name of the unit of the spawn.
+pub fn name(unit: &SystemdUnit, id: u32) -> String {
+    let kind = match unit.kind {
+        UnitKind::Scope => "scope",
+        UnitKind::Service => "service",
+    };
+    format!("pmppt-{}-{:03}.{}", std::process::id(), id, kind)
+}
+
+/// Command running the process in the unit.
+pub fn exec(
+    unit: &SystemdUnit,
+    id: u32,
+    cwd: Option<&Path>,
+    env: &[(String, String)],
+    cmd: &str,
+    args: &[String],
+) -> Exec {
+    let mut exec = Exec::cmd("systemd-run")
+        .arg(format!("--unit={}", name(unit, id)))
+        .args(&["--quiet", "--collect"]);
+    match unit.kind {
+        UnitKind::Scope => exec = exec.arg("--scope"),
+        UnitKind::Service => {
+            exec = exec.args(&["--wait", "--pipe", "--service-type=exec"]);
+            if let Some(cwd) = cwd {
+                exec = exec.arg(format!("--working-directory={}", cwd.to_string_lossy()));
+            }
+            for (key, value) in env {
+                exec = exec.arg(format!("--setenv={}={}", key, value));
+            }
+        }
+    }
+    for property in &unit.properties {
+        exec = exec.arg(format!("--property={}", property));
+    }
+    exec.arg("--").arg(cmd).args(args)
+}
+
+/// Stop the unit killing all its processes, the already removed unit is not an error.
+pub fn stop(name: &str) -> Result<(), String> {
+    let exec = Exec::cmd("systemctl").args(&["stop", "--quiet", name]);
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run systemctl - {}", e))?;
+    let output = capture.stdout_str();
+    match capture.success() || output.contains("not loaded") {
+        true => Ok(()),
+        false => Err(format!("cannot stop unit {}: {}", name, output.trim())),
+    }
+}
+
+#[test]
+fn service_command() {
+    let unit = SystemdUnit {
+        kind: UnitKind::Service,
+        properties: vec!["CPUQuota=50%".to_owned()],
+    };
+    let env = [("A".to_owned(), "b".to_owned())];
+    let cmdline = exec(
+        &unit,
+        7,
+        Some(Path::new("/w")),
+        &env,
+        "fio",
+        &["x".to_owned()],
+    )
+    .to_cmdline_lossy();
+    let expected = format!(
+        "systemd-run '--unit=pmppt-{}-007.service' --quiet --collect --wait --pipe \
+         '--service-type=exec' '--working-directory=/w' '--setenv=A=b' \
+         '--property=CPUQuota=50%' -- fio x",
+        std::process::id()
+    );
+    assert_eq!(cmdline, expected);
+}
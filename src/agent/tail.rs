@@ -0,0 +1,150 @@
+//! Follower of the growing log files on the target, like `tail -F`.
+//!
+//! Only the lines appended after the start are copied, every line is prefixed with the time it
+//! was read in the same form as the poll log timestamps, so the application logs share the
+//! timeline with the metrics. The file is reopened when it is rotated (replaced by a new one with
+//! the same name) or truncated, and waited for when it does not exist.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+
+/// Interval of checking the file for the new content.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Identity of the file to detect its replacement on rotation.
+#[cfg(unix)]
+fn file_id(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+// only the truncation is detected there
+#[cfg(not(unix))]
+fn file_id(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+struct Followed {
+    file: File,
+    id: Option<(u64, u64)>,
+    pos: u64,
+}
+
+impl Followed {
+    fn open(path: &Path, from_end: bool) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let id = file_id(&file.metadata()?);
+        let pos = match from_end {
+            true => file.seek(SeekFrom::End(0))?,
+            false => 0,
+        };
+        Ok(Self { file, id, pos })
+    }
+
+    /// Append the new content to the buffer.
+    fn read_new(&mut self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let read = self.file.read_to_end(buf)?;
+        self.pos += read as u64;
+        Ok(())
+    }
+}
+
+/// Check that the file can be followed, it may not exist yet.
+pub fn check(path: &Path) -> Result<(), String> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => Err(format!(
+            "cannot follow '{}' - it is a directory",
+            path.to_string_lossy()
+        )),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!(
+                "'{}' does not exist, waiting for it to appear",
+                path.to_string_lossy()
+            );
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "cannot follow '{}' - {}",
+            path.to_string_lossy(),
+            e
+        )),
+    }
+}
+
+/// Write the complete lines of the buffer with the timestamp, keeping the incomplete last one.
+pub fn write_lines(out: &mut impl Write, buf: &mut Vec<u8>) -> std::io::Result<()> {
+    let Some(end) = buf.iter().rposition(|&b| b == b'\n') else {
+        return Ok(());
+    };
+
+    let timestamp = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, false);
+    for line in buf[..end].split(|&b| b == b'\n') {
+        writeln!(out, "{} {}", timestamp, String::from_utf8_lossy(line))?;
+    }
+    buf.drain(..=end);
+    out.flush()
+}
+
+pub fn follow(path: PathBuf, dest: PathBuf, stop: Arc<AtomicBool>) {
+    let mut out = BufWriter::new(File::create(dest).expect("cannot open file"));
+    let mut followed = Followed::open(&path, true).ok();
+    let mut buf = Vec::new();
+
+    while !stop.load(Ordering::Acquire) {
+        if let Some(current) = &mut followed {
+            current
+                .read_new(&mut buf)
+                .expect("cannot read followed file");
+        }
+
+        // the content of the old file is read completely before switching to the new one
+        let reopen = match (&followed, std::fs::metadata(&path)) {
+            (Some(current), Ok(meta)) if file_id(&meta) != current.id => Some("was rotated"),
+            (Some(current), Ok(meta)) if meta.len() < current.pos => Some("was truncated"),
+            (None, Ok(_)) => Some("appeared"),
+            _ => None,
+        };
+        if let Some(reason) = reopen {
+            info!(
+                "followed file '{}' {}, reopening",
+                path.to_string_lossy(),
+                reason
+            );
+            // the new file is read from the beginning, it is all appended after the start
+            followed = Followed::open(&path, false).ok();
+            if let Some(current) = &mut followed {
+                current
+                    .read_new(&mut buf)
+                    .expect("cannot read followed file");
+            }
+        }
+
+        write_lines(&mut out, &mut buf).expect("cannot write");
+        std::thread::sleep(CHECK_INTERVAL);
+    }
+
+    // the last line may have no newline yet
+    if !buf.is_empty() {
+        buf.push(b'\n');
+        write_lines(&mut out, &mut buf).expect("cannot write");
+    }
+}
+
+#[test]
+fn timestamped_lines() {
+    let mut out = Vec::new();
+    let mut buf = b"first\nsecond\npart".to_vec();
+    write_lines(&mut out, &mut buf).unwrap();
+    assert_eq!(buf, b"part");
+
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().map(|l| l.split_once(' ').unwrap().1).collect();
+    assert_eq!(lines, ["first", "second"]);
+}
@@ -0,0 +1,133 @@
+//! Recorder of the filesystem events on the watched paths using inotify.
+//!
+//! Every event is logged as the line with its time in the same form as the poll log timestamps,
+//! the event name and the path, e.g. `2024-01-01T00:00:00.000000+00:00 MODIFY /dev/shm/data`.
+//! The directories report the events of their direct entries.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{BufWriter, Error, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Timeout of waiting for the events, limiting the stop latency.
+const WAIT_TIMEOUT_MS: i32 = 100;
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_MODIFY
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF;
+
+const EVENT_NAMES: &[(u32, &str)] = &[
+    (libc::IN_CREATE, "CREATE"),
+    (libc::IN_MODIFY, "MODIFY"),
+    (libc::IN_DELETE, "DELETE"),
+    (libc::IN_MOVED_FROM, "MOVED_FROM"),
+    (libc::IN_MOVED_TO, "MOVED_TO"),
+    (libc::IN_DELETE_SELF, "DELETE_SELF"),
+    (libc::IN_Q_OVERFLOW, "OVERFLOW"),
+];
+
+pub struct Watcher {
+    fd: OwnedFd,
+    paths: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    /// Start watching the paths, the events are queued by the kernel till they are recorded.
+    pub fn new(paths: &[PathBuf]) -> Result<Self, String> {
+        // SAFETY: plain syscall, the descriptor is owned right after
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(format!("cannot init inotify - {}", Error::last_os_error()));
+        }
+        // SAFETY: the descriptor is valid and not owned by anything else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut watched = HashMap::new();
+        for path in paths {
+            let cpath = CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| format!("bad path '{}' - {}", path.to_string_lossy(), e))?;
+            // SAFETY: the descriptor and the NUL-terminated path are valid
+            let wd = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), cpath.as_ptr(), WATCH_MASK) };
+            if wd < 0 {
+                return Err(format!(
+                    "cannot watch '{}' - {}",
+                    path.to_string_lossy(),
+                    Error::last_os_error()
+                ));
+            }
+            watched.insert(wd, path.clone());
+        }
+
+        Ok(Self { fd, paths: watched })
+    }
+
+    /// Wait for the events for a while, writing them into the output.
+    fn record(&self, out: &mut impl Write, buf: &mut [u8]) -> std::io::Result<()> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: the single valid pollfd is passed
+        if unsafe { libc::poll(&mut pollfd, 1, WAIT_TIMEOUT_MS) } <= 0 {
+            return Ok(());
+        }
+
+        // SAFETY: the buffer is valid for its length
+        let len = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+        if len < 0 {
+            let e = Error::last_os_error();
+            return match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(e),
+            };
+        }
+
+        let now = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, false);
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut pos = 0;
+        while pos + header <= len as usize {
+            // SAFETY: the kernel writes the complete events, the header may be unaligned
+            let event: libc::inotify_event =
+                unsafe { std::ptr::read_unaligned(buf[pos..].as_ptr().cast()) };
+            let name = &buf[pos + header..pos + header + event.len as usize];
+            pos += header + event.len as usize;
+
+            let mut path = self.paths.get(&event.wd).cloned().unwrap_or_default();
+            if event.len > 0 {
+                // the name is padded with NULs
+                let name = CStr::from_bytes_until_nul(name).map_or(name, CStr::to_bytes);
+                path.push(std::ffi::OsStr::from_bytes(name));
+            }
+            for (_, kind) in EVENT_NAMES.iter().filter(|(bit, _)| event.mask & bit != 0) {
+                writeln!(out, "{} {} {}", now, kind, path.to_string_lossy())?;
+            }
+        }
+        out.flush()
+    }
+}
+
+pub fn watch(watcher: Watcher, dest: PathBuf, stop: Arc<AtomicBool>) {
+    let mut out = BufWriter::new(File::create(dest).expect("cannot open file"));
+    // enough for many events with the longest names
+    let mut buf = vec![0u8; 64 << 10];
+
+    while !stop.load(Ordering::Acquire) {
+        watcher
+            .record(&mut out, &mut buf)
+            .expect("cannot record inotify events");
+    }
+
+    // the events queued just before the stop
+    watcher
+        .record(&mut out, &mut buf)
+        .expect("cannot record inotify events");
+}
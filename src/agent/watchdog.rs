@@ -0,0 +1,84 @@
+//! Termination of the timed background processes when their duration is over.
+//!
+//! The watchdog thread waits for the deadline and terminates the process, unless it is cancelled
+//! before. The agent holds the watchdog off while reaping the process and marks it exited, so the
+//! watchdog never signals the pid reused by some other process.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{error, info};
+
+pub struct Watchdog {
+    // set when the process has exited or is not watched anymore
+    done: Arc<(Mutex<bool>, Condvar)>,
+    thrd: JoinHandle<()>,
+}
+
+impl Watchdog {
+    /// Call `terminate` for the process after the duration.
+    pub fn start<F>(id: u32, duration: Duration, terminate: F) -> Self
+    where
+        F: FnOnce() -> Result<(), String> + Send + 'static,
+    {
+        let done = Arc::new((Mutex::new(false), Condvar::new()));
+        let done_thread = done.clone();
+        let thrd = std::thread::spawn(move || {
+            let (lock, cond) = &*done_thread;
+            let guard = lock.lock().expect("watchdog lock poisoned");
+            let (guard, _) = cond
+                .wait_timeout_while(guard, duration, |done| !*done)
+                .expect("watchdog lock poisoned");
+            if *guard {
+                return;
+            }
+            // the lock is held, so the process cannot be reaped meanwhile
+            info!(
+                "duration {:?} of id={} is over, terminating it",
+                duration, id
+            );
+            if let Err(e) = terminate() {
+                error!("cannot terminate the timed process id={}: {}", id, e);
+            }
+        });
+        Self { done, thrd }
+    }
+
+    /// Hold the watchdog off, setting the flag marks the process exited.
+    pub fn hold(&self) -> MutexGuard<'_, bool> {
+        self.done.0.lock().expect("watchdog lock poisoned")
+    }
+
+    /// Stop watching the process and wait for the watchdog thread.
+    pub fn cancel(self) {
+        *self.hold() = true;
+        self.done.1.notify_all();
+        self.thrd.join().expect("watchdog thread panicked");
+    }
+
+    /// Wait for the exit of the process, without reaping it, then stop watching it.
+    pub fn finish(self, pid: Option<u32>) {
+        if let Some(pid) = pid {
+            // SAFETY: zeroed plain struct filled by the call
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            loop {
+                // SAFETY: plain call on the own child, WNOWAIT leaves it for the reaping
+                let res = unsafe {
+                    libc::waitid(
+                        libc::P_PID,
+                        pid as libc::id_t,
+                        &mut info,
+                        libc::WEXITED | libc::WNOWAIT,
+                    )
+                };
+                if res == 0
+                    || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+                {
+                    break;
+                }
+            }
+        }
+        self.cancel();
+    }
+}
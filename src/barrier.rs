@@ -0,0 +1,195 @@
+//! Rendezvous of the agents of the multi-host scenario at the named barriers.
+//!
+//! Every agent at the barrier listens on its address and connects to the addresses of its peers,
+//! exchanging the barrier name and the random token of the agent both ways. The agent has arrived
+//! once it accepts the connections, so the agent arriving last learns about all the others right
+//! away, and they learn about it from its connections. The connections are accepted by the separate
+//! thread, so the agents connecting to each other at the same time do not wait for each other. The
+//! agents leave the barrier within the check interval and the network latency, without any central
+//! controller.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::warn;
+
+/// Header of the messages, for the strangers connecting to the port.
+const MAGIC: &str = "pmppt-barrier";
+/// Interval of checking for the new peers and retrying the connections.
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+/// Limit of the connection and the exchange with the single peer.
+const PEER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Token of this agent at the barrier, telling the peers apart from their both connections.
+fn token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    format!("{}-{}", std::process::id(), nanos)
+}
+
+/// Token of the peer from its message, if it is at the same barrier.
+fn parse_message(line: &str, name: &str) -> Option<String> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [MAGIC, barrier, token] if barrier == name => Some(token.to_owned()),
+        [MAGIC, barrier, _] => {
+            warn!("peer is at the barrier '{}', not '{}'", barrier, name);
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Send this agent's message and receive the peer's one, in the given order.
+fn exchange(stream: TcpStream, message: &str, name: &str, first: bool) -> Option<String> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(PEER_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(PEER_TIMEOUT)).ok()?;
+    let mut writer = stream.try_clone().ok()?;
+    if first {
+        writer.write_all(message.as_bytes()).ok()?;
+    }
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    if !first {
+        writer.write_all(message.as_bytes()).ok()?;
+    }
+    parse_message(&line, name)
+}
+
+/// Accept the peers till the stop, sending their tokens.
+fn accept(
+    listener: TcpListener,
+    message: &str,
+    name: &str,
+    stop: &AtomicBool,
+    tokens: Sender<Result<String, String>>,
+) {
+    while !stop.load(Ordering::Acquire) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Some(token) = exchange(stream, message, name, false) {
+                    let _ = tokens.send(Ok(token));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(CHECK_INTERVAL),
+            Err(e) => {
+                let _ = tokens.send(Err(format!("cannot accept the peers - {}", e)));
+                break;
+            }
+        }
+    }
+}
+
+fn connect(peer: &str, message: &str, name: &str) -> Option<String> {
+    let addr = peer.to_socket_addrs().ok()?.next()?;
+    let stream = TcpStream::connect_timeout(&addr, PEER_TIMEOUT).ok()?;
+    exchange(stream, message, name, true)
+}
+
+/// Wait for all the peers at the barrier, returns the time of waiting. The peers are the other
+/// agents only, this agent is not among them.
+pub fn wait(
+    name: &str,
+    listen: &str,
+    peers: &[String],
+    timeout: Duration,
+) -> Result<Duration, String> {
+    let started = Instant::now();
+    let message = format!("{} {} {}\n", MAGIC, name, token());
+    let listener = TcpListener::bind(listen)
+        .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+        .map_err(|e| format!("cannot listen on {} - {}", listen, e))?;
+
+    let stop = AtomicBool::new(false);
+    let (sender, tokens) = channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| accept(listener, &message, name, &stop, sender));
+        let res = meet(name, peers, timeout, started, &message, &tokens);
+        stop.store(true, Ordering::Release);
+        res
+    })
+}
+
+/// Connect to the peers till all of them arrive, the tokens of the accepted ones are received.
+fn meet(
+    name: &str,
+    peers: &[String],
+    timeout: Duration,
+    started: Instant,
+    message: &str,
+    accepted: &Receiver<Result<String, String>>,
+) -> Result<Duration, String> {
+    let mut arrived = HashSet::new();
+    let mut pending: Vec<&String> = peers.iter().collect();
+    loop {
+        while let Ok(token) = accepted.try_recv() {
+            arrived.insert(token?);
+        }
+        pending.retain(|peer| match connect(peer, message, name) {
+            Some(token) => {
+                arrived.insert(token);
+                false
+            }
+            None => true,
+        });
+
+        if arrived.len() >= peers.len() {
+            return Ok(started.elapsed());
+        }
+        if crate::signals::received().is_some() {
+            return Err(format!("barrier '{}' is interrupted", name));
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "barrier '{}' timed out after {:?}, {} of {} peers arrived",
+                name,
+                timeout,
+                arrived.len(),
+                peers.len()
+            ));
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+#[test]
+fn barrier_rendezvous() {
+    let addrs = ["127.0.0.1:47391", "127.0.0.1:47392", "127.0.0.1:47393"];
+    let agents: Vec<_> = (0..addrs.len())
+        .map(|i| {
+            let peers: Vec<String> = addrs
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, addr)| addr.to_string())
+                .collect();
+            std::thread::spawn(move || {
+                // the agents arrive at the different times
+                std::thread::sleep(Duration::from_millis(100 * i as u64));
+                wait("start", addrs[i], &peers, Duration::from_secs(5)).map(|_| Instant::now())
+            })
+        })
+        .collect();
+    let left: Vec<Instant> = agents
+        .into_iter()
+        .map(|agent| agent.join().unwrap().unwrap())
+        .collect();
+    let first = left.iter().min().unwrap();
+    let last = left.iter().max().unwrap();
+    assert!(last.duration_since(*first) < Duration::from_millis(100));
+
+    let peers = ["127.0.0.1:47394".to_owned()];
+    let res = wait(
+        "lonely",
+        "127.0.0.1:47395",
+        &peers,
+        Duration::from_millis(50),
+    );
+    assert!(res.unwrap_err().contains("0 of 1 peers arrived"));
+}
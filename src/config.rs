@@ -0,0 +1,261 @@
+//! Agent configuration file with the defaults for all the agent runs on the host.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::naming::FileNames;
+use crate::agent::poller::DEFAULT_SLEEP_TIME;
+use crate::agent::prerequisites::{self, Policy, Prerequisite};
+use crate::agent::protocol::{Mode, PollOptions, TimeFormat};
+use crate::agent::{Limits, Settings};
+use crate::hooks::Hooks;
+use crate::outdir::Scheme;
+
+/// Config location used when no explicit `--config` option is given.
+#[cfg(not(target_os = "android"))]
+pub const DEFAULT_PATH: &str = "/etc/pmppt-agent.toml";
+#[cfg(target_os = "android")]
+pub const DEFAULT_PATH: &str = "/data/local/tmp/pmppt-agent.toml";
+
+const DEFAULT_UPLOAD_RETRIES: u32 = 3;
+
+/// Effective config of the run stored in the outdir.
+const USED_NAME: &str = "config-used.json";
+
+/// Environment variables affecting the agent: its logging and the tools it finds.
+const ENVIRONMENT: &[&str] = &["RUST_LOG", "PATH"];
+
+/// Output base directory used when the config does not specify it.
+#[cfg(not(target_os = "android"))]
+const DEFAULT_OUTPUT_DIR: Option<&str> = None;
+/// The only location writable from `adb shell` on the production devices.
+#[cfg(target_os = "android")]
+const DEFAULT_OUTPUT_DIR: Option<&str> = Some("/data/local/tmp/pmppt");
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// File the config is loaded from, none if there is no config file.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// Base directory for the run output directories.
+    pub output_dir: Option<PathBuf>,
+    /// Naming of the run directories: "number" (default), "padded", "date" or "hybrid".
+    pub output_dir_scheme: Option<Scheme>,
+    /// Keep only this number of the latest runs in the base directory, removing the older ones
+    /// of the scheme, all are kept by default.
+    pub output_dir_keep: Option<usize>,
+    /// Poll period used for the pollers not specifying it explicitly.
+    pub poll_period_s: Option<f64>,
+    /// Buffering of the poll logs, every sample is written immediately by default.
+    pub poll_flush_interval_s: Option<f64>,
+    /// Periodic fsync of the poll logs, disabled by default.
+    pub poll_fsync_interval_s: Option<f64>,
+    /// Record the read latency statistics of all the pollers.
+    pub poll_stats: Option<bool>,
+    /// Store the structured samples of all the pollers into the run SQLite database.
+    pub poll_sqlite: Option<bool>,
+    /// Write the structured samples of all the pollers into the Parquet files.
+    pub poll_parquet: Option<bool>,
+    /// Directory for the poll logs during the run, e.g. on tmpfs, moved to the outdir at stop.
+    pub poll_staging_dir: Option<PathBuf>,
+    /// Fail the polls with any brace expansion of the pattern matching nothing.
+    pub poll_strict: Option<bool>,
+    /// Format of the poll log timestamps, the RFC 3339 local time by default.
+    pub poll_timestamp: Option<TimeFormat>,
+    /// Collect the core dumps of all the crashed spawned processes.
+    pub core_dumps: Option<bool>,
+    /// Detect the OOM kills during the run, enabled by default.
+    pub oom_watch: Option<bool>,
+    /// Upload the archived output directory to this `s3://` or `http(s)://` URL after the run.
+    pub upload_url: Option<String>,
+    /// Number of the upload retries, 3 by default.
+    pub upload_retries: Option<u32>,
+    /// Shell command run at the end of the local run, with its JSON summary on stdin and the
+    /// `PMPPT_OUTCOME` and `PMPPT_OUTDIR` environment variables.
+    pub notify_command: Option<String>,
+    /// Webhook getting the JSON summary at the end of the local run, e.g. the Slack one.
+    pub notify_url: Option<String>,
+    /// Site shell commands run before and after the local run and around every step.
+    pub hooks: Option<Hooks>,
+    /// Extra options of `scp` collecting the files of the other hosts, like `["-i", "KEY"]`.
+    pub scp_options: Option<Vec<String>>,
+    /// Record the clock synchronization status into the manifest, enabled by default.
+    pub clock_status: Option<bool>,
+    /// Interval of recording the clock synchronization status during the run, only at the start
+    /// and the stop by default.
+    pub clock_interval_s: Option<f64>,
+    /// Check the prerequisites of the reliable measurements before the run: "strict" refuses to
+    /// run with any of them degraded, "permissive" records them into the manifest. Not checked
+    /// by default.
+    pub prerequisites: Option<Policy>,
+    /// Prerequisites checked, all of "clock", "governor", "swap" and "throttling" by default.
+    pub prerequisite_checks: Option<Vec<Prerequisite>>,
+    /// Labels of all the runs on the host, e.g. the hardware one, the scenario ones take precedence.
+    pub tags: Option<BTreeMap<String, String>>,
+    /// Limit of the simultaneously running background processes, unlimited by default.
+    pub max_processes: Option<usize>,
+    /// Limit of the simultaneously running pollers, tails, watches, ingests and journals.
+    pub max_pollers: Option<usize>,
+    /// Limit of the processes spawned during the whole run.
+    pub max_spawned: Option<u32>,
+    /// Drop root to this user after the start, leaving the perf counters and the kernel log to the
+    /// root helper process, only on Linux. The isolation of the spawned processes needs root.
+    pub user: Option<String>,
+    /// Umask of the agent, so of its output files, and of the spawned processes, e.g. "027" to
+    /// keep the results private on the shared hosts, the inherited one by default. Only on unix.
+    pub umask: Option<Mode>,
+    /// Template of the names of the step files in the outdir, "{seq:03}-{kind}.{ext}" by
+    /// default. Also has the "{label}" placeholder, the program or the pattern of the step.
+    pub file_names: Option<FileNames>,
+    /// Write the JUnit XML summary of the steps into the outdir at the finish, for the CI test
+    /// reports.
+    pub junit: Option<bool>,
+}
+
+impl Config {
+    /// Load the config from the given path, or from the default one if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => Some(path),
+            None if Path::new(DEFAULT_PATH).exists() => Some(Path::new(DEFAULT_PATH)),
+            None => None,
+        };
+
+        let mut config: Config = match path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    format!("cannot read config '{}' - {}", path.to_string_lossy(), e)
+                })?;
+                toml::from_str(&content)
+                    .map_err(|e| format!("bad config '{}' - {}", path.to_string_lossy(), e))?
+            }
+            None => Config::default(),
+        };
+        config.path = path.map(Path::to_path_buf);
+
+        if let Some(interval) = config.clock_interval_s {
+            if !(interval.is_finite() && interval > 0.0) {
+                return Err(format!("bad clock status interval {}s", interval));
+            }
+        }
+        if config.output_dir.is_none() {
+            config.output_dir = DEFAULT_OUTPUT_DIR.map(PathBuf::from);
+        }
+        Ok(config)
+    }
+
+    /// The config with the defaults of the missing values filled in, the missing limits and
+    /// intervals stay missing as they have no value by default.
+    fn effective(&self) -> Config {
+        let mut config = self.clone();
+        config.output_dir_scheme.get_or_insert_default();
+        config
+            .poll_period_s
+            .get_or_insert(DEFAULT_SLEEP_TIME.as_secs_f64());
+        config.poll_timestamp.get_or_insert_default();
+        config.upload_retries.get_or_insert(DEFAULT_UPLOAD_RETRIES);
+        config
+            .prerequisite_checks
+            .get_or_insert_with(|| prerequisites::ALL.to_vec());
+        config.hooks.get_or_insert_default();
+        config.scp_options.get_or_insert_default();
+        config.tags.get_or_insert_default();
+        config.file_names.get_or_insert_default();
+        config.poll_stats.get_or_insert(false);
+        config.poll_sqlite.get_or_insert(false);
+        config.poll_parquet.get_or_insert(false);
+        config.poll_strict.get_or_insert(false);
+        config.core_dumps.get_or_insert(false);
+        config.oom_watch.get_or_insert(true);
+        config.clock_status.get_or_insert(true);
+        config.junit.get_or_insert(false);
+        config
+    }
+
+    /// Store the effective config with the command line and the environment into the outdir, the
+    /// resumed runs get the numbered copies like the scenario.
+    pub fn record_into(&self, outdir: &Path, stage: u32) -> Result<(), String> {
+        let environment: BTreeMap<&str, String> = ENVIRONMENT
+            .iter()
+            .filter_map(|&name| Some((name, std::env::var(name).ok()?)))
+            .collect();
+        let used = serde_json::json!({
+            "agent_version": env!("CARGO_PKG_VERSION"),
+            "args": std::env::args().collect::<Vec<_>>(),
+            "config_file": self.path,
+            "config": self.effective(),
+            "environment": environment,
+        });
+        let name = match stage {
+            1 => USED_NAME.to_owned(),
+            _ => USED_NAME.replace(".json", &format!("-{}.json", stage)),
+        };
+        let content = serde_json::to_string_pretty(&used).unwrap(); // never fails
+        std::fs::write(outdir.join(name), content)
+            .map_err(|e| format!("cannot store config into outdir - {}", e))
+    }
+
+    /// Check the prerequisites by the policy, returning the degraded ones to record.
+    pub fn check_prerequisites(&self) -> Result<Vec<(Prerequisite, String)>, String> {
+        let checks = self.prerequisite_checks.as_deref();
+        match self.prerequisites {
+            Some(policy) => prerequisites::enforce(policy, checks.unwrap_or(prerequisites::ALL)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn upload_retries(&self) -> u32 {
+        self.upload_retries.unwrap_or(DEFAULT_UPLOAD_RETRIES)
+    }
+
+    pub fn agent_settings(&self) -> Settings {
+        Settings {
+            poll: PollOptions {
+                period: self.poll_period_s.map(Duration::from_secs_f64),
+                flush_interval: self.poll_flush_interval_s.map(Duration::from_secs_f64),
+                fsync_interval: self.poll_fsync_interval_s.map(Duration::from_secs_f64),
+                stats: self.poll_stats,
+                sqlite: self.poll_sqlite,
+                parquet: self.poll_parquet,
+                staging_dir: self.poll_staging_dir.clone(),
+                strict: self.poll_strict,
+                timestamp: self.poll_timestamp,
+                ..Default::default()
+            },
+            core_dumps: self.core_dumps.unwrap_or(false),
+            oom_watch: self.oom_watch.unwrap_or(true),
+            tags: self.tags.clone().unwrap_or_default(),
+            limits: Limits {
+                processes: self.max_processes,
+                pollers: self.max_pollers,
+                spawned: self.max_spawned,
+            },
+            file_names: self.file_names.clone().unwrap_or_default(),
+            junit: self.junit.unwrap_or(false),
+            progress: false,
+            scp_options: self.scp_options.clone().unwrap_or_default(),
+            clock_status: self.clock_status.unwrap_or(true),
+            clock_interval: self.clock_interval_s.map(Duration::from_secs_f64),
+            degraded: Vec::new(),
+            preflight: None,
+        }
+    }
+}
+
+#[test]
+fn effective_config() {
+    let config: Config = toml::from_str("poll_stats = true\nfile_names = \"{seq}-{kind}.{ext}\"\n")
+        .expect("bad test config");
+    let used = serde_json::to_value(config.effective()).unwrap();
+    assert_eq!(used["poll_stats"], true);
+    assert_eq!(used["oom_watch"], true);
+    assert_eq!(used["poll_period_s"], 0.25);
+    assert_eq!(used["poll_timestamp"], "local");
+    assert_eq!(used["file_names"], "{seq}-{kind}.{ext}");
+    assert!(used["max_processes"].is_null());
+    assert!(used.get("path").is_none());
+}
@@ -0,0 +1,195 @@
+//! Offline conversion of the poll logs into the formats convenient for analysis.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+use log::info;
+use serde::Serialize;
+
+use crate::polllog::{self, PollLog, Sample};
+
+enum Format {
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(format!("unknown output format '{}'", name)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    content: &'a str,
+}
+
+fn csv_quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+fn write_sample(output: &mut dyn Write, format: &Format, sample: &Sample) -> std::io::Result<()> {
+    let line = match format {
+        Format::Csv => format!(
+            "{},{},{}",
+            csv_quote(&sample.timestamp),
+            sample.seq.map(|seq| seq.to_string()).unwrap_or_default(),
+            csv_quote(&sample.content)
+        ),
+        Format::Jsonl => serde_json::to_string(&JsonRecord {
+            timestamp: &sample.timestamp,
+            seq: sample.seq,
+            content: &sample.content,
+        })
+        .unwrap(), // should never fail
+    };
+    writeln!(output, "{}", line)
+}
+
+/// Time range of the samples to convert, both ends are inclusive.
+#[derive(Default)]
+pub struct Range {
+    pub since: Option<DateTime<FixedOffset>>,
+    pub until: Option<DateTime<FixedOffset>>,
+}
+
+impl Range {
+    pub fn parse_time(time: &str) -> Result<DateTime<FixedOffset>, String> {
+        DateTime::parse_from_rfc3339(time).map_err(|e| format!("bad time '{}' - {}", time, e))
+    }
+}
+
+/// Convert the poll log into the requested format, writing to stdout if no output is given.
+pub fn convert(
+    input: &Path,
+    format: &str,
+    range: &Range,
+    output: Option<&Path>,
+) -> Result<(), String> {
+    let mut log = PollLog::open(input)?;
+    info!(
+        "converting poll log: files={:?}, period={:?}",
+        log.header.files, log.header.period
+    );
+    if let Some(since) = range.since {
+        log.seek(since)?;
+    }
+
+    if format == "parquet" {
+        let output = output.ok_or("parquet output needs the output path")?;
+        return write_parquet(samples(log, range), output);
+    }
+    let format = Format::parse(format)?;
+
+    let mut output: Box<dyn Write> = match output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).map_err(|e| {
+                format!("cannot create '{}' - {}", path.to_string_lossy(), e)
+            })?))
+        }
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    if let Format::Csv = format {
+        writeln!(output, "timestamp,seq,content").map_err(|e| format!("cannot write - {}", e))?;
+    }
+
+    // the whole log needs no times, e.g. of the monotonic timestamps
+    let samples: Box<dyn Iterator<Item = Result<Sample, String>>> =
+        match range.since.or(range.until) {
+            None => Box::new(log),
+            Some(_) => Box::new(samples(log, range).map(|res| res.map(|(_, sample)| sample))),
+        };
+    for sample in samples {
+        write_sample(&mut output, &format, &sample?)
+            .map_err(|e| format!("cannot write - {}", e))?;
+    }
+
+    output.flush().map_err(|e| format!("cannot write - {}", e))
+}
+
+/// Samples of the log within the time range, with their parsed timestamps.
+fn samples(
+    log: PollLog,
+    range: &Range,
+) -> impl Iterator<Item = Result<(DateTime<FixedOffset>, Sample), String>> + '_ {
+    let format = log.header.timestamp;
+    log.map(move |sample| {
+        let sample = sample?;
+        Ok((polllog::wall_time(&sample.timestamp, format)?, sample))
+    })
+    .filter(|res| !matches!(res, Ok((time, _)) if range.since.is_some_and(|since| *time < since)))
+    .take_while(
+        |res| !matches!(res, Ok((time, _)) if range.until.is_some_and(|until| *time > until)),
+    )
+}
+
+/// Write the samples as the Parquet rows of the timestamp, the sequence number and the content.
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    samples: impl Iterator<Item = Result<(DateTime<FixedOffset>, Sample), String>>,
+    output: &Path,
+) -> Result<(), String> {
+    use crate::agent::poller::parquet::{create, write_column};
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+
+    const SCHEMA: &str = "
+        message sample {
+            REQUIRED INT64 time_us (TIMESTAMP(MICROS, true));
+            OPTIONAL INT64 seq (INTEGER(64, false));
+            REQUIRED BYTE_ARRAY content (UTF8);
+        }
+    ";
+    const ROW_GROUP_ROWS: usize = 16 << 10;
+
+    let mut file = create(output, SCHEMA, &Default::default())?;
+    let mut times = Vec::with_capacity(ROW_GROUP_ROWS);
+    let mut seqs = Vec::with_capacity(ROW_GROUP_ROWS);
+    let mut seq_defs = Vec::with_capacity(ROW_GROUP_ROWS);
+    let mut contents: Vec<ByteArray> = Vec::with_capacity(ROW_GROUP_ROWS);
+    let mut samples = samples.peekable();
+
+    while samples.peek().is_some() {
+        times.clear();
+        seqs.clear();
+        seq_defs.clear();
+        contents.clear();
+        for sample in samples.by_ref().take(ROW_GROUP_ROWS) {
+            let (time, sample) = sample?;
+            times.push(time.timestamp_micros());
+            seq_defs.push(sample.seq.is_some() as i16);
+            seqs.extend(sample.seq.map(|seq| seq as i64));
+            contents.push(sample.content.into_bytes().into());
+        }
+
+        let mut write_group = || {
+            let mut group = file.next_row_group()?;
+            write_column::<Int64Type>(&mut group, &times, None)?;
+            write_column::<Int64Type>(&mut group, &seqs, Some(&seq_defs))?;
+            write_column::<ByteArrayType>(&mut group, &contents, None)?;
+            group.close().map(|_| ())
+        };
+        write_group().map_err(|e| format!("cannot write - {}", e))?;
+    }
+
+    file.close()
+        .map(|_| ())
+        .map_err(|e| format!("cannot write - {}", e))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(
+    _samples: impl Iterator<Item = Result<(DateTime<FixedOffset>, Sample), String>>,
+    _output: &Path,
+) -> Result<(), String> {
+    Err("parquet output is not supported, the agent is built without the parquet feature".into())
+}
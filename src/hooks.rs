@@ -0,0 +1,48 @@
+//! Site hooks: the user commands run before and after the local run and around every step.
+//!
+//! The hooks are the glue of the lab set up once in the agent config, like mounting the result
+//! shares or toggling the power meters, without touching the scenarios. They are run by the shell
+//! with the context in the environment: `PMPPT_HOOK`, `PMPPT_OUTDIR` and `PMPPT_SCENARIO` for all
+//! of them, `PMPPT_STEP_INDEX` in the executed scenario and `PMPPT_STEP_TYPE` for the step ones,
+//! `PMPPT_STEP_RESULT` of `ok` or `failed` after the step, and `PMPPT_OUTCOME` after the run. The
+//! failed hook fails the run, except the `post_run` one, as the run is over by then.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use subprocess::{Exec, Redirection};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_run: Option<String>,
+    pub post_run: Option<String>,
+    pub pre_step: Option<String>,
+    pub post_step: Option<String>,
+}
+
+/// Run the hook if it is set, its output goes to the agent log.
+pub fn run(name: &str, command: Option<&str>, env: &[(&str, String)]) -> Result<(), String> {
+    let Some(command) = command else {
+        return Ok(());
+    };
+    let mut exec = Exec::shell(command).env("PMPPT_HOOK", name);
+    for (key, value) in env {
+        exec = exec.env(key, value);
+    }
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run {} hook '{}' - {}", name, command, e))?;
+    let output = capture.stdout_str();
+    for line in output.lines() {
+        info!("{}: {}", name, line);
+    }
+    match capture.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "{} hook '{}' failed: {:?}",
+            name, command, capture.exit_status
+        )),
+    }
+}
@@ -0,0 +1,361 @@
+//! Human-readable summary of the agent output directory for a quick triage.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::agent::manifest::{self, Entry};
+
+/// Everything known about a single poller or process from the manifest.
+pub struct Step {
+    pub kind: String,
+    pub name: String,
+    pub started: Option<DateTime<FixedOffset>>,
+    pub done: Option<DateTime<FixedOffset>>,
+    pub exit_code: Option<u32>,
+}
+
+impl Step {
+    pub fn duration(&self) -> Option<f64> {
+        let (started, done) = (self.started?, self.done?);
+        Some((done - started).num_microseconds().unwrap_or(0) as f64 / 1e6)
+    }
+}
+
+/// Digest of the run manifest.
+pub struct Summary {
+    pub tags: BTreeMap<String, String>,
+    pub steps: BTreeMap<u32, Step>,
+    pub errors: Vec<String>,
+    /// Conditions of the run making the results less reliable, like the degraded prerequisites.
+    pub warnings: Vec<String>,
+    pub outcome: String,
+}
+
+impl Summary {
+    /// The `key=value` tags separated by commas.
+    pub fn tags_line(&self) -> String {
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        tags.join(", ")
+    }
+}
+
+fn parse_time(time: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(time).ok()
+}
+
+pub fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn print_steps(steps: &BTreeMap<u32, Step>) {
+    println!("Steps:");
+    println!(
+        "  {:>4}  {:<14}  {:>10}  {:>6}  name",
+        "id", "kind", "duration", "exit"
+    );
+
+    for (id, step) in steps {
+        let duration = match (step.duration(), step.started) {
+            (Some(duration), _) => format!("{:.3}s", duration),
+            (None, Some(_)) => "unfinished".to_owned(),
+            (None, None) => "-".to_owned(),
+        };
+        let exit = step
+            .exit_code
+            .map_or_else(|| "-".to_owned(), |code| code.to_string());
+
+        println!(
+            "  {:>4}  {:<14}  {:>10}  {:>6}  {}",
+            id, step.kind, duration, exit, step.name
+        );
+    }
+}
+
+fn print_artifacts(outdir: &Path) -> Result<(), String> {
+    let mut files: Vec<(String, u64)> = outdir
+        .read_dir()
+        .map_err(|e| format!("cannot read '{}' - {}", outdir.to_string_lossy(), e))?
+        .flatten()
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            meta.is_file()
+                .then(|| (e.file_name().to_string_lossy().into_owned(), meta.len()))
+        })
+        .collect();
+    files.sort();
+
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+    println!("Artifacts: {} files, {}", files.len(), human_size(total));
+    for (name, size) in files {
+        println!("  {:<32}  {:>10}", name, human_size(size));
+    }
+
+    Ok(())
+}
+
+pub fn summarize(outdir: &Path) -> Result<Summary, String> {
+    let records = manifest::read(outdir)?;
+
+    let mut tags = BTreeMap::new();
+    let mut steps = BTreeMap::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let incomplete = "incomplete, the agent did not stop properly";
+    let mut outcome = incomplete.to_owned();
+
+    for record in records {
+        let time = parse_time(&record.time);
+        match record.entry {
+            Entry::Tags { tags: run_tags } => tags.extend(run_tags),
+            Entry::Poll { id, pattern, .. } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "poll".to_owned(),
+                        name: pattern,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Spawn { id, mode, cmd, .. } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: format!("{:?}", mode),
+                        name: cmd,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Tail { id, path } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "tail".to_owned(),
+                        name: path,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Watch { id, pattern } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "watch".to_owned(),
+                        name: pattern,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Latency { id, probe } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "latency".to_owned(),
+                        name: probe.to_string(),
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Load { id, load, .. } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "load".to_owned(),
+                        name: load.to_string(),
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Ingest { id, name } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "ingest".to_owned(),
+                        name,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Journal { id, units } => {
+                let name = match units.is_empty() {
+                    true => "<all units>".to_owned(),
+                    false => units.join(","),
+                };
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "journal".to_owned(),
+                        name,
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Collect { id, host, paths } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "collect".to_owned(),
+                        name: format!("{}:{}", host, paths.join(",")),
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Net { id, object } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "net".to_owned(),
+                        name: object.to_string(),
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Fault { id, fault } => {
+                steps.insert(
+                    id,
+                    Step {
+                        kind: "fault".to_owned(),
+                        name: fault.to_string(),
+                        started: time,
+                        done: None,
+                        exit_code: None,
+                    },
+                );
+            }
+            Entry::Done { id, exit_code } => {
+                if let Some(step) = steps.get_mut(&id) {
+                    step.done = time;
+                    step.exit_code = exit_code;
+                }
+            }
+            Entry::Paused { .. }
+            | Entry::Resumed { .. }
+            | Entry::Frozen { .. }
+            | Entry::Thawed { .. }
+            | Entry::Renice { .. }
+            | Entry::Startup { .. }
+            | Entry::Clock { .. }
+            | Entry::Timing { .. } => (),
+            Entry::Core { id, signal, path } => errors.push(format!(
+                "{}: id={} crashed by signal {}, core dump in {}",
+                record.time, id, signal, path
+            )),
+            Entry::OomKill { id, pid, process } => {
+                let pid = pid.map_or("?".to_owned(), |pid| pid.to_string());
+                let process = process.unwrap_or_else(|| "?".to_owned());
+                let step = id.map_or(String::new(), |id| format!(" of id={}", id));
+                errors.push(format!(
+                    "{}: OOM kill of pid {} ({}){}",
+                    record.time, pid, process, step
+                ))
+            }
+            Entry::Failed { request, error } => {
+                errors.push(format!("{}: {} - {}", record.time, request, error))
+            }
+            Entry::Noise { report } if !report.idle => {
+                let top: Vec<String> = report
+                    .top
+                    .iter()
+                    .map(|p| format!("{}({}) {:.1}%", p.process, p.pid, p.cpu_pct))
+                    .collect();
+                warnings.push(format!(
+                    "{}: target is not idle, CPU usage {:.1}%, top: {}",
+                    record.time,
+                    report.busy_pct,
+                    top.join(", ")
+                ))
+            }
+            Entry::Noise { .. } | Entry::Idle { .. } => (),
+            Entry::Degraded { check, detail } => warnings.push(format!(
+                "{}: degraded {} prerequisite - {}",
+                record.time, check, detail
+            )),
+            Entry::Stop { abnormal, reason } => {
+                outcome = match (abnormal, reason) {
+                    (false, _) => "finished".to_owned(),
+                    (true, Some(reason)) => format!("aborted: {}", reason),
+                    (true, None) => "aborted".to_owned(),
+                };
+            }
+            // the outcome of the continued run is the one of its last stage
+            Entry::Resume { .. } => outcome = incomplete.to_owned(),
+        }
+    }
+
+    Ok(Summary {
+        tags,
+        steps,
+        errors,
+        warnings,
+        outcome,
+    })
+}
+
+pub fn inspect(outdir: &Path) -> Result<(), String> {
+    let summary = summarize(outdir)?;
+
+    println!("Run: {}", outdir.to_string_lossy());
+    println!("Outcome: {}", summary.outcome);
+    if !summary.tags.is_empty() {
+        println!("Tags: {}", summary.tags_line());
+    }
+    println!();
+    print_steps(&summary.steps);
+    println!();
+
+    if !summary.errors.is_empty() {
+        println!("Errors:");
+        for error in summary.errors {
+            println!("  {}", error);
+        }
+        println!();
+    }
+    if !summary.warnings.is_empty() {
+        println!("Warnings:");
+        for warning in summary.warnings {
+            println!("  {}", warning);
+        }
+        println!();
+    }
+
+    print_artifacts(outdir)
+}
@@ -0,0 +1,145 @@
+//! JUnit XML summary of the run, rendered natively by the CI test reports like Jenkins or GitLab.
+//!
+//! Every step of the manifest is the test case failed by the non-zero exit code. The errors found
+//! in the manifest, like the failed requests or the crashes, are the failed test cases too, and
+//! the last one is the outcome of the whole run.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::inspect::{self, Summary};
+use crate::report::escape;
+
+/// Name of the summary file in the output directory.
+const NAME: &str = "junit.xml";
+
+struct Case {
+    class: String,
+    name: String,
+    time: f64,
+    failure: Option<String>,
+}
+
+fn cases(summary: &Summary) -> Vec<Case> {
+    let mut cases: Vec<Case> = summary
+        .steps
+        .iter()
+        .map(|(id, step)| Case {
+            class: format!("pmppt.{}", step.kind.to_lowercase()),
+            name: format!("id={} {}", id, step.name),
+            time: step.duration().unwrap_or(0.0),
+            failure: match step.exit_code {
+                Some(0) | None => None,
+                Some(code) => Some(format!("exit code {}", code)),
+            },
+        })
+        .collect();
+    cases.extend(summary.errors.iter().enumerate().map(|(i, error)| Case {
+        class: "pmppt.errors".to_owned(),
+        name: format!("error {}", i + 1),
+        time: 0.0,
+        failure: Some(error.clone()),
+    }));
+    cases.push(Case {
+        class: "pmppt".to_owned(),
+        name: "outcome".to_owned(),
+        time: 0.0,
+        failure: (summary.outcome != "finished").then(|| summary.outcome.clone()),
+    });
+    cases
+}
+
+fn render(summary: &Summary) -> String {
+    let cases = cases(summary);
+    let failures = cases.iter().filter(|c| c.failure.is_some()).count();
+    let times = summary
+        .steps
+        .values()
+        .flat_map(|s| [s.started, s.done])
+        .flatten();
+    let start = times.clone().min();
+    let time = match (start, times.max()) {
+        (Some(start), Some(end)) => (end - start).num_microseconds().unwrap_or(0) as f64 / 1e6,
+        _ => 0.0,
+    };
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let counts = format!(
+        "tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\"",
+        cases.len(),
+        failures,
+        time
+    );
+    writeln!(xml, "<testsuites name=\"pmppt\" {}>", counts).unwrap();
+    let timestamp = start.map_or(String::new(), |start| {
+        format!(" timestamp=\"{}\"", start.format("%Y-%m-%dT%H:%M:%S"))
+    });
+    writeln!(xml, "  <testsuite name=\"pmppt\" {}{}>", counts, timestamp).unwrap();
+    if !summary.tags.is_empty() {
+        xml.push_str("    <properties>\n");
+        for (key, value) in &summary.tags {
+            writeln!(
+                xml,
+                "      <property name=\"{}\" value=\"{}\"/>",
+                escape(key),
+                escape(value)
+            )
+            .unwrap();
+        }
+        xml.push_str("    </properties>\n");
+    }
+    for case in &cases {
+        write!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(&case.class),
+            escape(&case.name),
+            case.time
+        )
+        .unwrap();
+        match &case.failure {
+            None => xml.push_str("/>\n"),
+            Some(failure) => writeln!(
+                xml,
+                ">\n      <failure message=\"{}\"/>\n    </testcase>",
+                escape(failure)
+            )
+            .unwrap(),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Render the summary of the output directory and store it there, returning its path.
+pub fn write(outdir: &Path) -> Result<PathBuf, String> {
+    let summary = inspect::summarize(outdir)?;
+    let path = outdir.join(NAME);
+    std::fs::write(&path, render(&summary))
+        .map_err(|e| format!("cannot write '{}' - {}", path.to_string_lossy(), e))?;
+    Ok(path)
+}
+
+#[test]
+fn junit_cases() {
+    let step = |kind: &str, exit_code| inspect::Step {
+        kind: kind.to_owned(),
+        name: "sleep 1".to_owned(),
+        started: None,
+        done: None,
+        exit_code,
+    };
+    let summary = Summary {
+        tags: [("host".to_owned(), "a&b".to_owned())].into(),
+        steps: [(1, step("poll", None)), (2, step("Foreground", Some(3)))].into(),
+        errors: vec!["Spawn - failed to start".to_owned()],
+        warnings: Vec::new(),
+        outcome: "aborted: step failed".to_owned(),
+    };
+    let xml = render(&summary);
+    assert!(xml.contains("tests=\"4\" failures=\"3\""));
+    assert!(xml.contains("<property name=\"host\" value=\"a&amp;b\"/>"));
+    assert!(xml.contains("classname=\"pmppt.foreground\" name=\"id=2 sleep 1\""));
+    assert!(xml.contains("<failure message=\"exit code 3\"/>"));
+    assert!(xml.contains("<failure message=\"aborted: step failed\"/>"));
+}
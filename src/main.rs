@@ -1,96 +1,392 @@
 use std::path::{Path, PathBuf};
 
 use env_logger::Env;
-use log::{error, info};
+use log::{error, info, warn};
+
+use agent::protocol::{AbortReason, Mode};
+use agent::Outcome;
+use config::Config;
 
 mod agent;
+mod barrier;
+mod config;
+mod convert;
+mod hooks;
+mod inspect;
+mod junit;
+mod notify;
+mod outdir;
+mod polllog;
 mod protocol_impl;
+mod report;
+mod selftest;
+mod signals;
+mod tui;
+mod upload;
 
-/// Little helper function to convert str literals to error message.
-fn emsg<T, U: ?Sized + AsRef<str>>(s: &U) -> Result<T, String> {
-    Err(s.as_ref().into())
-}
+/// Process exit codes, see [`HELP`] for details.
+const EXIT_COMPLETED: i32 = 0;
+const EXIT_USAGE: i32 = 1;
+const EXIT_INVALID_SCENARIO: i32 = 2;
+const EXIT_ABORTED: i32 = 3;
+const EXIT_ENVIRONMENT: i32 = 4;
+const EXIT_ABORT_REQUESTED: i32 = 5;
+const EXIT_SIGNAL_BASE: i32 = 128;
+/// The command of the filtered process cannot be run, like in the shells.
+#[cfg(target_os = "linux")]
+const EXIT_EXEC_FAILED: i32 = 127;
+
+const HELP: &str = r#"pmppt-agent - device agent for PMPPT
+
+Usage: pmppt-agent [--config PATH] COMMAND ARGS...
+
+Commands:
+  local PATH_TO_SCENARIO [PATH_TO_OUTPUT]   run the scenario locally
+  local PATH_TO_SCENARIO --resume PATH_TO_OUTPUT_DIR
+                                            run the next stage of the scenario in the existing
+                                            run output directory, continuing its ids
+  local ... --progress (jsonl|tui)          print the manifest records to stdout as the run
+                                            goes, one JSON line per event, or show the live
+                                            status of the run in the top of the terminal
+  tcp                                       serve the remote controller (not implemented)
+  convert PATH_TO_POLL_LOG --to (csv|jsonl|parquet) [--since TIME] [--until TIME] [PATH_TO_OUTPUT]
+                                            convert the poll log for analysis, optionally
+                                            only the samples in the RFC 3339 time range
+  inspect PATH_TO_OUTPUT_DIR                summarize the run output directory
+  report PATH_TO_OUTPUT_DIR [--format (html|md|junit)]
+                                            render the run report into the output directory
+  selftest [PATH_TO_OUTPUT] [--json]        check the agent capabilities on this host
+  upload PATH_TO_OUTPUT_DIR [URL]           archive the output directory and upload it to the
+                                            s3:// or http(s):// URL, the configured by default
+
+Options:
+  --config PATH   agent config, /etc/pmppt-agent.toml is used by default if exists
+  -h, --help      show this help
 
-fn find_max_numeric_dir(base: &Path) -> u32 {
-    let mut max_dir = 0;
+Exit codes:
+  0       the scenario completed (or the command succeeded)
+  1       bad command line usage
+  2       the scenario is invalid
+  3       the scenario aborted by a failed step
+  4       environment error (files, directories, config, missing capabilities, controller
+          communication)
+  5       the scenario aborted by an explicit Abort
+  128+N   the scenario aborted by the signal N
+"#;
 
-    for dir in base.read_dir().expect("cannot read dir").flatten() {
-        let name = dir.file_name();
-        match name.to_string_lossy().parse::<u32>() {
-            Ok(value) => max_dir = std::cmp::max(max_dir, value),
-            Err(_) => continue,
+/// Agent invocation error with the exit code describing its reason.
+struct Failure {
+    code: i32,
+    msg: String,
+}
+
+impl From<String> for Failure {
+    // the errors are caused by the environment unless classified explicitly
+    fn from(msg: String) -> Self {
+        Self {
+            code: EXIT_ENVIRONMENT,
+            msg,
         }
     }
+}
+
+/// Little helper function to convert str literals to error message.
+fn emsg<T, U: ?Sized + AsRef<str>>(s: &U) -> Result<T, String> {
+    Err(s.as_ref().into())
+}
 
-    max_dir
+/// Same as [`emsg`] but for command line usage errors.
+fn usage<T, U: ?Sized + AsRef<str>>(s: &U) -> Result<T, Failure> {
+    Err(Failure {
+        code: EXIT_USAGE,
+        msg: s.as_ref().into(),
+    })
 }
 
-fn create_outdir(base: PathBuf) -> Result<PathBuf, String> {
+fn create_outdir(base: &Path, config: &Config) -> Result<PathBuf, String> {
     if base.exists() && !base.is_dir() {
         return emsg(&format!(
             "path provided '{}' is not a directory",
             base.to_string_lossy()
         ));
     }
+    let scheme = config.output_dir_scheme.unwrap_or_default();
+    outdir::create(base, scheme, config.output_dir_keep)
+}
 
-    let new_dir_num = if base.exists() {
-        find_max_numeric_dir(&base) + 1
-    } else {
-        0
-    };
+#[cfg(target_os = "linux")]
+fn separate_privileges(user: &str, outdir: &Path) -> Result<(), String> {
+    agent::privsep::start(user, outdir)?;
+    info!(
+        "running as '{}', privileged operations are done by the helper",
+        user
+    );
+    Ok(())
+}
 
-    let new_dir = base.join(Path::new(&new_dir_num.to_string()));
-    std::fs::create_dir_all(&new_dir).unwrap_or_else(|_| panic!("cannot create dir {:?}", new_dir));
+#[cfg(not(target_os = "linux"))]
+fn separate_privileges(_user: &str, _outdir: &Path) -> Result<(), String> {
+    emsg("privilege separation is supported only on Linux")
+}
 
-    Ok(new_dir)
+#[cfg(unix)]
+fn apply_umask(umask: Mode) -> Result<(), String> {
+    agent::set_umask(umask);
+    info!("umask is set to {}", String::from(umask));
+    Ok(())
 }
 
-fn main_local(args: &[String]) -> Result<(), String> {
-    if args.len() != 2 {
-        return emsg("usage: PROG local PATH_TO_CONFIG PATH_TO_OUTPUT");
-    }
+#[cfg(not(unix))]
+fn apply_umask(_umask: Mode) -> Result<(), String> {
+    emsg("umask is supported only on unix")
+}
 
-    let json_path = &args[0];
-    let logs_path = PathBuf::from(&args[1]);
-    let outdir = create_outdir(logs_path)?;
+fn main_local(args: &[String], config: &Config) -> Result<(), Failure> {
+    let (args, progress) = match args.iter().position(|a| a == "--progress") {
+        Some(i) => match args.get(i + 1).map(String::as_str) {
+            Some(format @ ("jsonl" | "tui")) => {
+                ([&args[..i], &args[i + 2..]].concat(), Some(format))
+            }
+            _ => return usage("usage: PROG local ... --progress (jsonl|tui)"),
+        },
+        None => (args.to_vec(), None),
+    };
+    if let Some(url) = &config.notify_url {
+        notify::check_url(url)?;
+    }
+    // the refused run leaves no output directory
+    let degraded = config.check_prerequisites()?;
+    let terminal = match progress {
+        Some("tui") => Some(tui::Terminal::open()?),
+        _ => None,
+    };
+    let (json_path, outdir, resumed) =
+        match (&args[..], &config.output_dir) {
+            ([json_path, flag, outdir], _) if flag == "--resume" => {
+                let outdir = PathBuf::from(outdir);
+                let resumed = agent::manifest::progress(&outdir)?;
+                (json_path, outdir, Some(resumed))
+            }
+            ([json_path, logs_path], _) if logs_path != "--resume" => (
+                json_path,
+                create_outdir(Path::new(logs_path), config)?,
+                None,
+            ),
+            ([json_path], Some(logs_path)) => (json_path, create_outdir(logs_path, config)?, None),
+            _ => return usage(
+                "usage: PROG local PATH_TO_SCENARIO [PATH_TO_OUTPUT | --resume PATH_TO_OUTPUT_DIR]",
+            ),
+        };
+    if let Some(user) = &config.user {
+        separate_privileges(user, &outdir)?;
+    }
 
     info!("agent is in local mode with config: {}", json_path);
     info!("output directory: {}", outdir.to_string_lossy());
-    let proto = protocol_impl::LocalProtocol::from_json(json_path)?;
-    let agent = agent::Agent::new(proto, outdir.clone());
+    let mut proto = protocol_impl::LocalProtocol::from_json(json_path).map_err(|msg| Failure {
+        code: EXIT_INVALID_SCENARIO,
+        msg,
+    })?;
+    let stage = resumed.as_ref().map_or(1, |p| p.stage);
+    proto.record_into(&outdir, stage)?;
+    config.record_into(&outdir, stage)?;
+    let hooks = config.hooks.clone().unwrap_or_default();
+    let hook_env = [
+        ("PMPPT_OUTDIR", outdir.to_string_lossy().into_owned()),
+        ("PMPPT_SCENARIO", json_path.clone()),
+    ];
+    hooks::run("pre_run", hooks.pre_run.as_deref(), &hook_env)?;
+    proto.hook_steps(&hooks, &outdir);
+    let mut settings = config.agent_settings();
+    settings.tags.extend(proto.tags().clone());
+    settings.progress = progress == Some("jsonl");
+    settings.degraded = degraded;
+    // the resumed run is already going on, the noise is of its own steps
+    settings.preflight = proto.preflight().filter(|_| resumed.is_none()).cloned();
+    let agent = match resumed {
+        Some(resumed) => agent::Agent::resume(proto, outdir.clone(), settings, resumed)?,
+        None => agent::Agent::new(proto, outdir.clone(), settings),
+    };
 
     info!("staring the agent");
-    agent.serve();
+    signals::install();
+    let view = terminal.map(|terminal| terminal.follow(&outdir));
+    let outcome = agent.serve();
+    if let Some(view) = view {
+        view.finish();
+    }
 
     info!("done, output directory: {}", outdir.to_string_lossy());
+
+    // the results of the aborted runs are delivered too, they are needed for the diagnosis
+    let uploaded = match &config.upload_url {
+        Some(url) => upload::upload(&outdir, url, config.upload_retries()),
+        None => Ok(()),
+    };
+    if let (Err(e), false) = (&uploaded, outcome == Outcome::Finished) {
+        error!("cannot upload the results: {}", e);
+    }
+    // the results are delivered by the time of the notification
+    let (command, url) = (
+        config.notify_command.as_deref(),
+        config.notify_url.as_deref(),
+    );
+    if command.is_some() || url.is_some() {
+        if let Err(e) = notify::notify(&outdir, command, url) {
+            warn!("cannot notify about the end of the run: {}", e);
+        }
+    }
+    let result = match outcome.abort_reason() {
+        None => "finished".to_owned(),
+        Some(reason) => format!("aborted: {}", reason),
+    };
+    let mut hook_env = hook_env.to_vec();
+    hook_env.push(("PMPPT_OUTCOME", result));
+    if let Err(e) = hooks::run("post_run", hooks.post_run.as_deref(), &hook_env) {
+        warn!("{}", e);
+    }
+    match outcome {
+        Outcome::Finished => Ok(uploaded?),
+        Outcome::Aborted(reason) => Err(Failure {
+            code: match reason {
+                AbortReason::StepFailed => EXIT_ABORTED,
+                AbortReason::Requested => EXIT_ABORT_REQUESTED,
+                AbortReason::Protocol | AbortReason::Signal => EXIT_ENVIRONMENT,
+            },
+            msg: format!("scenario aborted: {}", reason),
+        }),
+        Outcome::Signaled(signum) => Err(Failure {
+            code: EXIT_SIGNAL_BASE + signum,
+            msg: format!("scenario aborted by signal {}", signum),
+        }),
+    }
+}
+
+fn main_convert(args: &[String]) -> Result<(), Failure> {
+    let help =
+        "usage: PROG convert PATH_TO_POLL_LOG --to (csv|jsonl|parquet) [--since TIME] [--until TIME] \
+                [PATH_TO_OUTPUT]";
+
+    let mut paths = Vec::new();
+    let mut format = None;
+    let mut range = convert::Range::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().map_or_else(|| usage(help), Ok);
+        match arg.as_str() {
+            "--to" => format = Some(value()?),
+            "--since" => range.since = Some(convert::Range::parse_time(value()?)?),
+            "--until" => range.until = Some(convert::Range::parse_time(value()?)?),
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+
+    match (paths.as_slice(), format) {
+        ([input], Some(format)) => Ok(convert::convert(input, format, &range, None)?),
+        ([input, output], Some(format)) => {
+            Ok(convert::convert(input, format, &range, Some(output))?)
+        }
+        _ => usage(help),
+    }
+}
+
+fn main_inspect(args: &[String]) -> Result<(), Failure> {
+    match args {
+        [outdir] => Ok(inspect::inspect(Path::new(outdir))?),
+        _ => usage("usage: PROG inspect PATH_TO_OUTPUT_DIR"),
+    }
+}
+
+fn main_report(args: &[String]) -> Result<(), Failure> {
+    let path = match args {
+        [outdir] => report::report(Path::new(outdir), "html")?,
+        [outdir, flag, format] if flag == "--format" => report::report(Path::new(outdir), format)?,
+        _ => return usage("usage: PROG report PATH_TO_OUTPUT_DIR [--format (html|md|junit)]"),
+    };
+
+    info!("report is stored: {}", path.to_string_lossy());
     Ok(())
 }
 
-fn main_tcp(_args: &[String]) -> Result<(), String> {
-    emsg("tcp transport not implemented")
+fn main_selftest(args: &[String], config: &Config) -> Result<(), Failure> {
+    let json = args.iter().any(|a| a == "--json");
+    let paths: Vec<&String> = args.iter().filter(|a| *a != "--json").collect();
+    match paths.as_slice() {
+        [] => Ok(selftest::selftest(config.output_dir.clone(), json)?),
+        [path] => Ok(selftest::selftest(Some(PathBuf::from(path)), json)?),
+        _ => usage("usage: PROG selftest [PATH_TO_OUTPUT] [--json]"),
+    }
+}
+
+fn main_upload(args: &[String], config: &Config) -> Result<(), Failure> {
+    let (outdir, url) = match (args, &config.upload_url) {
+        ([outdir, url], _) => (outdir, url),
+        ([outdir], Some(url)) => (outdir, url),
+        _ => return usage("usage: PROG upload PATH_TO_OUTPUT_DIR [URL]"),
+    };
+    Ok(upload::upload(
+        Path::new(outdir),
+        url,
+        config.upload_retries(),
+    )?)
+}
+
+fn main_tcp(_args: &[String]) -> Result<(), Failure> {
+    usage("tcp transport not implemented")
 }
 
-fn main_wrapper(args: &[String]) -> Result<(), String> {
+fn main_wrapper(args: &[String]) -> Result<(), Failure> {
     // init log with Info level by default
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     info!("pmppt-agent");
 
-    if args.len() < 2 {
-        return emsg("usage: PROG (tcp|local) ARGS...");
+    if args[1..].iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", HELP);
+        return Ok(());
+    }
+
+    // global options go before the command
+    let (config_path, args) = match &args[1..] {
+        [flag, path, rest @ ..] if flag == "--config" => (Some(Path::new(path)), rest),
+        rest => (None, rest),
+    };
+    let config = Config::load(config_path)?;
+    if let Some(umask) = config.umask {
+        apply_umask(umask)?;
+    }
+
+    if args.is_empty() {
+        return usage("usage: PROG [--config PATH] COMMAND ARGS..., see --help for details");
     }
 
-    match args[1].as_str() {
-        "local" => main_local(&args[2..]),
-        "tcp" => main_tcp(&args[2..]),
-        _ => emsg("Only 'tcp' or 'local' transports supported"),
+    match args[0].as_str() {
+        "local" => main_local(&args[1..], &config),
+        "tcp" => main_tcp(&args[1..]),
+        "convert" => main_convert(&args[1..]),
+        "inspect" => main_inspect(&args[1..]),
+        "report" => main_report(&args[1..]),
+        "selftest" => main_selftest(&args[1..], &config),
+        "upload" => main_upload(&args[1..], &config),
+        cmd => usage(&format!("unsupported command '{}'", cmd)),
     }
 }
 
 fn main() {
     // TODO: here will be better CLI arguments parsing
     let args: Vec<String> = std::env::args().collect();
-    if let Err(msg) = main_wrapper(&args) {
-        error!("Error: {}", msg);
-        std::process::exit(1);
+
+    // the wrapper of the spawned process must not log anything into its outputs
+    #[cfg(target_os = "linux")]
+    if args
+        .get(1)
+        .is_some_and(|cmd| cmd == agent::seccomp::COMMAND)
+    {
+        eprintln!("pmppt-agent: {}", agent::seccomp::run(&args[2..]));
+        std::process::exit(EXIT_EXEC_FAILED);
+    }
+    if let Err(failure) = main_wrapper(&args) {
+        error!("Error: {}", failure.msg);
+        std::process::exit(failure.code);
     }
+    std::process::exit(EXIT_COMPLETED);
 }
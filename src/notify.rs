@@ -0,0 +1,131 @@
+//! Notification about the end of the run, for the engineers not watching the long runs.
+//!
+//! The JSON summary of the run is posted to the webhook by `curl`, its `text` field is the
+//! one-line message shown by the chat webhooks like the Slack or Matrix ones as is. The configured
+//! shell command gets the same summary on stdin, and the outcome and the outdir in the
+//! `PMPPT_OUTCOME` and `PMPPT_OUTDIR` environment variables, e.g. for `notify-send`.
+
+use std::path::Path;
+
+use log::info;
+use serde_json::json;
+use subprocess::{Exec, Redirection};
+
+use crate::inspect::{self, Summary};
+
+/// Limit of the webhook request time, the slow endpoint must not hold the agent exit.
+const WEBHOOK_TIMEOUT_S: &str = "30";
+
+fn payload(outdir: &Path, summary: &Summary) -> serde_json::Value {
+    let failed = summary
+        .steps
+        .values()
+        .filter(|step| step.exit_code.is_some_and(|code| code != 0))
+        .count();
+    let times = summary
+        .steps
+        .values()
+        .flat_map(|s| [s.started, s.done])
+        .flatten();
+    let duration = match (times.clone().min(), times.max()) {
+        (Some(start), Some(end)) => (end - start).num_milliseconds() as f64 / 1e3,
+        _ => 0.0,
+    };
+    let outdir = outdir.to_string_lossy();
+    let text = format!(
+        "pmppt run '{}' {} after {:.0}s: {} steps, {} failed, {} errors",
+        outdir,
+        summary.outcome,
+        duration,
+        summary.steps.len(),
+        failed,
+        summary.errors.len()
+    );
+    json!({
+        "text": text,
+        "outcome": summary.outcome,
+        "outdir": outdir,
+        "tags": summary.tags,
+        "duration_s": duration,
+        "steps": summary.steps.len(),
+        "failed_steps": failed,
+        "errors": summary.errors,
+    })
+}
+
+/// Run the tool with the payload on stdin, its output is returned as the error on failure.
+fn run(exec: Exec, payload: &str) -> Result<(), String> {
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdin(payload)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' failed: {:?} {}",
+            name,
+            capture.exit_status,
+            capture.stdout_str().trim()
+        )),
+    }
+}
+
+/// Check the webhook URL beforehand, not to learn about the typo only after the long run.
+pub fn check_url(url: &str) -> Result<(), String> {
+    match url.starts_with("http://") || url.starts_with("https://") {
+        true => Ok(()),
+        false => Err(format!("unsupported notification URL '{}'", url)),
+    }
+}
+
+/// Notify about the end of the run in the output directory by the command and the webhook.
+pub fn notify(outdir: &Path, command: Option<&str>, url: Option<&str>) -> Result<(), String> {
+    let summary = inspect::summarize(outdir)?;
+    let payload = payload(outdir, &summary).to_string();
+    if let Some(command) = command {
+        let exec = Exec::shell(command)
+            .env("PMPPT_OUTCOME", &summary.outcome)
+            .env("PMPPT_OUTDIR", outdir);
+        run(exec, &payload)?;
+        info!("notified by '{}'", command);
+    }
+    if let Some(url) = url {
+        let exec = Exec::cmd("curl")
+            .args(&["--fail", "--silent", "--show-error"])
+            .args(&["--max-time", WEBHOOK_TIMEOUT_S])
+            .args(&["--header", "Content-Type: application/json"])
+            .args(&["--data-binary", "@-"])
+            .arg(url);
+        run(exec, &payload)?;
+        info!("notified {}", url);
+    }
+    Ok(())
+}
+
+#[test]
+fn notification_payload() {
+    let step = |exit_code| inspect::Step {
+        kind: "Foreground".to_owned(),
+        name: "true".to_owned(),
+        started: None,
+        done: None,
+        exit_code,
+    };
+    let summary = Summary {
+        tags: [("host".to_owned(), "lab1".to_owned())].into(),
+        steps: [(1, step(Some(0))), (2, step(Some(1))), (3, step(None))].into(),
+        errors: vec!["Spawn - failed to start".to_owned()],
+        warnings: Vec::new(),
+        outcome: "aborted: step failed".to_owned(),
+    };
+    let payload = payload(Path::new("out/3"), &summary);
+    assert_eq!(
+        payload["text"],
+        "pmppt run 'out/3' aborted: step failed after 0s: 3 steps, 1 failed, 1 errors"
+    );
+    assert_eq!(payload["failed_steps"], 1);
+    assert_eq!(payload["tags"]["host"], "lab1");
+}
@@ -0,0 +1,150 @@
+//! Run output directories created under the base directory, named by the configured scheme.
+//!
+//! The runs are numbered `0, 1, 2, ...` by default, the padded numbers sort well in the listings,
+//! the dates tell when the run was made and the hybrid names have both. The old runs may be
+//! removed automatically, keeping the configured number of the latest ones, which is handy for the
+//! CI hosts running the scenarios hundreds of times.
+
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Digits of the padded run numbers.
+const WIDTH: usize = 6;
+
+/// Format of the run start time in the dated names.
+const DATE_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Order of the run directories, by the number and then by the dated name.
+type Key = (u32, String);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// `0`, `1`, `2`, ...
+    #[default]
+    Number,
+    /// `000000`, `000001`, ...
+    Padded,
+    /// `20261015-085205`, the runs started in the same second get `-2`, `-3`, ... suffixes.
+    Date,
+    /// `20261015-085205-000001`, the date of the run and its number.
+    Hybrid,
+}
+
+impl Scheme {
+    /// Order of the run directory by its name, none for the foreign ones.
+    fn key(self, name: &str) -> Option<Key> {
+        let number = |s: &str| match s.bytes().all(|b| b.is_ascii_digit()) {
+            true => s.parse().ok(),
+            false => None,
+        };
+        let date = |s: &str| chrono::NaiveDateTime::parse_from_str(s, DATE_FORMAT).is_ok();
+        match self {
+            Scheme::Number | Scheme::Padded => Some((number(name)?, String::new())),
+            // the dated runs are ordered by the time, then by the suffix
+            Scheme::Date => {
+                let (stamp, n) = match name.get(15..)? {
+                    "" => (name, 1),
+                    suffix => (&name[..15], number(suffix.strip_prefix('-')?)?),
+                };
+                date(stamp).then(|| (0, format!("{}-{:010}", stamp, n)))
+            }
+            Scheme::Hybrid => {
+                let (stamp, n) = name.rsplit_once('-')?;
+                date(stamp).then_some((number(n)?, String::new()))
+            }
+        }
+    }
+
+    /// Name of the next run after the ones in the base directory, `attempt` counts the taken
+    /// names of the same second.
+    fn name(self, next: u32, attempt: u32, now: chrono::DateTime<chrono::Local>) -> String {
+        let date = now.format(DATE_FORMAT);
+        match self {
+            Scheme::Number => next.to_string(),
+            Scheme::Padded => format!("{:0width$}", next, width = WIDTH),
+            Scheme::Date if attempt == 0 => date.to_string(),
+            Scheme::Date => format!("{}-{}", date, attempt + 1),
+            Scheme::Hybrid => format!("{}-{:0width$}", date, next, width = WIDTH),
+        }
+    }
+}
+
+/// Run directories of the scheme in the base directory, oldest first.
+fn runs(base: &Path, scheme: Scheme) -> Result<Vec<(PathBuf, Key)>, String> {
+    let entries = base
+        .read_dir()
+        .map_err(|e| format!("cannot read '{}' - {}", base.to_string_lossy(), e))?;
+    let mut runs: Vec<_> = entries
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|e| {
+            let key = scheme.key(&e.file_name().to_string_lossy())?;
+            Some((e.path(), key))
+        })
+        .collect();
+    runs.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(runs)
+}
+
+/// Remove the oldest runs, keeping the given number of the latest ones.
+fn remove_old(base: &Path, scheme: Scheme, keep: usize) -> Result<(), String> {
+    let runs = runs(base, scheme)?;
+    let old = runs.len().saturating_sub(keep);
+    for (path, _) in &runs[..old] {
+        match std::fs::remove_dir_all(path) {
+            Ok(()) => info!("removed old run '{}'", path.to_string_lossy()),
+            Err(e) => warn!("cannot remove old run '{}' - {}", path.to_string_lossy(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Create the directory of the new run in the base one, then remove the old runs over the limit.
+pub fn create(base: &Path, scheme: Scheme, keep: Option<usize>) -> Result<PathBuf, String> {
+    let existed = base.exists();
+    std::fs::create_dir_all(base)
+        .map_err(|e| format!("cannot create '{}' - {}", base.to_string_lossy(), e))?;
+
+    // the latest run is kept by the removals, so the numbers are never reused
+    let next = match runs(base, scheme)?.last() {
+        Some((_, (n, _))) => n + 1,
+        // the runs in the existing base directory always started from 1
+        None => existed as u32,
+    };
+    let now = chrono::Local::now();
+    let mut attempt = 0;
+    let dir = loop {
+        let dir = base.join(scheme.name(next, attempt, now));
+        match std::fs::create_dir(&dir) {
+            Ok(()) => break dir,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(format!("cannot create '{}' - {}", dir.to_string_lossy(), e)),
+        }
+        if scheme != Scheme::Date {
+            return Err(format!("run '{}' already exists", dir.to_string_lossy()));
+        }
+    };
+
+    if let Some(keep) = keep {
+        remove_old(base, scheme, keep.max(1))?;
+    }
+    Ok(dir)
+}
+
+#[test]
+fn run_names() {
+    let now = chrono::Local::now();
+    for scheme in [Scheme::Number, Scheme::Padded, Scheme::Date, Scheme::Hybrid] {
+        let first = scheme.key(&scheme.name(9, 0, now)).unwrap();
+        let second = scheme.key(&scheme.name(10, 1, now)).unwrap();
+        assert!(first < second, "{:?}", scheme);
+    }
+    assert_eq!(Scheme::Padded.name(7, 0, now), "000007");
+    assert_eq!(Scheme::Number.key("report.html"), None);
+    assert_eq!(Scheme::Hybrid.key("20261015-085205-000012").unwrap().0, 12);
+    assert_eq!(Scheme::Date.key("20261015-085205-x"), None);
+    assert_eq!(Scheme::Date.key("20261015-0852"), None);
+}
@@ -0,0 +1,357 @@
+//! Reader of the poll logs produced by the agent pollers.
+//!
+//! The log starts with the JSON [`PollHeader`] line followed by the samples. Every sample of the
+//! text log is the line with the timestamp and the sequence number, the concatenated content of
+//! all the polled files and the final newline. The binary logs store the samples as the frames
+//! described in [`binary`]. The logs of the format version 1 have no sequence numbers.
+//!
+//! The text logs with the numeric timestamps, see [`TimeFormat`], tell the sample lines from the
+//! numeric content lines by the order: the timestamps and the sequence numbers of the samples only
+//! grow.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::agent::poller::binary::{self, Frame, IndexEntry};
+use crate::agent::poller::{PollHeader, FORMAT_VERSION};
+use crate::agent::protocol::TimeFormat;
+
+/// Single sample of the poll log.
+pub struct Sample {
+    pub timestamp: String,
+    /// Number of the sample in the poller schedule, the gaps are the skipped samples.
+    pub seq: Option<u64>,
+    pub content: String,
+}
+
+pub struct PollLog {
+    pub header: PollHeader,
+    body: Body,
+}
+
+enum Body {
+    Text {
+        lines: Lines<BufReader<File>>,
+        version: u32,
+        format: TimeFormat,
+        next_stamp: Option<Stamp>,
+    },
+    Binary {
+        reader: BufReader<File>,
+        version: u32,
+        format: TimeFormat,
+        pos: u64,
+        end: u64,
+        index: Vec<IndexEntry>,
+        pending: Option<Frame>,
+    },
+}
+
+/// Timestamp and sequence number of the text log sample.
+type Stamp = (String, Option<u64>);
+
+/// Parse the sample line following the previous sample one, if any.
+fn parse_stamp(
+    line: &str,
+    version: u32,
+    format: TimeFormat,
+    prev: Option<&Stamp>,
+) -> Option<Stamp> {
+    let (timestamp, seq) = match version {
+        1 => (line, None),
+        _ => {
+            let (timestamp, seq) = line.split_once(' ')?;
+            (timestamp, Some(seq.parse().ok()?))
+        }
+    };
+    match format {
+        TimeFormat::Local | TimeFormat::Utc => {
+            chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+        }
+        TimeFormat::EpochNs | TimeFormat::MonotonicNs => {
+            let nanos: i64 = timestamp.parse().ok()?;
+            if let Some((prev_timestamp, prev_seq)) = prev {
+                let prev_nanos: i64 = prev_timestamp.parse().ok()?;
+                if nanos < prev_nanos || seq <= *prev_seq {
+                    return None;
+                }
+            }
+        }
+    }
+    Some((timestamp.to_owned(), seq))
+}
+
+/// Wall-clock time of the sample timestamp, the monotonic ones have none.
+pub fn wall_time(timestamp: &str, format: TimeFormat) -> Result<DateTime<FixedOffset>, String> {
+    let bad = |e: &dyn std::fmt::Display| format!("bad time '{}' - {}", timestamp, e);
+    match format {
+        TimeFormat::Local | TimeFormat::Utc => {
+            DateTime::parse_from_rfc3339(timestamp).map_err(|e| bad(&e))
+        }
+        TimeFormat::EpochNs => timestamp
+            .parse()
+            .map(|nanos| DateTime::from_timestamp_nanos(nanos).fixed_offset())
+            .map_err(|e| bad(&e)),
+        TimeFormat::MonotonicNs => Err(format!(
+            "monotonic timestamp '{}' has no wall-clock time",
+            timestamp
+        )),
+    }
+}
+
+/// Limit of the header read when looking for the poll logs, the other files are not read through.
+const HEADER_CAP: u64 = 16 * 1024 * 1024;
+
+/// Whether the file is the poll log by its header, as the logs are named by the naming template.
+pub fn is_poll_log(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut header = String::new();
+    BufReader::new(file.take(HEADER_CAP))
+        .read_line(&mut header)
+        .is_ok()
+        && serde_json::from_str::<PollHeader>(&header).is_ok()
+}
+
+/// Timestamp of the binary frame in the same form as in the text logs.
+fn format_timestamp(micros: i64, format: TimeFormat) -> String {
+    let secs = micros.div_euclid(1_000_000);
+    let nanos = micros.rem_euclid(1_000_000) as u32 * 1000;
+    match (chrono::DateTime::from_timestamp(secs, nanos), format) {
+        (_, TimeFormat::EpochNs) => (micros as i128 * 1000).to_string(),
+        (Some(time), TimeFormat::Utc) => time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        // the binary logs have no monotonic timestamps
+        (Some(time), TimeFormat::Local | TimeFormat::MonotonicNs) => time
+            .with_timezone(&chrono::Local)
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+        (None, _) => format!("<bad timestamp {}>", micros),
+    }
+}
+
+impl PollLog {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("cannot open '{}' - {}", path.to_string_lossy(), e))?;
+        let mut reader = BufReader::new(file);
+
+        let mut header = String::new();
+        let header_len = reader
+            .read_line(&mut header)
+            .map_err(|e| format!("cannot read poll log header - {}", e))?;
+        if header_len == 0 {
+            return Err("poll log is empty".into());
+        }
+        let header: PollHeader =
+            serde_json::from_str(&header).map_err(|e| format!("bad poll log header - {}", e))?;
+        if header.version > FORMAT_VERSION {
+            return Err(format!(
+                "poll log format version {} is newer than the supported {}",
+                header.version, FORMAT_VERSION
+            ));
+        }
+
+        let (version, format) = (header.version, header.timestamp);
+        let body = match header.binary {
+            true => Self::open_binary(reader.into_inner(), header_len as u64, version, format)
+                .map_err(|e| format!("cannot read poll log - {}", e))?,
+            false => Self::open_text(reader.lines(), version, format)?,
+        };
+        Ok(Self { header, body })
+    }
+
+    fn open_text(
+        mut lines: Lines<BufReader<File>>,
+        version: u32,
+        format: TimeFormat,
+    ) -> Result<Body, String> {
+        // the very first line after the header must be the timestamp
+        let next_stamp = match lines.next() {
+            None => None,
+            Some(Ok(line)) => match parse_stamp(&line, version, format, None) {
+                Some(stamp) => Some(stamp),
+                None => return Err(format!("expected sample timestamp, got '{}'", line)),
+            },
+            Some(Err(e)) => return Err(format!("cannot read poll log - {}", e)),
+        };
+
+        Ok(Body::Text {
+            lines,
+            version,
+            format,
+            next_stamp,
+        })
+    }
+
+    fn open_binary(
+        mut file: File,
+        start: u64,
+        version: u32,
+        format: TimeFormat,
+    ) -> std::io::Result<Body> {
+        // the logs of the interrupted pollers have no index and are read till the end
+        let (end, index) = match binary::read_index(&mut file, start)? {
+            Some(found) => found,
+            None => (file.metadata()?.len(), Vec::new()),
+        };
+        file.seek(SeekFrom::Start(start))?;
+
+        Ok(Body::Binary {
+            reader: BufReader::new(file),
+            version,
+            format,
+            pos: start,
+            end,
+            index,
+            pending: None,
+        })
+    }
+
+    /// Skip to the samples close to the given time using the index of the binary log.
+    ///
+    /// It is just a shortcut, some earlier samples may still be returned after it, and the text
+    /// logs are not changed at all.
+    pub fn seek(&mut self, time: DateTime<FixedOffset>) -> Result<(), String> {
+        let Body::Binary {
+            reader,
+            pos,
+            index,
+            pending,
+            ..
+        } = &mut self.body
+        else {
+            return Ok(());
+        };
+
+        let micros = time.timestamp_micros();
+        let found = index.partition_point(|entry| entry.timestamp <= micros);
+        if let Some(entry) = found.checked_sub(1).map(|i| index[i]) {
+            reader
+                .seek(SeekFrom::Start(entry.offset))
+                .map_err(|e| format!("cannot seek poll log - {}", e))?;
+            *pos = entry.offset;
+            *pending = None;
+        }
+        Ok(())
+    }
+}
+
+fn next_text(
+    lines: &mut Lines<BufReader<File>>,
+    version: u32,
+    format: TimeFormat,
+    next_stamp: &mut Option<Stamp>,
+) -> Option<Result<Sample, String>> {
+    let stamp = next_stamp.take()?;
+
+    // collect everything till the next timestamp or the end of the log
+    let mut content = String::new();
+    for line in lines.by_ref() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(format!("cannot read poll log - {}", e))),
+        };
+        if let Some(next) = parse_stamp(&line, version, format, Some(&stamp)) {
+            *next_stamp = Some(next);
+            break;
+        }
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    // drop the final newline of the sample
+    content.pop();
+    let (timestamp, seq) = stamp;
+    Some(Ok(Sample {
+        timestamp,
+        seq,
+        content,
+    }))
+}
+
+fn read_frame(
+    reader: &mut BufReader<File>,
+    version: u32,
+    pos: &mut u64,
+    end: u64,
+) -> Result<Option<Frame>, String> {
+    let mut input = reader.take(end.saturating_sub(*pos));
+    let frame = binary::read_frame(&mut input, version)
+        .map_err(|e| format!("cannot read poll log - {}", e));
+    *pos = end - input.limit();
+    frame
+}
+
+impl Iterator for PollLog {
+    type Item = Result<Sample, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (reader, version, format, pos, end, pending) = match &mut self.body {
+            Body::Text {
+                lines,
+                version,
+                format,
+                next_stamp,
+            } => return next_text(lines, *version, *format, next_stamp),
+            Body::Binary {
+                reader,
+                version,
+                format,
+                pos,
+                end,
+                pending,
+                ..
+            } => (reader, *version, *format, pos, *end, pending),
+        };
+
+        let first = match pending.take() {
+            Some(frame) => frame,
+            None => match read_frame(reader, version, pos, end) {
+                Ok(frame) => frame?,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+
+        // the sample consists of the frames till the next one of the first source
+        let mut content = String::from_utf8_lossy(&first.payload).into_owned();
+        loop {
+            match read_frame(reader, version, pos, end) {
+                Ok(Some(frame)) if frame.source == 0 => {
+                    *pending = Some(frame);
+                    break;
+                }
+                Ok(Some(frame)) => content.push_str(&String::from_utf8_lossy(&frame.payload)),
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        Some(Ok(Sample {
+            timestamp: format_timestamp(first.timestamp, format),
+            seq: first.seq,
+            content,
+        }))
+    }
+}
+
+#[test]
+fn numeric_timestamps() {
+    let first = parse_stamp("1760520000000000000 7", 2, TimeFormat::EpochNs, None).unwrap();
+    assert_eq!(first, ("1760520000000000000".to_owned(), Some(7)));
+    // the numeric content lines are not taken for the samples
+    let next = |line| parse_stamp(line, 2, TimeFormat::EpochNs, Some(&first));
+    assert!(next("1760520000250000000 8").is_some());
+    assert!(next("12 34").is_none());
+    assert!(next("1760520000250000000 3").is_none());
+    assert!(parse_stamp("2025-10-15T10:00:00Z 1", 2, TimeFormat::EpochNs, None).is_none());
+
+    let time = wall_time("1760520000250000000", TimeFormat::EpochNs).unwrap();
+    assert_eq!(
+        time,
+        wall_time("2025-10-15T09:20:00.25Z", TimeFormat::Utc).unwrap()
+    );
+    assert!(wall_time("123", TimeFormat::MonotonicNs).is_err());
+}
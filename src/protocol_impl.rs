@@ -1,16 +1,27 @@
 //! Implementations of PMPPT protocol for the agent.
 
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use log::{debug, error};
-use serde::Deserialize;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::agent::protocol::{PmpptRequest, PmpptResponse, Protocol, SpawnMode};
+use crate::agent::noise::{self, Preflight};
+use crate::agent::poller::MIN_PERIOD;
+use crate::agent::protocol::{
+    AbortReason, Container, Encoding, Excerpt, Fault, FgOutput, Isolation, LatencyProbe, Load,
+    Mode, NetObject, Pacing, PidTarget, PmpptRequest, PmpptResponse, PollOptions, PriorityOptions,
+    Protocol, SeccompProfile, SpawnMode, SpawnOptions, SystemdUnit, TimeFormat,
+};
+use crate::barrier;
+use crate::hooks::{self, Hooks};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Clone, Copy)]
 #[allow(non_camel_case_types)]
 enum ExecMode {
     fg,
@@ -29,17 +40,276 @@ fn local_mode_to_agent(mode: Option<ExecMode>) -> SpawnMode {
     }
 }
 
-#[derive(Deserialize)]
+/// Description of how to store FG process output into a scenario variable.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+enum Capture {
+    // just the trimmed stdout
+    Var(String),
+    // capture group of the regex applied to stdout, whole match by default
+    Regex {
+        var: String,
+        regex: String,
+        group: Option<usize>,
+    },
+}
+
+impl Capture {
+    fn var(&self) -> &str {
+        match self {
+            Capture::Var(var) => var,
+            Capture::Regex { var, .. } => var,
+        }
+    }
+
+    fn extract(&self, stdout: &str) -> Result<String, String> {
+        match self {
+            Capture::Var(_) => Ok(stdout.trim().to_owned()),
+            Capture::Regex { regex, group, .. } => {
+                let re = Regex::new(regex).map_err(|e| format!("bad regex '{}' - {}", regex, e))?;
+                let group = group.unwrap_or(0);
+                re.captures(stdout)
+                    .and_then(|c| c.get(group))
+                    .map(|m| m.as_str().trim().to_owned())
+                    .ok_or_else(|| format!("regex '{}' group {} did not match", regex, group))
+            }
+        }
+    }
+}
+
+/// Substitute `${name}` references in the string with the scenario variables.
+///
+/// References not looking like plain identifiers (e.g. `${pid:3}`) are left as-is, the agent
+/// resolves them itself.
+fn substitute(s: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap(); // static, should never fail
+
+    let mut missing = None;
+    let res = re.replace_all(s, |c: &regex::Captures| match vars.get(&c[1]) {
+        Some(value) => value.clone(),
+        None => {
+            missing.get_or_insert_with(|| c[1].to_owned());
+            String::new()
+        }
+    });
+
+    match missing {
+        None => Ok(res.into_owned()),
+        Some(var) => Err(format!("variable '{}' is not defined in '{}'", var, s)),
+    }
+}
+
+/// What to do when the step fails (after all the retries).
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+enum ErrorPolicy {
+    abort,
+    ignore,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct PollStep {
+    pattern: String,
+    period_s: Option<f64>,
+    realtime: Option<bool>,
+    fast_period_s: Option<f64>,
+    change_threshold: Option<f64>,
+    dedup: Option<bool>,
+    keyframe_s: Option<f64>,
+    max_bytes: Option<usize>,
+    parallel: Option<usize>,
+    flush_interval_s: Option<f64>,
+    fsync_interval_s: Option<f64>,
+    binary: Option<bool>,
+    split: Option<bool>,
+    stats: Option<bool>,
+    sqlite: Option<bool>,
+    parquet: Option<bool>,
+    staging_dir: Option<PathBuf>,
+    strict: Option<bool>,
+    encoding: Option<Encoding>,
+    warmup_samples: Option<u64>,
+    warmup_s: Option<f64>,
+    warmup_log: Option<bool>,
+    duration_s: Option<f64>,
+    timestamp: Option<TimeFormat>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SpawnStep {
+    cmd: String,
+    args: Option<Vec<String>>,
+    mode: Option<ExecMode>,
+    cwd: Option<PathBuf>,
+    env: Option<BTreeMap<String, String>>,
+    capture: Option<Capture>,
+    retries: Option<u32>,
+    retry_delay_s: Option<f64>,
+    core_dumps: Option<bool>,
+    isolate: Option<Isolation>,
+    seccomp: Option<SeccompProfile>,
+    pacing: Option<Pacing>,
+    systemd: Option<SystemdUnit>,
+    container: Option<Container>,
+    first_output: Option<bool>,
+    umask: Option<Mode>,
+    output_mode: Option<Mode>,
+    duration_s: Option<f64>,
+    reply: Option<Excerpt>,
+    on_error: Option<ErrorPolicy>,
+    // number of the failed attempts made so far
+    #[serde(skip)]
+    attempt: u32,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct PollPidStep {
+    pid: Option<u32>,
+    name: Option<String>,
+    container: Option<String>,
+    period_s: Option<f64>,
+    warmup_samples: Option<u64>,
+    warmup_s: Option<f64>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct BracketStep {
+    pattern: Option<String>,
+    commands: Option<Vec<Vec<String>>>,
+    cmd: String,
+    args: Option<Vec<String>>,
+    cwd: Option<PathBuf>,
+    env: Option<BTreeMap<String, String>>,
+    reply: Option<Excerpt>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct TailStep {
+    path: PathBuf,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct LatencyStep {
+    #[serde(flatten)]
+    probe: LatencyProbe,
+    realtime: Option<bool>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct LoadStep {
+    #[serde(flatten)]
+    load: Load,
+    duration_s: Option<f64>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct WatchStep {
+    pattern: String,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct IngestStep {
+    name: String,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct JournalStep {
+    units: Option<Vec<String>>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct CollectStep {
+    host: String,
+    paths: Vec<String>,
+    on_error: Option<ErrorPolicy>,
+}
+
+/// Rendezvous with the agents of the other hosts, see [`crate::barrier`].
+#[derive(Deserialize, Serialize, Clone)]
+struct BarrierStep {
+    name: String,
+    listen: String,
+    peers: Vec<String>,
+    timeout_s: Option<f64>,
+    on_error: Option<ErrorPolicy>,
+}
+
+/// Wait for the idle system, see [`noise::wait_idle`].
+#[derive(Deserialize, Serialize, Clone)]
+struct WaitIdleStep {
+    cpu_below: f64,
+    timeout_s: f64,
+    window_s: Option<f64>,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct IdStep {
+    id: u32,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct ReniceStep {
+    id: u32,
+    #[serde(flatten)]
+    opts: PriorityOptions,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct NetStep {
+    #[serde(flatten)]
+    object: NetObject,
+    on_error: Option<ErrorPolicy>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct FaultStep {
+    #[serde(flatten)]
+    fault: Fault,
+    on_error: Option<ErrorPolicy>,
+}
+
+// the steps are few and parsed once, their size does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(tag = "type", content = "data")]
 enum LocalRequest {
     // mapped PMPPT commands
-    Poll {
-        pattern: String,
-    },
-    Spawn {
-        cmd: String,
-        args: Option<Vec<String>>,
-        mode: Option<ExecMode>,
+    Poll(PollStep),
+    PollPid(PollPidStep),
+    Spawn(SpawnStep),
+    Bracket(BracketStep),
+    Tail(TailStep),
+    Watch(WatchStep),
+    Ingest(IngestStep),
+    Latency(LatencyStep),
+    Load(LoadStep),
+    Journal(JournalStep),
+    Collect(CollectStep),
+    PauseId(IdStep),
+    ResumeId(IdStep),
+    Freeze(IdStep),
+    Thaw(IdStep),
+    Renice(ReniceStep),
+    NetCreate(NetStep),
+    NetDestroy(IdStep),
+    Fault(FaultStep),
+    Revert(IdStep),
+    WaitIdle(WaitIdleStep),
+    /// Steps sent to the agent at once, executed in order.
+    Batch {
+        steps: Vec<LocalRequest>,
     },
     Abort,
     // local transport commands (non-PMPPT)
@@ -49,11 +319,382 @@ enum LocalRequest {
     Sleep {
         time: f64,
     },
+    Barrier(BarrierStep),
+}
+
+/// Scenario-wide values used for the step parameters not set explicitly.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    mode: Option<ExecMode>,
+    cwd: Option<PathBuf>,
+    env: Option<BTreeMap<String, String>>,
+    period_s: Option<f64>,
+    stats: Option<bool>,
+    sqlite: Option<bool>,
+    parquet: Option<bool>,
+    staging_dir: Option<PathBuf>,
+    strict: Option<bool>,
+    core_dumps: Option<bool>,
+    isolate: Option<Isolation>,
+    seccomp: Option<SeccompProfile>,
+    systemd: Option<SystemdUnit>,
+    umask: Option<Mode>,
+    output_mode: Option<Mode>,
+    reply: Option<Excerpt>,
+    on_error: Option<ErrorPolicy>,
+}
+
+impl Defaults {
+    fn apply(&self, req: &mut LocalRequest) {
+        match req {
+            LocalRequest::Poll(step) => {
+                step.period_s = step.period_s.or(self.period_s);
+                step.stats = step.stats.or(self.stats);
+                step.sqlite = step.sqlite.or(self.sqlite);
+                step.parquet = step.parquet.or(self.parquet);
+                step.staging_dir = step.staging_dir.take().or_else(|| self.staging_dir.clone());
+                step.strict = step.strict.or(self.strict);
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::PollPid(step) => {
+                step.period_s = step.period_s.or(self.period_s);
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Bracket(step) => {
+                step.cwd = step.cwd.take().or_else(|| self.cwd.clone());
+                step.reply = step.reply.or(self.reply);
+                step.on_error = step.on_error.or(self.on_error);
+
+                if let Some(env) = &self.env {
+                    let mut merged = env.clone();
+                    merged.extend(step.env.take().unwrap_or_default());
+                    step.env = Some(merged);
+                }
+            }
+            LocalRequest::Tail(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Watch(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Ingest(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Latency(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Load(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Journal(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Collect(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Barrier(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::WaitIdle(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::PauseId(step)
+            | LocalRequest::ResumeId(step)
+            | LocalRequest::Freeze(step)
+            | LocalRequest::Thaw(step)
+            | LocalRequest::NetDestroy(step)
+            | LocalRequest::Revert(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Renice(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::NetCreate(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Fault(step) => {
+                step.on_error = step.on_error.or(self.on_error);
+            }
+            LocalRequest::Batch { steps } => {
+                steps.iter_mut().for_each(|step| self.apply(step));
+            }
+            LocalRequest::Spawn(step) => {
+                step.mode = step.mode.or(self.mode);
+                step.cwd = step.cwd.take().or_else(|| self.cwd.clone());
+                step.core_dumps = step.core_dumps.or(self.core_dumps);
+                step.umask = step.umask.or(self.umask);
+                step.output_mode = step.output_mode.or(self.output_mode);
+                step.reply = step.reply.or(self.reply);
+                step.isolate = step.isolate.take().or_else(|| self.isolate.clone());
+                step.seccomp = step.seccomp.take().or_else(|| self.seccomp.clone());
+                step.systemd = step.systemd.take().or_else(|| self.systemd.clone());
+                step.on_error = step.on_error.or(self.on_error);
+
+                // environment is merged, the step values take precedence
+                if let Some(env) = &self.env {
+                    let mut merged = env.clone();
+                    merged.extend(step.env.take().unwrap_or_default());
+                    step.env = Some(merged);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Full scenario form, the plain list of steps is also accepted.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    defaults: Option<Defaults>,
+    /// Labels of the run, like the build id or the git sha of the tested software.
+    tags: Option<BTreeMap<String, String>>,
+    /// Sample the background noise of the target before the first step.
+    preflight: Option<Preflight>,
+    steps: Vec<Value>,
+}
+
+/// Names of the supported scenario steps, used for diagnostics.
+const STEP_TYPES: &[&str] = &[
+    "Poll",
+    "PollPid",
+    "Spawn",
+    "Bracket",
+    "Tail",
+    "Watch",
+    "Ingest",
+    "Latency",
+    "Load",
+    "Journal",
+    "Collect",
+    "PauseId",
+    "ResumeId",
+    "Freeze",
+    "Thaw",
+    "Renice",
+    "NetCreate",
+    "NetDestroy",
+    "Fault",
+    "Revert",
+    "WaitIdle",
+    "Batch",
+    "Abort",
+    "Pause",
+    "Sleep",
+    "Barrier",
+];
+
+/// Limit of the step text shown in the error messages.
+const SNIPPET_LEN: usize = 160;
+
+fn step_snippet(value: &Value) -> String {
+    let snippet = value.to_string();
+    match snippet.char_indices().nth(SNIPPET_LEN) {
+        Some((pos, _)) => format!("{}...", &snippet[..pos]),
+        None => snippet,
+    }
+}
+
+/// Check the step parameters beforehand to not fail in the middle of the scenario.
+fn check_step(req: &LocalRequest) -> Result<(), String> {
+    if let LocalRequest::Spawn(SpawnStep {
+        mode,
+        capture: Some(capture),
+        ..
+    }) = req
+    {
+        if !matches!(mode, None | Some(ExecMode::fg)) {
+            return Err(format!(
+                "capture of '{}' is supported only for foreground spawns",
+                capture.var()
+            ));
+        }
+        if let Capture::Regex { regex, .. } = capture {
+            Regex::new(regex).map_err(|e| format!("bad capture regex '{}' - {}", regex, e))?;
+        }
+    }
+
+    if let LocalRequest::Spawn(SpawnStep {
+        mode,
+        duration_s: Some(_),
+        ..
+    }) = req
+    {
+        if matches!(mode, None | Some(ExecMode::fg)) {
+            return Err("duration is supported only for background spawns".into());
+        }
+    }
+
+    let duration = match req {
+        LocalRequest::Poll(step) => step.duration_s,
+        LocalRequest::Spawn(step) => step.duration_s,
+        _ => None,
+    };
+    if let Some(duration) = duration {
+        if !(duration.is_finite() && duration > 0.0) {
+            return Err(format!("bad duration {}s", duration));
+        }
+    }
+
+    if let LocalRequest::Spawn(SpawnStep {
+        pacing: Some(pacing),
+        ..
+    }) = req
+    {
+        pacing.check()?;
+    }
+
+    if let LocalRequest::Latency(step) = req {
+        step.probe.check()?;
+    }
+
+    if let LocalRequest::Fault(step) = req {
+        step.fault.check()?;
+    }
+
+    if let LocalRequest::Load(step) = req {
+        step.load.check()?;
+        if let Some(duration) = step.duration_s {
+            if !(duration.is_finite() && duration > 0.0) {
+                return Err(format!("bad load duration {}s", duration));
+            }
+        }
+    }
+
+    if let LocalRequest::Poll(step) = req {
+        let intervals = [
+            step.period_s,
+            step.fast_period_s,
+            step.keyframe_s,
+            step.flush_interval_s,
+            step.fsync_interval_s,
+        ];
+        for period in intervals.into_iter().flatten() {
+            if period.is_nan() || period < MIN_PERIOD.as_secs_f64() {
+                return Err(format!(
+                    "poll interval {}s is shorter than the minimum of {:?}",
+                    period, MIN_PERIOD
+                ));
+            }
+        }
+    }
+
+    let warmup = match req {
+        LocalRequest::Poll(step) => step.warmup_s,
+        LocalRequest::PollPid(step) => step.warmup_s,
+        _ => None,
+    };
+    if let Some(warmup) = warmup {
+        if !(warmup.is_finite() && warmup >= 0.0) {
+            return Err(format!("bad poll warm-up {}s", warmup));
+        }
+    }
+
+    if let LocalRequest::PollPid(step) = req {
+        let targets = [
+            step.pid.is_some(),
+            step.name.is_some(),
+            step.container.is_some(),
+        ];
+        if targets.into_iter().filter(|&set| set).count() != 1 {
+            return Err("PollPid step needs exactly one of 'pid', 'name' and 'container'".into());
+        }
+        if let Some(name) = &step.name {
+            Regex::new(name).map_err(|e| format!("bad name pattern '{}' - {}", name, e))?;
+        }
+        if let Some(period) = step.period_s {
+            if period.is_nan() || period < MIN_PERIOD.as_secs_f64() {
+                return Err(format!(
+                    "poll interval {}s is shorter than the minimum of {:?}",
+                    period, MIN_PERIOD
+                ));
+            }
+        }
+    }
+
+    if let LocalRequest::Renice(step) = req {
+        step.opts.check()?;
+    }
+
+    if let LocalRequest::Barrier(step) = req {
+        if step.peers.is_empty() {
+            return Err(format!("barrier '{}' has no peers", step.name));
+        }
+        if let Some(timeout) = step.timeout_s {
+            if !(timeout.is_finite() && timeout > 0.0) {
+                return Err(format!("bad barrier timeout {}s", timeout));
+            }
+        }
+    }
+
+    if let LocalRequest::WaitIdle(step) = req {
+        let window = step.window_s.unwrap_or(DEFAULT_IDLE_WINDOW_S);
+        for time in [step.timeout_s, window] {
+            if !(time.is_finite() && time > 0.0) {
+                return Err(format!("bad idle wait time {}s", time));
+            }
+        }
+        noise::check_idle(
+            step.cpu_below,
+            Duration::from_secs_f64(window),
+            Duration::from_secs_f64(step.timeout_s),
+        )?;
+    }
+
+    if let LocalRequest::Batch { steps } = req {
+        for step in steps {
+            match step {
+                LocalRequest::Spawn(step) if step.capture.is_some() || step.retries.is_some() => {
+                    return Err("capture and retries are not supported in batch".into());
+                }
+                LocalRequest::Spawn(_) => (),
+                step if LocalProtocol::map_request(step).is_none() => {
+                    let value = serde_json::to_value(step).unwrap_or_default(); // never fails
+                    let kind = value["type"].as_str().unwrap_or_default();
+                    return Err(format!("{} step cannot be batched", kind));
+                }
+                _ => (),
+            }
+            check_step(step)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_step(value: Value, defaults: &Defaults) -> Result<LocalRequest, String> {
+    let mut req: LocalRequest = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    defaults.apply(&mut req);
+
+    check_step(&req)?;
+    Ok(req)
 }
 
+const SOURCE_NAME: &str = "scenario-source.json";
+const EXECUTED_NAME: &str = "scenario-executed.json";
+
 pub struct LocalProtocol {
+    source: PathBuf,
+    tags: BTreeMap<String, String>,
+    preflight: Option<Preflight>,
     requests: Vec<LocalRequest>,
     current: Option<PmpptRequest>,
+    step: Option<LocalRequest>,
+    capture: Option<Capture>,
+    vars: HashMap<String, String>,
+    // reason of the abort emulated on the next request
+    abort: Option<AbortReason>,
+    // the steps already executed with all the parameters resolved
+    executed: Vec<LocalRequest>,
+    executed_path: Option<PathBuf>,
+    // step hooks with the context common for all the steps
+    hooks: Hooks,
+    hook_env: Vec<(&'static str, String)>,
+    // index and type of the step waiting for its post_step hook
+    hooked: Option<(usize, String)>,
+    // the current step has failed, even if the failure is ignored
+    failed: bool,
 }
 
 impl LocalProtocol {
@@ -62,24 +703,391 @@ impl LocalProtocol {
         let content = fs::read_to_string(json_path)
             .map_err(|e| format!("cannot read '{}' - {}", json_path, e))?;
 
-        // parse as raw JSON list first
-        let values: Vec<Value> =
+        // parse as raw JSON first
+        let value: Value =
             serde_json::from_str(&content).map_err(|e| format!("bad JSON format - {}", e))?;
 
-        // then map every command to PMPPT protocol
-        let mut requests: Vec<LocalRequest> = serde_json::from_value(Value::Array(values))
-            .map_err(|e| format!("unsupported command found: {}", e))?;
+        let (defaults, tags, preflight, values) = match value {
+            Value::Array(values) => (Defaults::default(), BTreeMap::new(), None, values),
+            value => {
+                let scenario: Scenario = serde_json::from_value(value)
+                    .map_err(|e| format!("bad scenario format - {}", e))?;
+                (
+                    scenario.defaults.unwrap_or_default(),
+                    scenario.tags.unwrap_or_default(),
+                    scenario.preflight,
+                    scenario.steps,
+                )
+            }
+        };
+        if let Some(preflight) = &preflight {
+            preflight.check()?;
+        }
+
+        // then map every command to PMPPT protocol one by one to report the exact failed step
+        let mut requests = values
+            .into_iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let snippet = step_snippet(&value);
+                parse_step(value, &defaults).map_err(|e| {
+                    format!(
+                        "bad step at index {}: {}\n  step: {}\n  supported step types: {}",
+                        index,
+                        e,
+                        snippet,
+                        STEP_TYPES.join(", ")
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         // reverse the vector to extract the elements with `pop`
         requests.reverse();
 
         Ok(LocalProtocol {
+            source: PathBuf::from(json_path),
+            tags,
+            preflight,
             requests,
             current: None,
+            step: None,
+            capture: None,
+            vars: HashMap::default(),
+            abort: None,
+            executed: Vec::default(),
+            executed_path: None,
+            hooks: Hooks::default(),
+            hook_env: Vec::new(),
+            hooked: None,
+            failed: false,
+        })
+    }
+
+    /// Run the step hooks around every step sent to the agent, the batch is a single step and the
+    /// local sleeps and pauses are not hooked.
+    pub fn hook_steps(&mut self, hooks: &Hooks, outdir: &Path) {
+        self.hooks = hooks.clone();
+        self.hook_env = vec![
+            ("PMPPT_OUTDIR", outdir.to_string_lossy().into_owned()),
+            ("PMPPT_SCENARIO", self.source.to_string_lossy().into_owned()),
+        ];
+    }
+
+    /// Run the step hook with the context of the step, the failed hook aborts the run.
+    fn run_step_hook(&mut self, name: &str, index: usize, kind: &str, result: Option<&str>) {
+        let command = match name {
+            "pre_step" => self.hooks.pre_step.as_deref(),
+            _ => self.hooks.post_step.as_deref(),
+        };
+        let mut env = self.hook_env.clone();
+        env.push(("PMPPT_STEP_INDEX", index.to_string()));
+        env.push(("PMPPT_STEP_TYPE", kind.to_owned()));
+        if let Some(result) = result {
+            env.push(("PMPPT_STEP_RESULT", result.to_owned()));
+        }
+        if let Err(e) = hooks::run(name, command, &env) {
+            error!("{}", e);
+            self.abort = Some(AbortReason::StepFailed);
+        }
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
+    }
+
+    pub fn preflight(&self) -> Option<&Preflight> {
+        self.preflight.as_ref()
+    }
+
+    /// Store the scenario into the output directory: the source file as-is and the steps as they
+    /// are executed, with defaults applied and variables resolved, so the run can be repeated. The
+    /// files of the continued runs are numbered by their stage.
+    pub fn record_into(&mut self, outdir: &Path, stage: u32) -> Result<(), String> {
+        let name = |name: &str| match stage {
+            1 => name.to_owned(),
+            _ => name.replace(".json", &format!("-{}.json", stage)),
+        };
+        // not copied with the permissions of the source, the outdir files follow the umask
+        fs::read(&self.source)
+            .and_then(|content| fs::write(outdir.join(name(SOURCE_NAME)), content))
+            .map_err(|e| format!("cannot copy scenario into outdir - {}", e))?;
+
+        self.executed_path = Some(outdir.join(name(EXECUTED_NAME)));
+        self.store_executed();
+        Ok(())
+    }
+
+    fn record_executed(&mut self, req: LocalRequest) {
+        self.executed.push(req);
+        self.store_executed();
+    }
+
+    fn store_executed(&self) {
+        if let Some(path) = &self.executed_path {
+            // the scenarios are small, so just rewrite the whole file
+            let content = serde_json::to_string_pretty(&self.executed).unwrap(); // never fails
+            fs::write(path, content).expect("cannot store executed scenario");
+        }
+    }
+
+    /// Handle the failure of the current step: schedule its retry or apply the error policy.
+    fn step_failed(&mut self) {
+        self.capture = None;
+        self.failed = true;
+
+        let on_error = match self.step.take() {
+            Some(LocalRequest::Spawn(mut step)) if step.attempt < step.retries.unwrap_or(0) => {
+                step.attempt += 1;
+                warn!(
+                    "step failed, retrying '{}' (attempt {} of {})",
+                    step.cmd,
+                    step.attempt,
+                    step.retries.unwrap_or(0)
+                );
+                self.requests.push(LocalRequest::Spawn(step));
+                return;
+            }
+            Some(LocalRequest::Spawn(step)) => step.on_error,
+            Some(LocalRequest::Poll(step)) => step.on_error,
+            Some(LocalRequest::PollPid(step)) => step.on_error,
+            Some(LocalRequest::Bracket(step)) => step.on_error,
+            Some(LocalRequest::Tail(step)) => step.on_error,
+            Some(LocalRequest::Watch(step)) => step.on_error,
+            Some(LocalRequest::Ingest(step)) => step.on_error,
+            Some(LocalRequest::Latency(step)) => step.on_error,
+            Some(LocalRequest::Load(step)) => step.on_error,
+            Some(LocalRequest::Journal(step)) => step.on_error,
+            Some(LocalRequest::Collect(step)) => step.on_error,
+            Some(LocalRequest::PauseId(step)) => step.on_error,
+            Some(LocalRequest::ResumeId(step)) => step.on_error,
+            Some(LocalRequest::Freeze(step)) => step.on_error,
+            Some(LocalRequest::Thaw(step)) => step.on_error,
+            Some(LocalRequest::Renice(step)) => step.on_error,
+            Some(LocalRequest::NetCreate(step)) => step.on_error,
+            Some(LocalRequest::NetDestroy(step)) => step.on_error,
+            Some(LocalRequest::Fault(step)) => step.on_error,
+            Some(LocalRequest::Revert(step)) => step.on_error,
+            Some(LocalRequest::WaitIdle(step)) => step.on_error,
+            _ => None,
+        };
+
+        if on_error == Some(ErrorPolicy::ignore) {
+            warn!("step failure is ignored by the error policy");
+        } else {
+            // emulate the Abort message from the controller
+            self.abort = Some(AbortReason::StepFailed);
+        }
+    }
+
+    fn store_capture(&mut self, output: &FgOutput) {
+        let Some(capture) = self.capture.take() else {
+            return;
+        };
+
+        match capture.extract(&output.stdout) {
+            Ok(value) => {
+                info!("captured variable: {}='{}'", capture.var(), value);
+                self.vars.insert(capture.var().to_owned(), value);
+            }
+            Err(msg) => {
+                error!("cannot capture variable '{}': {}", capture.var(), msg);
+                self.abort = Some(AbortReason::StepFailed);
+            }
+        }
+    }
+
+    /// Resolve the variables captured by the previous steps.
+    fn resolve_spawn(&self, step: &SpawnStep) -> Result<SpawnStep, String> {
+        let args = step
+            .args
+            .iter()
+            .flatten()
+            .map(|a| substitute(a, &self.vars))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SpawnStep {
+            cmd: substitute(&step.cmd, &self.vars)?,
+            args: step.args.as_ref().map(|_| args),
+            ..step.clone()
         })
     }
+
+    /// Map the step to the PMPPT request as-is, `None` for the steps needing special handling.
+    fn map_request(step: &LocalRequest) -> Option<PmpptRequest> {
+        let req = match step {
+            LocalRequest::Poll(step) => PmpptRequest::Poll {
+                pattern: step.pattern.clone(),
+                opts: PollOptions {
+                    period: step.period_s.map(Duration::from_secs_f64),
+                    realtime: step.realtime,
+                    fast_period: step.fast_period_s.map(Duration::from_secs_f64),
+                    change_threshold: step.change_threshold,
+                    dedup: step.dedup,
+                    keyframe: step.keyframe_s.map(Duration::from_secs_f64),
+                    max_bytes: step.max_bytes,
+                    parallel: step.parallel,
+                    flush_interval: step.flush_interval_s.map(Duration::from_secs_f64),
+                    fsync_interval: step.fsync_interval_s.map(Duration::from_secs_f64),
+                    binary: step.binary,
+                    split: step.split,
+                    stats: step.stats,
+                    sqlite: step.sqlite,
+                    parquet: step.parquet,
+                    staging_dir: step.staging_dir.clone(),
+                    strict: step.strict,
+                    encoding: step.encoding,
+                    warmup_samples: step.warmup_samples,
+                    warmup: step.warmup_s.map(Duration::from_secs_f64),
+                    warmup_log: step.warmup_log,
+                    duration: step.duration_s.map(Duration::from_secs_f64),
+                    timestamp: step.timestamp,
+                },
+            },
+            LocalRequest::PollPid(step) => {
+                // exactly one of them is set, checked on loading
+                let target = match (step.pid, &step.name, &step.container) {
+                    (Some(pid), _, _) => PidTarget::Pid(pid),
+                    (None, Some(name), _) => PidTarget::Name(name.clone()),
+                    (None, None, spec) => PidTarget::Container(spec.clone().unwrap_or_default()),
+                };
+                PmpptRequest::PollPid {
+                    target,
+                    opts: PollOptions {
+                        period: step.period_s.map(Duration::from_secs_f64),
+                        warmup_samples: step.warmup_samples,
+                        warmup: step.warmup_s.map(Duration::from_secs_f64),
+                        ..Default::default()
+                    },
+                }
+            }
+            LocalRequest::Bracket(step) => PmpptRequest::Bracket {
+                pattern: step.pattern.clone(),
+                commands: step.commands.clone().unwrap_or_default(),
+                cmd: step.cmd.clone(),
+                args: step.args.clone().unwrap_or_default(),
+                opts: SpawnOptions {
+                    cwd: step.cwd.clone(),
+                    env: step.env.clone().unwrap_or_default().into_iter().collect(),
+                    core_dumps: None,
+                    isolate: None,
+                    seccomp: None,
+                    pacing: None,
+                    systemd: None,
+                    container: None,
+                    first_output: false,
+                    umask: None,
+                    output_mode: None,
+                    duration: None,
+                    reply: step.reply,
+                },
+            },
+            LocalRequest::Tail(step) => PmpptRequest::Tail {
+                path: step.path.clone(),
+            },
+            LocalRequest::Watch(step) => PmpptRequest::Watch {
+                pattern: step.pattern.clone(),
+            },
+            LocalRequest::Ingest(step) => PmpptRequest::Ingest {
+                name: step.name.clone(),
+            },
+            LocalRequest::Latency(step) => PmpptRequest::Latency {
+                probe: step.probe.clone(),
+                realtime: step.realtime.unwrap_or(false),
+            },
+            LocalRequest::Load(step) => PmpptRequest::Load {
+                load: step.load.clone(),
+                duration: step.duration_s.map(Duration::from_secs_f64),
+            },
+            LocalRequest::Journal(step) => PmpptRequest::Journal {
+                units: step.units.clone().unwrap_or_default(),
+            },
+            LocalRequest::Collect(step) => PmpptRequest::Collect {
+                host: step.host.clone(),
+                paths: step.paths.clone(),
+            },
+            LocalRequest::PauseId(step) => PmpptRequest::PauseId { id: step.id },
+            LocalRequest::ResumeId(step) => PmpptRequest::ResumeId { id: step.id },
+            LocalRequest::Freeze(step) => PmpptRequest::Freeze { id: step.id },
+            LocalRequest::Thaw(step) => PmpptRequest::Thaw { id: step.id },
+            LocalRequest::Renice(step) => PmpptRequest::Renice {
+                id: step.id,
+                opts: step.opts.clone(),
+            },
+            LocalRequest::NetCreate(step) => PmpptRequest::NetCreate {
+                object: step.object.clone(),
+            },
+            LocalRequest::NetDestroy(step) => PmpptRequest::NetDestroy { id: step.id },
+            LocalRequest::Fault(step) => PmpptRequest::Fault {
+                fault: step.fault.clone(),
+            },
+            LocalRequest::Revert(step) => PmpptRequest::Revert { id: step.id },
+            LocalRequest::WaitIdle(step) => PmpptRequest::WaitIdle {
+                cpu_below: step.cpu_below,
+                window: Duration::from_secs_f64(step.window_s.unwrap_or(DEFAULT_IDLE_WINDOW_S)),
+                timeout: Duration::from_secs_f64(step.timeout_s),
+            },
+            _ => return None,
+        };
+        Some(req)
+    }
+
+    /// Resolve the batch members, the spawns are resolved like the standalone ones.
+    fn resolve_batch(
+        &self,
+        steps: &[LocalRequest],
+    ) -> Result<(Vec<LocalRequest>, Vec<PmpptRequest>), String> {
+        let mut resolved = Vec::new();
+        let mut reqs = Vec::new();
+        for step in steps {
+            let (step, req) = match step {
+                LocalRequest::Spawn(step) => {
+                    let step = self.resolve_spawn(step)?;
+                    let req = Self::map_spawn(&step);
+                    (LocalRequest::Spawn(step), req)
+                }
+                // the other members are checked on loading
+                step => match Self::map_request(step) {
+                    Some(req) => (step.clone(), req),
+                    None => return Err("unexpected batch member".into()),
+                },
+            };
+            resolved.push(step);
+            reqs.push(req);
+        }
+        Ok((resolved, reqs))
+    }
+
+    fn map_spawn(step: &SpawnStep) -> PmpptRequest {
+        PmpptRequest::Spawn {
+            cmd: step.cmd.clone(),
+            args: step.args.clone().unwrap_or_default(), // default is no args
+            mode: local_mode_to_agent(step.mode),        // default is foreground
+            opts: SpawnOptions {
+                cwd: step.cwd.clone(),
+                env: step.env.clone().unwrap_or_default().into_iter().collect(),
+                core_dumps: step.core_dumps,
+                isolate: step.isolate.clone(),
+                seccomp: step.seccomp.clone(),
+                pacing: step.pacing.clone(),
+                systemd: step.systemd.clone(),
+                container: step.container.clone(),
+                first_output: step.first_output.unwrap_or(false),
+                umask: step.umask,
+                output_mode: step.output_mode,
+                duration: step.duration_s.map(Duration::from_secs_f64),
+                reply: step.reply,
+            },
+        }
+    }
 }
 
+/// Time of waiting for the peers at the barrier, when not set by the step.
+const DEFAULT_BARRIER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Time of the usage staying below the threshold of the idle wait, when not set by the step.
+const DEFAULT_IDLE_WINDOW_S: f64 = 3.0;
+
 const GENERIC_PROMPT: &str = r#"
 ==================================================
 =======   Further execution is paused.     =======
@@ -94,35 +1102,125 @@ impl Protocol for LocalProtocol {
         // In local mode we don't have any real PMPPT controller connected. So here we try to
         // imitate its existence by remembering the current executing request to associate agent
         // responses with it.
+        self.step = None;
         self.current = loop {
+            if crate::signals::received().is_some() {
+                break PmpptRequest::Abort {
+                    reason: AbortReason::Signal,
+                };
+            }
+            if let Some(reason) = self.abort {
+                break PmpptRequest::Abort { reason };
+            }
+
             match self.requests.pop() {
-                Some(local_req) => match local_req {
+                Some(local_req) => {
                     // provide mapped command as-is
-                    LocalRequest::Poll { pattern } => break PmpptRequest::Poll { pattern },
-                    LocalRequest::Spawn { cmd, args, mode } => {
-                        break PmpptRequest::Spawn {
-                            cmd,
-                            args: args.unwrap_or_default(), // default is no args
-                            mode: local_mode_to_agent(mode), // default is foreground
-                        };
+                    if let Some(req) = Self::map_request(&local_req) {
+                        self.record_executed(local_req.clone());
+                        self.step = Some(local_req);
+                        break req;
                     }
-                    LocalRequest::Abort => break PmpptRequest::Abort,
 
-                    // handle local commands specially
-                    LocalRequest::Sleep { time } => {
-                        std::thread::sleep(Duration::from_secs_f64(time));
-                        continue;
-                    }
-                    LocalRequest::Pause { prompt } => {
-                        println!("{}", GENERIC_PROMPT.trim());
-                        if let Some(prompt) = prompt {
-                            println!("Description: {}", prompt);
+                    match local_req {
+                        LocalRequest::Spawn(step) if step.attempt > 0 => {
+                            // retried step, it is already resolved and recorded
+                            if let Some(delay) = step.retry_delay_s {
+                                crate::signals::sleep(Duration::from_secs_f64(delay));
+                                if crate::signals::received().is_some() {
+                                    break PmpptRequest::Abort {
+                                        reason: AbortReason::Signal,
+                                    };
+                                }
+                            }
+                            let req = Self::map_spawn(&step);
+                            self.capture = step.capture.clone();
+                            self.step = Some(LocalRequest::Spawn(step));
+                            break req;
+                        }
+                        LocalRequest::Spawn(step) => match self.resolve_spawn(&step) {
+                            Ok(step) => {
+                                let req = Self::map_spawn(&step);
+                                self.record_executed(LocalRequest::Spawn(step.clone()));
+                                self.capture = step.capture.clone();
+                                self.step = Some(LocalRequest::Spawn(step));
+                                break req;
+                            }
+                            Err(msg) => {
+                                error!("cannot resolve spawn command: {}", msg);
+                                break PmpptRequest::Abort {
+                                    reason: AbortReason::StepFailed,
+                                };
+                            }
+                        },
+                        LocalRequest::Batch { ref steps } => match self.resolve_batch(steps) {
+                            Ok((steps, reqs)) => {
+                                let step = LocalRequest::Batch { steps };
+                                self.record_executed(step.clone());
+                                self.step = Some(step);
+                                break PmpptRequest::Batch(reqs);
+                            }
+                            Err(msg) => {
+                                error!("cannot resolve batch: {}", msg);
+                                break PmpptRequest::Abort {
+                                    reason: AbortReason::StepFailed,
+                                };
+                            }
+                        },
+                        LocalRequest::Abort => {
+                            self.record_executed(local_req);
+                            break PmpptRequest::Abort {
+                                reason: AbortReason::Requested,
+                            };
+                        }
+
+                        // handle local commands specially
+                        LocalRequest::Sleep { time } => {
+                            self.record_executed(local_req);
+                            crate::signals::sleep(Duration::from_secs_f64(time));
+                            continue;
+                        }
+                        LocalRequest::Pause { ref prompt } => {
+                            println!("{}", GENERIC_PROMPT.trim());
+                            if let Some(prompt) = prompt {
+                                println!("Description: {}", prompt);
+                            }
+                            self.record_executed(local_req.clone());
+                            std::io::stdin()
+                                .read_exact(&mut [0u8])
+                                .expect("stdin is broken");
                         }
-                        std::io::stdin()
-                            .read_exact(&mut [0u8])
-                            .expect("stdin is broken");
+                        LocalRequest::Barrier(ref step) => {
+                            self.record_executed(local_req.clone());
+                            let timeout = step
+                                .timeout_s
+                                .map_or(DEFAULT_BARRIER_TIMEOUT, Duration::from_secs_f64);
+                            info!(
+                                "waiting for {} peers at barrier '{}'",
+                                step.peers.len(),
+                                step.name
+                            );
+                            match barrier::wait(&step.name, &step.listen, &step.peers, timeout) {
+                                Ok(waited) => {
+                                    info!("passed barrier '{}' after {:?}", step.name, waited)
+                                }
+                                // the signal is handled at the next iteration
+                                Err(_) if crate::signals::received().is_some() => (),
+                                Err(msg) if step.on_error == Some(ErrorPolicy::ignore) => {
+                                    error!("{}", msg);
+                                    warn!("step failure is ignored by the error policy");
+                                }
+                                Err(msg) => {
+                                    error!("{}", msg);
+                                    break PmpptRequest::Abort {
+                                        reason: AbortReason::StepFailed,
+                                    };
+                                }
+                            }
+                        }
+                        _ => unreachable!("mapped PMPPT commands are handled above"),
                     }
-                },
+                }
 
                 // when local requests are over, implicitly generate Finish request
                 None => break PmpptRequest::Finish,
@@ -130,30 +1228,323 @@ impl Protocol for LocalProtocol {
         }
         .into();
 
+        if let Some(step) = &self.step {
+            let kind = serde_json::to_value(step).unwrap()["type"] // never fails
+                .as_str()
+                .unwrap_or_default()
+                .to_owned();
+            // the step is recorded by now, the retried one keeps its index
+            let index = self.executed.len() - 1;
+            self.run_step_hook("pre_step", index, &kind, None);
+            if let Some(reason) = self.abort {
+                // the step is not run without its hook
+                self.step = None;
+                self.current = Some(PmpptRequest::Abort { reason });
+            } else {
+                self.hooked = Some((index, kind));
+                self.failed = false;
+            }
+        }
+
         // return the request to the agent to execute
         self.current.clone()
     }
 
     // imitate that we "receive" a response from PMPPT agent
     fn send_response(&mut self, response: PmpptResponse) -> Option<()> {
+        // taken by the step itself, not by the members of the batch
+        let hooked = self.hooked.take();
+
+        // the response of the wrong kind means the agent is broken, its results cannot be trusted
+        if let Some(req) = &self.current {
+            if !response.answers(req) {
+                error!(
+                    "protocol mismatch: {} response to the {} request",
+                    response.kind(),
+                    req.kind()
+                );
+                self.abort = Some(AbortReason::Protocol);
+                return None;
+            }
+        }
+
         match response {
-            // TODO: stop the execution instead of just panic
             PmpptResponse::Poll(Err(msg)) => {
                 error!(
                     r#"Poll request failed: req={:?}, error="{}""#,
                     self.current, msg
                 );
-
-                // emulate the Abort message from the controller
-                self.requests.push(LocalRequest::Abort);
+                self.step_failed();
             }
 
             PmpptResponse::Poll(Ok(id)) => {
                 debug!("Poll result: id={}", id);
             }
+
+            PmpptResponse::Tail(Err(msg)) => {
+                error!(
+                    r#"Tail request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Tail(Ok(id)) => {
+                debug!("Tail result: id={}", id);
+            }
+
+            PmpptResponse::Watch(Err(msg)) => {
+                error!(
+                    r#"Watch request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Watch(Ok(id)) => {
+                debug!("Watch result: id={}", id);
+            }
+
+            PmpptResponse::Ingest(Err(msg)) => {
+                error!(
+                    r#"Ingest request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Ingest(Ok(id)) => {
+                debug!("Ingest result: id={}", id);
+            }
+
+            PmpptResponse::Latency(Err(msg)) => {
+                error!(
+                    r#"Latency request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Latency(Ok(id)) => {
+                debug!("Latency result: id={}", id);
+            }
+
+            PmpptResponse::Load(Err(msg)) => {
+                error!(
+                    r#"Load request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Load(Ok(id)) => {
+                debug!("Load result: id={}", id);
+            }
+
+            PmpptResponse::Journal(Err(msg)) => {
+                error!(
+                    r#"Journal request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Journal(Ok(id)) => {
+                debug!("Journal result: id={}", id);
+            }
+
+            PmpptResponse::Collect(Err(msg)) => {
+                error!(
+                    r#"Collect request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Collect(Ok(id)) => {
+                debug!("Collect result: id={}", id);
+            }
+
+            PmpptResponse::PauseId(Err(msg)) | PmpptResponse::ResumeId(Err(msg)) => {
+                error!(
+                    r#"Pause request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::PauseId(Ok(())) | PmpptResponse::ResumeId(Ok(())) => {
+                debug!("Pause result: ok");
+            }
+
+            PmpptResponse::Freeze(Err(msg)) | PmpptResponse::Thaw(Err(msg)) => {
+                error!(
+                    r#"Freeze request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Freeze(Ok(())) | PmpptResponse::Thaw(Ok(())) => {
+                debug!("Freeze result: ok");
+            }
+
+            PmpptResponse::Renice(Err(msg)) => {
+                error!(
+                    r#"Renice request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Renice(Ok(())) => {
+                debug!("Renice result: ok");
+            }
+
+            PmpptResponse::NetCreate(Err(msg)) | PmpptResponse::NetDestroy(Err(msg)) => {
+                error!(
+                    r#"Network setup request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::NetCreate(Ok(id)) => {
+                debug!("NetCreate result: id={}", id);
+            }
+
+            PmpptResponse::NetDestroy(Ok(())) => {
+                debug!("NetDestroy result: ok");
+            }
+
+            PmpptResponse::Fault(Err(msg)) | PmpptResponse::Revert(Err(msg)) => {
+                error!(
+                    r#"Fault injection request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::Fault(Ok(id)) => {
+                debug!("Fault result: id={}", id);
+            }
+
+            PmpptResponse::Revert(Ok(())) => {
+                debug!("Revert result: ok");
+            }
+
+            PmpptResponse::WaitIdle(Err(msg)) => {
+                error!(
+                    r#"WaitIdle request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::WaitIdle(Ok(waited)) => {
+                debug!("WaitIdle result: waited={:?}", waited);
+            }
+
+            PmpptResponse::SpawnFg(Err(msg))
+            | PmpptResponse::SpawnBg(Err(msg))
+            | PmpptResponse::Bracket(Err(msg)) => {
+                error!(
+                    r#"Spawn request failed: req={:?}, error="{}""#,
+                    self.current, msg
+                );
+                self.step_failed();
+            }
+
+            PmpptResponse::SpawnFg(Ok(output)) | PmpptResponse::Bracket(Ok(output)) => {
+                debug!(
+                    "Spawn result: id={}, exit_code={:?}, duration={:?}, first_output={:?}, \
+                     omitted={}",
+                    output.id,
+                    output.exit_code,
+                    output.duration,
+                    output.first_output,
+                    output.omitted
+                );
+                if output.success() {
+                    self.store_capture(&output);
+                } else {
+                    error!(
+                        "FG process failed: req={:?}, exit_code={:?}",
+                        self.current, output.exit_code
+                    );
+                    self.step_failed();
+                }
+            }
+
+            PmpptResponse::Batch(responses) => {
+                debug!("Batch result: {} responses", responses.len());
+                // every member response is handled as if it was the standalone step
+                let (Some(LocalRequest::Batch { steps }), Some(PmpptRequest::Batch(reqs))) =
+                    (self.step.take(), self.current.take())
+                else {
+                    unreachable!("batch response to the non-batch request");
+                };
+                for ((step, req), response) in steps.into_iter().zip(reqs).zip(responses) {
+                    self.step = Some(step);
+                    self.current = Some(req);
+                    self.send_response(response);
+                }
+                self.step = None;
+                self.current = None;
+            }
+
+            PmpptResponse::SpawnBg(Ok(proc)) => {
+                debug!(
+                    "Spawn result: id={}, pid={:?}, pgid={:?}",
+                    proc.id, proc.pid, proc.pgid
+                );
+            }
+        }
+
+        if let Some((index, kind)) = hooked {
+            let result = if self.failed { "failed" } else { "ok" };
+            self.run_step_hook("post_step", index, &kind, Some(result));
         }
 
         // in local mode this function cannot fail
         Some(())
     }
 }
+
+#[test]
+fn substitute_variables() {
+    let vars = HashMap::from([("dev".to_owned(), "nvme0n1".to_owned())]);
+
+    assert_eq!(
+        substitute("--filename=/dev/${dev}", &vars).unwrap(),
+        "--filename=/dev/nvme0n1"
+    );
+    assert_eq!(substitute("${pid:3}", &vars).unwrap(), "${pid:3}");
+    assert!(substitute("${unknown}", &vars).is_err());
+}
+
+#[test]
+fn step_types_are_supported() {
+    for step in STEP_TYPES {
+        let value = serde_json::json!({ "type": step });
+        let err = parse_step(value, &Defaults::default()).err();
+        assert!(
+            !err.unwrap_or_default().contains("unknown variant"),
+            "step type '{}' is not supported",
+            step
+        );
+    }
+}
+
+#[test]
+fn batch_members_are_checked() {
+    let batch = |step: Value| serde_json::json!({"type": "Batch", "data": {"steps": [step]}});
+    let defaults = Defaults::default();
+
+    let poll = serde_json::json!({"type": "Poll", "data": {"pattern": "/proc/loadavg"}});
+    assert!(parse_step(batch(poll), &defaults).is_ok());
+    let sleep = serde_json::json!({"type": "Sleep", "data": {"time": 1.0}});
+    assert!(parse_step(batch(sleep), &defaults).is_err());
+    let short = serde_json::json!({"type": "Poll", "data": {"pattern": "/", "period_s": 0.0}});
+    assert!(parse_step(batch(short), &defaults).is_err());
+}
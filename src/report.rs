@@ -0,0 +1,454 @@
+//! Self-contained run report rendered from the output directory.
+//!
+//! The report contains the timeline of the executed steps from the manifest and the charts of the
+//! numeric values found in the poll logs: `key: value` lines like in `/proc/meminfo` or
+//! `/proc/vmstat`, and single-value files like the sysfs attributes.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+
+use crate::inspect::{self, Summary};
+use crate::junit;
+use crate::polllog::{self, PollLog};
+
+/// Maximum number of charts rendered for a single poll log.
+const MAX_CHARTS: usize = 32;
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 160.0;
+const CHART_MARGIN: f64 = 72.0;
+
+/// Numeric value changing over the run time.
+struct Series {
+    name: String,
+    points: Vec<(f64, f64)>,
+}
+
+impl Series {
+    fn min(&self) -> f64 {
+        self.points
+            .iter()
+            .map(|p| p.1)
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    fn max(&self) -> f64 {
+        self.points
+            .iter()
+            .map(|p| p.1)
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    fn avg(&self) -> f64 {
+        self.points.iter().map(|p| p.1).sum::<f64>() / self.points.len() as f64
+    }
+}
+
+struct PollData {
+    name: String,
+    files: Vec<String>,
+    samples: usize,
+    series: Vec<Series>,
+    omitted: usize,
+}
+
+enum Format {
+    Html,
+    Markdown,
+}
+
+/// Find the numeric values in the sample content.
+fn extract_values(re: &Regex, content: &str, single_file: Option<&str>) -> Vec<(String, f64)> {
+    // the whole content is a single number, use the file name as a key
+    if let (Some(file), Ok(value)) = (single_file, content.trim().parse::<f64>()) {
+        return vec![(file.to_owned(), value)];
+    }
+
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    content
+        .lines()
+        .filter_map(|line| re.captures(line))
+        .filter_map(|c| {
+            let key = c.get(1)?.as_str();
+            let value = c.get(2)?.as_str().parse().ok()?;
+
+            // the same keys are possible when polling many similar files
+            let count = seen.entry(key).or_default();
+            *count += 1;
+            let name = match count {
+                1 => key.to_owned(),
+                n => format!("{}#{}", key, n),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+fn load_poll(path: &Path, start: DateTime<FixedOffset>) -> Result<PollData, String> {
+    let re = Regex::new(r"^([A-Za-z_][\w().-]*):?\s+(-?\d+(?:\.\d+)?)(?:\s|$)").unwrap();
+    let log = PollLog::open(path)?;
+    let log_format = log.header.timestamp;
+    let files = log.header.files.clone();
+    let single_file = match files.as_slice() {
+        [file] => Some(file.clone()),
+        _ => None,
+    };
+
+    let mut order = Vec::new();
+    let mut values: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
+    let mut samples = 0;
+
+    for sample in log {
+        let sample = sample?;
+        samples += 1;
+
+        let Ok(time) = polllog::wall_time(&sample.timestamp, log_format) else {
+            continue;
+        };
+        let offset = (time - start).num_microseconds().unwrap_or(0) as f64 / 1e6;
+
+        for (name, value) in extract_values(&re, &sample.content, single_file.as_deref()) {
+            let points = values.entry(name.clone()).or_insert_with(|| {
+                order.push(name);
+                Vec::new()
+            });
+            points.push((offset, value));
+        }
+    }
+
+    // constant values are not interesting to plot
+    let mut series: Vec<Series> = order
+        .into_iter()
+        .map(|name| Series {
+            points: values.remove(&name).unwrap_or_default(),
+            name,
+        })
+        .filter(|s| s.points.len() > 1 && s.max() > s.min())
+        .collect();
+    let omitted = series.len().saturating_sub(MAX_CHARTS);
+    series.truncate(MAX_CHARTS);
+
+    Ok(PollData {
+        name: path.file_name().unwrap().to_string_lossy().into_owned(),
+        files,
+        samples,
+        series,
+        omitted,
+    })
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_chart(series: &Series, duration: f64) -> String {
+    let (min, max) = (series.min(), series.max());
+    let width = CHART_WIDTH - CHART_MARGIN - 8.0;
+    let height = CHART_HEIGHT - 40.0;
+
+    let points: Vec<String> = series
+        .points
+        .iter()
+        .map(|(t, v)| {
+            let x = CHART_MARGIN + t / duration.max(f64::EPSILON) * width;
+            let y = 20.0 + (max - v) / (max - min) * height;
+            format!("{:.1},{:.1}", x, y)
+        })
+        .collect();
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" font-size="11" font-family="sans-serif">
+<text x="{m}" y="12" font-weight="bold">{name}</text>
+<rect x="{m}" y="20" width="{pw}" height="{ph}" fill="none" stroke="#ccc"/>
+<text x="{lx}" y="28" text-anchor="end">{max}</text>
+<text x="{lx}" y="{by}" text-anchor="end">{min}</text>
+<text x="{m}" y="{ty}">0s</text>
+<text x="{rx}" y="{ty}" text-anchor="end">{duration:.1}s</text>
+<polyline fill="none" stroke="#1f77b4" stroke-width="1.5" points="{points}"/>
+</svg>"##,
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        m = CHART_MARGIN,
+        pw = width,
+        ph = height,
+        lx = CHART_MARGIN - 4.0,
+        by = 20.0 + height,
+        ty = 20.0 + height + 14.0,
+        rx = CHART_MARGIN + width,
+        name = escape(&series.name),
+        max = max,
+        min = min,
+        duration = duration,
+        points = points.join(" "),
+    )
+}
+
+fn step_offset(summary: &Summary, start: DateTime<FixedOffset>, id: u32) -> Option<f64> {
+    let started = summary.steps.get(&id)?.started?;
+    Some((started - start).num_microseconds().unwrap_or(0) as f64 / 1e6)
+}
+
+fn render_timeline(summary: &Summary, start: DateTime<FixedOffset>, duration: f64) -> String {
+    let row = 18.0;
+    let width = CHART_WIDTH - CHART_MARGIN - 8.0;
+    let scale = width / duration.max(f64::EPSILON);
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-size="11" font-family="sans-serif">"#,
+        CHART_WIDTH,
+        row * summary.steps.len() as f64 + 4.0
+    );
+    for (i, (id, step)) in summary.steps.iter().enumerate() {
+        let begin = step_offset(summary, start, *id).unwrap_or(0.0);
+        let length = step.duration().unwrap_or(duration - begin);
+        let color = if step.kind == "poll" {
+            "#2ca02c"
+        } else {
+            "#1f77b4"
+        };
+        let y = row * i as f64 + 2.0;
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text><rect x="{:.1}" y="{}" width="{:.1}" height="{}" fill="{}"><title>{}</title></rect>"#,
+            CHART_MARGIN - 4.0,
+            y + 12.0,
+            id,
+            CHART_MARGIN + begin * scale,
+            y,
+            (length * scale).max(1.0),
+            row - 4.0,
+            color,
+            escape(&step.name),
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn render_html(
+    outdir: &Path,
+    summary: &Summary,
+    polls: &[PollData],
+    start: DateTime<FixedOffset>,
+    duration: f64,
+) -> String {
+    let mut html = String::new();
+    let title = format!("PMPPT run report: {}", outdir.to_string_lossy());
+
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\n\
+         <style>body{{font-family:sans-serif}} table{{border-collapse:collapse}} \
+         td,th{{border:1px solid #ccc;padding:2px 6px}}</style></head><body>\n<h1>{0}</h1>",
+        escape(&title)
+    );
+    let _ = writeln!(
+        html,
+        "<p>Outcome: <b>{}</b>, duration: {:.1}s</p>",
+        summary.outcome, duration
+    );
+    if !summary.tags.is_empty() {
+        let _ = writeln!(html, "<p>Tags: {}</p>", escape(&summary.tags_line()));
+    }
+
+    let _ = writeln!(
+        html,
+        "<h2>Timeline</h2>\n{}",
+        render_timeline(summary, start, duration)
+    );
+    let _ = writeln!(
+        html,
+        "<table><tr><th>id</th><th>kind</th><th>start</th><th>duration</th><th>exit</th><th>name</th></tr>"
+    );
+    for (id, step) in &summary.steps {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{:.3}s</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            id,
+            step.kind,
+            step_offset(summary, start, *id).unwrap_or(0.0),
+            step.duration().map_or("-".into(), |d| format!("{:.3}s", d)),
+            step.exit_code.map_or("-".into(), |c| c.to_string()),
+            escape(&step.name),
+        );
+    }
+    html.push_str("</table>\n");
+
+    if !summary.errors.is_empty() {
+        html.push_str("<h2>Errors</h2>\n<ul>\n");
+        for error in &summary.errors {
+            let _ = writeln!(html, "<li>{}</li>", escape(error));
+        }
+        html.push_str("</ul>\n");
+    }
+    if !summary.warnings.is_empty() {
+        html.push_str("<h2>Warnings</h2>\n<ul>\n");
+        for warning in &summary.warnings {
+            let _ = writeln!(html, "<li>{}</li>", escape(warning));
+        }
+        html.push_str("</ul>\n");
+    }
+
+    for poll in polls {
+        let _ = writeln!(
+            html,
+            "<h2>{}</h2>\n<p>{} samples of: {}</p>",
+            escape(&poll.name),
+            poll.samples,
+            escape(&poll.files.join(", "))
+        );
+        if poll.series.is_empty() {
+            html.push_str("<p>No changing numeric values found.</p>\n");
+        }
+        for series in &poll.series {
+            let _ = writeln!(html, "{}", render_chart(series, duration));
+        }
+        if poll.omitted > 0 {
+            let _ = writeln!(html, "<p>{} more charts omitted.</p>", poll.omitted);
+        }
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn render_markdown(
+    outdir: &Path,
+    summary: &Summary,
+    polls: &[PollData],
+    start: DateTime<FixedOffset>,
+    duration: f64,
+) -> String {
+    let mut md = String::new();
+
+    let _ = writeln!(md, "# PMPPT run report: {}\n", outdir.to_string_lossy());
+    let _ = writeln!(
+        md,
+        "Outcome: **{}**, duration: {:.1}s\n",
+        summary.outcome, duration
+    );
+    if !summary.tags.is_empty() {
+        let _ = writeln!(md, "Tags: {}\n", summary.tags_line());
+    }
+
+    md.push_str("## Timeline\n\n| id | kind | start | duration | exit | name |\n|---|---|---|---|---|---|\n");
+    for (id, step) in &summary.steps {
+        let _ = writeln!(
+            md,
+            "| {} | {} | {:.3}s | {} | {} | `{}` |",
+            id,
+            step.kind,
+            step_offset(summary, start, *id).unwrap_or(0.0),
+            step.duration().map_or("-".into(), |d| format!("{:.3}s", d)),
+            step.exit_code.map_or("-".into(), |c| c.to_string()),
+            step.name.replace('|', "\\|"),
+        );
+    }
+
+    if !summary.errors.is_empty() {
+        md.push_str("\n## Errors\n\n");
+        for error in &summary.errors {
+            let _ = writeln!(md, "- {}", error);
+        }
+    }
+    if !summary.warnings.is_empty() {
+        md.push_str("\n## Warnings\n\n");
+        for warning in &summary.warnings {
+            let _ = writeln!(md, "- {}", warning);
+        }
+    }
+
+    for poll in polls {
+        let _ = writeln!(
+            md,
+            "\n## {}\n\n{} samples of: `{}`\n",
+            poll.name,
+            poll.samples,
+            poll.files.join(", ")
+        );
+        if poll.series.is_empty() {
+            md.push_str("No changing numeric values found.\n");
+            continue;
+        }
+        md.push_str("| value | min | avg | max |\n|---|---|---|---|\n");
+        for series in &poll.series {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {:.2} | {} |",
+                series.name,
+                series.min(),
+                series.avg(),
+                series.max()
+            );
+        }
+        if poll.omitted > 0 {
+            let _ = writeln!(md, "\n{} more values omitted.", poll.omitted);
+        }
+    }
+
+    md
+}
+
+/// Render the report of the output directory and store it there, returning the report path.
+pub fn report(outdir: &Path, format: &str) -> Result<PathBuf, String> {
+    let format = match format {
+        "html" => Format::Html,
+        "md" => Format::Markdown,
+        "junit" => return junit::write(outdir),
+        _ => return Err(format!("unknown report format '{}'", format)),
+    };
+
+    let summary = inspect::summarize(outdir)?;
+
+    // the run time is measured from the very first step
+    let times = summary
+        .steps
+        .values()
+        .flat_map(|s| [s.started, s.done])
+        .flatten();
+    let start = times
+        .clone()
+        .min()
+        .ok_or("no steps found in the manifest")?;
+    let duration = (times.max().unwrap() - start)
+        .num_microseconds()
+        .unwrap_or(0) as f64
+        / 1e6;
+
+    let mut logs: Vec<PathBuf> = outdir
+        .read_dir()
+        .map_err(|e| format!("cannot read '{}' - {}", outdir.to_string_lossy(), e))?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| polllog::is_poll_log(p))
+        .collect();
+    logs.sort();
+
+    let polls = logs
+        .iter()
+        .map(|path| load_poll(path, start))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (name, content) = match format {
+        Format::Html => (
+            "report.html",
+            render_html(outdir, &summary, &polls, start, duration),
+        ),
+        Format::Markdown => (
+            "report.md",
+            render_markdown(outdir, &summary, &polls, start, duration),
+        ),
+    };
+
+    let path = outdir.join(name);
+    std::fs::write(&path, content)
+        .map_err(|e| format!("cannot write '{}' - {}", path.to_string_lossy(), e))?;
+    Ok(path)
+}
@@ -0,0 +1,198 @@
+//! Self-test of the environment the agent depends on.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use subprocess::Exec;
+
+/// Paths the pollers usually rely on.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const REQUIRED_PATHS: &[&str] = &[
+    "/proc/stat",
+    "/proc/meminfo",
+    "/proc/diskstats",
+    "/proc/net/dev",
+    "/proc/self/stat",
+    "/sys/devices/system/cpu",
+];
+
+/// No procfs on other systems, the pollers emulate it or use the native counters instead.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const REQUIRED_PATHS: &[&str] = &[];
+
+/// Trivial command to check the process spawning.
+#[cfg(unix)]
+const TRIVIAL_CMD: &[&str] = &["true"];
+#[cfg(windows)]
+const TRIVIAL_CMD: &[&str] = &["cmd", "/C", "exit 0"];
+
+/// Tools used by the typical scenarios, but not needed by the agent itself.
+const OPTIONAL_TOOLS: &[&str] = &["perf", "tcpdump", "fio", "iperf3", "stress-ng"];
+
+const WRITE_TEST_SIZE: usize = 16 << 20;
+const WRITE_BLOCK_SIZE: usize = 1 << 20;
+
+/// Result of a single capability check.
+#[derive(Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, res: Result<String, String>) -> Self {
+        let (ok, detail) = match res {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        Self {
+            name: name.to_owned(),
+            ok,
+            detail,
+        }
+    }
+}
+
+/// Capability report of the target.
+#[derive(Serialize)]
+pub struct Capabilities {
+    pub paths: Vec<Check>,
+    pub spawn: Check,
+    pub write: Check,
+    pub tools: Vec<Check>,
+    pub clock_resolution_ns: u64,
+}
+
+impl Capabilities {
+    /// Whether everything the agent needs to run scenarios is available.
+    pub fn is_sufficient(&self) -> bool {
+        self.paths.iter().all(|c| c.ok) && self.spawn.ok && self.write.ok
+    }
+}
+
+fn check_path(path: &str) -> Result<String, String> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        path.read_dir()
+            .map(|_| "readable directory".to_owned())
+            .map_err(|e| e.to_string())
+    } else {
+        std::fs::read(path)
+            .map(|content| format!("readable, {} bytes", content.len()))
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn check_spawn() -> Result<String, String> {
+    let status = Exec::cmd(TRIVIAL_CMD[0])
+        .args(&TRIVIAL_CMD[1..])
+        .join()
+        .map_err(|e| e.to_string())?;
+    match status.success() {
+        true => Ok("processes can be spawned".to_owned()),
+        false => Err(format!("'{}' failed: {:?}", TRIVIAL_CMD.join(" "), status)),
+    }
+}
+
+fn check_write(dir: &Path) -> Result<String, String> {
+    let path = dir.join(format!(".pmppt-selftest-{}", std::process::id()));
+    let block = vec![0xa5u8; WRITE_BLOCK_SIZE];
+
+    let measure = || -> std::io::Result<Duration> {
+        let start = Instant::now();
+        let mut file = File::create_new(&path)?;
+        for _ in 0..WRITE_TEST_SIZE / WRITE_BLOCK_SIZE {
+            file.write_all(&block)?;
+        }
+        file.sync_all()?;
+        Ok(start.elapsed())
+    };
+
+    let res = measure();
+    let _ = std::fs::remove_file(&path);
+
+    let elapsed =
+        res.map_err(|e| format!("cannot write to '{}' - {}", dir.to_string_lossy(), e))?;
+    let mibs = (WRITE_TEST_SIZE >> 20) as f64 / elapsed.as_secs_f64();
+    Ok(format!("{:.1} MiB/s with fsync", mibs))
+}
+
+fn find_tool(name: &str) -> Result<String, String> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+        .ok_or_else(|| "not found in PATH".to_owned())
+}
+
+/// Estimate the clock resolution as the smallest observable step of the monotonic clock.
+fn clock_resolution() -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..1000 {
+        let start = Instant::now();
+        let mut now = Instant::now();
+        while now == start {
+            now = Instant::now();
+        }
+        best = best.min(now - start);
+    }
+    best
+}
+
+pub fn probe(write_dir: &Path) -> Capabilities {
+    Capabilities {
+        paths: REQUIRED_PATHS
+            .iter()
+            .map(|p| Check::new(p, check_path(p)))
+            .collect(),
+        spawn: Check::new("spawn", check_spawn()),
+        write: Check::new("write", check_write(write_dir)),
+        tools: OPTIONAL_TOOLS
+            .iter()
+            .map(|t| Check::new(t, find_tool(t)))
+            .collect(),
+        clock_resolution_ns: clock_resolution().as_nanos() as u64,
+    }
+}
+
+fn print_check(check: &Check) {
+    print_check_status(check, "FAIL");
+}
+
+fn print_optional(check: &Check) {
+    print_check_status(check, "--");
+}
+
+fn print_check_status(check: &Check, failed: &str) {
+    let status = if check.ok { "ok" } else { failed };
+    println!("  [{:>4}] {:<28} {}", status, check.name, check.detail);
+}
+
+pub fn selftest(write_dir: Option<PathBuf>, json: bool) -> Result<(), String> {
+    let write_dir = write_dir.unwrap_or_else(std::env::temp_dir);
+    let caps = probe(&write_dir);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&caps).unwrap()); // should never fail
+    } else {
+        println!("Paths:");
+        caps.paths.iter().for_each(print_check);
+        println!("Process spawning:");
+        print_check(&caps.spawn);
+        println!("Output location ({}):", write_dir.to_string_lossy());
+        print_check(&caps.write);
+        println!("Optional tools:");
+        caps.tools.iter().for_each(print_optional);
+        println!("Clock resolution: {} ns", caps.clock_resolution_ns);
+    }
+
+    match caps.is_sufficient() {
+        true => Ok(()),
+        false => Err("the environment lacks required capabilities".into()),
+    }
+}
@@ -0,0 +1,50 @@
+//! Termination signals handling to stop the agent in a controlled way.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Number of the last termination signal received, zero if none.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Granularity of the interruptible sleeps.
+const SLEEP_STEP: Duration = Duration::from_millis(100);
+
+extern "C" fn on_signal(signum: libc::c_int) {
+    // only async-signal-safe operations are allowed here
+    RECEIVED.store(signum, Ordering::Release);
+}
+
+/// Install the handlers for SIGINT and SIGTERM, on Windows SIGINT is raised on Ctrl-C.
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signum in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to the atomic variable
+        let res = unsafe { libc::signal(signum, handler) };
+        // SIG_ERR has different types on the platforms
+        assert_ne!(
+            res,
+            libc::SIG_ERR as libc::sighandler_t,
+            "cannot install signal handler"
+        );
+    }
+}
+
+/// Get the termination signal received, if any.
+pub fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::Acquire) {
+        0 => None,
+        signum => Some(signum),
+    }
+}
+
+/// Sleep for the given time, returning earlier if the termination signal is received.
+pub fn sleep(time: Duration) {
+    let deadline = Instant::now() + time;
+    while received().is_none() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(SLEEP_STEP));
+    }
+}
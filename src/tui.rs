@@ -0,0 +1,246 @@
+//! Live status view of the local run for the long interactive experiments.
+//!
+//! The view follows the manifest of the run, like `inspect` does for the finished runs, and is
+//! redrawn every second in the top lines of the terminal: the active pollers, the running
+//! processes with their elapsed time, the size of the outdir and the recent errors. The rest of
+//! the terminal is the scroll region of the agent log and of the prompts of the pauses. The size
+//! of the terminal is taken once at the start.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::inspect::{self, Summary};
+
+/// Rows of the pollers and of the processes each, the rest are counted only.
+const ROWS: usize = 4;
+/// Recent errors shown.
+const ERRORS: usize = 3;
+/// Lines of the status panel: the header, the sections and the separator.
+const PANEL: usize = 1 + 2 * (1 + ROWS + 1) + 1 + ERRORS + 1;
+const PERIOD: Duration = Duration::from_secs(1);
+
+const PROCESS_KINDS: [&str; 3] = ["Foreground", "BackgroundKill", "BackgroundWait"];
+/// Steps staying unfinished till the stop without running anything.
+const OBJECT_KINDS: [&str; 3] = ["net", "fault", "collect"];
+
+pub struct View {
+    stop: Arc<AtomicBool>,
+    thrd: JoinHandle<()>,
+}
+
+/// Rows and columns of the terminal on stdout.
+#[cfg(unix)]
+fn terminal_size() -> Result<(usize, usize), String> {
+    // SAFETY: plain call on the standard descriptor
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+        return Err("stdout is not a terminal".into());
+    }
+    // SAFETY: zeroed plain struct filled by the call
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: plain call with the valid struct
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("cannot get the terminal size - {}", e));
+    }
+    Ok((size.ws_row as usize, size.ws_col as usize))
+}
+
+#[cfg(not(unix))]
+fn terminal_size() -> Result<(usize, usize), String> {
+    Err("the terminal view is supported only on unix".into())
+}
+
+/// Total size of the files in the directory tree, the symlinks are not followed.
+fn disk_usage(dir: &Path) -> u64 {
+    let Ok(entries) = dir.read_dir() else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => disk_usage(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map_or(0, |m| m.len()),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn clock(secs: u64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Lines of the panel, cut to the width, the outcome is shown when the run is over.
+fn panel(
+    outdir: &Path,
+    summary: &Summary,
+    elapsed: Duration,
+    over: bool,
+    width: usize,
+) -> Vec<String> {
+    let now = chrono::Local::now().fixed_offset();
+    let running: Vec<_> = summary
+        .steps
+        .iter()
+        .filter(|(_, step)| step.done.is_none() && !OBJECT_KINDS.contains(&step.kind.as_str()))
+        .collect();
+    let (procs, polls): (Vec<_>, Vec<_>) = running
+        .into_iter()
+        .partition(|(_, step)| PROCESS_KINDS.contains(&step.kind.as_str()));
+
+    let mut lines = vec![format!(
+        "pmppt-agent  {}  {}  outdir {}  {}",
+        outdir.to_string_lossy(),
+        clock(elapsed.as_secs()),
+        inspect::human_size(disk_usage(outdir)),
+        if over { &summary.outcome } else { "running" },
+    )];
+    for (title, steps) in [("Pollers", &polls), ("Processes", &procs)] {
+        lines.push(format!("{} ({}):", title, steps.len()));
+        for (id, step) in steps.iter().take(ROWS) {
+            let secs = step
+                .started
+                .map_or(0, |started| (now - started).num_seconds());
+            lines.push(format!(
+                "  {:>4}  {:<14}  {}  {}",
+                id,
+                step.kind,
+                clock(secs.max(0) as u64),
+                step.name
+            ));
+        }
+        match steps.len().checked_sub(ROWS) {
+            Some(more) if more > 0 => lines.push(format!("  ... {} more", more)),
+            _ => lines.push(String::new()),
+        }
+        lines.resize(
+            lines.len() + ROWS.saturating_sub(steps.len()),
+            String::new(),
+        );
+    }
+    lines.push(format!("Errors ({}):", summary.errors.len()));
+    let recent = summary.errors.len().saturating_sub(ERRORS);
+    lines.extend(summary.errors[recent..].iter().map(|e| format!("  {}", e)));
+    lines.resize(PANEL - 1, String::new());
+    lines.push("-".repeat(width));
+    lines
+        .into_iter()
+        .map(|line| line.chars().take(width).collect())
+        .collect()
+}
+
+/// Draw the panel in the top lines keeping the cursor in the log region, as a single write.
+fn draw(lines: &[String]) {
+    let mut frame = String::from("\x1b7");
+    for (row, line) in lines.iter().enumerate() {
+        frame.push_str(&format!("\x1b[{};1H\x1b[2K{}", row + 1, line));
+    }
+    frame.push_str("\x1b8");
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout
+        .write_all(frame.as_bytes())
+        .and_then(|()| stdout.flush());
+}
+
+/// Terminal checked to fit the view, before anything is started.
+pub struct Terminal {
+    rows: usize,
+    cols: usize,
+}
+
+impl Terminal {
+    pub fn open() -> Result<Self, String> {
+        let (rows, cols) = terminal_size()?;
+        if rows < 2 * PANEL {
+            return Err(format!(
+                "terminal has {} rows, the view needs {}",
+                rows,
+                2 * PANEL
+            ));
+        }
+        Ok(Self { rows, cols })
+    }
+
+    /// Reserve the top of the terminal and follow the run in the output directory.
+    pub fn follow(self, outdir: &Path) -> View {
+        let Self { rows, cols } = self;
+        // clear the screen and leave only the lines under the panel scrolling
+        print!("\x1b[2J\x1b[{};{}r\x1b[{};1H", PANEL + 1, rows, PANEL + 1);
+        let _ = std::io::stdout().flush();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_thread = stop.clone();
+        let outdir = PathBuf::from(outdir);
+        let thrd = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut shown = None;
+            loop {
+                let stopped = stop_thread.load(Ordering::Acquire);
+                // the last record may be partially written, the previous state is kept then
+                if let Ok(summary) = inspect::summarize(&outdir) {
+                    shown = Some(summary);
+                }
+                if let Some(summary) = &shown {
+                    let lines = panel(&outdir, summary, started.elapsed(), stopped, cols);
+                    draw(&lines);
+                }
+                if stopped {
+                    break;
+                }
+                std::thread::park_timeout(PERIOD);
+            }
+            // the whole terminal scrolls again, the log goes on under the final panel
+            print!("\x1b[r\x1b[{};1H", rows);
+            let _ = std::io::stdout().flush();
+        });
+        View { stop, thrd }
+    }
+}
+
+impl View {
+    /// Draw the final state and give the terminal back.
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Release);
+        self.thrd.thread().unpark();
+        self.thrd.join().expect("view thread panicked");
+    }
+}
+
+#[test]
+fn panel_lines() {
+    let step = |kind: &str, name: &str| inspect::Step {
+        kind: kind.to_owned(),
+        name: name.to_owned(),
+        started: None,
+        done: None,
+        exit_code: None,
+    };
+    let steps = (1..=6).map(|id| (id, step("BackgroundKill", "sleep 100")));
+    let summary = Summary {
+        tags: Default::default(),
+        steps: steps
+            .chain([(7, step("poll", "/proc/stat")), (8, step("net", "netns"))])
+            .collect(),
+        errors: (1..=5).map(|i| format!("error {}", i)).collect(),
+        warnings: Vec::new(),
+        outcome: "running".to_owned(),
+    };
+    let lines = panel(
+        Path::new("out/0"),
+        &summary,
+        Duration::from_secs(3725),
+        false,
+        60,
+    );
+    assert_eq!(lines.len(), PANEL);
+    assert!(lines[0].starts_with("pmppt-agent  out/0  01:02:05"));
+    assert_eq!(lines[1], "Pollers (1):");
+    assert!(lines[2].ends_with("/proc/stat"));
+    assert_eq!(lines[7], "Processes (6):");
+    assert_eq!(lines[12], "  ... 2 more");
+    assert_eq!(lines[13..15], ["Errors (5):", "  error 3"]);
+    assert!(lines.iter().all(|line| line.chars().count() <= 60));
+}
@@ -0,0 +1,127 @@
+//! Delivery of the run output to the remote storage after the run.
+//!
+//! The output directory is archived by `tar` next to itself and the archive is uploaded by the
+//! common tools: `aws s3 cp` for the `s3://` URLs, which does the multipart upload of the large
+//! archives and honours the usual `AWS_*` environment like `AWS_ENDPOINT_URL` for the
+//! S3-compatible storages, and `curl --upload-file` (HTTP PUT) for the `http(s)://` ones. The
+//! archive name is appended to the URLs ending with `/`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{info, warn};
+use subprocess::{Exec, Redirection};
+
+/// Delay before the first retry, doubled for every next one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Run the tool, its output is returned as the error on failure.
+fn run(exec: Exec) -> Result<(), String> {
+    let name = exec.to_cmdline_lossy();
+    let capture = exec
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("cannot run '{}' - {}", name, e))?;
+    match capture.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "'{}' failed: {:?} {}",
+            name,
+            capture.exit_status,
+            capture.stdout_str().trim()
+        )),
+    }
+}
+
+fn archive(outdir: &Path) -> Result<PathBuf, String> {
+    let outdir = outdir
+        .canonicalize()
+        .map_err(|e| format!("bad output dir '{}' - {}", outdir.to_string_lossy(), e))?;
+    let (Some(base), Some(name)) = (outdir.parent(), outdir.file_name()) else {
+        return Err(format!("cannot archive '{}'", outdir.to_string_lossy()));
+    };
+
+    // the run directories are just numbered, so the time makes the name unique in the storage
+    let time = chrono::Local::now().format("%Y%m%dT%H%M%S");
+    let path = base.join(format!("pmppt-{}-{}.tar.gz", name.to_string_lossy(), time));
+    run(Exec::cmd("tar")
+        .arg("-czf")
+        .arg(&path)
+        .arg("-C")
+        .arg(base)
+        .arg(name))?;
+    Ok(path)
+}
+
+fn target(url: &str, archive: &Path) -> String {
+    match url.ends_with('/') {
+        true => format!(
+            "{}{}",
+            url,
+            archive.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        false => url.to_owned(),
+    }
+}
+
+fn send(archive: &Path, url: &str) -> Result<(), String> {
+    let exec = if url.starts_with("s3://") {
+        Exec::cmd("aws")
+            .args(&["s3", "cp", "--only-show-errors"])
+            .arg(archive)
+            .arg(url)
+    } else if url.starts_with("http://") || url.starts_with("https://") {
+        Exec::cmd("curl")
+            .args(&["--fail", "--silent", "--show-error", "--upload-file"])
+            .arg(archive)
+            .arg(url)
+    } else {
+        return Err(format!("unsupported upload URL '{}'", url));
+    };
+    run(exec)
+}
+
+/// Archive the output directory and upload it, retrying the failed uploads.
+pub fn upload(outdir: &Path, url: &str, retries: u32) -> Result<(), String> {
+    let archive = archive(outdir)?;
+    let url = target(url, &archive);
+    info!("uploading '{}' to {}", archive.to_string_lossy(), url);
+
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    let res = loop {
+        match send(&archive, &url) {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!(
+                    "upload failed, retrying in {:?} (attempt {} of {}): {}",
+                    delay, attempt, retries, e
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            res => break res,
+        }
+    };
+
+    // the archive is kept for the manual delivery if the upload failed
+    if res.is_ok() {
+        info!("uploaded to {}", url);
+        let _ = std::fs::remove_file(&archive);
+    }
+    res
+}
+
+#[test]
+fn upload_target() {
+    let archive = Path::new("/out/pmppt-3-20240101T000000.tar.gz");
+    assert_eq!(
+        target("s3://bucket/lab/", archive),
+        "s3://bucket/lab/pmppt-3-20240101T000000.tar.gz"
+    );
+    assert_eq!(
+        target("https://host/runs/latest.tar.gz", archive),
+        "https://host/runs/latest.tar.gz"
+    );
+}